@@ -1,28 +1,268 @@
+use std::future::Future;
+use std::io;
 use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::process::Command as TokioCommand;
+use tokio::sync::watch;
+use tracing::{debug, instrument};
 
-pub async fn run_external_command(
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandLimits {
+    /// Cut short further by the deadline of the enclosing `CommandScope`, if any.
+    pub timeout: Duration,
+    /// Bytes allowed on each of stdout and stderr; the command is stopped past them.
+    pub max_output: usize,
+}
+
+impl Default for CommandLimits {
+    fn default() -> Self {
+        CommandLimits {
+            timeout: Duration::from_secs(60),
+            max_output: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl CommandLimits {
+    pub fn with_timeout(timeout: Duration) -> Self {
+        CommandLimits {
+            timeout,
+            ..CommandLimits::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when a signal ended the process.
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+}
+
+tokio::task_local! {
+    static SCOPE: CommandScope;
+}
+
+/// Deadline and cancellation shared by every command started inside `CommandScope::run`, so
+/// an RPC handler can hand its request's lifetime down without threading it through each call.
+#[derive(Debug, Clone, Default)]
+pub struct CommandScope {
+    deadline: Option<Instant>,
+    /// Nothing is ever sent; the channel closing is the cancellation.
+    cancelled: Option<watch::Receiver<()>>,
+}
+
+/// Cancels its scope when dropped, which stops the commands still running in it.
+#[derive(Debug)]
+pub struct CancelOnDrop {
+    _sender: watch::Sender<()>,
+}
+
+impl CommandScope {
+    pub fn new(deadline: Instant) -> (Self, CancelOnDrop) {
+        let (sender, receiver) = watch::channel(());
+        let scope = CommandScope {
+            deadline: Some(deadline),
+            cancelled: Some(receiver),
+        };
+        (scope, CancelOnDrop { _sender: sender })
+    }
+
+    pub async fn run<F: Future>(self, f: F) -> F::Output {
+        SCOPE.scope(self, f).await
+    }
+
+    fn current() -> Self {
+        SCOPE.try_with(Clone::clone).unwrap_or_default()
+    }
+
+    async fn cancelled(&mut self) {
+        match &mut self.cancelled {
+            Some(receiver) => while receiver.changed().await.is_ok() {},
+            None => std::future::pending().await,
+        }
+    }
+}
+
+/// Fails once the current scope's deadline has passed or it was cancelled, so work made of
+/// several steps can stop between them even when no command is running.
+pub fn check_scope() -> io::Result<()> {
+    let scope = CommandScope::current();
+    if scope
+        .deadline
+        .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "the deadline has passed",
+        ));
+    }
+    if scope
+        .cancelled
+        .is_some_and(|receiver| receiver.has_changed().is_err())
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            "the request was cancelled",
+        ));
+    }
+    Ok(())
+}
+
+/// Runs `f` outside any scope, for cleanup that has to happen even when the request that
+/// started it is gone.
+pub async fn unscoped<F: Future>(f: F) -> F::Output {
+    CommandScope::default().run(f).await
+}
+
+/// Runs `program` in a process group of its own. The whole group is killed when the command
+/// times out, writes more than allowed or its scope is cancelled, and when the returned future
+/// is dropped before the command ends.
+// Arguments stay out of the span; some carry credentials, such as git remotes with a token.
+#[instrument(level = "debug", skip_all, fields(program = %program))]
+pub async fn run_command(
     program: &str,
     args: &[&str],
-) -> Result<String, tokio::io::Error> {
-    let output = TokioCommand::new(program)
+    limits: CommandLimits,
+) -> io::Result<CommandOutput> {
+    let started = Instant::now();
+    let mut scope = CommandScope::current();
+    let timeout = scope.deadline.map_or(limits.timeout, |deadline| {
+        deadline
+            .saturating_duration_since(started)
+            .min(limits.timeout)
+    });
+    if timeout.is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{program} not started, the deadline has passed"),
+        ));
+    }
+    let mut child = TokioCommand::new(program)
         .args(args)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .output()
-        .await?;
+        .process_group(0)
+        .spawn()?;
+    let mut group = ProcessGroup(child.id());
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+
+    let output = async {
+        tokio::try_join!(
+            read_capped(program, stdout, limits.max_output),
+            read_capped(program, stderr, limits.max_output),
+            child.wait(),
+        )
+    };
+    let result = tokio::select! {
+        result = tokio::time::timeout(timeout, output) => result.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("{program} timed out after {timeout:?}"),
+            ))
+        }),
+        _ = scope.cancelled() => Err(io::Error::new(
+            io::ErrorKind::Interrupted,
+            format!("{program} was cancelled"),
+        )),
+    };
+    let (stdout, stderr, status) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            group.kill();
+            let _ = child.wait().await;
+            return Err(e);
+        }
+    };
+    // Reaped, so its id may belong to someone else by now.
+    group.0 = None;
+
+    let output = CommandOutput {
+        stdout: String::from_utf8_lossy(&stdout).to_string(),
+        stderr: String::from_utf8_lossy(&stderr).to_string(),
+        exit_code: status.code(),
+        duration: started.elapsed(),
+    };
+    debug!(
+        "{program} exited with {:?} after {:?}",
+        output.exit_code, output.duration
+    );
+    Ok(output)
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+/// Runs `program` with the default limits and returns its trimmed stdout.
+pub async fn run_external_command(
+    program: &str,
+    args: &[&str],
+) -> Result<String, tokio::io::Error> {
+    run_external_command_with(program, args, CommandLimits::default()).await
+}
 
-    if !output.status.success() {
+pub async fn run_external_command_with(
+    program: &str,
+    args: &[&str],
+    limits: CommandLimits,
+) -> Result<String, tokio::io::Error> {
+    let output = run_command(program, args, limits).await?;
+
+    if !output.success() {
         return Err(tokio::io::Error::other(format!(
-            "Command failed: {}. Stderr: {}",
+            "Command failed: {} (exit code {:?}). Stderr: {}",
             program,
-            stderr.trim()
+            output.exit_code,
+            output.stderr.trim()
         )));
     }
 
-    Ok(stdout.trim().to_string())
+    Ok(output.stdout.trim().to_string())
+}
+
+async fn read_capped(
+    program: &str,
+    pipe: Option<impl AsyncRead + Unpin>,
+    max_output: usize,
+) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(pipe) = pipe {
+        pipe.take(max_output as u64 + 1)
+            .read_to_end(&mut buf)
+            .await?;
+    }
+    if buf.len() > max_output {
+        return Err(io::Error::other(format!(
+            "{program} wrote more than {max_output} bytes"
+        )));
+    }
+    Ok(buf)
+}
+
+/// The group a child leads, killed on drop while the child is not reaped yet.
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    fn kill(&mut self) {
+        if let Some(id) = self.0.take() {
+            // SAFETY: killpg only sends a signal. The leader is not reaped yet, so the id
+            // still names its group.
+            unsafe {
+                libc::killpg(id as libc::pid_t, libc::SIGKILL);
+            }
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
 }
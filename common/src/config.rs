@@ -0,0 +1,154 @@
+//! Settings every service reads at startup. All services share one TOML file,
+//! `/etc/hivehost/config.toml` unless `--config <path>` names another, and each takes the
+//! sections it uses; a missing file or setting keeps the default. `HIVEHOST_<SECTION>__<KEY>`
+//! environment variables override the file, e.g. `HIVEHOST_RPC__SERVER_PORT=6051`.
+//! `--print-config` prints the settings a service ends up with and exits.
+//!
+//! Secrets and credentials stay out of it, in the environment as before.
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub use crate::paths::PathsConfig;
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/hivehost/config.toml";
+const ENV_PREFIX: &str = "HIVEHOST";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Could not read the configuration: {0}")]
+    Load(#[from] ::config::ConfigError),
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Usage(String),
+}
+
+/// The configuration of one service.
+pub trait Settings: Serialize + DeserializeOwned {
+    /// Checked once everything is read; the message names the setting at fault.
+    fn validate(&self) -> Result<(), String>;
+}
+
+/// Settings of the RPC between the services, which both ends of each connection must share.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Port of the server's RPC listener, which the website connects to.
+    pub server_port: u16,
+    /// Port of the server's HTTP listener for token actions, which browsers reach directly.
+    pub server_token_port: u16,
+    pub helper_socket: String,
+    pub hosting_socket: String,
+    /// Largest RPC message in bytes, either way.
+    pub max_frame_length: usize,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            server_port: 5051,
+            server_token_port: 5052,
+            helper_socket: "/run/hivehost_server_helper/server_helper.sock".to_string(),
+            hosting_socket: "/run/hivehost_server_hosting/server_hosting.sock".to_string(),
+            max_frame_length: 10 * 10 * 1024,
+        }
+    }
+}
+
+impl RpcConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.server_port == 0 || self.server_token_port == 0 {
+            return Err("rpc.server_port and rpc.server_token_port must be set".to_string());
+        }
+        if self.server_port == self.server_token_port {
+            return Err("rpc.server_port and rpc.server_token_port must differ".to_string());
+        }
+        for (name, path) in [
+            ("helper_socket", &self.helper_socket),
+            ("hosting_socket", &self.hosting_socket),
+        ] {
+            if !Path::new(path).is_absolute() {
+                return Err(format!("rpc.{name} must be absolute: {path}"));
+            }
+        }
+        if self.max_frame_length < 1024 {
+            return Err("rpc.max_frame_length must be at least 1024 bytes".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Reads and validates the settings of this process, from the file and the environment as
+/// described above. Prints them and exits the process if `--print-config` was given.
+pub fn load<T: Settings>() -> Result<T, ConfigError> {
+    let options = Options::parse()?;
+    let path = options.path.as_deref().unwrap_or(DEFAULT_CONFIG_PATH);
+    let settings: T = ::config::Config::builder()
+        .add_source(
+            ::config::File::new(path, ::config::FileFormat::Toml).required(options.path.is_some()),
+        )
+        .add_source(
+            ::config::Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true),
+        )
+        .build()?
+        .try_deserialize()?;
+    settings.validate().map_err(ConfigError::Invalid)?;
+    if options.print {
+        let toml =
+            toml::to_string_pretty(&settings).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        print!("{toml}");
+        std::process::exit(0);
+    }
+    Ok(settings)
+}
+
+/// The command line arguments, without the program name and the options `load` reads.
+pub fn other_args() -> Vec<String> {
+    Options::parse().map(|o| o.others).unwrap_or_default()
+}
+
+struct Options {
+    path: Option<String>,
+    print: bool,
+    others: Vec<String>,
+}
+
+impl Options {
+    fn parse() -> Result<Self, ConfigError> {
+        let mut options = Options {
+            path: None,
+            print: false,
+            others: Vec::new(),
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    let path = args.next().ok_or_else(|| {
+                        ConfigError::Usage("--config needs the path of a file".to_string())
+                    })?;
+                    options.path = Some(path);
+                }
+                "--print-config" => options.print = true,
+                _ => match arg.strip_prefix("--config=") {
+                    Some(path) => options.path = Some(path.to_string()),
+                    None => options.others.push(arg),
+                },
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Fails with a message naming `setting` unless `seconds` is positive.
+pub fn require_positive_secs(setting: &str, seconds: u64) -> Result<(), String> {
+    if seconds == 0 {
+        return Err(format!("{setting} must be at least one second"));
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+//! Errors that cross the tarpc boundary. Each side logs the full error where it happens and
+//! sends only its kind and a message that is safe to show to users.
+
+use crate::SanitizeError;
+use serde::{Deserialize, Serialize};
+use std::io;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum RemoteErrorKind {
+    InvalidInput,
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    Conflict,
+    QuotaExceeded,
+    DiskFull,
+    TimedOut,
+    Unavailable,
+    Internal,
+}
+
+impl RemoteErrorKind {
+    /// Stable identifier of the kind, for logs and for clients that match on it.
+    pub fn code(self) -> &'static str {
+        match self {
+            RemoteErrorKind::InvalidInput => "invalid_input",
+            RemoteErrorKind::NotFound => "not_found",
+            RemoteErrorKind::PermissionDenied => "permission_denied",
+            RemoteErrorKind::AlreadyExists => "already_exists",
+            RemoteErrorKind::Conflict => "conflict",
+            RemoteErrorKind::QuotaExceeded => "quota_exceeded",
+            RemoteErrorKind::DiskFull => "disk_full",
+            RemoteErrorKind::TimedOut => "timed_out",
+            RemoteErrorKind::Unavailable => "unavailable",
+            RemoteErrorKind::Internal => "internal",
+        }
+    }
+
+    /// Whether the same request may succeed when sent again unchanged.
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            RemoteErrorKind::TimedOut | RemoteErrorKind::Unavailable
+        )
+    }
+
+    pub fn default_message(self) -> &'static str {
+        match self {
+            RemoteErrorKind::InvalidInput => "Invalid input",
+            RemoteErrorKind::NotFound => "Not found",
+            RemoteErrorKind::PermissionDenied => "Permission denied",
+            RemoteErrorKind::AlreadyExists => "Already exists",
+            RemoteErrorKind::Conflict => "Conflict with the current state",
+            RemoteErrorKind::QuotaExceeded => "Disk quota exceeded",
+            RemoteErrorKind::DiskFull => "No space left on the server",
+            RemoteErrorKind::TimedOut => "Timed out",
+            RemoteErrorKind::Unavailable => "Server unavailable",
+            RemoteErrorKind::Internal => "Internal server error",
+        }
+    }
+}
+
+impl From<io::ErrorKind> for RemoteErrorKind {
+    fn from(kind: io::ErrorKind) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => RemoteErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+                RemoteErrorKind::PermissionDenied
+            }
+            io::ErrorKind::AlreadyExists => RemoteErrorKind::AlreadyExists,
+            io::ErrorKind::DirectoryNotEmpty => RemoteErrorKind::Conflict,
+            io::ErrorKind::InvalidInput
+            | io::ErrorKind::InvalidData
+            | io::ErrorKind::InvalidFilename
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::FileTooLarge => RemoteErrorKind::InvalidInput,
+            io::ErrorKind::QuotaExceeded => RemoteErrorKind::QuotaExceeded,
+            io::ErrorKind::StorageFull => RemoteErrorKind::DiskFull,
+            io::ErrorKind::TimedOut => RemoteErrorKind::TimedOut,
+            io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe => RemoteErrorKind::Unavailable,
+            _ => RemoteErrorKind::Internal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error, Deserialize, Serialize)]
+#[error("{message}")]
+pub struct RemoteError {
+    pub kind: RemoteErrorKind,
+    /// Shown to users as is, so it never carries paths, command output or other internals.
+    pub message: String,
+}
+
+impl RemoteError {
+    pub fn new(kind: RemoteErrorKind, message: impl Into<String>) -> Self {
+        RemoteError {
+            kind,
+            message: message.into(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.kind.code()
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.kind.is_retryable()
+    }
+}
+
+impl From<RemoteErrorKind> for RemoteError {
+    fn from(kind: RemoteErrorKind) -> Self {
+        RemoteError::new(kind, kind.default_message())
+    }
+}
+
+/// Keeps only the kind; an I/O error's text can name paths.
+impl From<&io::Error> for RemoteError {
+    fn from(error: &io::Error) -> Self {
+        RemoteErrorKind::from(error.kind()).into()
+    }
+}
+
+impl From<SanitizeError> for RemoteError {
+    fn from(_: SanitizeError) -> Self {
+        RemoteErrorKind::InvalidInput.into()
+    }
+}
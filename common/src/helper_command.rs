@@ -1,50 +1,55 @@
-use crate::{ProjectSlugStr, SanitizeError, SnapShotNameStr, UserSlugStr, Validate};
+use crate::error::RemoteError;
+use crate::{
+    ProjectSlugStr, SanitizeError, SnapShotNameStr, UserSlugStr, Validate,
+    get_project_snapshot_prefix, sanitize_err,
+};
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "tarpc-server-to-helper")]
 pub mod tarpc {
     use crate::helper_command::{HelperCommand, HelperResponse};
-    use crate::tarpc_client::{TarpcClient, TarpcClientError};
-    use crate::{AuthResponse, AuthToken, Validate};
+    use crate::protocol::ProtocolInfo;
+    use crate::rpc_methods;
+    use crate::tarpc_client::{Idempotent, Ping, TarpcClient};
+    use crate::{AuthResponse, AuthToken};
+    use std::time::Duration;
     use tarpc::client::RpcError;
-    use tarpc::context;
+    use tarpc::context::Context;
 
-    pub const HELPER_SOCKET_PATH: &str = "/run/hivehost_server_helper/server_helper.sock";
+    /// Time a batch gets before the helper stops it and rolls it back.
+    pub const HELPER_REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+    /// What this build speaks on `ServerHelper`. Every command is part of the base protocol.
+    pub fn protocol_info(software_version: &str) -> ProtocolInfo {
+        ProtocolInfo::new(software_version, &[])
+    }
 
     #[tarpc::service]
     pub trait ServerHelper {
+        async fn auth(token: AuthToken, client: ProtocolInfo) -> AuthResponse;
+        async fn ping();
         async fn execute(actions: Vec<HelperCommand>) -> HelperResponse;
-        async fn auth(token: AuthToken) -> AuthResponse;
     }
 
-    impl TarpcClient<ServerHelperClient> {
-        pub async fn execute(
-            &self,
-            actions: Vec<HelperCommand>,
-        ) -> Result<HelperResponse, TarpcClientError> {
-            let client = self.get_or_connect_client().await?;
-            let result = client.execute(context::current(), actions.clone()).await;
-            if let Err(RpcError::Shutdown) = result {
-                self.disconnect().await;
-                let client = self.get_or_connect_client().await?;
-                client
-                    .execute(context::current(), actions)
-                    .await
-                    .map_err(From::from)
-            } else {
-                result.map_err(From::from)
-            }
+    impl Ping for ServerHelperClient {
+        fn ping(&self, context: Context) -> impl Future<Output = Result<(), RpcError>> + Send {
+            ServerHelperClient::ping(self, context)
         }
+    }
 
-        pub async fn auth(&self, token: AuthToken) -> Result<bool, TarpcClientError> {
-            if token.validate().is_err() {
-                return Ok(false);
-            }
-            let client = self.get_or_connect_client().await?;
-            match client.auth(context::current(), token.clone()).await {
-                Ok(AuthResponse::Ok) => Ok(true),
-                _ => Ok(false),
-            }
+    impl Idempotent for HelperCommand {
+        fn is_idempotent(&self) -> bool {
+            matches!(
+                self,
+                HelperCommand::EnableQuota | HelperCommand::ProjectUsage { .. }
+            )
+        }
+    }
+
+    impl TarpcClient<ServerHelperClient> {
+        rpc_methods! {
+            fn execute(actions: Vec<HelperCommand>) -> HelperResponse,
+                timeout = HELPER_REQUEST_TIMEOUT, retry if actions.is_idempotent();
         }
     }
 }
@@ -92,9 +97,12 @@ pub enum HelperCommand {
     DeleteSnapshot {
         snapshot_name: SnapShotNameStr,
     },
+    /// Replaces the dev subvolume with a copy of `snapshot_name`, keeping the current one as
+    /// the read-only `backup_snapshot_name`.
     RestoreSnapshot {
         project_slug: ProjectSlugStr,
         snapshot_name: SnapShotNameStr,
+        backup_snapshot_name: SnapShotNameStr,
     },
     MountSnapshot {
         project_slug: ProjectSlugStr,
@@ -103,6 +111,15 @@ pub enum HelperCommand {
     UnmountProd {
         project_slug: ProjectSlugStr,
     },
+
+    EnableQuota,
+    SetProjectQuota {
+        project_slug: ProjectSlugStr,
+        limit_bytes: Option<u64>,
+    },
+    ProjectUsage {
+        project_slug: ProjectSlugStr,
+    },
 }
 
 impl Validate for HelperCommand {
@@ -168,9 +185,17 @@ impl Validate for HelperCommand {
             HelperCommand::RestoreSnapshot {
                 project_slug,
                 snapshot_name,
+                backup_snapshot_name,
             } => {
                 project_slug.validate()?;
                 snapshot_name.validate()?;
+                backup_snapshot_name.validate()?;
+                let prefix = get_project_snapshot_prefix(project_slug);
+                if !snapshot_name.0.starts_with(&prefix)
+                    || !backup_snapshot_name.0.starts_with(&prefix)
+                {
+                    return sanitize_err();
+                }
             }
             HelperCommand::MountSnapshot {
                 project_slug,
@@ -182,6 +207,13 @@ impl Validate for HelperCommand {
             HelperCommand::UnmountProd { project_slug } => {
                 project_slug.validate()?;
             }
+            HelperCommand::EnableQuota => {}
+            HelperCommand::SetProjectQuota { project_slug, .. } => {
+                project_slug.validate()?;
+            }
+            HelperCommand::ProjectUsage { project_slug } => {
+                project_slug.validate()?;
+            }
         }
         Ok(())
     }
@@ -190,5 +222,30 @@ impl Validate for HelperCommand {
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum HelperResponse {
     Ok,
-    Error(String),
+    /// Command `step` of the batch failed. The commands before it were undone in reverse
+    /// order; `rolled_back` is false when one of them could not be.
+    Error {
+        step: usize,
+        error: RemoteError,
+        rolled_back: bool,
+    },
+    ProjectUsage(ProjectUsage),
+}
+
+/// Btrfs qgroup numbers, in bytes. `referenced` counts every extent the subvolume can reach,
+/// `exclusive` only the ones no other subvolume shares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct QgroupUsage {
+    pub referenced: u64,
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct ProjectUsage {
+    /// The project qgroup: dev subvolume and snapshots, shared extents counted once.
+    pub total: QgroupUsage,
+    /// Referenced bytes allowed for the project qgroup, if any.
+    pub limit_bytes: Option<u64>,
+    pub dev: QgroupUsage,
+    pub snapshots: Vec<(SnapShotNameStr, QgroupUsage)>,
 }
@@ -1,51 +1,47 @@
+use crate::error::RemoteError;
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "tarpc-server-to-hosting")]
 pub mod tarpc {
     use crate::hosting_command::{HostingCommand, HostingResponse};
-    use crate::tarpc_client::{TarpcClient, TarpcClientError};
-    use crate::{AuthResponse, AuthToken, ProjectSlugStr, Validate};
+    use crate::protocol::ProtocolInfo;
+    use crate::rpc_methods;
+    use crate::tarpc_client::{Idempotent, Ping, TarpcClient};
+    use crate::{AuthResponse, AuthToken, ProjectSlugStr};
     use tarpc::client::RpcError;
-    use tarpc::context;
-    pub const HOSTING_SOCKET_PATH: &str = "/run/hivehost_server_hosting/server_hosting.sock";
+    use tarpc::context::Context;
+
+    /// What this build speaks on `ServerHosting`. Every command is part of the base protocol.
+    pub fn protocol_info(software_version: &str) -> ProtocolInfo {
+        ProtocolInfo::new(software_version, &[])
+    }
+
     #[tarpc::service]
     pub trait ServerHosting {
+        async fn auth(token: AuthToken, client: ProtocolInfo) -> AuthResponse;
+
+        async fn ping();
+
         async fn hosting(project_slug: ProjectSlugStr, action: HostingCommand) -> HostingResponse;
+    }
 
-        async fn auth(token: AuthToken) -> AuthResponse;
+    impl Ping for ServerHostingClient {
+        fn ping(&self, context: Context) -> impl Future<Output = Result<(), RpcError>> + Send {
+            ServerHostingClient::ping(self, context)
+        }
     }
 
-    impl TarpcClient<ServerHostingClient> {
-        pub async fn hosting(
-            &self,
-            project_slug: ProjectSlugStr,
-            action: HostingCommand,
-        ) -> Result<HostingResponse, TarpcClientError> {
-            let client = self.get_or_connect_client().await?;
-            let result = client
-                .hosting(context::current(), project_slug.clone(), action.clone())
-                .await;
-            if let Err(RpcError::Shutdown) = result {
-                self.disconnect().await;
-                let client = self.get_or_connect_client().await?;
-                client
-                    .hosting(context::current(), project_slug, action)
-                    .await
-                    .map_err(From::from)
-            } else {
-                result.map_err(From::from)
-            }
+    /// Reloading or dropping a project from the cache twice leaves it as once.
+    impl Idempotent for HostingCommand {
+        fn is_idempotent(&self) -> bool {
+            true
         }
+    }
 
-        pub async fn auth(&self, token: AuthToken) -> Result<bool, TarpcClientError> {
-            if token.validate().is_err() {
-                return Ok(false);
-            }
-            let client = self.get_or_connect_client().await?;
-            match client.auth(context::current(), token.clone()).await {
-                Ok(AuthResponse::Ok) => Ok(true),
-                _ => Ok(false),
-            }
+    impl TarpcClient<ServerHostingClient> {
+        rpc_methods! {
+            fn hosting(project_slug: ProjectSlugStr, action: HostingCommand) -> HostingResponse,
+                retry if action.is_idempotent();
         }
     }
 }
@@ -59,5 +55,5 @@ pub enum HostingCommand {
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum HostingResponse {
     Ok,
-    Error(String),
+    Error(RemoteError),
 }
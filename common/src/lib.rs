@@ -3,6 +3,11 @@
 #[cfg(feature = "server-command")]
 pub mod command;
 
+pub mod error;
+
+pub mod paths;
+pub mod protocol;
+
 #[cfg(feature = "website-to-server")]
 pub mod server_action;
 
@@ -12,9 +17,26 @@ pub mod hosting_command;
 #[cfg(feature = "tarpc-client")]
 pub mod tarpc_client;
 
+#[cfg(feature = "unix-listener")]
+pub mod unix_listener;
+
+#[cfg(feature = "rpc-auth")]
+pub mod rpc_auth;
+
+#[cfg(feature = "rpc-tls")]
+pub mod tls;
+
+#[cfg(feature = "telemetry")]
+pub mod telemetry;
+
+#[cfg(feature = "config")]
+pub mod config;
+
 #[cfg(feature = "server-to-helper")]
 pub mod helper_command;
 
+use crate::paths::paths;
+use crate::protocol::ProtocolInfo;
 use reactive_stores::Patch;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
@@ -22,19 +44,15 @@ use thiserror::Error;
 
 pub const SERVICE_USER: &str = "hivehost_server";
 pub const USER_GROUP: &str = "sftp_users";
-pub const SERVER_PORT: u16 = 5051;
-pub const SERVER_TOKEN_PORT: u16 = 5052;
 pub const GITHUB_APP_NAME: &str = "hivehost git";
 
-pub const DEV_ROOT_PATH_PREFIX: &str = "/hivehost/dev";
-pub const PROD_ROOT_PATH_PREFIX: &str = "/hivehost/prod";
-pub const USER_ROOT_PATH_PREFIX: &str = "/hivehost/users";
-pub const TEMP_ROOT_PATH_PREFIX: &str = "/hivehost/temp";
-
+/// Answer to `auth()`, which also tells the client what the server speaks.
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum AuthResponse {
-    Ok,
+    Ok(ProtocolInfo),
     Error,
+    /// The client's protocol version isn't the server's; the channel stays unauthenticated.
+    Incompatible(ProtocolInfo),
 }
 
 #[derive(Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -68,22 +86,31 @@ impl Validate for AuthToken {
 }
 
 pub fn get_temp_token_path(token: &str) -> String {
-    format!("{TEMP_ROOT_PATH_PREFIX}/{token}")
+    format!("{}/{token}", paths().temp_root)
 }
 
 pub fn get_project_dev_path(project_slug_str: &ProjectSlugStr) -> String {
-    format!("{DEV_ROOT_PATH_PREFIX}/{}", project_slug_str.0)
+    format!("{}/{}", paths().dev_root, project_slug_str.0)
 }
 pub fn get_project_snapshot_path(snapshot_name: &str) -> String {
-    format!("{DEV_ROOT_PATH_PREFIX}/{snapshot_name}")
+    format!("{}/{snapshot_name}", paths().dev_root)
+}
+
+/// Every snapshot name of a project starts with this, which ties a snapshot to its project.
+pub fn get_project_snapshot_prefix(project_slug_str: &ProjectSlugStr) -> String {
+    format!("{}_snapshot_", project_slug_str.0)
+}
+
+pub fn get_project_trash_path(project_slug_str: &ProjectSlugStr) -> String {
+    format!("{}/{}", paths().trash_root, project_slug_str.0)
 }
 
 pub fn get_project_prod_path(project_slug_str: &ProjectSlugStr) -> String {
-    format!("{PROD_ROOT_PATH_PREFIX}/{}", project_slug_str.0)
+    format!("{}/{}", paths().prod_root, project_slug_str.0)
 }
 
 pub fn get_user_path(user_slug_str: &UserSlugStr) -> String {
-    format!("{USER_ROOT_PATH_PREFIX}/{}", user_slug_str.0)
+    format!("{}/{}", paths().users_root, user_slug_str.0)
 }
 
 pub fn get_user_projects_path(user_slug_str: &UserSlugStr) -> String {
@@ -128,81 +155,6 @@ pub trait Validate {
     fn validate(&self) -> Result<(), SanitizeError>;
 }
 
-#[cfg(feature = "validate-path")]
-pub async fn ensure_path_in_project_path(
-    project_slug: &ProjectSlugStr,
-    project_path_: &str,
-    is_file: bool,
-    should_exist: bool,
-) -> Result<std::path::PathBuf, SanitizeError> {
-    // 1) Canonicaliser la racine projet
-    let mut project_path_ = project_path_.to_string();
-    if !project_path_.starts_with("root/") {
-        return sanitize_err();
-    }
-    project_path_ = project_path_.replacen("root/", "./", 1);
-
-    let project_root = std::path::PathBuf::from(get_project_dev_path(project_slug));
-    let project_root = tokio::fs::canonicalize(&project_root).await?;
-
-    // 2) Rejeter tout chemin absolu ou contenant `..`
-    let rel = std::path::PathBuf::from(project_path_);
-    if rel.is_absolute()
-        || rel
-            .components()
-            .any(|c| matches!(c, std::path::Component::ParentDir))
-    {
-        return sanitize_err();
-    }
-
-    // Chemin final (peut ne pas exister)
-    let full_path = project_root.join(rel);
-
-    if should_exist {
-        // 3A) On attend que la cible existe → canonicaliser puis métadonnées
-        let canon = tokio::fs::canonicalize(&full_path).await?;
-
-        // 4A) Vérifier qu’elle reste sous project_root
-        if !canon.starts_with(&project_root) {
-            return Err(SanitizeError::Invalid);
-        }
-
-        // 5A) Vérifier fichier vs dossier
-        let meta = tokio::fs::metadata(&canon).await?;
-        if is_file && !meta.is_file() {
-            return Err(SanitizeError::Invalid);
-        }
-        if !is_file && !meta.is_dir() {
-            return Err(SanitizeError::Invalid);
-        }
-
-        Ok(canon)
-    } else {
-        // 3B) Création de la cible → vérifier uniquement le parent
-        let parent = full_path.parent().ok_or(SanitizeError::Invalid)?;
-        let parent_canon = tokio::fs::canonicalize(parent).await?;
-
-        // 4B) S’assurer que le parent est dans le projet
-        if !parent_canon.starts_with(&project_root) {
-            return sanitize_err();
-        }
-        // 5B) Vérifier que le parent est un dossier
-        let meta = tokio::fs::metadata(&parent_canon).await?;
-        if !meta.is_dir() {
-            return Err(SanitizeError::Invalid);
-        }
-        // 6B) Vérifier que le nom est sanitisé
-        let child = full_path.file_name().ok_or(SanitizeError::Invalid)?;
-        let sanitized = sanitize_filename::sanitize(child.to_str().unwrap_or_default());
-        if sanitized.is_empty() {
-            return Err(SanitizeError::Invalid);
-        }
-
-        // 7B) OK pour créer : retourner le chemin (non-canon) où l’on créera.
-        Ok(parent_canon.join(sanitized))
-    }
-}
-
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Hash)]
 pub struct SnapShotNameStr(pub String);
 
@@ -363,6 +315,135 @@ impl FromStr for GitBranchNameStr {
     }
 }
 
+pub const PROJECT_PATH_ROOT: &str = "root/";
+pub const PROJECT_PATH_MAX_LEN: usize = 4096;
+pub const FILE_NAME_MAX_LEN: usize = 255;
+
+/// Characters `sanitize_filename` replaces in a name, on every platform.
+const FILE_NAME_RESERVED_CHARS: &[char] = &['/', '?', '<', '>', '\\', ':', '*', '|', '"'];
+
+/// A single file or folder name that `sanitize_filename` would leave untouched with its Windows
+/// rules on: no reserved or control character, not only dots, no trailing dot or space and no
+/// reserved device name such as `CON` or `lpt1.txt`.
+///
+/// The server sanitizes names it creates with `sanitize_filename`; checking the same rules here
+/// means a validated name reaches the disk unchanged and can still be downloaded on Windows.
+fn is_valid_file_name(name: &str) -> bool {
+    if name.is_empty()
+        || name.len() > FILE_NAME_MAX_LEN
+        || name.chars().all(|c| c == '.')
+        || name.ends_with(['.', ' '])
+        || name
+            .chars()
+            .any(|c| FILE_NAME_RESERVED_CHARS.contains(&c) || c.is_control())
+    {
+        return false;
+    }
+    // `CON`, `com1.txt`: Windows reserves these device names whatever the extension.
+    let stem = name.split('.').next().unwrap_or(name).to_ascii_lowercase();
+    let device = matches!(stem.as_str(), "con" | "prn" | "aux" | "nul")
+        || (stem.len() == 4
+            && (stem.starts_with("com") || stem.starts_with("lpt"))
+            && stem.as_bytes()[3].is_ascii_digit());
+    !device
+}
+
+// validated file or folder name, for renames
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Hash)]
+pub struct FileNameStr(pub String);
+impl Validate for FileNameStr {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        Self::from_str(&self.0)?;
+        Ok(())
+    }
+}
+
+impl FromStr for FileNameStr {
+    type Err = SanitizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !is_valid_file_name(s) {
+            return sanitize_err();
+        }
+        Ok(FileNameStr(s.to_string()))
+    }
+}
+
+/// Validated path inside a project, as the file manager shows it: `root/` then names joined
+/// by `/`, `root/` alone being the project root.
+///
+/// `from_str` normalizes (`root/a//b/./c/` becomes `root/a/b/c`); `validate` only accepts the
+/// normalized form, so the server can map components to the disk one to one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Hash)]
+pub struct ProjectRelPath(pub String);
+impl Validate for ProjectRelPath {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        if Self::from_str(&self.0)?.0 != self.0 {
+            return sanitize_err();
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ProjectRelPath {
+    type Err = SanitizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > PROJECT_PATH_MAX_LEN {
+            return sanitize_err();
+        }
+        let relative = match s.strip_prefix(PROJECT_PATH_ROOT) {
+            Some(relative) => relative,
+            None if s == PROJECT_PATH_ROOT.trim_end_matches('/') => "",
+            None => return sanitize_err(),
+        };
+        let mut components = Vec::new();
+        for component in relative.split('/') {
+            match component {
+                "" | "." => {}
+                name if is_valid_file_name(name) => components.push(name),
+                _ => return sanitize_err(),
+            }
+        }
+        Ok(ProjectRelPath(format!(
+            "{PROJECT_PATH_ROOT}{}",
+            components.join("/")
+        )))
+    }
+}
+
+impl ProjectRelPath {
+    pub fn root() -> Self {
+        ProjectRelPath(PROJECT_PATH_ROOT.to_string())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == PROJECT_PATH_ROOT
+    }
+
+    /// The names below the project root, outermost first.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0[PROJECT_PATH_ROOT.len().min(self.0.len())..]
+            .split('/')
+            .filter(|component| !component.is_empty())
+    }
+
+    /// `name` below this path; `name` may itself contain `/`.
+    pub fn join(&self, name: &str) -> Result<Self, SanitizeError> {
+        Self::from_str(&format!("{}/{name}", self.0.trim_end_matches('/')))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ProjectRelPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, Patch)]
 pub struct Slug {
     pub id: i64,
@@ -386,13 +467,6 @@ pub enum SanitizeError {
     Invalid,
 }
 
-#[cfg(feature = "validate-path")]
-impl From<std::io::Error> for SanitizeError {
-    fn from(_err: std::io::Error) -> Self {
-        SanitizeError::Invalid
-    }
-}
-
 pub fn sanitize_err<T>() -> Result<T, SanitizeError> {
     Err(SanitizeError::Invalid)
 }
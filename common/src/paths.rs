@@ -0,0 +1,71 @@
+//! Where projects, users and their trash live on a hosting machine. The server, helper and
+//! hosting must agree on it, so each installs the `[paths]` of its configuration at startup
+//! with `set_paths`, before anything builds a path.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+
+static PATHS: OnceLock<PathsConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Mount point of the Btrfs filesystem holding the project subvolumes and snapshots.
+    pub dev_root: String,
+    pub prod_root: String,
+    pub users_root: String,
+    pub temp_root: String,
+    /// On the dev filesystem so trashing reflinks instead of copying, but outside every
+    /// project subvolume.
+    pub trash_root: String,
+}
+
+impl Default for PathsConfig {
+    fn default() -> Self {
+        PathsConfig {
+            dev_root: "/hivehost/dev".to_string(),
+            prod_root: "/hivehost/prod".to_string(),
+            users_root: "/hivehost/users".to_string(),
+            temp_root: "/hivehost/temp".to_string(),
+            trash_root: "/hivehost/dev/.trash".to_string(),
+        }
+    }
+}
+
+impl PathsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, path) in [
+            ("dev_root", &self.dev_root),
+            ("prod_root", &self.prod_root),
+            ("users_root", &self.users_root),
+            ("temp_root", &self.temp_root),
+            ("trash_root", &self.trash_root),
+        ] {
+            if !Path::new(path).is_absolute() || path.ends_with('/') {
+                return Err(format!(
+                    "paths.{name} must be absolute, without a trailing slash: {path}"
+                ));
+            }
+        }
+        if !Path::new(&self.trash_root).starts_with(&self.dev_root)
+            || self.trash_root == self.dev_root
+        {
+            return Err(format!(
+                "paths.trash_root must be inside paths.dev_root ({})",
+                self.dev_root
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Installs the paths of this process. Only the first call counts.
+pub fn set_paths(paths: PathsConfig) {
+    let _ = PATHS.set(paths);
+}
+
+/// The installed paths, or the defaults if none were.
+pub fn paths() -> &'static PathsConfig {
+    PATHS.get_or_init(PathsConfig::default)
+}
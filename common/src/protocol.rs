@@ -0,0 +1,46 @@
+//! Versioning of the messages the tarpc services exchange. Both ends report a `ProtocolInfo`
+//! through `auth()`, and a connection between different protocol versions is refused before
+//! anything the other side might not decode is sent.
+//!
+//! `auth()` comes first in every service and it and `AuthResponse` keep their shape across
+//! versions, so that even mismatched peers get as far as learning each other's version.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Bumped whenever a message changes in a way the previous version can't decode.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// What one end of a connection speaks. `capabilities` names the optional parts it handles,
+/// so additions within a version can be used only with peers that have them.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+    pub software_version: String,
+    pub capabilities: Vec<String>,
+}
+
+impl ProtocolInfo {
+    pub fn new(software_version: &str, capabilities: &[&str]) -> Self {
+        ProtocolInfo {
+            version: PROTOCOL_VERSION,
+            software_version: software_version.to_string(),
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Whether this build can talk to a peer reporting `self`.
+    pub fn is_supported(&self) -> bool {
+        self.version == PROTOCOL_VERSION
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl fmt::Display for ProtocolInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (protocol {})", self.software_version, self.version)
+    }
+}
@@ -0,0 +1,168 @@
+//! Authentication of RPC channels. Each channel authenticates on its own through `auth()` and
+//! only unlocks itself; failed attempts are counted per peer, and a peer that fails too often
+//! is refused for a while without its token being looked at.
+
+use crate::AuthResponse;
+use crate::protocol::{PROTOCOL_VERSION, ProtocolInfo};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Failed attempts a peer gets within `AUTH_FAILURE_WINDOW` before it is refused.
+pub const MAX_AUTH_FAILURES: u32 = 5;
+pub const AUTH_FAILURE_WINDOW: Duration = Duration::from_secs(60);
+
+/// One channel: who is on the other end, whether it has authenticated and the challenge it
+/// was last given, if it authenticates that way.
+#[derive(Debug)]
+pub struct ChannelAuth {
+    peer: String,
+    authenticated: AtomicBool,
+    challenge: Mutex<Option<[u8; 32]>>,
+}
+
+impl ChannelAuth {
+    pub fn new(peer: impl Into<String>) -> Arc<Self> {
+        Arc::new(ChannelAuth {
+            peer: peer.into(),
+            authenticated: AtomicBool::new(false),
+            challenge: Mutex::new(None),
+        })
+    }
+
+    /// A fresh random challenge, replacing any earlier one.
+    pub fn new_challenge(&self) -> [u8; 32] {
+        let mut challenge = [0; 32];
+        getrandom::fill(&mut challenge).expect("the system random number generator failed");
+        *self
+            .challenge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(challenge);
+        challenge
+    }
+
+    /// The last challenge, which can only be answered once.
+    pub fn take_challenge(&self) -> Option<[u8; 32]> {
+        self.challenge
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Acquire)
+    }
+}
+
+/// Failed attempts by peer, shared by every channel of a listener.
+#[derive(Debug)]
+pub struct AuthLimiter {
+    max_failures: u32,
+    window: Duration,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    since: Instant,
+}
+
+impl Default for AuthLimiter {
+    fn default() -> Self {
+        AuthLimiter::new(MAX_AUTH_FAILURES, AUTH_FAILURE_WINDOW)
+    }
+}
+
+impl AuthLimiter {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        AuthLimiter {
+            max_failures,
+            window,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets `channel` authenticated or not, as `verify` says the credentials are right or not.
+    /// `verify` isn't called for a peer that is being refused, and a failure also ends an earlier
+    /// success on the same channel. Returns whether it passed.
+    pub fn authenticate_with(&self, channel: &ChannelAuth, verify: impl FnOnce() -> bool) -> bool {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        // Old entries go first, so the table only holds peers that failed recently.
+        failures.retain(|_, failed| now.duration_since(failed.since) < self.window);
+
+        let failed = failures.get(channel.peer()).copied();
+        if let Some(failed) = failed.filter(|failed| failed.count >= self.max_failures) {
+            channel.authenticated.store(false, Ordering::Release);
+            let retry_after = self.window.saturating_sub(now.duration_since(failed.since));
+            debug!(
+                "Auth from {} refused, too many failures, retry in {retry_after:?}",
+                channel.peer()
+            );
+            return false;
+        }
+
+        let passed = verify();
+        channel.authenticated.store(passed, Ordering::Release);
+        if passed {
+            failures.remove(channel.peer());
+            info!("Auth success from {}", channel.peer());
+        } else {
+            let failed = failures
+                .entry(channel.peer().to_string())
+                .or_insert(Failures {
+                    count: 0,
+                    since: now,
+                });
+            failed.count += 1;
+            warn!(
+                "Auth failed from {} ({} of {} allowed in {:?})",
+                channel.peer(),
+                failed.count,
+                self.max_failures,
+                self.window
+            );
+        }
+        passed
+    }
+
+    /// The answer to `auth()`, `server` being what this end speaks. A client speaking another
+    /// protocol version is only told the server's; otherwise `verify` decides as in
+    /// `authenticate_with`.
+    pub fn auth_response(
+        &self,
+        channel: &ChannelAuth,
+        client: &ProtocolInfo,
+        server: ProtocolInfo,
+        verify: impl FnOnce() -> bool,
+    ) -> AuthResponse {
+        if !client.is_supported() {
+            channel.authenticated.store(false, Ordering::Release);
+            warn!(
+                "Auth from {} refused, it speaks protocol {} ({}) and this end {PROTOCOL_VERSION}",
+                channel.peer(),
+                client.version,
+                client.software_version
+            );
+            return AuthResponse::Incompatible(server);
+        }
+        if self.authenticate_with(channel, verify) {
+            AuthResponse::Ok(server)
+        } else {
+            AuthResponse::Error
+        }
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken says nothing about
+/// how much of the token was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -6,100 +6,146 @@ pub mod user_action;
 
 #[cfg(feature = "tarpc-website-to-server")]
 pub mod tarpc {
-    use crate::server_action::project_action::{ProjectAction, ProjectResponse};
+    use crate::protocol::ProtocolInfo;
+    use crate::rpc_methods;
+    use crate::server_action::permission::Permission;
+    use crate::server_action::project_action::{
+        IsProjectServerAction, PROJECT_ACTION_CAPABILITIES, PROJECT_STATUS_CAPABILITY,
+        ProjectAction, ProjectResponse, ProjectStatusResponse,
+    };
     use crate::server_action::token_action::{TokenAction, TokenActionResponse};
-    use crate::server_action::user_action::{ServerUserAction, ServerUserResponse};
-    use crate::tarpc_client::{TarpcClient, TarpcClientError};
-    use crate::{AuthResponse, AuthToken, ProjectSlugStr, Validate};
+    use crate::server_action::user_action::{
+        SET_PROJECT_QUOTA_CAPABILITY, ServerUserAction, ServerUserResponse,
+    };
+    use crate::tarpc_client::{Idempotent, Ping, TarpcClient};
+    use crate::{AuthResponse, ProjectSlugStr, UserSlugStr};
+    use hmac::{Hmac, Mac};
+    use serde::{Deserialize, Serialize};
+    use sha2::Sha256;
+    use std::time::Duration;
     use tarpc::client::RpcError;
-    use tarpc::context;
+    use tarpc::context::Context;
+
+    /// Time user and project actions get on the server; git clones and pulls run inside them.
+    pub const SERVER_REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+    /// Mixed into every proof so one made for this protocol means nothing anywhere else.
+    const AUTH_PROOF_CONTEXT: &[u8] = b"hivehost website-to-server auth v1";
+
+    /// Random nonce the server hands out for a single authentication attempt on a channel.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct AuthChallenge(pub [u8; 32]);
+
+    /// HMAC-SHA256 of a challenge keyed with the server's token, which never goes on the wire.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct AuthProof(pub [u8; 32]);
+
+    fn auth_mac(token: &str, challenge: &AuthChallenge) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes())
+            .expect("HMAC takes keys of any length");
+        mac.update(AUTH_PROOF_CONTEXT);
+        mac.update(&challenge.0);
+        mac
+    }
+
+    pub fn auth_proof(token: &str, challenge: &AuthChallenge) -> AuthProof {
+        let mut proof = [0; 32];
+        proof.copy_from_slice(&auth_mac(token, challenge).finalize().into_bytes());
+        AuthProof(proof)
+    }
+
+    /// Checks `proof` in constant time.
+    pub fn verify_auth_proof(token: &str, challenge: &AuthChallenge, proof: &AuthProof) -> bool {
+        auth_mac(token, challenge).verify_slice(&proof.0).is_ok()
+    }
+
+    /// What this build speaks on `WebsiteToServer`, from either end.
+    pub fn protocol_info(software_version: &str) -> ProtocolInfo {
+        let capabilities = [
+            PROJECT_ACTION_CAPABILITIES,
+            &[PROJECT_STATUS_CAPABILITY, SET_PROJECT_QUOTA_CAPABILITY],
+        ]
+        .concat();
+        ProtocolInfo::new(software_version, &capabilities)
+    }
 
     #[tarpc::service]
     pub trait WebsiteToServer {
+        /// Starts authenticating the channel; the challenge is good for one `auth()` call.
+        async fn auth_challenge() -> AuthChallenge;
+        async fn auth(proof: AuthProof, client: ProtocolInfo) -> AuthResponse;
+        async fn ping();
+
+        /// `user_slug` is the user acting on the website; the server does the file access as
+        /// that system user, so its ACLs apply.
         async fn token_action(
             project_slug_str: ProjectSlugStr,
+            user_slug: UserSlugStr,
             action: TokenAction,
         ) -> TokenActionResponse;
 
         async fn user_action(action: ServerUserAction) -> ServerUserResponse;
+        /// `user_slug` is `None` for actions the website starts on its own, which then cannot
+        /// touch project files.
         async fn project_action(
             project_slug: ProjectSlugStr,
+            user_slug: Option<UserSlugStr>,
             action: ProjectAction,
         ) -> ProjectResponse;
+        /// Which change holds the project and how many wait behind it. Comes last, so servers
+        /// without it still decode every other request.
+        async fn project_status(project_slug: ProjectSlugStr) -> ProjectStatusResponse;
+    }
 
-        async fn auth(token: AuthToken) -> AuthResponse;
+    impl Ping for WebsiteToServerClient {
+        fn ping(&self, context: Context) -> impl Future<Output = Result<(), RpcError>> + Send {
+            WebsiteToServerClient::ping(self, context)
+        }
     }
 
-    impl TarpcClient<WebsiteToServerClient> {
-        pub async fn auth(&self, token: AuthToken) -> Result<bool, TarpcClientError> {
-            if token.validate().is_err() {
-                return Ok(false);
-            }
-            let client = self.get_or_connect_client().await?;
-            match client.auth(context::current(), token.clone()).await {
-                Ok(AuthResponse::Ok) => Ok(true),
-                _ => Ok(false),
-            }
+    /// Only queries, which are the actions that read-only members may run.
+    impl Idempotent for ProjectAction {
+        fn is_idempotent(&self) -> bool {
+            self.permission() == Permission::Read
         }
+    }
 
-        pub async fn token_action(
-            &self,
-            project_slug_str: ProjectSlugStr,
-            action: TokenAction,
-        ) -> Result<TokenActionResponse, TarpcClientError> {
-            let client = self.get_or_connect_client().await?;
-            let result = client
-                .token_action(context::current(), project_slug_str.clone(), action.clone())
-                .await;
-            if let Err(RpcError::Shutdown) = result {
-                self.disconnect().await;
-                let client = self.get_or_connect_client().await?;
-                client
-                    .token_action(context::current(), project_slug_str, action)
-                    .await
-                    .map_err(From::from)
-            } else {
-                result.map_err(From::from)
-            }
+    /// Setting a limit twice leaves the same limit.
+    impl Idempotent for ServerUserAction {
+        fn is_idempotent(&self) -> bool {
+            matches!(self, ServerUserAction::SetProjectQuota { .. })
         }
+    }
 
-        pub async fn project_action(
-            &self,
-            project_slug: ProjectSlugStr,
-            action: ProjectAction,
-        ) -> Result<ProjectResponse, TarpcClientError> {
-            let client = self.get_or_connect_client().await?;
-            let result = client
-                .project_action(context::current(), project_slug.clone(), action.clone())
-                .await;
-            if let Err(RpcError::Shutdown) = result {
-                self.disconnect().await;
-                let client = self.get_or_connect_client().await?;
-                client
-                    .project_action(context::current(), project_slug, action)
-                    .await
-                    .map_err(From::from)
-            } else {
-                result.map_err(From::from)
-            }
+    /// A token lost with its answer expires unused.
+    impl Idempotent for TokenAction {
+        fn is_idempotent(&self) -> bool {
+            true
         }
+    }
+
+    impl TarpcClient<WebsiteToServerClient> {
+        rpc_methods! {
+            fn token_action(
+                project_slug_str: ProjectSlugStr,
+                user_slug: UserSlugStr,
+                action: TokenAction,
+            ) -> TokenActionResponse, retry if action.is_idempotent();
+
+            fn project_action(
+                project_slug: ProjectSlugStr,
+                user_slug: Option<UserSlugStr>,
+                action: ProjectAction,
+            ) -> ProjectResponse,
+                timeout = SERVER_REQUEST_TIMEOUT,
+                requires action.capability(),
+                retry if action.is_idempotent();
+
+            fn user_action(action: ServerUserAction) -> ServerUserResponse,
+                timeout = SERVER_REQUEST_TIMEOUT, retry if action.is_idempotent();
 
-        pub async fn user_action(
-            &self,
-            action: ServerUserAction,
-        ) -> Result<ServerUserResponse, TarpcClientError> {
-            let client = self.get_or_connect_client().await?;
-            let result = client.user_action(context::current(), action.clone()).await;
-            if let Err(RpcError::Shutdown) = result {
-                self.disconnect().await;
-                let client = self.get_or_connect_client().await?;
-                client
-                    .user_action(context::current(), action)
-                    .await
-                    .map_err(From::from)
-            } else {
-                result.map_err(From::from)
-            }
+            fn project_status(project_slug: ProjectSlugStr) -> ProjectStatusResponse,
+                requires PROJECT_STATUS_CAPABILITY, retry if true;
         }
     }
 }
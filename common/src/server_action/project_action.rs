@@ -1,15 +1,21 @@
 pub mod git_action;
 pub mod io_action;
 pub mod permission;
+pub mod quota;
 pub mod snapshot;
 
 use crate::server_action::permission::Permission;
+use crate::server_action::project_action::io_action::ServerProjectIoReport;
 use crate::server_action::project_action::io_action::dir_action::ServerProjectIoDirActionLsResponse;
+use crate::server_action::project_action::io_action::search_action::ServerProjectIoSearchResponse;
+use crate::server_action::project_action::io_action::trash_action::ServerProjectIoTrashResponse;
 
-use crate::Validate;
-use crate::helper_command::HelperResponse;
+use crate::error::RemoteError;
+use crate::helper_command::{HelperResponse, ProjectUsage};
 use crate::hosting_command::HostingResponse;
+use crate::{UserSlugStr, Validate};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProjectAction {
@@ -17,6 +23,44 @@ pub enum ProjectAction {
     Permission(permission::ProjectPermissionAction),
     Snapshot(snapshot::ProjectSnapshotAction),
     Git(git_action::ProjectGitAction),
+    Quota(quota::ProjectQuotaAction),
+}
+
+/// Capabilities of a server handling each kind of `ProjectAction`.
+pub const PROJECT_ACTION_CAPABILITIES: &[&str] = &[
+    "project.io",
+    "project.permission",
+    "project.snapshot",
+    "project.git",
+    "project.quota",
+];
+
+/// Capability of a server answering `project_status`.
+pub const PROJECT_STATUS_CAPABILITY: &str = "project.status";
+
+impl ProjectAction {
+    /// What the server must report for this action to be sent to it.
+    pub fn capability(&self) -> &'static str {
+        match self {
+            ProjectAction::Io(_) => "project.io",
+            ProjectAction::Permission(_) => "project.permission",
+            ProjectAction::Snapshot(_) => "project.snapshot",
+            ProjectAction::Git(_) => "project.git",
+            ProjectAction::Quota(_) => "project.quota",
+        }
+    }
+
+    /// What the action does, without its arguments, e.g. `snapshot.restore`.
+    pub fn name(&self) -> String {
+        let (kind, action): (&str, &'static str) = match self {
+            ProjectAction::Io(action) => ("io", action.into()),
+            ProjectAction::Permission(action) => ("permission", action.into()),
+            ProjectAction::Snapshot(action) => ("snapshot", action.into()),
+            ProjectAction::Git(action) => ("git", action.into()),
+            ProjectAction::Quota(action) => ("quota", action.into()),
+        };
+        format!("{kind}.{action}")
+    }
 }
 
 impl Validate for ProjectAction {
@@ -26,6 +70,7 @@ impl Validate for ProjectAction {
             ProjectAction::Permission(action) => action.validate(),
             ProjectAction::Snapshot(action) => action.validate(),
             ProjectAction::Git(action) => action.validate(),
+            ProjectAction::Quota(action) => action.validate(),
         }
     }
 }
@@ -37,6 +82,7 @@ impl IsProjectServerAction for ProjectAction {
             ProjectAction::Permission(action) => action.permission(),
             ProjectAction::Snapshot(action) => action.permission(),
             ProjectAction::Git(action) => action.permission(),
+            ProjectAction::Quota(action) => action.permission(),
         }
     }
 
@@ -46,6 +92,7 @@ impl IsProjectServerAction for ProjectAction {
             ProjectAction::Permission(action) => action.require_csrf(),
             ProjectAction::Snapshot(action) => action.require_csrf(),
             ProjectAction::Git(action) => action.require_csrf(),
+            ProjectAction::Quota(action) => action.require_csrf(),
         }
     }
 }
@@ -53,10 +100,51 @@ impl IsProjectServerAction for ProjectAction {
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProjectResponse {
     Ok,
-    Error(String),
+    Error(RemoteError),
     HelperResponses(HelperResponse),
     HostingResponse(HostingResponse),
     Ls(ServerProjectIoDirActionLsResponse),
+    Search(ServerProjectIoSearchResponse),
+    IoReport(ServerProjectIoReport),
+    Batch(Vec<ProjectResponse>),
+    Trash(ServerProjectIoTrashResponse),
+    Usage(ProjectUsage),
+}
+
+impl ProjectResponse {
+    /// Moves failures, the helper's and the hosting service's included, to the error side.
+    /// Errors of single `Batch` items stay in the batch.
+    pub fn into_result(self) -> Result<Self, RemoteError> {
+        match self {
+            ProjectResponse::Error(error)
+            | ProjectResponse::HelperResponses(HelperResponse::Error { error, .. })
+            | ProjectResponse::HostingResponse(HostingResponse::Error(error)) => Err(error),
+            response => Ok(response),
+        }
+    }
+}
+
+/// Changes to a project run one at a time on its server; this is what it is running.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct ProjectStatus {
+    /// The change holding the project, `None` while it is idle.
+    pub running: Option<ProjectOperation>,
+    /// Changes queued behind it.
+    pub waiting: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProjectOperation {
+    /// Such as `git.pull`, see `ProjectAction::name`.
+    pub name: String,
+    pub user_slug: Option<UserSlugStr>,
+    pub running_for: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProjectStatusResponse {
+    Ok(ProjectStatus),
+    Error(RemoteError),
 }
 
 pub trait IsProjectServerAction {
@@ -4,8 +4,10 @@ use crate::{
     GitBranchNameStr, GitCommitStr, GitRepoFullNameStr, GitTokenStr, Validate, impl_chain_from,
 };
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum ProjectGitAction {
     Pull {
         branch: GitBranchNameStr,
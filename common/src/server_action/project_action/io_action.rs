@@ -1,15 +1,42 @@
-use crate::Validate;
 use crate::server_action::permission::Permission;
 use crate::server_action::project_action::IsProjectServerAction;
+use crate::{ProjectRelPath, SanitizeError, Validate, sanitize_err};
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
+pub mod batch_action;
 pub mod dir_action;
 pub mod file_action;
+pub mod search_action;
+pub mod trash_action;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum ProjectIoAction {
     Dir(dir_action::ProjectIoDirAction),
     File(file_action::ProjectIoFileAction),
+    Search(search_action::ProjectIoSearchAction),
+    Batch(batch_action::ProjectIoBatchAction),
+    Trash(trash_action::ProjectIoTrashAction),
+}
+
+/// What to do when the destination of a move or copy already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum ConflictStrategy {
+    #[default]
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+/// A path naming an entry, which the project root is not: it cannot be created, deleted,
+/// renamed or moved.
+pub fn validate_entry_path(path: &ProjectRelPath) -> Result<(), SanitizeError> {
+    path.validate()?;
+    if path.is_root() {
+        return sanitize_err();
+    }
+    Ok(())
 }
 
 impl Validate for ProjectIoAction {
@@ -17,6 +44,9 @@ impl Validate for ProjectIoAction {
         match self {
             ProjectIoAction::Dir(action) => action.validate(),
             ProjectIoAction::File(action) => action.validate(),
+            ProjectIoAction::Search(action) => action.validate(),
+            ProjectIoAction::Batch(action) => action.validate(),
+            ProjectIoAction::Trash(action) => action.validate(),
         }
     }
 }
@@ -26,6 +56,9 @@ impl IsProjectServerAction for ProjectIoAction {
         match self {
             ProjectIoAction::Dir(action) => action.permission(),
             ProjectIoAction::File(action) => action.permission(),
+            ProjectIoAction::Search(action) => action.permission(),
+            ProjectIoAction::Batch(action) => action.permission(),
+            ProjectIoAction::Trash(action) => action.permission(),
         }
     }
 
@@ -33,6 +66,28 @@ impl IsProjectServerAction for ProjectIoAction {
         match self {
             ProjectIoAction::Dir(action) => action.require_csrf(),
             ProjectIoAction::File(action) => action.require_csrf(),
+            ProjectIoAction::Search(action) => action.require_csrf(),
+            ProjectIoAction::Batch(action) => action.require_csrf(),
+            ProjectIoAction::Trash(action) => action.require_csrf(),
         }
     }
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub struct ServerProjectIoReport {
+    pub done: u64,
+    pub issues: Vec<IoReportEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IoReportEntry {
+    pub path: String,
+    pub outcome: IoReportOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum IoReportOutcome {
+    Skipped,
+    Renamed { new_path: String },
+    Failed { message: String },
+}
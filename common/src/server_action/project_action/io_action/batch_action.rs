@@ -0,0 +1,93 @@
+use crate::server_action::permission::Permission;
+use crate::server_action::project_action::io_action::ProjectIoAction;
+use crate::server_action::project_action::io_action::dir_action::ProjectIoDirAction;
+use crate::server_action::project_action::io_action::file_action::ProjectIoFileAction;
+use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
+use crate::{SanitizeError, Validate, impl_chain_from, sanitize_err};
+
+use serde::{Deserialize, Serialize};
+
+pub const MAX_BATCH_LEN: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProjectIoBatchItem {
+    Dir(ProjectIoDirAction),
+    File(ProjectIoFileAction),
+}
+
+impl From<ProjectIoDirAction> for ProjectIoBatchItem {
+    fn from(action: ProjectIoDirAction) -> Self {
+        ProjectIoBatchItem::Dir(action)
+    }
+}
+
+impl From<ProjectIoFileAction> for ProjectIoBatchItem {
+    fn from(action: ProjectIoFileAction) -> Self {
+        ProjectIoBatchItem::File(action)
+    }
+}
+
+impl Validate for ProjectIoBatchItem {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        match self {
+            ProjectIoBatchItem::Dir(ProjectIoDirAction::Ls { .. }) => sanitize_err(),
+            ProjectIoBatchItem::Dir(action) => action.validate(),
+            ProjectIoBatchItem::File(action) => action.validate(),
+        }
+    }
+}
+
+impl IsProjectServerAction for ProjectIoBatchItem {
+    fn permission(&self) -> Permission {
+        match self {
+            ProjectIoBatchItem::Dir(action) => action.permission(),
+            ProjectIoBatchItem::File(action) => action.permission(),
+        }
+    }
+
+    fn require_csrf(&self) -> bool {
+        match self {
+            ProjectIoBatchItem::Dir(action) => action.require_csrf(),
+            ProjectIoBatchItem::File(action) => action.require_csrf(),
+        }
+    }
+}
+
+/// Items run in order, so a batch can create a folder and move files into it.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProjectIoBatchAction {
+    pub items: Vec<ProjectIoBatchItem>,
+}
+
+impl_chain_from!(ProjectAction, ProjectAction::Io | ProjectIoAction::Batch => ProjectIoBatchAction);
+
+impl Validate for ProjectIoBatchAction {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        if self.items.is_empty() || self.items.len() > MAX_BATCH_LEN {
+            return sanitize_err();
+        }
+        for item in &self.items {
+            item.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl IsProjectServerAction for ProjectIoBatchAction {
+    fn permission(&self) -> Permission {
+        self.items.iter().map(|item| item.permission()).fold(
+            Permission::Read,
+            |strongest, permission| {
+                if strongest.has_permission(&permission) {
+                    strongest
+                } else {
+                    permission
+                }
+            },
+        )
+    }
+
+    fn require_csrf(&self) -> bool {
+        self.items.iter().any(|item| item.require_csrf())
+    }
+}
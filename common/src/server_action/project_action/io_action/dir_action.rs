@@ -1,30 +1,66 @@
 use crate::server_action::permission::Permission;
 use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
-use crate::{Validate, impl_chain_from};
+use crate::{FileNameStr, ProjectRelPath, SanitizeError, Validate, impl_chain_from};
 
-use crate::server_action::project_action::io_action::ProjectIoAction;
+use crate::server_action::project_action::io_action::{
+    ConflictStrategy, ProjectIoAction, validate_entry_path,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProjectIoDirAction {
-    Create { path: String },
-    Rename { path: String, new_name: String },
-    Delete { path: String },
-    Ls { path: String },
+    Create {
+        path: ProjectRelPath,
+    },
+    /// Renames the folder in place.
+    Rename {
+        path: ProjectRelPath,
+        new_name: FileNameStr,
+    },
+    Delete {
+        path: ProjectRelPath,
+    },
+    Ls {
+        path: ProjectRelPath,
+    },
+    Move {
+        path: ProjectRelPath,
+        new_path: ProjectRelPath,
+        on_conflict: ConflictStrategy,
+    },
+    Copy {
+        path: ProjectRelPath,
+        new_path: ProjectRelPath,
+        on_conflict: ConflictStrategy,
+    },
 }
 
 impl_chain_from!(ProjectAction , ProjectAction::Io | ProjectIoAction::Dir  => ProjectIoDirAction);
 
 impl Validate for ProjectIoDirAction {
-    fn validate(&self) -> Result<(), crate::SanitizeError> {
+    fn validate(&self) -> Result<(), SanitizeError> {
         match self {
-            ProjectIoDirAction::Create { path: _ } => {}
-            ProjectIoDirAction::Rename {
-                path: _,
-                new_name: _,
-            } => {}
-            ProjectIoDirAction::Delete { path: _ } => {}
-            ProjectIoDirAction::Ls { path: _ } => {}
+            ProjectIoDirAction::Create { path } | ProjectIoDirAction::Delete { path } => {
+                validate_entry_path(path)?;
+            }
+            ProjectIoDirAction::Rename { path, new_name } => {
+                validate_entry_path(path)?;
+                new_name.validate()?;
+            }
+            ProjectIoDirAction::Ls { path } => path.validate()?,
+            ProjectIoDirAction::Move {
+                path,
+                new_path,
+                on_conflict: _,
+            }
+            | ProjectIoDirAction::Copy {
+                path,
+                new_path,
+                on_conflict: _,
+            } => {
+                validate_entry_path(path)?;
+                validate_entry_path(new_path)?;
+            }
         }
         Ok(())
     }
@@ -35,7 +71,9 @@ impl IsProjectServerAction for ProjectIoDirAction {
         match self {
             ProjectIoDirAction::Create { .. }
             | ProjectIoDirAction::Rename { .. }
-            | ProjectIoDirAction::Delete { .. } => Permission::Write,
+            | ProjectIoDirAction::Delete { .. }
+            | ProjectIoDirAction::Move { .. }
+            | ProjectIoDirAction::Copy { .. } => Permission::Write,
             ProjectIoDirAction::Ls { .. } => Permission::Read,
         }
     }
@@ -44,7 +82,9 @@ impl IsProjectServerAction for ProjectIoDirAction {
         match self {
             ProjectIoDirAction::Create { .. }
             | ProjectIoDirAction::Rename { .. }
-            | ProjectIoDirAction::Delete { .. } => true,
+            | ProjectIoDirAction::Delete { .. }
+            | ProjectIoDirAction::Move { .. }
+            | ProjectIoDirAction::Copy { .. } => true,
             ProjectIoDirAction::Ls { .. } => false,
         }
     }
@@ -1,38 +1,50 @@
 use crate::server_action::permission::Permission;
-use crate::server_action::project_action::io_action::ProjectIoAction;
+use crate::server_action::project_action::io_action::{ProjectIoAction, validate_entry_path};
 use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
-use crate::{Validate, impl_chain_from};
+use crate::{FileNameStr, ProjectRelPath, SanitizeError, Validate, impl_chain_from};
 
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProjectIoFileAction {
-    Create { path: String },
-    Rename { path: String, new_name: String },
-    Delete { path: String },
-    Move { path: String, new_path: String },
-    Copy { path: String, new_path: String },
+    Create {
+        path: ProjectRelPath,
+    },
+    /// Renames the file in place.
+    Rename {
+        path: ProjectRelPath,
+        new_name: FileNameStr,
+    },
+    Delete {
+        path: ProjectRelPath,
+    },
+    Move {
+        path: ProjectRelPath,
+        new_path: ProjectRelPath,
+    },
+    Copy {
+        path: ProjectRelPath,
+        new_path: ProjectRelPath,
+    },
 }
 
 impl_chain_from!(ProjectAction, ProjectAction::Io | ProjectIoAction::File => ProjectIoFileAction);
 
 impl Validate for ProjectIoFileAction {
-    fn validate(&self) -> Result<(), crate::SanitizeError> {
+    fn validate(&self) -> Result<(), SanitizeError> {
         match self {
-            ProjectIoFileAction::Create { path: _ } => {}
-            ProjectIoFileAction::Rename {
-                path: _,
-                new_name: _,
-            } => {}
-            ProjectIoFileAction::Delete { path: _ } => {}
-            ProjectIoFileAction::Move {
-                path: _,
-                new_path: _,
-            } => {}
-            ProjectIoFileAction::Copy {
-                path: _,
-                new_path: _,
-            } => {}
+            ProjectIoFileAction::Create { path } | ProjectIoFileAction::Delete { path } => {
+                validate_entry_path(path)?;
+            }
+            ProjectIoFileAction::Rename { path, new_name } => {
+                validate_entry_path(path)?;
+                new_name.validate()?;
+            }
+            ProjectIoFileAction::Move { path, new_path }
+            | ProjectIoFileAction::Copy { path, new_path } => {
+                validate_entry_path(path)?;
+                validate_entry_path(new_path)?;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,132 @@
+use crate::server_action::permission::Permission;
+use crate::server_action::project_action::io_action::ProjectIoAction;
+use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
+use crate::{
+    PROJECT_PATH_MAX_LEN, ProjectRelPath, SanitizeError, Validate, impl_chain_from, sanitize_err,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const SEARCH_PAGE_SIZE: usize = 100;
+pub const SEARCH_MAX_FILE_SIZE: u64 = 1024 * 1024;
+pub const SEARCH_MAX_QUERY_LEN: usize = 256;
+pub const SEARCH_MAX_LINE_LEN: usize = 200;
+/// Work one search request may do; the next request resumes where it stopped.
+pub const SEARCH_MAX_SCANNED_ENTRIES: usize = 20_000;
+pub const SEARCH_MAX_SCANNED_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProjectIoSearchAction {
+    Name {
+        path: ProjectRelPath,
+        pattern: String,
+        cursor: Option<SearchCursor>,
+    },
+    Content {
+        path: ProjectRelPath,
+        query: String,
+        is_regex: bool,
+        case_sensitive: bool,
+        cursor: Option<SearchCursor>,
+    },
+}
+
+impl_chain_from!(ProjectAction, ProjectAction::Io | ProjectIoAction::Search => ProjectIoSearchAction);
+
+fn validate_query(query: &str) -> Result<(), SanitizeError> {
+    if query.trim().is_empty() || query.len() > SEARCH_MAX_QUERY_LEN || query.contains('\0') {
+        return sanitize_err();
+    }
+    Ok(())
+}
+
+/// The cursor is only compared with the paths the walk visits, never opened, so it just has to
+/// lie below the searched folder.
+fn validate_cursor(
+    path: &ProjectRelPath,
+    cursor: Option<&SearchCursor>,
+) -> Result<(), SanitizeError> {
+    let Some(cursor) = cursor else {
+        return Ok(());
+    };
+    let below = cursor
+        .path
+        .strip_prefix(path.as_str().trim_end_matches('/'))
+        .is_some_and(|rest| rest.starts_with('/'));
+    if !below || cursor.path.len() > PROJECT_PATH_MAX_LEN || cursor.path.contains('\0') {
+        return sanitize_err();
+    }
+    Ok(())
+}
+
+impl Validate for ProjectIoSearchAction {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        match self {
+            ProjectIoSearchAction::Name {
+                path,
+                pattern,
+                cursor,
+            } => {
+                path.validate()?;
+                validate_query(pattern)?;
+                validate_cursor(path, cursor.as_ref())?;
+                if pattern.contains('/') {
+                    return sanitize_err();
+                }
+            }
+            ProjectIoSearchAction::Content {
+                path,
+                query,
+                is_regex: _,
+                case_sensitive: _,
+                cursor,
+            } => {
+                path.validate()?;
+                validate_query(query)?;
+                validate_cursor(path, cursor.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IsProjectServerAction for ProjectIoSearchAction {
+    fn permission(&self) -> Permission {
+        Permission::Read
+    }
+
+    fn require_csrf(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServerProjectIoSearchResponse {
+    pub inner: Vec<SearchMatch>,
+    /// Where the next request resumes, when the search stopped before the end of the folder.
+    pub next: Option<SearchCursor>,
+    /// The search stopped at the scan limits rather than on a full page.
+    pub truncated: bool,
+}
+
+/// Where a search stopped, so the next request walks on from there instead of from the start.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchCursor {
+    /// The entry the search was at, in the form of `SearchMatch::path`.
+    pub path: String,
+    /// Matches of that entry already returned.
+    pub skip: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchMatch {
+    pub path: String,
+    pub is_dir: bool,
+    pub line: Option<SearchLineMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SearchLineMatch {
+    pub line_number: u64,
+    pub content: String,
+}
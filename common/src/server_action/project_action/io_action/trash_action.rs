@@ -0,0 +1,74 @@
+use crate::server_action::permission::Permission;
+use crate::server_action::project_action::io_action::ProjectIoAction;
+use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
+use crate::{SanitizeError, Validate, impl_chain_from, sanitize_err};
+
+use serde::{Deserialize, Serialize};
+
+pub const TRASH_MAX_ID_LEN: usize = 64;
+/// Entries older than this are purged.
+pub const TRASH_RETENTION_DAYS: u64 = 30;
+/// Per project; past it the oldest entries are purged first.
+pub const TRASH_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProjectIoTrashAction {
+    List,
+    Restore { id: String },
+    Delete { id: String },
+    Empty,
+}
+
+impl_chain_from!(ProjectAction, ProjectAction::Io | ProjectIoAction::Trash => ProjectIoTrashAction);
+
+fn validate_trash_id(id: &str) -> Result<(), SanitizeError> {
+    if id.is_empty()
+        || id.len() > TRASH_MAX_ID_LEN
+        || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return sanitize_err();
+    }
+    Ok(())
+}
+
+impl Validate for ProjectIoTrashAction {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        match self {
+            ProjectIoTrashAction::List | ProjectIoTrashAction::Empty => Ok(()),
+            ProjectIoTrashAction::Restore { id } | ProjectIoTrashAction::Delete { id } => {
+                validate_trash_id(id)
+            }
+        }
+    }
+}
+
+impl IsProjectServerAction for ProjectIoTrashAction {
+    fn permission(&self) -> Permission {
+        match self {
+            ProjectIoTrashAction::List => Permission::Read,
+            ProjectIoTrashAction::Restore { .. }
+            | ProjectIoTrashAction::Delete { .. }
+            | ProjectIoTrashAction::Empty => Permission::Write,
+        }
+    }
+
+    fn require_csrf(&self) -> bool {
+        !matches!(self, ProjectIoTrashAction::List)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ServerProjectIoTrashResponse {
+    pub inner: Vec<TrashEntry>,
+}
+
+/// A deleted file or folder, kept with the path it had in the project.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TrashEntry {
+    pub id: String,
+    pub original_path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Unix timestamp in seconds.
+    pub deleted_at: i64,
+}
@@ -3,8 +3,10 @@ use crate::server_action::project_action::{IsProjectServerAction, ProjectAction}
 use crate::{UserSlugStr, Validate, impl_chain_from};
 
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum ProjectPermissionAction {
     Grant {
         user_slug: UserSlugStr,
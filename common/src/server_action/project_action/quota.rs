@@ -0,0 +1,35 @@
+use crate::server_action::permission::Permission;
+use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
+use crate::{SanitizeError, Validate, impl_chain_from};
+use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
+pub enum ProjectQuotaAction {
+    /// Disk usage of the project, its dev subvolume and each snapshot.
+    Usage,
+}
+
+impl Validate for ProjectQuotaAction {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        match self {
+            ProjectQuotaAction::Usage => {}
+        }
+        Ok(())
+    }
+}
+
+impl_chain_from!(ProjectAction, ProjectAction::Quota => ProjectQuotaAction);
+
+impl IsProjectServerAction for ProjectQuotaAction {
+    fn permission(&self) -> Permission {
+        match self {
+            ProjectQuotaAction::Usage => Permission::Read,
+        }
+    }
+
+    fn require_csrf(&self) -> bool {
+        false
+    }
+}
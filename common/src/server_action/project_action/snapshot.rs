@@ -2,8 +2,10 @@ use crate::server_action::permission::Permission;
 use crate::server_action::project_action::{IsProjectServerAction, ProjectAction};
 use crate::{SanitizeError, SnapShotNameStr, Validate, impl_chain_from};
 use serde::{Deserialize, Serialize};
+use strum_macros::IntoStaticStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize, IntoStaticStr)]
+#[strum(serialize_all = "snake_case")]
 pub enum ProjectSnapshotAction {
     Create {
         snapshot_name: SnapShotNameStr,
@@ -13,6 +15,7 @@ pub enum ProjectSnapshotAction {
     },
     Restore {
         snapshot_name: SnapShotNameStr,
+        backup_snapshot_name: SnapShotNameStr,
     },
     MountSnapshotProd {
         snapshot_name: SnapShotNameStr,
@@ -30,8 +33,12 @@ impl Validate for ProjectSnapshotAction {
             ProjectSnapshotAction::Delete { snapshot_name } => {
                 snapshot_name.validate()?;
             }
-            ProjectSnapshotAction::Restore { snapshot_name } => {
+            ProjectSnapshotAction::Restore {
+                snapshot_name,
+                backup_snapshot_name,
+            } => {
                 snapshot_name.validate()?;
+                backup_snapshot_name.validate()?;
             }
             ProjectSnapshotAction::MountSnapshotProd {
                 snapshot_name,
@@ -1,27 +1,92 @@
+use crate::error::RemoteError;
 use crate::server_action::permission::Permission;
 use crate::server_action::project_action::IsProjectServerAction;
+use crate::server_action::project_action::io_action::validate_entry_path;
+use crate::{ProjectRelPath, SanitizeError, Validate, sanitize_err};
 use serde::{Deserialize, Serialize};
 
+/// Bytes of text returned by `ViewFile` and by each `ReadFileRange` call.
+pub const TEXT_PAGE_SIZE: u64 = 64 * 1024;
+/// Largest side of the PNG returned by `Thumbnail`.
+pub const THUMBNAIL_SIZE: u32 = 256;
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TokenAction {
-    UploadFiles { path: String },
-    UpdateFile { path: String },
-    ViewFile { path: String },
-    DownloadFile { path: String },
+    UploadFiles {
+        path: ProjectRelPath,
+    },
+    UpdateFile {
+        path: ProjectRelPath,
+        version: String,
+    },
+    ViewFile {
+        path: ProjectRelPath,
+    },
+    DownloadFile {
+        path: ProjectRelPath,
+    },
+    ReadFileRange {
+        path: ProjectRelPath,
+        offset: u64,
+    },
+    StreamFile {
+        path: ProjectRelPath,
+    },
+    Thumbnail {
+        path: ProjectRelPath,
+    },
+}
+
+impl Validate for TokenAction {
+    fn validate(&self) -> Result<(), SanitizeError> {
+        match self {
+            TokenAction::UploadFiles { path } => path.validate(),
+            TokenAction::UpdateFile { path, version } => {
+                if version.len() > 64 {
+                    return sanitize_err();
+                }
+                validate_entry_path(path)
+            }
+            TokenAction::ViewFile { path }
+            | TokenAction::DownloadFile { path }
+            | TokenAction::ReadFileRange { path, .. }
+            | TokenAction::StreamFile { path }
+            | TokenAction::Thumbnail { path } => validate_entry_path(path),
+        }
+    }
+}
+
+impl TokenAction {
+    /// Served over GET and reusable until the token expires, so media elements can load the
+    /// URL directly and issue range requests against it.
+    pub fn is_stream(&self) -> bool {
+        matches!(
+            self,
+            TokenAction::StreamFile { .. } | TokenAction::Thumbnail { .. }
+        )
+    }
 }
 
 impl IsProjectServerAction for TokenAction {
     fn permission(&self) -> Permission {
         match self {
             TokenAction::UpdateFile { .. } | TokenAction::UploadFiles { .. } => Permission::Write,
-            TokenAction::DownloadFile { .. } | TokenAction::ViewFile { .. } => Permission::Read,
+            TokenAction::DownloadFile { .. }
+            | TokenAction::ViewFile { .. }
+            | TokenAction::ReadFileRange { .. }
+            | TokenAction::StreamFile { .. }
+            | TokenAction::Thumbnail { .. } => Permission::Read,
         }
     }
 
     fn require_csrf(&self) -> bool {
         match self {
             TokenAction::UpdateFile { .. } | TokenAction::UploadFiles { .. } => true,
-            TokenAction::DownloadFile { .. } | TokenAction::ViewFile { .. } => false,
+            TokenAction::DownloadFile { .. }
+            | TokenAction::ViewFile { .. }
+            | TokenAction::ReadFileRange { .. }
+            | TokenAction::StreamFile { .. }
+            | TokenAction::Thumbnail { .. } => false,
         }
     }
 }
@@ -29,7 +94,7 @@ impl IsProjectServerAction for TokenAction {
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum TokenActionResponse {
     Ok(String),
-    Error(String),
+    Error(RemoteError),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -37,17 +102,51 @@ pub enum UsedTokenActionResponse {
     Ok,
     Content(Vec<u8>),
     File(FileInfo),
+    TextRange(TextRange),
     UploadReport(Vec<FileUploadStatus>),
-    Error(String),
+    /// The file was saved; its new version.
+    Saved(String),
+    /// The file changed since the given version was read; carries the file as it is now.
+    Conflict(FileInfo),
+    Error(RemoteError),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
+    pub mime: String,
+    pub kind: FileKind,
+    /// Detected text encoding, `None` for anything that is not text.
+    pub encoding: Option<String>,
+    /// The first `TEXT_PAGE_SIZE` bytes of a text file, decoded.
     pub content: Option<String>,
+    /// Byte offset where `content` stops; equals `size` when the whole file is loaded.
+    pub content_end: u64,
     pub size: u64,
     pub last_modified: String,
+    /// Changes whenever the file is written, from here or over SFTP. `UpdateFile` is refused
+    /// with `Conflict` when it no longer matches the version the edit started from.
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FileKind {
+    Text,
+    Image,
+    Audio,
+    Video,
+    Pdf,
+    Binary,
+}
+
+/// A page of a text file; pass `end` as the next `offset` to continue reading.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TextRange {
+    pub offset: u64,
+    pub end: u64,
+    pub size: u64,
+    pub content: String,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -3,9 +3,13 @@ use crate::{
     Validate,
 };
 
+use crate::error::RemoteError;
 use crate::helper_command::HelperResponse;
 use serde::{Deserialize, Serialize};
 
+/// Capability of a server taking `ServerUserAction::SetProjectQuota`.
+pub const SET_PROJECT_QUOTA_CAPABILITY: &str = "user.set_project_quota";
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ServerUserAction {
     Create {
@@ -15,6 +19,8 @@ pub enum ServerUserAction {
         user_slug: UserSlugStr,
         project_slug: ProjectSlugStr,
         github_info: Option<(Option<GitTokenStr>, GitRepoFullNameStr, GitBranchNameStr)>,
+        /// Referenced bytes the project and its snapshots may use, `None` for no limit.
+        quota_bytes: Option<u64>,
     },
     RemoveProject {
         user_slugs: Vec<UserSlugStr>,
@@ -23,6 +29,12 @@ pub enum ServerUserAction {
     Delete {
         user_slug: UserSlugStr,
     },
+    /// Applies the limit of the project's current plan, which may have changed since the
+    /// project was added. Last, so servers without it still decode the other actions.
+    SetProjectQuota {
+        project_slug: ProjectSlugStr,
+        quota_bytes: Option<u64>,
+    },
 }
 
 impl Validate for ServerUserAction {
@@ -35,6 +47,7 @@ impl Validate for ServerUserAction {
                 user_slug,
                 project_slug,
                 github_info,
+                ..
             } => {
                 user_slug.validate()?;
                 project_slug.validate()?;
@@ -58,6 +71,9 @@ impl Validate for ServerUserAction {
             ServerUserAction::Delete { user_slug } => {
                 user_slug.validate()?;
             }
+            ServerUserAction::SetProjectQuota { project_slug, .. } => {
+                project_slug.validate()?;
+            }
         }
         Ok(())
     }
@@ -67,5 +83,16 @@ impl Validate for ServerUserAction {
 pub enum ServerUserResponse {
     Ok,
     Helper(HelperResponse),
-    Error(String),
+    Error(RemoteError),
+}
+
+impl ServerUserResponse {
+    /// Moves failures, the helper's included, to the error side.
+    pub fn into_result(self) -> Result<Self, RemoteError> {
+        match self {
+            ServerUserResponse::Error(error)
+            | ServerUserResponse::Helper(HelperResponse::Error { error, .. }) => Err(error),
+            response => Ok(response),
+        }
+    }
 }
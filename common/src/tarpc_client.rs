@@ -1,17 +1,24 @@
 use std::fmt;
 use std::future::Future;
 
+use crate::AuthResponse;
+use crate::protocol::{PROTOCOL_VERSION, ProtocolInfo};
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::{debug, error, info};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+use tarpc::client::RpcError;
+use tarpc::context::Context;
+use tokio::sync::{Notify, watch};
+use tokio::time::MissedTickBehavior;
+use tracing::{Instrument, debug, debug_span, error, info, warn};
 
 #[derive(Debug, thiserror::Error, Clone, Serialize, Deserialize)]
 pub enum TarpcClientError {
-    #[error(
-        "Client not connected. Reconnection attempt might be in progress or will be triggered if not."
-    )]
+    #[error("Client not connected, reconnection is in progress")]
     NotConnected,
     #[error("Inner client application error: {0}")]
     ClientError(String),
@@ -19,10 +26,14 @@ pub enum TarpcClientError {
     ConnectionError(String),
     #[error("RPC error: {0}")]
     RpcError(String),
+    #[error("Peer speaks protocol {}, this build speaks {}", .0.version, PROTOCOL_VERSION)]
+    Incompatible(ProtocolInfo),
+    #[error("Peer doesn't support {0}")]
+    Unsupported(String),
 }
 
-impl From<tarpc::client::RpcError> for TarpcClientError {
-    fn from(err: tarpc::client::RpcError) -> Self {
+impl From<RpcError> for TarpcClientError {
+    fn from(err: RpcError) -> Self {
         TarpcClientError::RpcError(err.to_string())
     }
 }
@@ -35,151 +46,496 @@ impl From<std::io::Error> for TarpcClientError {
 
 pub type TarpcClientResult<T> = Result<T, TarpcClientError>;
 
-type Connector<T> = Box<
-    dyn Fn(String, String) -> Pin<Box<dyn Future<Output = Result<T, TarpcClientError>> + Send>>
-        + Send
-        + Sync,
->;
+/// Reads the answer to `auth()` and gives what the peer speaks, or refuses a peer speaking
+/// another protocol version.
+pub fn check_auth_response(
+    response: Result<AuthResponse, RpcError>,
+) -> TarpcClientResult<ProtocolInfo> {
+    match response? {
+        AuthResponse::Ok(peer) if peer.is_supported() => Ok(peer),
+        AuthResponse::Ok(peer) | AuthResponse::Incompatible(peer) => {
+            Err(TarpcClientError::Incompatible(peer))
+        }
+        AuthResponse::Error => Err(TarpcClientError::ConnectionError("Auth failed".to_string())),
+    }
+}
 
-pub struct TarpcClient<T: Clone + Send + Sync + 'static> {
-    inner: Arc<Mutex<Option<T>>>,
-    server_addr: String,
-    token: String,
-    connector: Arc<Connector<T>>,
+/// A request context expiring `timeout` from now. The other side stops working on the request
+/// at its deadline, so requests that may run longer than tarpc's default ask for more.
+pub fn context_with_timeout(timeout: Duration) -> Context {
+    let mut context = tarpc::context::current();
+    context.deadline = Instant::now() + timeout;
+    context
 }
 
-impl<T: Clone + Send + Sync + 'static> Clone for TarpcClient<T> {
-    fn clone(&self) -> Self {
-        TarpcClient {
-            inner: Arc::clone(&self.inner),
-            server_addr: self.server_addr.clone(),
-            token: self.token.clone(),
-            connector: Arc::clone(&self.connector),
+/// How a `TarpcClient` keeps its connection up and how long its calls may take.
+#[derive(Debug, Clone)]
+pub struct TarpcClientConfig {
+    /// Wait after the first failed connection attempt, doubled after each further failure. A
+    /// connection that drops within `keepalive_interval` of opening counts as a failure.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Time a connection attempt, handshake included, gets before it counts as failed.
+    pub connect_timeout: Duration,
+    /// Time between pings on an open connection; one that goes unanswered closes it.
+    pub keepalive_interval: Duration,
+    pub keepalive_timeout: Duration,
+    /// Time a call waits for the connection to come up before failing with `NotConnected`.
+    pub connect_wait: Duration,
+    /// Deadline of calls that neither the client nor the method asks another one for.
+    pub call_timeout: Duration,
+    /// Times an idempotent call is sent when its connection keeps dropping before the answer.
+    pub max_attempts: u32,
+}
+
+impl Default for TarpcClientConfig {
+    fn default() -> Self {
+        TarpcClientConfig {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            connect_timeout: Duration::from_secs(10),
+            keepalive_interval: Duration::from_secs(15),
+            keepalive_timeout: Duration::from_secs(5),
+            connect_wait: Duration::from_secs(5),
+            call_timeout: Duration::from_secs(10),
+            max_attempts: 3,
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// The last attempt failed or the connection dropped; the next attempt follows after a
+    /// backoff.
+    Disconnected {
+        error: String,
+    },
+    /// The peer speaks another protocol version. It is tried again at the longest backoff, in
+    /// case it gets upgraded.
+    Incompatible(ProtocolInfo),
+}
+
+/// Services whose clients a `TarpcClient` can keep alive.
+pub trait Ping: Clone + Send + Sync + 'static {
+    fn ping(&self, context: Context) -> impl Future<Output = Result<(), RpcError>> + Send;
+}
+
+/// Requests with the same effect sent twice as once. A call whose connection drops before the
+/// answer may have been carried out or not, so only these are sent again.
+pub trait Idempotent {
+    fn is_idempotent(&self) -> bool;
+}
+
+impl<T: Idempotent> Idempotent for [T] {
+    fn is_idempotent(&self) -> bool {
+        self.iter().all(Idempotent::is_idempotent)
+    }
+}
+
+impl<T: Idempotent> Idempotent for Vec<T> {
+    fn is_idempotent(&self) -> bool {
+        self.as_slice().is_idempotent()
+    }
+}
+
+type Connected<T> = Result<(T, ProtocolInfo), TarpcClientError>;
+
+type Connector<T> =
+    Box<dyn Fn(String, String) -> Pin<Box<dyn Future<Output = Connected<T>> + Send>> + Send + Sync>;
+
+/// A client of one peer. A supervisor task started with it connects, pings the connection and
+/// reconnects with backoff when it drops; it stops once every clone of the client is dropped.
+#[derive(Clone)]
+pub struct TarpcClient<T: Clone + Send + Sync + 'static> {
+    shared: Arc<Shared<T>>,
+    state: watch::Receiver<ConnectionState>,
+    call_timeout: Option<Duration>,
+}
+
+struct Shared<T> {
+    server_addr: String,
+    config: TarpcClientConfig,
+    connection: RwLock<Option<Connection<T>>>,
+    /// Woken by calls that found the connection closed before the next ping did.
+    broken: Notify,
+    /// Id of the connection such a call found closed. A wakeup left over from a connection
+    /// that is already gone must not close the next one.
+    broken_id: AtomicU64,
+    peer_protocol: RwLock<Option<ProtocolInfo>>,
+}
+
+#[derive(Clone)]
+struct Connection<T> {
+    id: u64,
+    client: T,
+}
+
+/// How `TarpcClient::call` sends one method. `rpc_methods!` fills it from each declaration.
+#[derive(Debug, Clone, Copy)]
+pub struct CallPolicy {
+    /// Name the call is traced under.
+    pub method: &'static str,
+    /// Deadline unless the handle asks for another; `call_timeout` from the config if `None`.
+    pub timeout: Option<Duration>,
+    /// Capability the peer must have reported for the call to be sent at all.
+    pub capability: Option<&'static str>,
+    /// Whether the call may be sent again after its connection dropped before the answer.
+    pub idempotent: bool,
+}
+
 impl<T: Clone + Send + Sync + 'static> fmt::Debug for TarpcClient<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("ProxyClient")
-            .field("server_addr", &self.server_addr)
-            .field("inner", &"Arc<Mutex<Option<...>>>")
-            .field("connector", &"Arc<Connector<...>>")
+        f.debug_struct("TarpcClient")
+            .field("server_addr", &self.shared.server_addr)
+            .field("state", &*self.state.borrow())
+            .field("call_timeout", &self.call_timeout)
             .finish()
     }
 }
 
-impl<T: Clone + Send + Sync + 'static> TarpcClient<T> {
+impl<T: Ping> TarpcClient<T> {
     pub fn new<F, Fut>(server_addr: String, token: String, connect_fn: F) -> Self
     where
         F: Fn(String, String) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = Result<T, TarpcClientError>> + Send + 'static,
+        Fut: Future<Output = Connected<T>> + Send + 'static,
+    {
+        Self::with_config(server_addr, token, TarpcClientConfig::default(), connect_fn)
+    }
+
+    /// Starts the supervisor, so this must run inside a tokio runtime.
+    pub fn with_config<F, Fut>(
+        server_addr: String,
+        token: String,
+        config: TarpcClientConfig,
+        connect_fn: F,
+    ) -> Self
+    where
+        F: Fn(String, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Connected<T>> + Send + 'static,
     {
         let connector: Connector<T> =
             Box::new(move |addr, token| Box::pin(connect_fn(addr, token)));
-        TarpcClient {
-            inner: Arc::new(Mutex::new(None)),
+        let shared = Arc::new(Shared {
             server_addr,
+            config,
+            connection: RwLock::new(None),
+            broken: Notify::new(),
+            broken_id: AtomicU64::new(0),
+            peer_protocol: RwLock::new(None),
+        });
+        let (state_sender, state) = watch::channel(ConnectionState::Connecting);
+        tokio::spawn(supervise(
+            Arc::clone(&shared),
+            state_sender,
             token,
-            connector: Arc::new(connector),
+            connector,
+        ));
+        TarpcClient {
+            shared,
+            state,
+            call_timeout: None,
         }
     }
+}
 
-    async fn establish_connection(
-        connector: Arc<Connector<T>>,
-        server_addr: String,
-        token: String,
-    ) -> Result<T, TarpcClientError> {
-        println!("Establishing connection to server at {server_addr}...",);
-        connector(server_addr, token).await
+impl<T: Clone + Send + Sync + 'static> TarpcClient<T> {
+    /// A handle on the same connection whose calls all get `timeout`, whatever they would get
+    /// otherwise.
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        TarpcClient {
+            call_timeout: Some(timeout),
+            ..self.clone()
+        }
     }
 
-    pub(crate) async fn get_or_connect_client(&self) -> Result<T, TarpcClientError> {
-        let mut inner_guard = self.inner.lock().await;
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
 
-        if let Some(client) = inner_guard.as_ref() {
-            return Ok(client.clone());
-        }
+    pub fn is_connected(&self) -> bool {
+        *self.state.borrow() == ConnectionState::Connected
+    }
 
-        info!(
-            "Client not connected, attempting synchronous connection to {}...",
-            self.server_addr
-        );
-        match Self::establish_connection(
-            Arc::clone(&self.connector),
-            self.server_addr.clone(),
-            self.token.clone(),
-        )
-        .await
-        {
-            Ok(new_client) => {
-                println!("Successfully connected to {}.", self.server_addr);
-                *inner_guard = Some(new_client.clone());
-                Ok(new_client)
+    /// What the peer reported at the last handshake, still known after it disconnects.
+    pub fn peer_protocol(&self) -> Option<ProtocolInfo> {
+        self.shared
+            .peer_protocol
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Fails unless the peer reported `capability` at the last handshake.
+    pub fn require_capability(&self, capability: &str) -> TarpcClientResult<()> {
+        match self.peer_protocol() {
+            Some(peer) if !peer.supports(capability) => {
+                Err(TarpcClientError::Unsupported(capability.to_string()))
             }
-            Err(e) => {
-                println!(
-                    "Failed to connect synchronously to {}: {:?}",
-                    self.server_addr, e
-                );
+            _ => Ok(()),
+        }
+    }
 
-                Err(e)
+    /// Sends a request through `send`, given the connected client and the request's context.
+    /// A request whose connection turns out closed gets the connection replaced, and is sent
+    /// again on the new one if `policy` says it is idempotent, up to `max_attempts` times.
+    pub async fn call<R, F, Fut>(&self, policy: CallPolicy, send: F) -> TarpcClientResult<R>
+    where
+        F: Fn(T, Context) -> Fut,
+        Fut: Future<Output = Result<R, RpcError>>,
+    {
+        let span = debug_span!("rpc", peer = %self.shared.server_addr, method = policy.method);
+        async {
+            let mut attempt = 1;
+            loop {
+                let connection = self.connection().await?;
+                if let Some(capability) = policy.capability {
+                    self.require_capability(capability)?;
+                }
+                let timeout = policy.timeout.unwrap_or(self.shared.config.call_timeout);
+                let started = Instant::now();
+                let result = send(
+                    connection.client.clone(),
+                    context_with_timeout(self.call_timeout.unwrap_or(timeout)),
+                )
+                .await;
+                match result {
+                    Ok(response) => {
+                        debug!("Answered in {:?}", started.elapsed());
+                        return Ok(response);
+                    }
+                    Err(RpcError::Shutdown) => {
+                        self.report_broken(&connection);
+                        if !policy.idempotent || attempt >= self.shared.config.max_attempts {
+                            warn!("Connection closed before the answer, attempt {attempt}");
+                            return Err(RpcError::Shutdown.into());
+                        }
+                        debug!("Connection closed before the answer, sending again");
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        warn!("Failed after {:?}: {e}", started.elapsed());
+                        return Err(e.into());
+                    }
+                }
             }
         }
+        .instrument(span)
+        .await
     }
 
-    pub async fn connect(&self) -> Result<(), TarpcClientError> {
-        let mut inner_guard = self.inner.lock().await;
+    /// The open connection, waiting up to `connect_wait` for the supervisor to open one.
+    async fn connection(&self) -> TarpcClientResult<Connection<T>> {
+        let mut state = self.state.clone();
+        let ready = state.wait_for(|state| match state {
+            ConnectionState::Connected => self.shared.connection().is_some(),
+            ConnectionState::Incompatible(_) => true,
+            _ => false,
+        });
+        let state = match tokio::time::timeout(self.shared.config.connect_wait, ready).await {
+            Ok(Ok(state)) => state.clone(),
+            Ok(Err(_)) | Err(_) => return Err(TarpcClientError::NotConnected),
+        };
+        if let ConnectionState::Incompatible(peer) = state {
+            return Err(TarpcClientError::Incompatible(peer));
+        }
+        self.shared
+            .connection()
+            .ok_or(TarpcClientError::NotConnected)
+    }
 
-        if inner_guard.is_some() {
-            println!(
-                "Explicit connect: Client is already connected to {}.",
-                self.server_addr
-            );
-            return Ok(());
+    /// Called when a call on `connection` failed because it is closed, so the supervisor
+    /// replaces it without waiting for the next ping.
+    fn report_broken(&self, connection: &Connection<T>) {
+        let mut current = self
+            .shared
+            .connection
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if current.as_ref().is_some_and(|c| c.id == connection.id) {
+            *current = None;
+            self.shared
+                .broken_id
+                .store(connection.id, Ordering::Release);
+            self.shared.broken.notify_one();
         }
+    }
+}
 
-        println!(
-            "Explicit connect: Attempting connection to server at {}...",
-            self.server_addr
-        );
+/// Declares `TarpcClient` methods that send the service method of the same name through
+/// `TarpcClient::call`, one line each, inside an `impl TarpcClient<SomeClient>` block:
+///
+/// ```ignore
+/// rpc_methods! {
+///     fn execute(actions: Vec<HelperCommand>) -> HelperResponse,
+///         timeout = HELPER_REQUEST_TIMEOUT, retry if actions.is_idempotent();
+/// }
+/// ```
+///
+/// `timeout` overrides the config's `call_timeout`, `requires` names a capability the peer
+/// must have, and `retry if` says when the call may be sent again. Without `retry if` a call
+/// is sent at most once. The options, all optional, come in that order.
+#[macro_export]
+macro_rules! rpc_methods {
+    ($(
+        $(#[$meta:meta])*
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $response:ty
+            $(, timeout = $timeout:expr)?
+            $(, requires $capability:expr)?
+            $(, retry if $idempotent:expr)?;
+    )*) => {$(
+        $(#[$meta])*
+        pub async fn $name(
+            &self,
+            $($arg: $ty),*
+        ) -> $crate::tarpc_client::TarpcClientResult<$response> {
+            let policy = $crate::tarpc_client::CallPolicy {
+                method: stringify!($name),
+                timeout: $crate::rpc_methods!(@option $($timeout)?),
+                capability: $crate::rpc_methods!(@option $($capability)?),
+                idempotent: $crate::rpc_methods!(@flag $($idempotent)?),
+            };
+            self.call(policy, |client, context| {
+                $(let $arg = $arg.clone();)*
+                async move { client.$name(context, $($arg),*).await }
+            })
+            .await
+        }
+    )*};
 
-        match Self::establish_connection(
-            Arc::clone(&self.connector),
-            self.server_addr.clone(),
-            self.token.clone(),
-        )
-        .await
-        {
-            Ok(client_instance) => {
-                *inner_guard = Some(client_instance);
-                println!(
-                    "Explicit connect: Successfully connected to {}.",
-                    self.server_addr
+    (@option) => { None };
+    (@option $value:expr) => { Some($value) };
+    (@flag) => { false };
+    (@flag $value:expr) => { $value };
+}
+
+impl<T: Clone> Shared<T> {
+    fn connection(&self) -> Option<Connection<T>> {
+        self.connection
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn set_connection(&self, connection: Option<Connection<T>>) {
+        *self
+            .connection
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = connection;
+    }
+
+    fn set_peer_protocol(&self, peer: ProtocolInfo) {
+        *self
+            .peer_protocol
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(peer);
+    }
+}
+
+async fn supervise<T: Ping>(
+    shared: Arc<Shared<T>>,
+    state: watch::Sender<ConnectionState>,
+    token: String,
+    connector: Connector<T>,
+) {
+    let config = &shared.config;
+    let mut backoff = config.initial_backoff;
+    let mut next_id = 0;
+    loop {
+        state.send_replace(ConnectionState::Connecting);
+        let attempt = tokio::time::timeout(
+            config.connect_timeout,
+            connector(shared.server_addr.clone(), token.clone()),
+        );
+        let connected = tokio::select! {
+            _ = state.closed() => return,
+            connected = attempt => connected.unwrap_or_else(|_| {
+                Err(TarpcClientError::ConnectionError("timed out".to_string()))
+            }),
+        };
+        let failed = match connected {
+            Ok((client, peer)) => {
+                info!("Connected to {}, which runs {peer}", shared.server_addr);
+                shared.set_peer_protocol(peer);
+                next_id += 1;
+                shared.set_connection(Some(Connection {
+                    id: next_id,
+                    client: client.clone(),
+                }));
+                state.send_replace(ConnectionState::Connected);
+                let connected_at = Instant::now();
+                let lost = tokio::select! {
+                    _ = state.closed() => return,
+                    lost = keep_alive(&shared, next_id, &client) => lost,
+                };
+                shared.set_connection(None);
+                warn!("Connection to {} lost: {lost}", shared.server_addr);
+                // A peer that drops connections right after the handshake, crash-looping or
+                // resetting after auth, keeps backing off like one that refuses them.
+                if connected_at.elapsed() >= config.keepalive_interval {
+                    backoff = config.initial_backoff;
+                }
+                ConnectionState::Disconnected { error: lost }
+            }
+            Err(TarpcClientError::Incompatible(peer)) => {
+                error!(
+                    "{} speaks protocol {}, this build speaks {PROTOCOL_VERSION}",
+                    shared.server_addr, peer.version
                 );
-                Ok(())
+                shared.set_peer_protocol(peer.clone());
+                backoff = config.max_backoff;
+                ConnectionState::Incompatible(peer)
             }
             Err(e) => {
-                println!(
-                    "Explicit connect: Failed to connect to {}: {:?}",
-                    self.server_addr, e
-                );
-                Err(e)
+                warn!("Could not connect to {}: {e}", shared.server_addr);
+                ConnectionState::Disconnected {
+                    error: e.to_string(),
+                }
             }
-        }
-    }
+        };
+        state.send_replace(failed);
 
-    pub async fn is_connected(&self) -> bool {
-        self.inner.lock().await.is_some()
+        let delay = jittered(backoff);
+        debug!(
+            "Next connection attempt to {} in {delay:?}",
+            shared.server_addr
+        );
+        tokio::select! {
+            _ = state.closed() => return,
+            _ = tokio::time::sleep(delay) => {}
+        }
+        backoff = (backoff * 2).min(config.max_backoff);
     }
+}
 
-    pub async fn disconnect(&self) {
-        let mut inner_guard = self.inner.lock().await;
-        if inner_guard.is_some() {
-            *inner_guard = None;
-            info!("Client disconnected from {}.", self.server_addr);
-        } else {
-            debug!("Client already disconnected from {}.", self.server_addr);
+/// Pings `client`, the connection `id`, until a ping fails or a call reports the connection
+/// closed, and says which.
+async fn keep_alive<T: Ping>(shared: &Shared<T>, id: u64, client: &T) -> String {
+    let mut interval = tokio::time::interval(shared.config.keepalive_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // The first tick is immediate, and the handshake has just shown the connection works.
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = shared.broken.notified() => {
+                if shared.broken_id.load(Ordering::Acquire) == id {
+                    return "a call found it closed".to_string();
+                }
+            }
+            _ = interval.tick() => {
+                let context = context_with_timeout(shared.config.keepalive_timeout);
+                if let Err(e) = client.ping(context).await {
+                    return format!("ping failed: {e}");
+                }
+            }
         }
     }
 }
+
+/// `backoff` scaled by a random factor between 0.5 and 1, so clients that lost their peer at
+/// the same time don't all come back at the same time.
+fn jittered(backoff: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    backoff.mul_f64(0.5 + random as f64 / u64::MAX as f64 / 2.0)
+}
@@ -0,0 +1,93 @@
+//! Logging and tracing setup shared by every service. Logs go to stdout as before; spans also
+//! go through an OpenTelemetry layer, which gives each request a trace id that tarpc carries in
+//! its `Context` from the website down to the helper and hosting. With an OTLP endpoint set, the
+//! spans are exported there too, e.g. to a collector on `http://localhost:4317`.
+
+use opentelemetry::KeyValue;
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{Config, TracerProvider};
+use opentelemetry_sdk::{Resource, runtime};
+use tracing::level_filters::LevelFilter;
+use tracing::{Level, info, warn};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Where spans are exported, over OTLP/gRPC. Nothing is exported when it isn't set.
+pub const OTLP_ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Keeps the tracer provider for the life of the service; spans still waiting to be exported
+/// are sent when it is dropped.
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Could not export the last spans: {e}");
+        }
+    }
+}
+
+/// Installs the global subscriber for the service built as `crate_name`. `RUST_LOG` filters
+/// the logs, which default to the service's own at debug. Spans of the service, of this crate
+/// and of tarpc are traced whatever `RUST_LOG` says, as tarpc's carry the trace across services.
+/// Must run inside a tokio runtime when `otlp_endpoint` is set.
+pub fn init(crate_name: &'static str, otlp_endpoint: Option<String>) -> Telemetry {
+    let mut builder = TracerProvider::builder().with_config(
+        Config::default().with_resource(Resource::new([KeyValue::new("service.name", crate_name)])),
+    );
+    let exporter = otlp_endpoint.as_ref().map(|endpoint| {
+        opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint)
+            .build_span_exporter()
+    });
+    let exporter_error = match exporter {
+        Some(Ok(exporter)) => {
+            builder = builder.with_batch_exporter(exporter, runtime::Tokio);
+            None
+        }
+        Some(Err(e)) => Some(e),
+        None => None,
+    };
+    let provider = builder.build();
+
+    let log_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| format!("{crate_name}=debug").into());
+    let trace_filter = Targets::new()
+        .with_target(crate_name, Level::DEBUG)
+        .with_target("common", Level::DEBUG)
+        .with_target("tarpc", Level::INFO)
+        .with_default(LevelFilter::OFF);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(log_filter))
+        .with(
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(crate_name))
+                .with_filter(trace_filter),
+        )
+        .init();
+
+    match (otlp_endpoint, exporter_error) {
+        (Some(endpoint), Some(e)) => warn!("Not exporting spans to {endpoint}: {e}"),
+        (Some(endpoint), None) => info!("Exporting spans to {endpoint}"),
+        _ => {}
+    }
+    Telemetry { provider }
+}
+
+/// Trace id of the current span, which identifies the request it belongs to in every service
+/// it reached. `None` outside any traced span.
+pub fn request_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
@@ -0,0 +1,178 @@
+//! TLS with certificates on both ends, for RPC over TCP. Both sides trust one CA, kept by the
+//! website, which issues each server's certificate and the website's own client certificate.
+
+use futures::{Stream, StreamExt};
+use rustls::RootCertStore;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use serde::{Deserialize, Serialize};
+use std::future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tarpc::serde_transport::{self, Transport};
+use tarpc::tokio_serde::formats::Bincode;
+use tarpc::tokio_util::codec::LengthDelimitedCodec;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::{TlsAcceptor, TlsConnector, client, server};
+use tracing::warn;
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+/// Time a peer gets to complete the handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshakes a listener runs at once, so slow clients don't hold up the others.
+const MAX_PENDING_HANDSHAKES: usize = 32;
+
+pub type ServerTlsTransport<Item, SinkItem> =
+    Transport<server::TlsStream<TcpStream>, Item, SinkItem, Bincode<Item, SinkItem>>;
+pub type ClientTlsTransport<Item, SinkItem> =
+    Transport<client::TlsStream<TcpStream>, Item, SinkItem, Bincode<Item, SinkItem>>;
+
+/// Presents the certificate at `cert_path` and only accepts clients whose certificate was
+/// issued by the CA at `client_ca_path`.
+pub fn server_config(
+    cert_path: &str,
+    key_path: &str,
+    client_ca_path: &str,
+) -> io::Result<Arc<ServerConfig>> {
+    let verifier =
+        WebPkiClientVerifier::builder_with_provider(Arc::new(roots(client_ca_path)?), provider())
+            .build()
+            .map_err(io::Error::other)?;
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(certs(cert_path)?, key(key_path)?)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
+
+/// Presents the certificate at `cert_path` and only trusts servers whose certificate was issued
+/// by the CA at `ca_path`.
+pub fn client_config(
+    ca_path: &str,
+    cert_path: &str,
+    key_path: &str,
+) -> io::Result<Arc<ClientConfig>> {
+    let config = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_root_certificates(roots(ca_path)?)
+        .with_client_auth_cert(certs(cert_path)?, key(key_path)?)
+        .map_err(io::Error::other)?;
+    Ok(Arc::new(config))
+}
+
+/// Connects to `addr`, whose certificate must be valid for `server_name`, an IP address or a
+/// host name.
+pub async fn connect<Item, SinkItem>(
+    config: Arc<ClientConfig>,
+    addr: &str,
+    server_name: &str,
+    max_frame_length: usize,
+) -> io::Result<ClientTlsTransport<Item, SinkItem>>
+where
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    let server_name = ServerName::try_from(server_name.to_string())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let stream = TcpStream::connect(addr).await?;
+    let handshake = TlsConnector::from(config).connect(server_name, stream);
+    let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+    Ok(transport(stream, max_frame_length))
+}
+
+/// Accepts connections on `listener` and yields each client that completes the handshake,
+/// with its address. Failed handshakes are logged and dropped.
+pub fn incoming<Item, SinkItem>(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    max_frame_length: usize,
+) -> impl Stream<Item = (SocketAddr, ServerTlsTransport<Item, SinkItem>)>
+where
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    let acceptor = TlsAcceptor::from(config);
+    futures::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .filter_map(|accepted| {
+        future::ready(
+            accepted
+                .inspect_err(|e| warn!("Could not accept a connection: {e}"))
+                .ok(),
+        )
+    })
+    .map(move |(stream, addr)| {
+        let handshake = acceptor.accept(stream);
+        async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+                Ok(Ok(stream)) => Some((addr, transport(stream, max_frame_length))),
+                Ok(Err(e)) => {
+                    warn!("TLS handshake with {addr} failed: {e}");
+                    None
+                }
+                Err(_) => {
+                    warn!("TLS handshake with {addr} timed out");
+                    None
+                }
+            }
+        }
+    })
+    .buffer_unordered(MAX_PENDING_HANDSHAKES)
+    .filter_map(future::ready)
+}
+
+fn transport<S, Item, SinkItem>(
+    stream: S,
+    max_frame_length: usize,
+) -> Transport<S, Item, SinkItem, Bincode<Item, SinkItem>>
+where
+    S: AsyncRead + AsyncWrite,
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    let framed = LengthDelimitedCodec::builder()
+        .max_frame_length(max_frame_length)
+        .new_framed(stream);
+    serde_transport::new(framed, Bincode::default())
+}
+
+/// Ring, named rather than left to rustls to pick, as other dependencies may enable a second
+/// provider.
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn roots(path: &str) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in certs(path)? {
+        roots.add(cert).map_err(io::Error::other)?;
+    }
+    Ok(roots)
+}
+
+fn certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| pem_error(path, e))
+}
+
+fn key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| pem_error(path, e))
+}
+
+fn pem_error(path: &str, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{path}: {e}"))
+}
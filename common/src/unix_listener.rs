@@ -0,0 +1,135 @@
+//! Unix socket listeners for the privileged services. The kernel reports who is on the other
+//! end of each connection (`SO_PEERCRED`), and connections from anyone other than the allowed
+//! account are closed before a single request is read, whatever token they would send.
+
+use crate::SERVICE_USER;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::io;
+use tarpc::serde_transport::{self, Transport};
+use tarpc::tokio_serde::formats::Bincode;
+use tarpc::tokio_util::codec::LengthDelimitedCodec;
+use tokio::net::unix::UCred;
+use tokio::net::{UnixListener, UnixStream};
+use tracing::warn;
+
+/// The only account allowed to connect, by uid and primary gid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllowedPeer {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl AllowedPeer {
+    /// The service account the server runs as.
+    pub fn service_user() -> io::Result<Self> {
+        let name = CString::new(SERVICE_USER).map_err(|_| io::ErrorKind::InvalidInput)?;
+        let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+        // SAFETY: `passwd` is plain data that getpwnam_r fills in.
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call and `buffer` is as long as announced.
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if result.is_null() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no user {SERVICE_USER}"),
+            ));
+        }
+        Ok(AllowedPeer {
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+        })
+    }
+
+    /// The account this process runs as, for services started without privileges.
+    pub fn current_process() -> Self {
+        // SAFETY: getuid and getgid can't fail.
+        unsafe {
+            AllowedPeer {
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        }
+    }
+
+    pub fn allows(&self, cred: &UCred) -> bool {
+        cred.uid() == self.uid && cred.gid() == self.gid
+    }
+}
+
+pub type PeerTransport<Item, SinkItem> =
+    Transport<UnixStream, Item, SinkItem, Bincode<Item, SinkItem>>;
+
+/// Listens on `path`, replacing a socket left by a previous run, and yields a transport for
+/// each connection from `peer`, with the credentials of the process that made it. Other
+/// connections are logged and closed.
+pub fn listen<Item, SinkItem>(
+    path: &str,
+    peer: AllowedPeer,
+    max_frame_length: usize,
+) -> io::Result<impl Stream<Item = (UCred, PeerTransport<Item, SinkItem>)>>
+where
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    Ok(futures::stream::unfold(
+        listener,
+        move |listener| async move {
+            let accepted = accept(&listener, peer, max_frame_length).await;
+            Some((accepted, listener))
+        },
+    ))
+}
+
+async fn accept<Item, SinkItem>(
+    listener: &UnixListener,
+    peer: AllowedPeer,
+    max_frame_length: usize,
+) -> (UCred, PeerTransport<Item, SinkItem>)
+where
+    Item: for<'de> Deserialize<'de>,
+    SinkItem: Serialize,
+{
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("Could not accept a connection: {e}");
+                continue;
+            }
+        };
+        match stream.peer_cred() {
+            Ok(cred) if peer.allows(&cred) => {
+                let framed = LengthDelimitedCodec::builder()
+                    .max_frame_length(max_frame_length)
+                    .new_framed(stream);
+                return (cred, serde_transport::new(framed, Bincode::default()));
+            }
+            Ok(cred) => warn!(
+                "Refused a connection from uid {} gid {} pid {:?}",
+                cred.uid(),
+                cred.gid(),
+                cred.pid()
+            ),
+            Err(e) => warn!("Refused a connection without peer credentials: {e}"),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! Settings of the server: the shared `[paths]` and `[rpc]` sections and its own `[server]`.
+
+use common::config::{require_positive_secs, PathsConfig, RpcConfig, Settings};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub rpc: RpcConfig,
+    pub server: ServerConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address of the RPC and token listeners, on `rpc.server_port` and `rpc.server_token_port`.
+    pub bind_address: IpAddr,
+    /// How long a token for a single upload or download stays usable.
+    pub token_action_ttl_secs: u64,
+    pub stream_token_ttl_secs: u64,
+    /// How long an upload may sit between its chunks before it is forgotten.
+    pub file_upload_ttl_secs: u64,
+    /// Largest request body the token listener takes, uploads included.
+    pub upload_body_limit_bytes: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            token_action_ttl_secs: 15,
+            stream_token_ttl_secs: 3600,
+            file_upload_ttl_secs: 3600,
+            upload_body_limit_bytes: 65536000,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn token_action_ttl(&self) -> Duration {
+        Duration::from_secs(self.token_action_ttl_secs)
+    }
+
+    pub fn stream_token_ttl(&self) -> Duration {
+        Duration::from_secs(self.stream_token_ttl_secs)
+    }
+
+    pub fn file_upload_ttl(&self) -> Duration {
+        Duration::from_secs(self.file_upload_ttl_secs)
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<(), String> {
+        self.paths.validate()?;
+        self.rpc.validate()?;
+        let server = &self.server;
+        for (setting, secs) in [
+            ("server.token_action_ttl_secs", server.token_action_ttl_secs),
+            ("server.stream_token_ttl_secs", server.stream_token_ttl_secs),
+            ("server.file_upload_ttl_secs", server.file_upload_ttl_secs),
+        ] {
+            require_positive_secs(setting, secs)?;
+        }
+        if server.upload_body_limit_bytes == 0 {
+            return Err("server.upload_body_limit_bytes must be positive".to_string());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,146 @@
+//! File access under the acting user's identity.
+//!
+//! `setfsuid`/`setfsgid` change only the identity the kernel checks file permissions against,
+//! and only for the calling thread. Web file operations run on a blocking thread switched to
+//! the project user, so the project ACLs decide what they may touch and new files belong to
+//! that user. The service needs `CAP_SETUID` and `CAP_SETGID` for the switch.
+//!
+//! Supplementary groups aren't part of the filesystem identity and stay the service's own, so
+//! the service account must not have any: checks would otherwise also pass through them. The
+//! server refuses to start if it has some.
+
+use crate::{ServerError, ServerResult};
+use common::UserSlugStr;
+use std::ffi::CString;
+use std::io;
+use tracing::Span;
+
+/// Filesystem identity of a project user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsUser {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+impl FsUser {
+    /// Looks the system account of `user_slug` up, as created by the helper.
+    pub fn lookup(user_slug: &UserSlugStr) -> ServerResult<Self> {
+        let name = CString::new(user_slug.0.as_str()).map_err(|_| ServerError::UnknownUser)?;
+        // SAFETY: `passwd` is plain data, filled in by getpwnam_r.
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let mut buf = vec![0 as libc::c_char; 1024];
+        loop {
+            // SAFETY: every pointer is valid and `buf.len()` is the size of `buf`.
+            let rc = unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                )
+            };
+            match rc {
+                0 => break,
+                libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                rc => return Err(io::Error::from_raw_os_error(rc).into()),
+            }
+        }
+        if result.is_null() {
+            return Err(ServerError::UnknownUser);
+        }
+        Ok(Self {
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+        })
+    }
+
+    /// Runs `f` on a blocking thread switched to this identity, switched back afterwards.
+    pub async fn spawn<T, F>(self, f: F) -> ServerResult<T>
+    where
+        F: FnOnce() -> ServerResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let span = Span::current();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let _identity = FsIdentity::switch(self.uid, self.gid)?;
+            f()
+        })
+        .await?
+    }
+}
+
+/// Runs `f` with the service's own identity, from a thread switched by `FsUser::spawn`. For
+/// the few steps that touch server-owned files, such as the trash.
+pub fn as_service<T>(f: impl FnOnce() -> ServerResult<T>) -> ServerResult<T> {
+    // SAFETY: geteuid and getegid cannot fail.
+    let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let _identity = FsIdentity::switch(uid, gid)?;
+    f()
+}
+
+/// Fails if the process has groups besides its own, see the module documentation.
+pub fn check_no_supplementary_groups() -> ServerResult<()> {
+    // SAFETY: a size of zero only asks for the number of groups.
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut groups = vec![0 as libc::gid_t; count as usize];
+    // SAFETY: `groups` has room for `count` ids.
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    if count < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    groups.truncate(count as usize);
+    // The list may or may not include the effective group.
+    // SAFETY: getegid cannot fail.
+    let gid = unsafe { libc::getegid() };
+    groups.retain(|group| *group != gid);
+    if !groups.is_empty() {
+        return Err(ServerError::SupplementaryGroups(groups));
+    }
+    Ok(())
+}
+
+/// Restores the thread's previous filesystem identity when dropped.
+struct FsIdentity {
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+impl FsIdentity {
+    fn switch(uid: libc::uid_t, gid: libc::gid_t) -> ServerResult<Self> {
+        // SAFETY: both calls only change the calling thread's credentials.
+        let previous = unsafe {
+            let gid = libc::setfsgid(gid) as libc::gid_t;
+            let uid = libc::setfsuid(uid) as libc::uid_t;
+            Self { uid, gid }
+        };
+        // Both calls return the previous id whether or not they succeeded; an invalid id
+        // changes nothing and reads the current one back.
+        // SAFETY: as above.
+        let current = unsafe {
+            (
+                libc::setfsuid(libc::uid_t::MAX) as libc::uid_t,
+                libc::setfsgid(libc::gid_t::MAX) as libc::gid_t,
+            )
+        };
+        if current != (uid, gid) {
+            return Err(ServerError::FsIdentitySwitchFailed);
+        }
+        Ok(previous)
+    }
+}
+
+impl Drop for FsIdentity {
+    fn drop(&mut self) {
+        // SAFETY: see `switch`.
+        unsafe {
+            libc::setfsuid(self.uid);
+            libc::setfsgid(self.gid);
+        }
+    }
+}
@@ -1,19 +1,35 @@
-use crate::{AppState, ServerError};
+use crate::fs_user::FsUser;
+use crate::preview::{
+    decode_page, parse_range, sniff, thumbnail_png, Sniffed, SNIFF_LEN, THUMBNAIL_MAX_SOURCE_SIZE,
+};
+use crate::project_fs::{
+    create_file_at, display_path, metadata_at, open_file_at, project_rel_path, rename_at,
+    unlink_at, Entry, ProjectDir,
+};
+use crate::{invalid_input, AppState, ServerError, ServerResult};
 use axum::body::Body;
 use axum::extract::{Multipart, Path, State};
-use axum::http::{header, Response, StatusCode};
+use axum::http::{header, HeaderMap, HeaderValue, Response, StatusCode};
 use axum::response::IntoResponse;
 use axum::Json;
 use chrono::{DateTime, Utc};
+use common::error::{RemoteError, RemoteErrorKind};
 use common::server_action::token_action::{
-    FileInfo, FileUploadStatus, TokenAction, UsedTokenActionResponse,
+    FileInfo, FileKind, FileUploadStatus, TextRange, TokenAction, UsedTokenActionResponse,
+    TEXT_PAGE_SIZE,
 };
-use common::{ensure_path_in_project_path, get_project_dev_path};
+use common::{ProjectRelPath, ProjectSlugStr};
 use futures::StreamExt;
+use std::ffi::{OsStr, OsString};
+use std::fs::Metadata;
+use std::io::SeekFrom;
+use std::os::fd::{AsFd, OwnedFd};
+use std::path::{Path as FsPath, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
 use tarpc::tokio_util::io::ReaderStream;
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
-use tokio::io::BufReader as TokioBufReader;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 use tracing::info;
 
 pub async fn server_project_action_token(
@@ -22,21 +38,38 @@ pub async fn server_project_action_token(
     mut form: Multipart,
 ) -> impl IntoResponse {
     info!("server_project_action_token: {}", token);
-    if let Some((project_slug, action)) = state.project_token_action_cache.remove(&token).await {
+    if let Some((project_slug, user_slug, action)) =
+        state.project_token_action_cache.remove(&token).await
+    {
         info!(
-            "token action cache hit : {:?} => {:?}",
-            project_slug, action
+            "token action cache hit : {:?} by {:?} => {:?}",
+            project_slug, user_slug, action
         );
+        let fs_user = match FsUser::lookup(&user_slug) {
+            Ok(fs_user) => fs_user,
+            Err(e) => return error_response("Token user lookup failed", e),
+        };
+        // Uploads and saves change the dev tree, so they queue with the project's other changes.
+        let name = match &action {
+            TokenAction::UploadFiles { .. } => Some("token.upload_files"),
+            TokenAction::UpdateFile { .. } => Some("token.update_file"),
+            _ => None,
+        };
+        let _turn = match name {
+            Some(name) => Some(
+                state
+                    .project_locks
+                    .lock(&project_slug, name.to_string(), Some(user_slug.clone()))
+                    .await,
+            ),
+            None => None,
+        };
         match action {
             TokenAction::UploadFiles { path } => {
-                let base_upload_path =
-                    match ensure_path_in_project_path(&project_slug, &path, false, true).await {
-                        Ok(path) => path,
-                        Err(e) => {
-                            return Json(UsedTokenActionResponse::Error(format!("Error: {e}")))
-                                .into_response();
-                        }
-                    };
+                let upload_dir = match open_project_dir(fs_user, &project_slug, &path).await {
+                    Ok(dir) => Arc::new(dir),
+                    Err(e) => return error_response("Failed to open upload directory", e),
+                };
                 let mut upload_statuses = Vec::new();
                 while let Ok(Some(mut field)) = form.next_field().await {
                     // field needs to be mutable for .next()
@@ -64,29 +97,8 @@ pub async fn server_project_action_token(
                         continue;
                     }
 
-                    let final_path = base_upload_path.join(&sanitized_filename);
-                    let final_path = match final_path.canonicalize() {
-                        Ok(path) => path,
-                        Err(e) => {
-                            upload_statuses.push(FileUploadStatus {
-                                filename: original_filename.clone(),
-                                success: false,
-                                message: format!("Error canonicalizing path: {e}"),
-                            });
-                            continue;
-                        }
-                    };
-
-                    if !final_path.starts_with(&base_upload_path) {
-                        upload_statuses.push(FileUploadStatus {
-                            filename: original_filename.clone(),
-                            success: false,
-                            message: "Attempted path traversal detected.".to_string(),
-                        });
-                        continue;
-                    }
-
-                    match tokio::fs::File::create(&final_path).await {
+                    let file_name = OsStr::new(&sanitized_filename);
+                    match create_file_as(fs_user, &upload_dir, file_name, true).await {
                         Ok(mut file_to_write) => {
                             let mut field_successfully_streamed = true;
                             while let Some(chunk_result) = field.next().await {
@@ -102,7 +114,7 @@ pub async fn server_project_action_token(
                                             });
                                             field_successfully_streamed = false;
                                             // Attempt to clean up partially written file
-                                            tokio::fs::remove_file(&final_path).await.ok();
+                                            unlink_as(fs_user, &upload_dir, file_name).await;
                                             break; // Stop processing this field's chunks
                                         }
                                     }
@@ -116,7 +128,7 @@ pub async fn server_project_action_token(
                                         });
                                         field_successfully_streamed = false;
                                         // Attempt to clean up partially written file (if created)
-                                        tokio::fs::remove_file(&final_path).await.ok();
+                                        unlink_as(fs_user, &upload_dir, file_name).await;
                                         break; // Stop processing this field's chunks
                                     }
                                 }
@@ -132,7 +144,7 @@ pub async fn server_project_action_token(
                                             "Error flushing/syncing file to disk: {e}"
                                         ),
                                     });
-                                    tokio::fs::remove_file(&final_path).await.ok();
+                                    unlink_as(fs_user, &upload_dir, file_name).await;
                                 // Clean up
                                 } else {
                                     file_to_write.sync_all().await.unwrap();
@@ -156,90 +168,60 @@ pub async fn server_project_action_token(
 
                 if upload_statuses.is_empty() && form.next_field().await.is_err() {
                     // Check if multipart itself had an error or was empty
-                    Json(UsedTokenActionResponse::Error(
-                        "No files were processed or multipart form was empty/invalid.".to_string(),
-                    ))
+                    Json(UsedTokenActionResponse::Error(invalid_input(
+                        "No files were processed or multipart form was empty/invalid.",
+                    )))
                     .into_response()
                 } else {
                     Json(UsedTokenActionResponse::UploadReport(upload_statuses)).into_response()
                 }
             }
             TokenAction::ViewFile { path } => {
-                let path = match ensure_path_in_project_path(&project_slug, &path, true, true).await
-                {
-                    Ok(path) => path,
-                    Err(e) => {
-                        return Json(UsedTokenActionResponse::Error(format!("Error: {e}")))
-                            .into_response();
-                    }
-                };
-                let path_copy = path.clone();
-                let path_copy = path_copy
-                    .strip_prefix(get_project_dev_path(&project_slug))
-                    .unwrap();
-                let name = path
-                    .file_name()
-                    .ok_or(ServerError::CantReadFileName(
-                        path.to_string_lossy().to_string(),
-                    ))
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string();
-                let file = File::open(path).await.unwrap();
-                let metadata = file.metadata().await.unwrap();
-                let size = metadata.len();
-                let modified = metadata.modified().unwrap();
-                let modified: DateTime<Utc> = modified.into();
-                let last_modified = modified.format("%a, %d %b %Y %T").to_string();
-
-                let content = if size < 64 * 1024 {
-                    let mut reader = TokioBufReader::new(file);
-                    let mut buf = Vec::new();
-                    tokio::io::copy(&mut reader, &mut buf).await.unwrap();
-                    Some(String::from_utf8_lossy(&buf).to_string())
-                } else {
-                    None
+                let file_info = match open_project_file(fs_user, &project_slug, &path).await {
+                    Ok((mut file, rel)) => read_file_info(&mut file, &rel).await,
+                    Err(e) => Err(e),
                 };
-                Json(UsedTokenActionResponse::File(FileInfo {
-                    name,
-                    content,
-                    size,
-                    path: format!("root/{}", path_copy.to_string_lossy()),
-                    last_modified,
-                }))
-                .into_response()
+                match file_info {
+                    Ok(file_info) => Json(UsedTokenActionResponse::File(file_info)).into_response(),
+                    Err(e) => error_response("Failed to view file", e),
+                }
             }
-            TokenAction::UpdateFile { path } => {
-                let target_path = ensure_path_in_project_path(&project_slug, &path, true, true)
-                    .await
-                    .unwrap();
-                // Create a temporary file path. It's good practice to put it in the same filesystem/directory
-                // to ensure atomic rename works and to inherit permissions/mount properties if relevant.
-                let temp_file_name = format!(".tmp_update_{}", uuid::Uuid::new_v4());
-                let temp_path = target_path.with_file_name(temp_file_name);
+            TokenAction::UpdateFile { path, version } => {
+                let (target, rel) =
+                    match open_project_file_entry(fs_user, &project_slug, &path).await {
+                        Ok(target) => target,
+                        Err(e) => return error_response("Failed to open file for update", e),
+                    };
+                let Entry {
+                    parent: target_dir,
+                    name: target_name,
+                } = target;
+                let target_dir = Arc::new(target_dir);
+                // The temporary file sits next to the target, behind the same directory handle,
+                // so the final rename is atomic and cannot land anywhere else.
+                let temp_name = OsString::from(format!(".tmp_update_{}", uuid::Uuid::new_v4()));
 
                 // Expecting a single file field in the multipart form for the update content
                 if let Ok(Some(mut field)) = form.next_field().await {
-                    match tokio::fs::File::create(&temp_path).await {
+                    match create_file_as(fs_user, &target_dir, &temp_name, false).await {
                         Ok(mut temp_file_to_write) => {
                             while let Some(chunk_result) = field.next().await {
                                 match chunk_result {
                                     Ok(chunk) => {
                                         if let Err(e) = temp_file_to_write.write_all(&chunk).await {
-                                            // Log error
-                                            tokio::fs::remove_file(&temp_path).await.ok(); // Clean up temp file
-                                            return Json(UsedTokenActionResponse::Error(format!(
-                                                "Error writing to temporary file: {e}"
-                                            )))
-                                            .into_response();
+                                            unlink_as(fs_user, &target_dir, &temp_name).await; // Clean up temp file
+                                            return error_response(
+                                                "Error writing to temporary file",
+                                                e,
+                                            );
                                         }
                                     }
                                     Err(e) => {
-                                        // Log error
-                                        tokio::fs::remove_file(&temp_path).await.ok(); // Clean up temp file
-                                        return Json(UsedTokenActionResponse::Error(format!(
-                                            "Error reading update data chunk: {e}"
-                                        )))
+                                        tracing::error!("Error reading update data chunk: {}", e);
+                                        unlink_as(fs_user, &target_dir, &temp_name).await; // Clean up temp file
+                                        return Json(UsedTokenActionResponse::Error(
+                                            invalid_input("Error reading update data"),
+                                        ))
                                         .into_response();
                                     }
                                 }
@@ -247,66 +229,92 @@ pub async fn server_project_action_token(
 
                             // Ensure data is flushed from OS buffers to disk before rename
                             if let Err(e) = temp_file_to_write.flush().await {
-                                tokio::fs::remove_file(&temp_path).await.ok(); // Clean up temp file
-                                return Json(UsedTokenActionResponse::Error(format!(
-                                    "Error flushing/syncing temp file: {e}"
-                                )))
-                                .into_response();
+                                unlink_as(fs_user, &target_dir, &temp_name).await; // Clean up temp file
+                                return error_response("Error flushing/syncing temp file", e);
                             } else {
                                 temp_file_to_write.sync_all().await.unwrap();
                             }
                             // Explicitly drop/close temp_file_to_write before rename on some OSes, though tokio::fs::rename should handle it.
                             drop(temp_file_to_write);
 
+                            // Checked as late as possible, so edits made over SFTP while the
+                            // upload was streaming are not overwritten either.
+                            match metadata_at(target_dir.as_fd(), &target_name) {
+                                Ok(metadata) if file_version(&metadata) == version => {}
+                                _ => {
+                                    unlink_as(fs_user, &target_dir, &temp_name).await;
+                                    let current = match open_file_as(
+                                        fs_user,
+                                        &target_dir,
+                                        &target_name,
+                                    )
+                                    .await
+                                    {
+                                        Ok(mut file) => read_file_info(&mut file, &rel).await,
+                                        Err(e) => Err(e),
+                                    };
+                                    return match current {
+                                        Ok(current) => {
+                                            Json(UsedTokenActionResponse::Conflict(current))
+                                                .into_response()
+                                        }
+                                        Err(e) => error_response("Error reading current file", e),
+                                    };
+                                }
+                            }
+
                             // Atomically replace the old file with the new one
-                            if let Err(e) = tokio::fs::rename(&temp_path, &target_path).await {
-                                tokio::fs::remove_file(&temp_path).await.ok(); // Attempt to clean up temp file
-                                Json(UsedTokenActionResponse::Error(format!(
-                                    "Error finalizing file update (rename): {e}"
-                                )))
-                                .into_response()
+                            if let Err(e) =
+                                rename_as(fs_user, &target_dir, &temp_name, &target_name).await
+                            {
+                                unlink_as(fs_user, &target_dir, &temp_name).await; // Attempt to clean up temp file
+                                error_response("Error finalizing file update (rename)", e)
                             } else {
-                                Json(UsedTokenActionResponse::Ok).into_response()
+                                match metadata_at(target_dir.as_fd(), &target_name) {
+                                    Ok(metadata) => Json(UsedTokenActionResponse::Saved(
+                                        file_version(&metadata),
+                                    ))
+                                    .into_response(),
+                                    Err(e) => error_response("Error reading saved file", e),
+                                }
                             }
                         }
-                        Err(e) => Json(UsedTokenActionResponse::Error(format!(
-                            "Error creating temporary file for update: {e}"
-                        )))
-                        .into_response(),
+                        Err(e) => error_response("Error creating temporary file for update", e),
                     }
                 } else {
                     // No file content provided, or error reading the first field
-                    Json(UsedTokenActionResponse::Error(
-                        "No content provided or error in multipart form for file update."
-                            .to_string(),
-                    ))
+                    Json(UsedTokenActionResponse::Error(invalid_input(
+                        "No content provided or error in multipart form for file update.",
+                    )))
                     .into_response()
                 }
             }
+            TokenAction::ReadFileRange { path, offset } => {
+                match read_text_range(fs_user, &project_slug, &path, offset).await {
+                    Ok(range) => Json(UsedTokenActionResponse::TextRange(range)).into_response(),
+                    Err(e) => error_response("Failed to read file range", e),
+                }
+            }
+            TokenAction::StreamFile { .. } | TokenAction::Thumbnail { .. } => Json(
+                UsedTokenActionResponse::Error(invalid_input("Stream tokens are served over GET")),
+            )
+            .into_response(),
             TokenAction::DownloadFile { path } => {
-                // Renamed to avoid conflict
-                let validated_path = ensure_path_in_project_path(&project_slug, &path, true, true)
-                    .await
-                    .unwrap();
-
-                let file = match tokio::fs::File::open(&validated_path).await {
-                    Ok(f) => f,
+                let (file, rel) = match open_project_file(fs_user, &project_slug, &path).await {
+                    Ok(opened) => opened,
                     Err(e) => {
                         // Log the error server-side for details
-                        tracing::error!(
-                            "Failed to open file for download {:?}: {}",
-                            validated_path,
-                            e
-                        );
-                        return Json(UsedTokenActionResponse::Error(
-                            "File not found or could not be opened.".to_string(),
-                        ))
+                        tracing::error!("Failed to open file for download {:?}: {}", path, e);
+                        return Json(UsedTokenActionResponse::Error(RemoteError::new(
+                            RemoteErrorKind::NotFound,
+                            "File not found or could not be opened.",
+                        )))
                         .into_response();
                     }
                 };
 
                 // Get filename for Content-Disposition
-                let filename = validated_path
+                let filename = rel
                     .file_name()
                     .and_then(|name| name.to_str())
                     .unwrap_or("downloaded_file") // Fallback filename
@@ -345,9 +353,291 @@ pub async fn server_project_action_token(
         }
     } else {
         info!("token action cache miss : {}", token);
-        Json(UsedTokenActionResponse::Error(
-            "Token not found".to_string(),
-        ))
+        Json(UsedTokenActionResponse::Error(RemoteError::new(
+            RemoteErrorKind::NotFound,
+            "Token not found",
+        )))
         .into_response()
     }
 }
+
+/// Logs the full error and answers with what the user may see of it.
+fn error_response(context: &str, error: impl Into<ServerError>) -> axum::response::Response {
+    let error = error.into();
+    tracing::error!("{}: {}", context, error);
+    Json(UsedTokenActionResponse::Error((&error).into())).into_response()
+}
+
+/// Modification time and size; cheap enough to compute on every view and save.
+fn file_version(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!(
+        "{}.{:09}-{}",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        metadata.len()
+    )
+}
+
+/// Reads up to `len` bytes at `offset`.
+async fn read_at(file: &mut File, offset: u64, len: u64) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = Vec::new();
+    (&mut *file).take(len).read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+/// Opens the regular file at the project path `path` through the project root handle, as
+/// `fs_user`. Permissions are checked at open time, so reads on the handle need no switch.
+async fn open_project_file(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+) -> ServerResult<(File, PathBuf)> {
+    let project_slug = project_slug.clone();
+    let rel = project_rel_path(path);
+    let (file, rel) = fs_user
+        .spawn(move || {
+            let file = ProjectDir::open(&project_slug)?.open_file(&rel)?;
+            Ok((file, rel))
+        })
+        .await?;
+    Ok((File::from_std(file), rel))
+}
+
+async fn open_project_file_entry(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+) -> ServerResult<(Entry, PathBuf)> {
+    let project_slug = project_slug.clone();
+    let rel = project_rel_path(path);
+    fs_user
+        .spawn(move || {
+            let entry = ProjectDir::open(&project_slug)?.file_entry(&rel)?;
+            Ok((entry, rel))
+        })
+        .await
+}
+
+async fn open_project_dir(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+) -> ServerResult<OwnedFd> {
+    let project_slug = project_slug.clone();
+    let rel = project_rel_path(path);
+    fs_user
+        .spawn(move || ProjectDir::open(&project_slug)?.open_dir(&rel))
+        .await
+}
+
+async fn open_file_as(fs_user: FsUser, dir: &Arc<OwnedFd>, name: &OsStr) -> ServerResult<File> {
+    let (dir, name) = (dir.clone(), name.to_os_string());
+    let file = fs_user
+        .spawn(move || Ok(open_file_at(dir.as_fd(), FsPath::new(&name))?))
+        .await?;
+    Ok(File::from_std(file))
+}
+
+/// The new file belongs to `fs_user`; writes go through the returned handle.
+async fn create_file_as(
+    fs_user: FsUser,
+    dir: &Arc<OwnedFd>,
+    name: &OsStr,
+    overwrite: bool,
+) -> ServerResult<File> {
+    let (dir, name) = (dir.clone(), name.to_os_string());
+    let file = fs_user
+        .spawn(move || Ok(create_file_at(dir.as_fd(), &name, overwrite)?))
+        .await?;
+    Ok(File::from_std(file))
+}
+
+async fn rename_as(
+    fs_user: FsUser,
+    dir: &Arc<OwnedFd>,
+    from: &OsStr,
+    to: &OsStr,
+) -> ServerResult<()> {
+    let (dir, from, to) = (dir.clone(), from.to_os_string(), to.to_os_string());
+    fs_user
+        .spawn(move || Ok(rename_at(dir.as_fd(), &from, dir.as_fd(), &to, false)?))
+        .await
+}
+
+/// Best-effort cleanup of a partial upload.
+async fn unlink_as(fs_user: FsUser, dir: &Arc<OwnedFd>, name: &OsStr) {
+    let (dir, name) = (dir.clone(), name.to_os_string());
+    fs_user
+        .spawn(move || Ok(unlink_at(dir.as_fd(), &name, false)?))
+        .await
+        .ok();
+}
+
+/// `rel` is where `file` was opened, relative to the project root.
+async fn read_file_info(file: &mut File, rel: &FsPath) -> ServerResult<FileInfo> {
+    let name = rel
+        .file_name()
+        .ok_or(ServerError::CantReadFileName(
+            rel.to_string_lossy().to_string(),
+        ))?
+        .to_string_lossy()
+        .to_string();
+    let metadata = file.metadata().await?;
+    let size = metadata.len();
+    let modified: DateTime<Utc> = metadata.modified()?.into();
+    let last_modified = modified.format("%a, %d %b %Y %T").to_string();
+
+    let head = read_at(file, 0, SNIFF_LEN as u64).await?;
+    let sniffed = sniff(rel, &head);
+    let (content, content_end) = match (sniffed.kind, sniffed.encoding) {
+        (FileKind::Text, Some(encoding)) => {
+            let page = read_at(file, 0, TEXT_PAGE_SIZE).await?;
+            let is_last = page.len() as u64 >= size;
+            let (content, consumed) = decode_page(&page, 0, encoding, is_last);
+            (Some(content), consumed as u64)
+        }
+        _ => (None, 0),
+    };
+    Ok(FileInfo {
+        name,
+        mime: sniffed.mime,
+        kind: sniffed.kind,
+        encoding: sniffed
+            .encoding
+            .map(|encoding| encoding.label().to_string()),
+        content,
+        content_end,
+        size,
+        path: display_path(rel),
+        last_modified,
+        version: file_version(&metadata),
+    })
+}
+
+async fn read_text_range(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+    offset: u64,
+) -> ServerResult<TextRange> {
+    let (mut file, rel) = open_project_file(fs_user, project_slug, path).await?;
+    let size = file.metadata().await?.len();
+    let head = read_at(&mut file, 0, SNIFF_LEN as u64).await?;
+    let encoding = match sniff(&rel, &head) {
+        Sniffed {
+            kind: FileKind::Text,
+            encoding: Some(encoding),
+            ..
+        } => encoding,
+        _ => return Err(ServerError::NotATextFile),
+    };
+    let offset = offset.min(size);
+    let page = read_at(&mut file, offset, TEXT_PAGE_SIZE).await?;
+    let is_last = offset + page.len() as u64 >= size;
+    let (content, consumed) = decode_page(&page, offset, encoding, is_last);
+    Ok(TextRange {
+        offset,
+        end: offset + consumed as u64,
+        size,
+        content,
+    })
+}
+
+/// Serves the file inline with its detected type, honouring single `Range` requests so
+/// audio and video can seek.
+async fn stream_file(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+    range: Option<&HeaderValue>,
+) -> ServerResult<Response<Body>> {
+    let (mut file, rel) = open_project_file(fs_user, project_slug, path).await?;
+    let size = file.metadata().await?.len();
+    let head = read_at(&mut file, 0, SNIFF_LEN as u64).await?;
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, sniff(&rel, &head).mime)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CONTENT_DISPOSITION, "inline")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        // Opened directly, an HTML or SVG file must not run scripts on the token origin.
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox");
+    let range = range.map(|range| {
+        range
+            .to_str()
+            .ok()
+            .and_then(|range| parse_range(range, size))
+    });
+    let (builder, start, len) = match range {
+        None => (builder.status(StatusCode::OK), 0, size),
+        Some(Some((start, end))) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(header::CONTENT_RANGE, format!("bytes {start}-{end}/{size}")),
+            start,
+            end - start + 1,
+        ),
+        Some(None) => {
+            return Ok(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{size}"))
+                .body(Body::empty())?);
+        }
+    };
+    file.seek(SeekFrom::Start(start)).await?;
+    let body = Body::from_stream(ReaderStream::new(file.take(len)));
+    Ok(builder.header(header::CONTENT_LENGTH, len).body(body)?)
+}
+
+async fn thumbnail(
+    fs_user: FsUser,
+    project_slug: &ProjectSlugStr,
+    path: &ProjectRelPath,
+) -> ServerResult<Response<Body>> {
+    let (file, _) = open_project_file(fs_user, project_slug, path).await?;
+    if file.metadata().await?.len() > THUMBNAIL_MAX_SOURCE_SIZE {
+        return Err(ServerError::FileTooLarge);
+    }
+    let file = file.into_std().await;
+    let png = tokio::task::spawn_blocking(move || thumbnail_png(file)).await??;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "image/png")
+        .header(header::CACHE_CONTROL, "private, max-age=3600")
+        .body(Body::from(png))?)
+}
+
+/// GET counterpart of `server_project_action_token` for stream tokens, which stay valid
+/// until they expire so the browser can come back with range requests.
+pub async fn server_project_stream_token(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let Some((project_slug, user_slug, action)) =
+        state.project_stream_token_cache.get(&token).await
+    else {
+        info!("stream token cache miss : {}", token);
+        return (StatusCode::NOT_FOUND, "Token not found").into_response();
+    };
+    let fs_user = match FsUser::lookup(&user_slug) {
+        Ok(fs_user) => fs_user,
+        Err(e) => return <(StatusCode, String)>::from(e).into_response(),
+    };
+    let response = match action {
+        TokenAction::StreamFile { path } => {
+            stream_file(fs_user, &project_slug, &path, headers.get(header::RANGE)).await
+        }
+        TokenAction::Thumbnail { path } => thumbnail(fs_user, &project_slug, &path).await,
+        _ => return (StatusCode::BAD_REQUEST, "Not a stream token").into_response(),
+    };
+    match response {
+        Ok(response) => response.into_response(),
+        Err(e) => <(StatusCode, String)>::from(e).into_response(),
+    }
+}
@@ -1,32 +1,58 @@
+pub mod config;
+pub mod fs_user;
 pub mod handle_token;
+pub mod preview;
 pub mod project_action;
+pub mod project_fs;
+pub mod project_lock;
 pub mod server_action;
+pub mod transfer;
+pub mod trash;
 
 use crate::project_action::handle_server_project_action;
+use crate::project_lock::ProjectLocks;
 use crate::server_action::handle_user_action;
 use axum::extract::FromRef;
 use axum::http::StatusCode;
-use common::helper_command::tarpc::ServerHelperClient;
-use common::hosting_command::tarpc::ServerHostingClient;
-use common::server_action::project_action::{ProjectAction, ProjectResponse};
-use common::server_action::tarpc::WebsiteToServer;
+use common::config::ConfigError;
+use common::error::{RemoteError, RemoteErrorKind};
+use common::helper_command::tarpc::{protocol_info as helper_protocol_info, ServerHelperClient};
+use common::hosting_command::tarpc::{protocol_info as hosting_protocol_info, ServerHostingClient};
+use common::protocol::ProtocolInfo;
+use common::rpc_auth::{AuthLimiter, ChannelAuth};
+use common::server_action::permission::Permission;
+use common::server_action::project_action::{
+    IsProjectServerAction, ProjectAction, ProjectResponse, ProjectStatusResponse,
+};
+use common::server_action::tarpc::{
+    protocol_info as website_to_server_protocol_info, verify_auth_proof, AuthChallenge, AuthProof,
+    WebsiteToServer,
+};
 use common::server_action::token_action::{TokenAction, TokenActionResponse};
 use common::server_action::user_action::{ServerUserAction, ServerUserResponse};
-use common::tarpc_client::{TarpcClient, TarpcClientError};
-use common::{AuthResponse, AuthToken, ProjectId, ProjectSlugStr, SanitizeError, UserId, Validate};
+use common::tarpc_client::{check_auth_response, TarpcClient, TarpcClientError};
+use common::telemetry;
+use common::{
+    AuthResponse, AuthToken, ProjectId, ProjectSlugStr, SanitizeError, UserId, UserSlugStr,
+    Validate,
+};
 use moka::future::Cache;
 use secrecy::{ExposeSecret, SecretString};
 use std::path::StripPrefixError;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 use tarpc::context::Context;
 use tarpc::tokio_serde::formats::Bincode;
 use tarpc::{client, context};
 use thiserror::Error;
-use tokio::sync::RwLock;
-use tracing::info;
+use tracing::{info, instrument};
 use uuid::Uuid;
 
+/// Git clones and fetches, kept under `SERVER_REQUEST_TIMEOUT` so they fail with a clear
+/// error before the request they run in expires.
+pub const GIT_COMMAND_TIMEOUT: Duration = Duration::from_secs(240);
+
 pub type ServerResult<T> = Result<T, ServerError>;
 
 #[derive(Debug, Error)]
@@ -35,6 +61,8 @@ pub enum ServerError {
     Io(#[from] tokio::io::Error),
     #[error("DotEnv error: {0}")]
     DotEnv(#[from] dotenvy::Error),
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
     #[error("AddrParse error: {0}")]
     AddrParse(#[from] std::net::AddrParseError),
     #[error("StripPrefix error: {0}")]
@@ -59,6 +87,8 @@ pub enum ServerError {
     PathIsNotFile,
     #[error("Path is not a directory")]
     PathIsNotDir,
+    #[error("Target is inside source")]
+    TargetInsideSource,
     #[error("Path has no parent")]
     PathHasNoParent,
     #[error("Path is not a valid project path")]
@@ -67,6 +97,30 @@ pub enum ServerError {
     CantReadFileName(String),
     #[error("Invalid Message Length")]
     InvalidMessageLength,
+    #[error("Invalid search pattern: {0}")]
+    InvalidSearchPattern(#[from] regex::Error),
+    #[error("Join error: {0}")]
+    JoinError(#[from] tokio::task::JoinError),
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Trash entry not found")]
+    TrashEntryNotFound,
+    #[error("Some entries could not be moved to the trash")]
+    TrashIncomplete,
+    #[error("Not a text file")]
+    NotATextFile,
+    #[error("File too large")]
+    FileTooLarge,
+    #[error("Image error: {0}")]
+    Image(#[from] image::ImageError),
+    #[error("Http error: {0}")]
+    Http(#[from] axum::http::Error),
+    #[error("Unknown user")]
+    UnknownUser,
+    #[error("Could not switch to the user's file identity")]
+    FsIdentitySwitchFailed,
+    #[error("The service account must not have supplementary groups: {0:?}")]
+    SupplementaryGroups(Vec<libc::gid_t>),
 
     #[error("Tarpc Client Error {0}")]
     TarpcClientError(#[from] TarpcClientError),
@@ -86,6 +140,39 @@ impl From<ServerError> for (StatusCode, String) {
     }
 }
 
+/// What the website gets to see of a `ServerError`: messages of errors caused by the request
+/// itself are kept, anything from the system underneath becomes its kind's default message.
+impl From<&ServerError> for RemoteError {
+    fn from(error: &ServerError) -> Self {
+        let kind = match error {
+            ServerError::Io(e) => return e.into(),
+            ServerError::RpcError(client::RpcError::DeadlineExceeded) => RemoteErrorKind::TimedOut,
+            ServerError::RpcError(_) | ServerError::TarpcClientError(_) => {
+                RemoteErrorKind::Unavailable
+            }
+            ServerError::Unauthorized => RemoteErrorKind::PermissionDenied,
+            ServerError::TargetNotFound
+            | ServerError::TrashEntryNotFound
+            | ServerError::UnknownUser => RemoteErrorKind::NotFound,
+            ServerError::InvalidPath
+            | ServerError::OutOfProjectsScope
+            | ServerError::PathIsNotFile
+            | ServerError::PathIsNotDir
+            | ServerError::TargetInsideSource
+            | ServerError::PathHasNoParent
+            | ServerError::PathNotAValidProjectPath
+            | ServerError::InvalidMessageLength
+            | ServerError::InvalidSearchPattern(_)
+            | ServerError::NotATextFile
+            | ServerError::FileTooLarge => RemoteErrorKind::InvalidInput,
+            ServerError::SanitizeError(e) => return e.clone().into(),
+            ServerError::TrashIncomplete => RemoteErrorKind::Conflict,
+            _ => return RemoteErrorKind::Internal.into(),
+        };
+        RemoteError::new(kind, error.to_string())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ServerUserId(pub String);
 
@@ -108,16 +195,18 @@ pub type TarpcHelperClient = Arc<TarpcClient<ServerHelperClient>>;
 pub type TarpcHostingClient = Arc<TarpcClient<ServerHostingClient>>;
 
 pub type FileUploads = Arc<Cache<String, FileUpload>>;
-pub type ProjectTokenActionCache = Arc<Cache<String, (ProjectSlugStr, TokenAction)>>;
+pub type ProjectTokenActionCache = Arc<Cache<String, (ProjectSlugStr, UserSlugStr, TokenAction)>>;
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub token_auth: SecretString,
     pub project_token_action_cache: ProjectTokenActionCache,
+    pub project_stream_token_cache: ProjectTokenActionCache,
     pub helper_client: TarpcHelperClient,
     pub hosting_client: TarpcHostingClient,
     pub file_uploads: FileUploads,
-    pub connected: Arc<RwLock<bool>>,
+    pub auth_limiter: Arc<AuthLimiter>,
+    pub project_locks: Arc<ProjectLocks>,
 }
 
 #[derive(Clone, Debug)]
@@ -127,119 +216,213 @@ pub struct FileUpload {
     pub project_slug: ProjectSlugStr,
 }
 
+/// Created for each channel, so `auth()` only unlocks the channel it is called on.
 #[derive(Clone)]
-pub struct WebsiteToServerServer(pub AppState);
+pub struct WebsiteToServerServer(pub AppState, pub Arc<ChannelAuth>);
 impl WebsiteToServer for WebsiteToServerServer {
+    #[instrument(
+        skip_all,
+        fields(request_id = telemetry::request_id(), project = %project_slug_str.0)
+    )]
     async fn token_action(
         self,
         _: Context,
         project_slug_str: ProjectSlugStr,
+        user_slug: UserSlugStr,
         action: TokenAction,
     ) -> TokenActionResponse {
-        if !*self.0.connected.read().await {
-            return TokenActionResponse::Error("Not connected".to_string());
+        if !self.1.is_authenticated() {
+            return TokenActionResponse::Error(RemoteErrorKind::Unavailable.into());
         }
-        if let Err(e) = project_slug_str.validate() {
-            return TokenActionResponse::Error(format!("Invalid project slug: {e}"));
+        if project_slug_str.validate().is_err() {
+            return TokenActionResponse::Error(invalid_input("Invalid project slug"));
+        };
+        if user_slug.validate().is_err() {
+            return TokenActionResponse::Error(invalid_input("Invalid user slug"));
+        };
+        if action.validate().is_err() {
+            return TokenActionResponse::Error(invalid_input("Invalid action"));
         };
         let token = Uuid::new_v4().to_string();
         info!(
             "Token action: {:?} for project: {:?}",
             action, project_slug_str
         );
-        self.0
-            .project_token_action_cache
-            .insert(token.clone(), (project_slug_str.clone(), action))
+        let cache = if action.is_stream() {
+            &self.0.project_stream_token_cache
+        } else {
+            &self.0.project_token_action_cache
+        };
+        cache
+            .insert(token.clone(), (project_slug_str.clone(), user_slug, action))
             .await;
         TokenActionResponse::Ok(token)
     }
 
+    #[instrument(skip_all, fields(request_id = telemetry::request_id()))]
     async fn user_action(self, _: Context, action: ServerUserAction) -> ServerUserResponse {
-        if !*self.0.connected.read().await {
-            return ServerUserResponse::Error("Not connected".to_string());
+        if !self.1.is_authenticated() {
+            return ServerUserResponse::Error(RemoteErrorKind::Unavailable.into());
         }
-        if let Err(e) = action.validate() {
-            return ServerUserResponse::Error(format!("Invalid action: {e}"));
+        if action.validate().is_err() {
+            return ServerUserResponse::Error(invalid_input("Invalid action"));
+        };
+        let _turn = match &action {
+            ServerUserAction::AddProject {
+                user_slug,
+                project_slug,
+                ..
+            } => Some(
+                self.0
+                    .project_locks
+                    .lock(
+                        project_slug,
+                        "user.add_project".to_string(),
+                        Some(user_slug.clone()),
+                    )
+                    .await,
+            ),
+            ServerUserAction::RemoveProject { project_slug, .. } => Some(
+                self.0
+                    .project_locks
+                    .lock(project_slug, "user.remove_project".to_string(), None)
+                    .await,
+            ),
+            ServerUserAction::SetProjectQuota { project_slug, .. } => Some(
+                self.0
+                    .project_locks
+                    .lock(project_slug, "user.set_project_quota".to_string(), None)
+                    .await,
+            ),
+            ServerUserAction::Create { .. } | ServerUserAction::Delete { .. } => None,
         };
         handle_user_action(self.0.helper_client.clone(), action)
             .await
             .unwrap_or_else(|e| {
                 tracing::error!("Error in user action: {}", e);
-                ServerUserResponse::Error(e.to_string())
+                ServerUserResponse::Error((&e).into())
             })
     }
 
+    #[instrument(
+        skip_all,
+        fields(request_id = telemetry::request_id(), project = %project_slug.0)
+    )]
     async fn project_action(
         self,
         _: Context,
         project_slug: ProjectSlugStr,
+        user_slug: Option<UserSlugStr>,
         action: ProjectAction,
     ) -> ProjectResponse {
-        if !*self.0.connected.read().await {
-            return ProjectResponse::Error("Not connected".to_string());
+        if !self.1.is_authenticated() {
+            return ProjectResponse::Error(RemoteErrorKind::Unavailable.into());
         }
-        if let Err(e) = action.validate() {
-            return ProjectResponse::Error(format!("Invalid action: {e}"));
+        if action.validate().is_err() {
+            return ProjectResponse::Error(invalid_input("Invalid action"));
         };
-        if let Err(e) = project_slug.validate() {
-            return ProjectResponse::Error(format!("Invalid project slug: {e}"));
+        if project_slug.validate().is_err() {
+            return ProjectResponse::Error(invalid_input("Invalid project slug"));
+        };
+        if let Some(Err(_)) = user_slug.as_ref().map(|user_slug| user_slug.validate()) {
+            return ProjectResponse::Error(invalid_input("Invalid user slug"));
         };
 
+        // Changes wait for the ones before them on the project; reads go straight through.
+        let _turn = match action.permission() {
+            Permission::Read => None,
+            Permission::Write | Permission::Owner => Some(
+                self.0
+                    .project_locks
+                    .lock(&project_slug, action.name(), user_slug.clone())
+                    .await,
+            ),
+        };
         handle_server_project_action(
             self.0.hosting_client.clone(),
             self.0.helper_client.clone(),
             project_slug,
+            user_slug,
             action,
         )
         .await
         .unwrap_or_else(|e| {
             tracing::error!("Error in project action: {}", e);
-            ProjectResponse::Error(e.to_string())
+            ProjectResponse::Error((&e).into())
         })
     }
 
-    async fn auth(self, _: Context, token: AuthToken) -> AuthResponse {
-        let mut connected = self.0.connected.write().await;
-        if self.0.token_auth.expose_secret().eq(&token.0) {
-            info!("Token auth success");
-            *connected = true;
-            AuthResponse::Ok
-        } else {
-            *connected = false;
-            info!("Token auth failed");
-            AuthResponse::Error
+    async fn project_status(
+        self,
+        _: Context,
+        project_slug: ProjectSlugStr,
+    ) -> ProjectStatusResponse {
+        if !self.1.is_authenticated() {
+            return ProjectStatusResponse::Error(RemoteErrorKind::Unavailable.into());
         }
+        if project_slug.validate().is_err() {
+            return ProjectStatusResponse::Error(invalid_input("Invalid project slug"));
+        };
+        ProjectStatusResponse::Ok(self.0.project_locks.status(&project_slug))
+    }
+
+    async fn ping(self, _: Context) {}
+
+    async fn auth_challenge(self, _: Context) -> AuthChallenge {
+        AuthChallenge(self.1.new_challenge())
+    }
+
+    async fn auth(self, _: Context, proof: AuthProof, client: ProtocolInfo) -> AuthResponse {
+        let challenge = self.1.take_challenge().map(AuthChallenge);
+        self.0.auth_limiter.auth_response(
+            &self.1,
+            &client,
+            website_to_server_protocol_info(env!("CARGO_PKG_VERSION")),
+            || {
+                challenge.is_some_and(|challenge| {
+                    verify_auth_proof(self.0.token_auth.expose_secret(), &challenge, &proof)
+                })
+            },
+        )
     }
 }
 
+pub fn invalid_input(message: &str) -> RemoteError {
+    RemoteError::new(RemoteErrorKind::InvalidInput, message)
+}
+
 pub async fn connect_server_hosting_client(
     addr: String,
     token: String,
-) -> Result<ServerHostingClient, TarpcClientError> {
+    max_frame_length: usize,
+) -> Result<(ServerHostingClient, ProtocolInfo), TarpcClientError> {
     let mut transport = tarpc::serde_transport::unix::connect(addr, Bincode::default);
-    transport.config_mut().max_frame_length(10 * 10 * 1024);
+    transport.config_mut().max_frame_length(max_frame_length);
     let client = ServerHostingClient::new(client::Config::default(), transport.await?).spawn();
-    match client
-        .auth(context::current(), AuthToken::from_str(&token).unwrap())
-        .await
-    {
-        Ok(AuthResponse::Ok) => Ok(client),
-        _ => Err(TarpcClientError::ConnectionError("Auth failed".to_string())),
-    }
+    let response = client
+        .auth(
+            context::current(),
+            AuthToken::from_str(&token).unwrap(),
+            hosting_protocol_info(env!("CARGO_PKG_VERSION")),
+        )
+        .await;
+    Ok((client, check_auth_response(response)?))
 }
 
 pub async fn connect_server_helper_client(
     addr: String,
     token: String,
-) -> Result<ServerHelperClient, TarpcClientError> {
+    max_frame_length: usize,
+) -> Result<(ServerHelperClient, ProtocolInfo), TarpcClientError> {
     let mut transport = tarpc::serde_transport::unix::connect(addr, Bincode::default);
-    transport.config_mut().max_frame_length(10 * 10 * 1024);
+    transport.config_mut().max_frame_length(max_frame_length);
     let client = ServerHelperClient::new(client::Config::default(), transport.await?).spawn();
-    match client
-        .auth(context::current(), AuthToken::from_str(&token).unwrap())
-        .await
-    {
-        Ok(AuthResponse::Ok) => Ok(client),
-        _ => Err(TarpcClientError::ConnectionError("Auth failed".to_string())),
-    }
+    let response = client
+        .auth(
+            context::current(),
+            AuthToken::from_str(&token).unwrap(),
+            helper_protocol_info(env!("CARGO_PKG_VERSION")),
+        )
+        .await;
+    Ok((client, check_auth_response(response)?))
 }
@@ -1,70 +1,99 @@
 use axum::extract::DefaultBodyLimit;
 use axum::routing::post;
 use axum::Router;
-use common::helper_command::tarpc::HELPER_SOCKET_PATH;
-use common::hosting_command::tarpc::HOSTING_SOCKET_PATH;
+use common::config;
+use common::helper_command::{HelperCommand, HelperResponse};
+use common::paths::set_paths;
+use common::rpc_auth::{AuthLimiter, ChannelAuth};
 use common::server_action::tarpc::WebsiteToServer;
-use common::tarpc_client::TarpcClient;
-use common::{SERVER_PORT, SERVER_TOKEN_PORT};
-use futures::{future, StreamExt};
-use hivehost_server::handle_token::server_project_action_token;
+use common::tarpc_client::{ConnectionState, TarpcClient};
+use common::telemetry::{self, OTLP_ENDPOINT_VAR};
+use common::tls;
+use futures::StreamExt;
+use hivehost_server::config::Config;
+use hivehost_server::fs_user::check_no_supplementary_groups;
+use hivehost_server::handle_token::{server_project_action_token, server_project_stream_token};
+use hivehost_server::project_lock::ProjectLocks;
+use hivehost_server::trash::purge_all_trash;
 use hivehost_server::{
     connect_server_helper_client, connect_server_hosting_client, AppState, ServerResult,
     WebsiteToServerServer,
 };
 use moka::future::Cache;
 use secrecy::{ExposeSecret, SecretString};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tarpc::server;
 use tarpc::server::Channel;
-use tarpc::tokio_serde::formats::Bincode;
 use tower_http::cors::CorsLayer;
 use tracing::{error, info};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
 
 #[tokio::main]
 async fn main() -> ServerResult<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let config: Config = config::load()?;
+    set_paths(config.paths.clone());
+    let max_frame_length = config.rpc.max_frame_length;
+    let _telemetry = telemetry::init(
+        env!("CARGO_CRATE_NAME"),
+        dotenvy::var(OTLP_ENDPOINT_VAR).ok(),
+    );
+    check_no_supplementary_groups()?;
     let token_action_auth = SecretString::from(dotenvy::var("TOKEN_AUTH")?);
     let server_action_auth = SecretString::from(dotenvy::var("SERVER_AUTH")?);
 
     let server_helper_client = Arc::new(TarpcClient::new(
-        HELPER_SOCKET_PATH.to_string(),
+        config.rpc.helper_socket.clone(),
         server_action_auth.expose_secret().to_string(),
-        connect_server_helper_client,
+        move |addr, token| connect_server_helper_client(addr, token, max_frame_length),
     ));
     let server_helper_client_to_connect = server_helper_client.clone();
     tokio::spawn(async move {
-        if let Err(e) = server_helper_client_to_connect.connect().await {
-            error!("Initial WebsiteServerClient connection failed: {:?}", e);
+        let mut helper_state = server_helper_client_to_connect.state();
+        if helper_state
+            .wait_for(|state| *state == ConnectionState::Connected)
+            .await
+            .is_err()
+        {
+            return;
+        }
+        // Project qgroups and limits need quotas on; enabling them again is a no-op.
+        match server_helper_client_to_connect
+            .execute(vec![HelperCommand::EnableQuota])
+            .await
+        {
+            Ok(HelperResponse::Ok) => {}
+            r => error!("Enabling Btrfs quotas failed: {:?}", r),
         }
     });
 
     let server_hosting_client = Arc::new(TarpcClient::new(
-        HOSTING_SOCKET_PATH.to_string(),
+        config.rpc.hosting_socket.clone(),
         server_action_auth.expose_secret().to_string(),
-        connect_server_hosting_client,
+        move |addr, token| connect_server_hosting_client(addr, token, max_frame_length),
     ));
-    let server_hosting_client_to_connect = server_hosting_client.clone();
-    tokio::spawn(async move {
-        if let Err(e) = server_hosting_client_to_connect.connect().await {
-            error!("Initial WebsiteServerClient connection failed: {:?}", e);
+
+    let project_locks = Arc::new(ProjectLocks::default());
+    tokio::spawn({
+        let project_locks = project_locks.clone();
+        async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                purge_all_trash(&project_locks).await;
+            }
         }
     });
 
     let app_state = AppState {
         project_token_action_cache: Arc::new(
             Cache::builder()
-                .time_to_live(Duration::from_secs(15))
+                .time_to_live(config.server.token_action_ttl())
+                .build(),
+        ),
+        project_stream_token_cache: Arc::new(
+            Cache::builder()
+                .time_to_live(config.server.stream_token_ttl())
                 .build(),
         ),
         token_auth: token_action_auth,
@@ -72,26 +101,30 @@ async fn main() -> ServerResult<()> {
         hosting_client: server_hosting_client,
         file_uploads: Arc::new(
             Cache::builder()
-                .time_to_live(Duration::from_secs(3600))
+                .time_to_live(config.server.file_upload_ttl())
                 .build(),
         ),
-        connected: Arc::new(tokio::sync::RwLock::new(false)),
+        auth_limiter: Arc::new(AuthLimiter::default()),
+        project_locks,
     };
 
-    let listener_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), SERVER_PORT);
-    let mut website_server_listener =
-        tarpc::serde_transport::tcp::listen(&listener_addr, Bincode::default).await?;
-    website_server_listener
-        .config_mut()
-        .max_frame_length(10 * 10 * 1024);
+    // Only the website, with a client certificate from its CA, gets past the handshake.
+    let tls_config = tls::server_config(
+        &dotenvy::var("SERVER_TLS_CERT")?,
+        &dotenvy::var("SERVER_TLS_KEY")?,
+        &dotenvy::var("WEBSITE_CA_CERT")?,
+    )?;
+    let listener_addr = SocketAddr::new(config.server.bind_address, config.rpc.server_port);
+    let website_server_listener = tokio::net::TcpListener::bind(&listener_addr).await?;
 
     let listener_state = app_state.clone();
     tokio::spawn(async move {
-        website_server_listener
-            .filter_map(|r| future::ready(r.ok()))
-            .map(server::BaseChannel::with_defaults)
-            .map(|channel| {
-                let server = WebsiteToServerServer(listener_state.clone());
+        tls::incoming(website_server_listener, tls_config, max_frame_length)
+            .map(|(addr, transport)| {
+                let channel = server::BaseChannel::with_defaults(transport);
+                // Failed auth attempts are counted by address.
+                let channel_auth = ChannelAuth::new(addr.ip().to_string());
+                let server = WebsiteToServerServer(listener_state.clone(), channel_auth);
                 channel
                     .execute(server.serve())
                     .for_each(|response| async move {
@@ -105,13 +138,15 @@ async fn main() -> ServerResult<()> {
     info!("Listener on {}", listener_addr);
 
     let token_app = Router::new()
-        .route("/token/{token}", post(server_project_action_token))
-        .layer(DefaultBodyLimit::max(65536000))
+        .route(
+            "/token/{token}",
+            post(server_project_action_token).get(server_project_stream_token),
+        )
+        .layer(DefaultBodyLimit::max(config.server.upload_body_limit_bytes))
         .layer(CorsLayer::permissive())
         .with_state(app_state);
 
-    let tcp_addr_token =
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), SERVER_TOKEN_PORT);
+    let tcp_addr_token = SocketAddr::new(config.server.bind_address, config.rpc.server_token_port);
     let listener_token = tokio::net::TcpListener::bind(&tcp_addr_token).await?;
     info!("Token Listener on {}", tcp_addr_token);
     axum::serve(listener_token, token_app).await?;
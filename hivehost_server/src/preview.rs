@@ -0,0 +1,257 @@
+use crate::{ServerError, ServerResult};
+use common::server_action::token_action::{FileKind, THUMBNAIL_SIZE};
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+/// Bytes read from the start of a file to detect its type and encoding.
+pub const SNIFF_LEN: usize = 8 * 1024;
+/// Larger images are not decoded for a thumbnail.
+pub const THUMBNAIL_MAX_SOURCE_SIZE: u64 = 32 * 1024 * 1024;
+/// A small file can declare huge dimensions; these bound what decoding it may take.
+const THUMBNAIL_MAX_DIMENSION: u32 = 8192;
+const THUMBNAIL_MAX_ALLOC: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl TextEncoding {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf-8",
+            TextEncoding::Utf16Le => "utf-16le",
+            TextEncoding::Utf16Be => "utf-16be",
+            TextEncoding::Latin1 => "iso-8859-1",
+        }
+    }
+
+    /// Byte order mark at the start of `head`, and its length.
+    fn from_bom(head: &[u8]) -> Option<(TextEncoding, usize)> {
+        match head {
+            [0xEF, 0xBB, 0xBF, ..] => Some((TextEncoding::Utf8, 3)),
+            [0xFF, 0xFE, ..] => Some((TextEncoding::Utf16Le, 2)),
+            [0xFE, 0xFF, ..] => Some((TextEncoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sniffed {
+    pub mime: String,
+    pub kind: FileKind,
+    pub encoding: Option<TextEncoding>,
+}
+
+fn sniff_magic(head: &[u8]) -> Option<(&'static str, FileKind)> {
+    let found = match head {
+        [0x89, b'P', b'N', b'G', ..] => ("image/png", FileKind::Image),
+        [0xFF, 0xD8, 0xFF, ..] => ("image/jpeg", FileKind::Image),
+        [b'G', b'I', b'F', b'8', ..] => ("image/gif", FileKind::Image),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            ("image/webp", FileKind::Image)
+        }
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => {
+            ("audio/wav", FileKind::Audio)
+        }
+        [b'B', b'M', ..] => ("image/bmp", FileKind::Image),
+        [0x00, 0x00, 0x01, 0x00, ..] => ("image/x-icon", FileKind::Image),
+        [b'%', b'P', b'D', b'F', ..] => ("application/pdf", FileKind::Pdf),
+        [b'O', b'g', b'g', b'S', ..] => ("audio/ogg", FileKind::Audio),
+        [b'f', b'L', b'a', b'C', ..] => ("audio/flac", FileKind::Audio),
+        [b'I', b'D', b'3', ..] => ("audio/mpeg", FileKind::Audio),
+        [0xFF, 0xFB | 0xF3 | 0xF2 | 0xFA, ..] => ("audio/mpeg", FileKind::Audio),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => ("video/webm", FileKind::Video),
+        [_, _, _, _, b'f', b't', b'y', b'p', b'M', b'4', b'A', ..] => {
+            ("audio/mp4", FileKind::Audio)
+        }
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => ("video/mp4", FileKind::Video),
+        _ => return None,
+    };
+    Some(found)
+}
+
+fn mime_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let mime = match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "txt" => "text/plain",
+        "wasm" => "application/wasm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Detects what `head`, the first `SNIFF_LEN` bytes of `path`, is: magic numbers first,
+/// then text (byte order mark, valid UTF-8, or Latin-1 without control characters).
+pub fn sniff(path: &Path, head: &[u8]) -> Sniffed {
+    if let Some((mime, kind)) = sniff_magic(head) {
+        return Sniffed {
+            mime: mime.to_string(),
+            kind,
+            encoding: None,
+        };
+    }
+    let extension_mime = mime_from_extension(path);
+    let encoding = TextEncoding::from_bom(head)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| detect_bomless_encoding(head));
+    match encoding {
+        Some(encoding) => Sniffed {
+            mime: extension_mime.unwrap_or("text/plain").to_string(),
+            kind: if extension_mime == Some("image/svg+xml") {
+                FileKind::Image
+            } else {
+                FileKind::Text
+            },
+            encoding: Some(encoding),
+        },
+        None => Sniffed {
+            mime: extension_mime
+                .unwrap_or("application/octet-stream")
+                .to_string(),
+            kind: FileKind::Binary,
+            encoding: None,
+        },
+    }
+}
+
+fn detect_bomless_encoding(head: &[u8]) -> Option<TextEncoding> {
+    if head.contains(&0) {
+        return None;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => return Some(TextEncoding::Utf8),
+        // Only cut in the middle of a character by the sniff length.
+        Err(e) if e.error_len().is_none() => return Some(TextEncoding::Utf8),
+        Err(_) => {}
+    }
+    let is_latin1_text = head
+        .iter()
+        .all(|&b| b >= 0x20 || matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B));
+    is_latin1_text.then_some(TextEncoding::Latin1)
+}
+
+/// Decodes `bytes`, read at `offset` in the file. Unless `is_last`, a character cut by the end
+/// of the buffer is left out; the returned length is how many bytes were consumed.
+pub fn decode_page(
+    bytes: &[u8],
+    offset: u64,
+    encoding: TextEncoding,
+    is_last: bool,
+) -> (String, usize) {
+    let bom_len = match (offset, TextEncoding::from_bom(bytes)) {
+        (0, Some((bom_encoding, len))) if bom_encoding == encoding => len,
+        _ => 0,
+    };
+    let body = &bytes[bom_len..];
+    let (content, consumed) = match encoding {
+        TextEncoding::Utf8 => {
+            let cut = if is_last { body.len() } else { utf8_cut(body) };
+            (String::from_utf8_lossy(&body[..cut]).into_owned(), cut)
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let mut cut = body.len() - body.len() % 2;
+            let units = body[..cut].chunks_exact(2).map(|pair| match encoding {
+                TextEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                _ => u16::from_be_bytes([pair[0], pair[1]]),
+            });
+            let mut units = units.collect::<Vec<_>>();
+            // A high surrogate whose pair is in the next page.
+            if !is_last
+                && units
+                    .last()
+                    .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+            {
+                units.pop();
+                cut -= 2;
+            }
+            (String::from_utf16_lossy(&units), cut)
+        }
+        TextEncoding::Latin1 => (body.iter().map(|&b| b as char).collect(), body.len()),
+    };
+    (content, bom_len + consumed)
+}
+
+/// Length of `bytes` without a trailing, incomplete UTF-8 sequence.
+fn utf8_cut(bytes: &[u8]) -> usize {
+    let tail_start = bytes.len().saturating_sub(4);
+    let Some(start) = bytes[tail_start..]
+        .iter()
+        .rposition(|b| b & 0xC0 != 0x80)
+        .map(|position| tail_start + position)
+    else {
+        return bytes.len();
+    };
+    let needed = match bytes[start] {
+        b if b >= 0xF0 => 4,
+        b if b >= 0xE0 => 3,
+        b if b >= 0xC0 => 2,
+        _ => 1,
+    };
+    if bytes.len() - start < needed {
+        start
+    } else {
+        bytes.len()
+    }
+}
+
+/// PNG no larger than `THUMBNAIL_SIZE` on either side.
+pub fn thumbnail_png(file: File) -> ServerResult<Vec<u8>> {
+    let mut reader = image::ImageReader::new(BufReader::new(file)).with_guessed_format()?;
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(THUMBNAIL_MAX_DIMENSION);
+    limits.max_image_height = Some(THUMBNAIL_MAX_DIMENSION);
+    limits.max_alloc = Some(THUMBNAIL_MAX_ALLOC);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| match e {
+        image::ImageError::Limits(_) => ServerError::FileTooLarge,
+        e => e.into(),
+    })?;
+    let mut png = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .write_to(&mut png, image::ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Inclusive byte range of a single-range `Range: bytes=...` header, `None` when it is
+/// malformed or cannot be satisfied.
+pub fn parse_range(header: &str, size: u64) -> Option<(u64, u64)> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let last = size.checked_sub(1)?;
+    let (start, end) = if start.is_empty() {
+        let suffix = end.parse::<u64>().ok().filter(|suffix| *suffix > 0)?;
+        (size.saturating_sub(suffix), last)
+    } else {
+        let start = start.parse::<u64>().ok()?;
+        let end = if end.is_empty() {
+            last
+        } else {
+            end.parse::<u64>().ok()?.min(last)
+        };
+        (start, end)
+    };
+    (start <= end).then_some((start, end))
+}
@@ -1,32 +1,67 @@
+use crate::fs_user::{as_service, FsUser};
+use crate::project_fs::{
+    check_removable_tree_at, check_writable_at, create_file_at, display_path, mkdir_at,
+    open_dir_at, open_file_at, project_rel_path, read_dir_at, rename_at, Entry, ProjectDir,
+};
 use crate::server_action::{
     add_user_to_project, remove_user_from_project_commands, update_user_in_project,
 };
-use crate::{ServerError, ServerResult, TarpcHelperClient, TarpcHostingClient};
+use crate::transfer::{copy_file_at, move_file_at, transfer_dir, TransferMode};
+use crate::trash::{delete_from_trash, empty_trash, list_trash, move_to_trash, restore_from_trash};
+use crate::{
+    ServerError, ServerResult, TarpcHelperClient, TarpcHostingClient, GIT_COMMAND_TIMEOUT,
+};
 
-use common::command::run_external_command;
+use common::command::{run_external_command, run_external_command_with, CommandLimits};
 use common::helper_command::{HelperCommand, HelperResponse};
 use common::hosting_command::HostingCommand;
 use common::server_action::project_action::git_action::ProjectGitAction;
+use common::server_action::project_action::io_action::batch_action::{
+    ProjectIoBatchAction, ProjectIoBatchItem,
+};
 use common::server_action::project_action::io_action::dir_action::{
     LsElement, ProjectIoDirAction, ServerProjectIoDirActionLsResponse,
 };
 use common::server_action::project_action::io_action::file_action::ProjectIoFileAction;
-use common::server_action::project_action::io_action::ProjectIoAction;
+use common::server_action::project_action::io_action::search_action::{
+    ProjectIoSearchAction, SearchCursor, SearchLineMatch, SearchMatch,
+    ServerProjectIoSearchResponse, SEARCH_MAX_FILE_SIZE, SEARCH_MAX_LINE_LEN,
+    SEARCH_MAX_SCANNED_BYTES, SEARCH_MAX_SCANNED_ENTRIES, SEARCH_PAGE_SIZE,
+};
+use common::server_action::project_action::io_action::trash_action::{
+    ProjectIoTrashAction, ServerProjectIoTrashResponse,
+};
+use common::server_action::project_action::io_action::{ConflictStrategy, ProjectIoAction};
 use common::server_action::project_action::permission::ProjectPermissionAction;
+use common::server_action::project_action::quota::ProjectQuotaAction;
 use common::server_action::project_action::snapshot::ProjectSnapshotAction;
 use common::server_action::project_action::{ProjectAction, ProjectResponse};
-use common::{ensure_path_in_project_path, get_project_dev_path, ProjectSlugStr};
+use common::{
+    get_project_dev_path, ProjectRelPath, ProjectSlugStr, UserSlugStr, PROJECT_PATH_ROOT,
+};
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::{Path, PathBuf};
 use tracing::info;
 
 pub async fn handle_server_project_action(
     hosting_client: TarpcHostingClient,
     helper_client: TarpcHelperClient,
     project_slug: ProjectSlugStr,
+    user_slug: Option<UserSlugStr>,
     action: ProjectAction,
 ) -> ServerResult<ProjectResponse> {
-    info!("Server Project action: {:?}", action);
+    info!("Server Project action: {:?} by {:?}", action, user_slug);
     match action {
-        ProjectAction::Io(io) => handle_server_project_action_io(project_slug, io).await,
+        ProjectAction::Io(io) => {
+            // File access always happens as a user, so their permissions apply.
+            let user_slug = user_slug.ok_or(ServerError::Unauthorized)?;
+            let fs_user = FsUser::lookup(&user_slug)?;
+            handle_server_project_action_io(project_slug, fs_user, io).await
+        }
         ProjectAction::Permission(permission) => {
             handle_server_project_action_permission(helper_client, project_slug, permission).await
         }
@@ -40,9 +75,30 @@ pub async fn handle_server_project_action(
             .await
         }
         ProjectAction::Git(git) => handle_server_project_action_git(project_slug, git).await,
+        ProjectAction::Quota(quota) => {
+            handle_server_project_action_quota(helper_client, project_slug, quota).await
+        }
     }
 }
 
+pub async fn handle_server_project_action_quota(
+    helper_client: TarpcHelperClient,
+    project_slug: ProjectSlugStr,
+    action: ProjectQuotaAction,
+) -> ServerResult<ProjectResponse> {
+    Ok(match action {
+        ProjectQuotaAction::Usage => {
+            match helper_client
+                .execute(vec![HelperCommand::ProjectUsage { project_slug }])
+                .await?
+            {
+                HelperResponse::ProjectUsage(usage) => ProjectResponse::Usage(usage),
+                helper_response => ProjectResponse::HelperResponses(helper_response),
+            }
+        }
+    })
+}
+
 pub async fn handle_server_project_action_git(
     project_slug: ProjectSlugStr,
     action: ProjectGitAction,
@@ -57,7 +113,12 @@ pub async fn handle_server_project_action_git(
             let dev_path = get_project_dev_path(&project_slug);
             let token = format!("oauth2:{}@", token.0);
             let url = format!("https://{token}github.com/{}.git", repo_full_name.0);
-            run_external_command("git", &["-C", &dev_path, "fetch", &url, &branch.0]).await?;
+            run_external_command_with(
+                "git",
+                &["-C", &dev_path, "fetch", &url, &branch.0],
+                CommandLimits::with_timeout(GIT_COMMAND_TIMEOUT),
+            )
+            .await?;
             run_external_command("git", &["-C", &dev_path, "reset", "--hard", &commit.0]).await?;
             ProjectResponse::Ok
         }
@@ -126,11 +187,15 @@ pub async fn handle_server_project_action_snapshot(
                 ProjectResponse::HelperResponses(helper_response)
             }
         }
-        ProjectSnapshotAction::Restore { snapshot_name } => {
+        ProjectSnapshotAction::Restore {
+            snapshot_name,
+            backup_snapshot_name,
+        } => {
             let helper_response = helper_client
                 .execute(vec![HelperCommand::RestoreSnapshot {
                     project_slug,
                     snapshot_name,
+                    backup_snapshot_name,
                 }])
                 .await?;
             ProjectResponse::HelperResponses(helper_response)
@@ -162,109 +227,533 @@ pub async fn handle_server_project_action_permission(
 }
 pub async fn handle_server_project_action_io(
     project_slug: ProjectSlugStr,
+    fs_user: FsUser,
     action: ProjectIoAction,
 ) -> ServerResult<ProjectResponse> {
     match action {
-        ProjectIoAction::Dir(dir) => handle_server_project_action_dir(project_slug, dir).await,
-        ProjectIoAction::File(file) => handle_server_project_action_file(project_slug, file).await,
+        ProjectIoAction::Dir(dir) => {
+            handle_server_project_action_dir(project_slug, fs_user, dir).await
+        }
+        ProjectIoAction::File(file) => {
+            handle_server_project_action_file(project_slug, fs_user, file).await
+        }
+        ProjectIoAction::Search(search) => {
+            handle_server_project_action_search(project_slug, fs_user, search).await
+        }
+        ProjectIoAction::Batch(batch) => {
+            handle_server_project_action_batch(project_slug, fs_user, batch).await
+        }
+        ProjectIoAction::Trash(trash) => {
+            handle_server_project_action_trash(project_slug, fs_user, trash).await
+        }
     }
 }
 
-pub async fn handle_server_project_action_dir(
+/// Only restoring writes to the project; the trash itself belongs to the service.
+pub async fn handle_server_project_action_trash(
     project_slug: ProjectSlugStr,
-    action: ProjectIoDirAction,
+    fs_user: FsUser,
+    action: ProjectIoTrashAction,
 ) -> ServerResult<ProjectResponse> {
     match action {
-        ProjectIoDirAction::Create { path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, false, false).await?;
-            tokio::fs::create_dir_all(path)
+        ProjectIoTrashAction::Restore { id } => {
+            fs_user
+                .spawn(move || {
+                    let project = ProjectDir::open(&project_slug)?;
+                    let report = restore_from_trash(&project_slug, &project, &id, fs_user)?;
+                    Ok(ProjectResponse::IoReport(report))
+                })
                 .await
-                .map_err(ServerError::from)?;
         }
-        ProjectIoDirAction::Rename { path, new_name } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, false, true).await?;
-            let new_name =
-                ensure_path_in_project_path(&project_slug, &new_name, false, false).await?;
-            tokio::fs::rename(path, new_name)
-                .await
-                .map_err(ServerError::from)?;
+        ProjectIoTrashAction::List => {
+            let inner = tokio::task::spawn_blocking(move || list_trash(&project_slug)).await??;
+            Ok(ProjectResponse::Trash(ServerProjectIoTrashResponse {
+                inner,
+            }))
         }
-        ProjectIoDirAction::Delete { path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, false, true).await?;
-            tokio::fs::remove_dir_all(path)
-                .await
-                .map_err(ServerError::from)?;
+        ProjectIoTrashAction::Delete { id } => {
+            tokio::task::spawn_blocking(move || delete_from_trash(&project_slug, &id)).await??;
+            Ok(ProjectResponse::Ok)
         }
-        ProjectIoDirAction::Ls { path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, false, true).await?;
-            let mut entries = tokio::fs::read_dir(path).await.map_err(ServerError::from)?;
-            let mut result = Vec::new();
-            while let Ok(Some(entry)) = entries.next_entry().await {
-                let meta = entry.metadata().await?;
-                result.push(LsElement {
-                    name: entry.file_name().to_string_lossy().to_string(),
-                    is_dir: meta.is_dir(),
-                })
-            }
-            return Ok(ProjectResponse::Ls(ServerProjectIoDirActionLsResponse {
-                inner: result,
-            }));
+        ProjectIoTrashAction::Empty => {
+            tokio::task::spawn_blocking(move || empty_trash(&project_slug)).await??;
+            Ok(ProjectResponse::Ok)
         }
     }
-    Ok(ProjectResponse::Ok)
 }
+
+/// Moves a project entry to the trash for the user the thread acts as. The user must be
+/// allowed to remove it, and for a directory everything below it; the move itself runs as
+/// the service, which owns the trash and would otherwise reach what the user can't.
+fn trash_entry(project_slug: &ProjectSlugStr, entry: &Entry, rel: &Path) -> ServerResult<()> {
+    check_writable_at(entry.dir())?;
+    if entry.metadata()?.is_dir() {
+        check_removable_tree_at(entry.dir(), &entry.name)?;
+    }
+    as_service(|| move_to_trash(project_slug, entry, rel))
+}
+
+/// Runs every item even when earlier ones fail; each item gets its own response.
+pub async fn handle_server_project_action_batch(
+    project_slug: ProjectSlugStr,
+    fs_user: FsUser,
+    action: ProjectIoBatchAction,
+) -> ServerResult<ProjectResponse> {
+    let mut responses = Vec::with_capacity(action.items.len());
+    for item in action.items {
+        let result = match item {
+            ProjectIoBatchItem::Dir(dir) => {
+                handle_server_project_action_dir(project_slug.clone(), fs_user, dir).await
+            }
+            ProjectIoBatchItem::File(file) => {
+                handle_server_project_action_file(project_slug.clone(), fs_user, file).await
+            }
+        };
+        responses.push(result.unwrap_or_else(|e| {
+            tracing::error!("Error in batch item: {}", e);
+            ProjectResponse::Error((&e).into())
+        }));
+    }
+    Ok(ProjectResponse::Batch(responses))
+}
+
+pub async fn handle_server_project_action_dir(
+    project_slug: ProjectSlugStr,
+    fs_user: FsUser,
+    action: ProjectIoDirAction,
+) -> ServerResult<ProjectResponse> {
+    fs_user
+        .spawn(move || {
+            let project = ProjectDir::open(&project_slug)?;
+            match action {
+                ProjectIoDirAction::Create { path } => {
+                    let entry = project.new_entry(&project_rel_path(&path))?;
+                    match mkdir_at(entry.dir(), &entry.name) {
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                            if !entry.metadata()?.is_dir() {
+                                return Err(ServerError::PathIsNotDir);
+                            }
+                        }
+                        result => result?,
+                    }
+                }
+                ProjectIoDirAction::Rename { path, new_name } => {
+                    let rel = project_rel_path(&path);
+                    let entry = project.dir_entry(&rel)?;
+                    let new_entry = project.new_entry(&rel.with_file_name(&new_name.0))?;
+                    rename_at(
+                        entry.dir(),
+                        &entry.name,
+                        new_entry.dir(),
+                        &new_entry.name,
+                        false,
+                    )?;
+                }
+                ProjectIoDirAction::Delete { path } => {
+                    let rel = project_rel_path(&path);
+                    trash_entry(&project_slug, &project.dir_entry(&rel)?, &rel)?;
+                }
+                ProjectIoDirAction::Ls { path } => {
+                    let dir = project.open_dir(&project_rel_path(&path))?;
+                    let mut result = Vec::new();
+                    for entry in read_dir_at(dir.as_fd())?.filter_map(|entry| entry.ok()) {
+                        result.push(LsElement {
+                            name: entry.file_name().to_string_lossy().to_string(),
+                            is_dir: entry.file_type()?.is_dir(),
+                        })
+                    }
+                    return Ok(ProjectResponse::Ls(ServerProjectIoDirActionLsResponse {
+                        inner: result,
+                    }));
+                }
+                ProjectIoDirAction::Move {
+                    path,
+                    new_path,
+                    on_conflict,
+                } => {
+                    return project_dir_transfer(
+                        &project,
+                        &path,
+                        &new_path,
+                        TransferMode::Move,
+                        on_conflict,
+                    );
+                }
+                ProjectIoDirAction::Copy {
+                    path,
+                    new_path,
+                    on_conflict,
+                } => {
+                    return project_dir_transfer(
+                        &project,
+                        &path,
+                        &new_path,
+                        TransferMode::Copy,
+                        on_conflict,
+                    );
+                }
+            }
+            Ok(ProjectResponse::Ok)
+        })
+        .await
+}
+
+fn project_dir_transfer(
+    project: &ProjectDir,
+    path: &ProjectRelPath,
+    new_path: &ProjectRelPath,
+    mode: TransferMode,
+    on_conflict: ConflictStrategy,
+) -> ServerResult<ProjectResponse> {
+    let rel = project_rel_path(path);
+    let new_rel = project_rel_path(new_path);
+    // Both paths are plain components below the same root, so this holds on disk as well.
+    if new_rel.starts_with(&rel) {
+        return Err(ServerError::TargetInsideSource);
+    }
+    let source = project.dir_entry(&rel)?;
+    let target = project.new_entry(&new_rel)?;
+    let report = transfer_dir(
+        &source,
+        &target,
+        &display_path(&rel),
+        &display_path(&new_rel.with_file_name(&target.name)),
+        mode,
+        on_conflict,
+    )?;
+    Ok(ProjectResponse::IoReport(report))
+}
+
 pub async fn handle_server_project_action_file(
     project_slug: ProjectSlugStr,
+    fs_user: FsUser,
     action: ProjectIoFileAction,
 ) -> ServerResult<ProjectResponse> {
-    match action {
-        ProjectIoFileAction::Create { path } => {
-            ensure_path_in_project_path(&project_slug, &path, true, false).await?;
-            let _writer = tokio::fs::File::create(&path)
-                .await
-                .map_err(ServerError::from)?;
-        }
-        ProjectIoFileAction::Rename { path, new_name } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, true, true).await?;
-            let sanitized = sanitize_filename::sanitize(&new_name);
-            if sanitized.is_empty() {
-                return Err(ServerError::SanityCheckFailed);
+    fs_user
+        .spawn(move || {
+            let project = ProjectDir::open(&project_slug)?;
+            match action {
+                ProjectIoFileAction::Create { path } => {
+                    let entry = project.new_entry(&project_rel_path(&path))?;
+                    create_file_at(entry.dir(), &entry.name, false)?;
+                }
+                ProjectIoFileAction::Rename { path, new_name } => {
+                    let rel = project_rel_path(&path);
+                    let entry = project.file_entry(&rel)?;
+                    let new_entry = project.new_entry(&rel.with_file_name(&new_name.0))?;
+                    rename_at(
+                        entry.dir(),
+                        &entry.name,
+                        new_entry.dir(),
+                        &new_entry.name,
+                        false,
+                    )?;
+                }
+                ProjectIoFileAction::Delete { path } => {
+                    let rel = project_rel_path(&path);
+                    trash_entry(&project_slug, &project.file_entry(&rel)?, &rel)?;
+                }
+                ProjectIoFileAction::Move { path, new_path } => {
+                    let entry = project.file_entry(&project_rel_path(&path))?;
+                    let new_entry = project.new_entry(&project_rel_path(&new_path))?;
+                    move_file_at(entry.dir(), &entry.name, new_entry.dir(), &new_entry.name)?;
+                }
+                ProjectIoFileAction::Copy { path, new_path } => {
+                    let entry = project.file_entry(&project_rel_path(&path))?;
+                    let new_entry = project.new_entry(&project_rel_path(&new_path))?;
+                    copy_file_at(
+                        entry.dir(),
+                        &entry.name,
+                        new_entry.dir(),
+                        &new_entry.name,
+                        true,
+                    )?;
+                }
             }
-            let new_path = path.parent().unwrap().join(&sanitized);
-            let new_path = ensure_path_in_project_path(
-                &project_slug,
-                &new_path.to_string_lossy(),
-                true,
-                false,
-            )
-            .await?;
-            tokio::fs::rename(path, new_path)
-                .await
-                .map_err(ServerError::from)?;
+            Ok(ProjectResponse::Ok)
+        })
+        .await
+}
+
+enum SearchMatcher {
+    Name(Regex),
+    Content(Regex),
+}
+
+pub async fn handle_server_project_action_search(
+    project_slug: ProjectSlugStr,
+    fs_user: FsUser,
+    action: ProjectIoSearchAction,
+) -> ServerResult<ProjectResponse> {
+    let (path, matcher, cursor) = match action {
+        ProjectIoSearchAction::Name {
+            path,
+            pattern,
+            cursor,
+        } => (path, SearchMatcher::Name(glob_to_regex(&pattern)?), cursor),
+        ProjectIoSearchAction::Content {
+            path,
+            query,
+            is_regex,
+            case_sensitive,
+            cursor,
+        } => {
+            let query = if is_regex {
+                query
+            } else {
+                regex::escape(&query)
+            };
+            let regex = RegexBuilder::new(&query)
+                .case_insensitive(!case_sensitive)
+                .size_limit(1 << 20)
+                .build()?;
+            (path, SearchMatcher::Content(regex), cursor)
         }
-        ProjectIoFileAction::Delete { path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, true, true).await?;
+    };
+    let rel = project_rel_path(&path);
+    let response = fs_user
+        .spawn(move || {
+            let search_root = ProjectDir::open(&project_slug)?.open_dir(&rel)?;
+            let mut search_page = SearchPage::new(cursor);
+            search_dir(search_root.as_fd(), &rel, &matcher, &mut search_page);
+            Ok(ServerProjectIoSearchResponse {
+                next: search_page.next,
+                inner: search_page.inner,
+                truncated: search_page.truncated,
+            })
+        })
+        .await?;
+    Ok(ProjectResponse::Search(response))
+}
 
-            tokio::fs::remove_file(path)
-                .await
-                .map_err(ServerError::from)?;
+/// Collects one page of matches, resuming from the cursor of the previous page. Entries
+/// before the cursor in walk order are passed over without being read, so each request only
+/// scans from where the last one stopped, up to the caps.
+struct SearchPage {
+    /// Project path of the cursor entry, and how many of its matches were already returned;
+    /// cleared once the walk gets there.
+    resume: Option<(PathBuf, usize)>,
+    /// The entry being scanned, its matches to pass over and the ones found so far.
+    entry: String,
+    entry_skip: usize,
+    entry_matches: usize,
+    inner: Vec<SearchMatch>,
+    next: Option<SearchCursor>,
+    scanned_entries: usize,
+    scanned_bytes: u64,
+    truncated: bool,
+}
+
+/// Where an entry stands against the cursor.
+enum Resume {
+    /// The entry and everything under it come before the cursor.
+    Skip,
+    /// The cursor is under the entry, which was scanned already.
+    Descend,
+    /// The walk is at the cursor or past it; the number of matches to pass over.
+    Visit(usize),
+}
+
+impl SearchPage {
+    fn new(cursor: Option<SearchCursor>) -> Self {
+        let resume = cursor.map(|cursor| {
+            let path = cursor
+                .path
+                .strip_prefix(PROJECT_PATH_ROOT)
+                .unwrap_or_default();
+            (PathBuf::from(path), cursor.skip as usize)
+        });
+        Self {
+            resume,
+            entry: String::new(),
+            entry_skip: 0,
+            entry_matches: 0,
+            inner: Vec::new(),
+            next: None,
+            scanned_entries: 0,
+            scanned_bytes: 0,
+            truncated: false,
         }
-        ProjectIoFileAction::Move { path, new_path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, true, true).await?;
-            let new_path =
-                ensure_path_in_project_path(&project_slug, &new_path, true, false).await?;
-            tokio::fs::rename(path, new_path)
-                .await
-                .map_err(ServerError::from)?;
+    }
+
+    /// Places the entry at `rel` against the cursor, dropping the cursor once it is reached.
+    fn resume(&mut self, rel: &Path) -> Resume {
+        let Some((cursor, skip)) = &self.resume else {
+            return Resume::Visit(0);
+        };
+        if cursor.starts_with(rel) && cursor != rel {
+            return Resume::Descend;
         }
-        ProjectIoFileAction::Copy { path, new_path } => {
-            let path = ensure_path_in_project_path(&project_slug, &path, true, true).await?;
-            let new_path =
-                ensure_path_in_project_path(&project_slug, &new_path, true, false).await?;
-            tokio::fs::copy(path, new_path)
-                .await
-                .map_err(ServerError::from)?;
+        // Paths compare by component, the order of the walk.
+        let skip = match rel.cmp(cursor.as_path()) {
+            Ordering::Less => return Resume::Skip,
+            Ordering::Equal => *skip,
+            // The cursor entry is gone; carry on from the one after it.
+            Ordering::Greater => 0,
+        };
+        self.resume = None;
+        Resume::Visit(skip)
+    }
+
+    /// Starts scanning the entry at `path`. Returns false once the request has scanned as
+    /// many entries as it may and the walk has to stop.
+    fn visit(&mut self, path: String, skip: usize) -> bool {
+        self.entry = path;
+        self.entry_skip = skip;
+        self.entry_matches = 0;
+        self.scan(1, 0)
+    }
+
+    /// Counts entries and the bytes read from them. Returns false once the request has
+    /// scanned as much as it may; the next one starts over at the current entry.
+    fn scan(&mut self, entries: usize, bytes: u64) -> bool {
+        self.scanned_entries += entries;
+        self.scanned_bytes += bytes;
+        if self.scanned_entries > SEARCH_MAX_SCANNED_ENTRIES
+            || self.scanned_bytes > SEARCH_MAX_SCANNED_BYTES
+        {
+            self.truncated = true;
+            self.stop_at(self.entry_skip);
+            return false;
+        }
+        true
+    }
+
+    /// Adds a match of the current entry. Returns false once the page is full and the walk
+    /// can stop.
+    fn push(&mut self, search_match: SearchMatch) -> bool {
+        self.entry_matches += 1;
+        if self.entry_matches <= self.entry_skip {
+            return true;
+        }
+        if self.inner.len() == SEARCH_PAGE_SIZE {
+            self.stop_at(self.entry_matches - 1);
+            return false;
+        }
+        self.inner.push(search_match);
+        true
+    }
+
+    fn stop_at(&mut self, skip: usize) {
+        self.next = Some(SearchCursor {
+            path: self.entry.clone(),
+            skip: skip as u32,
+        });
+    }
+}
+
+/// Walks `dir`, the project directory at `rel`, depth first in file name order. Children
+/// are opened through their parent handle and symlinks are skipped, so the walk cannot be
+/// redirected out of the project. Returns false once the page is full or the scan limits are
+/// reached, with the cursor to resume from set.
+fn search_dir(
+    dir: BorrowedFd,
+    rel: &Path,
+    matcher: &SearchMatcher,
+    search_page: &mut SearchPage,
+) -> bool {
+    let Ok(entries) = read_dir_at(dir) else {
+        return true;
+    };
+    let mut entries = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| Some((entry.file_name(), entry.file_type().ok()?)))
+        .collect::<Vec<_>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (name, file_type) in entries {
+        if file_type.is_symlink() {
+            continue;
+        }
+        let rel = rel.join(&name);
+        let path = display_path(&rel);
+        let skip = match search_page.resume(&rel) {
+            Resume::Skip => continue,
+            // Scanned by an earlier request; only part of its subtree is left.
+            Resume::Descend => None,
+            Resume::Visit(skip) => Some(skip),
+        };
+        if let Some(skip) = skip {
+            if !search_page.visit(path.clone(), skip) {
+                return false;
+            }
+            match matcher {
+                SearchMatcher::Name(regex) => {
+                    if regex.is_match(&name.to_string_lossy())
+                        && !search_page.push(SearchMatch {
+                            path,
+                            is_dir: file_type.is_dir(),
+                            line: None,
+                        })
+                    {
+                        return false;
+                    }
+                }
+                SearchMatcher::Content(regex) => {
+                    if file_type.is_file() {
+                        let content = open_file_at(dir, Path::new(&name))
+                            .ok()
+                            .and_then(read_searchable_file);
+                        let read = content.as_ref().map_or(0, |content| content.len() as u64);
+                        if !search_page.scan(0, read) {
+                            return false;
+                        }
+                        for (index, line) in
+                            content.as_deref().unwrap_or_default().lines().enumerate()
+                        {
+                            if regex.is_match(line)
+                                && !search_page.push(SearchMatch {
+                                    path: path.clone(),
+                                    is_dir: false,
+                                    line: Some(SearchLineMatch {
+                                        line_number: index as u64 + 1,
+                                        content: line.chars().take(SEARCH_MAX_LINE_LEN).collect(),
+                                    }),
+                                })
+                            {
+                                return false;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if file_type.is_dir() && name != ".git" {
+            if let Ok(child) = open_dir_at(dir, Path::new(&name)) {
+                if !search_dir(child.as_fd(), &rel, matcher, search_page) {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Reads a file for content search, skipping files above the size cap and binary files.
+fn read_searchable_file(file: File) -> Option<String> {
+    if file.metadata().ok()?.len() > SEARCH_MAX_FILE_SIZE {
+        return None;
+    }
+    let mut buf = Vec::new();
+    file.take(SEARCH_MAX_FILE_SIZE).read_to_end(&mut buf).ok()?;
+    if buf.iter().take(8 * 1024).any(|b| *b == 0) {
+        return None;
+    }
+    String::from_utf8(buf).ok()
+}
+
+/// Translates a file name glob (`*` and `?` wildcards) into an anchored, case-insensitive regex.
+fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
         }
     }
-    Ok(ProjectResponse::Ok)
+    regex.push('$');
+    RegexBuilder::new(&regex)
+        .case_insensitive(true)
+        .size_limit(1 << 20)
+        .build()
 }
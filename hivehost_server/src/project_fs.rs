@@ -0,0 +1,370 @@
+//! File access pinned to directory handles.
+//!
+//! Project paths are never checked and then reopened by name: every lookup is an `openat2`
+//! relative to a handle on the project root (or on a directory reached that way) with
+//! `RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS`. A component swapped for a symlink over SFTP makes
+//! the lookup fail instead of leaving the project, and `*at` calls act on the entry behind the
+//! handle that was resolved.
+
+use crate::{ServerError, ServerResult};
+use common::{get_project_dev_path, ProjectRelPath, ProjectSlugStr};
+use std::ffi::{CString, OsStr, OsString};
+use std::fs::{File, Metadata, ReadDir};
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+const RESOLVE: u64 =
+    libc::RESOLVE_BENEATH | libc::RESOLVE_NO_SYMLINKS | libc::RESOLVE_NO_MAGICLINKS;
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+fn cvt(result: libc::c_int) -> io::Result<()> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Opens `path` below `dir`; an empty path opens `dir` itself again.
+pub fn openat2(dir: BorrowedFd, path: &Path, flags: libc::c_int, mode: u32) -> io::Result<OwnedFd> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let path = c_path(path)?;
+    let mut how: libc::open_how = unsafe { std::mem::zeroed() };
+    how.flags = (flags | libc::O_CLOEXEC) as u64;
+    how.mode = if flags & libc::O_CREAT != 0 {
+        mode as u64
+    } else {
+        0
+    };
+    how.resolve = RESOLVE;
+    // SAFETY: `how` and `path` outlive the call; the returned descriptor is owned by nobody else.
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            std::mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: openat2 returned a new descriptor.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Opens a trusted, server-owned directory by its absolute path.
+pub fn open_dir_path(path: &Path) -> io::Result<OwnedFd> {
+    let path = c_path(path)?;
+    // SAFETY: `path` is a valid C string for the duration of the call.
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_PATH | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: open returned a new descriptor.
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Path naming the directory behind `fd`, for std and walkdir APIs that only take paths. The
+/// kernel resolves it to the pinned directory, not by name.
+pub fn fd_path(fd: BorrowedFd) -> PathBuf {
+    PathBuf::from(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+}
+
+pub fn open_dir_at(dir: BorrowedFd, path: &Path) -> io::Result<OwnedFd> {
+    openat2(dir, path, libc::O_PATH | libc::O_DIRECTORY, 0)
+}
+
+/// Opens a regular file for reading; anything else is refused. `O_NONBLOCK` keeps a FIFO
+/// planted in the project from hanging the open.
+pub fn open_file_at(dir: BorrowedFd, path: &Path) -> io::Result<File> {
+    let file = File::from(openat2(dir, path, libc::O_RDONLY | libc::O_NONBLOCK, 0)?);
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Not a regular file",
+        ));
+    }
+    Ok(file)
+}
+
+/// Creates `name` in `dir`, truncating an existing file only when `overwrite` is set.
+pub fn create_file_at(dir: BorrowedFd, name: &OsStr, overwrite: bool) -> io::Result<File> {
+    let flags = if overwrite {
+        libc::O_WRONLY | libc::O_NONBLOCK | libc::O_CREAT | libc::O_TRUNC
+    } else {
+        libc::O_WRONLY | libc::O_NONBLOCK | libc::O_CREAT | libc::O_EXCL
+    };
+    let file = File::from(openat2(dir, Path::new(name), flags, 0o666)?);
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Not a regular file",
+        ));
+    }
+    Ok(file)
+}
+
+/// Metadata of `name` in `dir`, without following it if it is a symlink.
+pub fn metadata_at(dir: BorrowedFd, name: &OsStr) -> io::Result<Metadata> {
+    fd_path(dir).join(name).symlink_metadata()
+}
+
+pub fn exists_at(dir: BorrowedFd, name: &OsStr) -> bool {
+    metadata_at(dir, name).is_ok()
+}
+
+pub fn read_dir_at(dir: BorrowedFd) -> io::Result<ReadDir> {
+    std::fs::read_dir(fd_path(dir))
+}
+
+pub fn mkdir_at(dir: BorrowedFd, name: &OsStr) -> io::Result<()> {
+    let name = c_path(Path::new(name))?;
+    // SAFETY: `name` is a valid C string and `dir` an open descriptor.
+    cvt(unsafe { libc::mkdirat(dir.as_raw_fd(), name.as_ptr(), 0o777) })
+}
+
+/// Renames within or across directories; `no_replace` fails with `EEXIST` instead of replacing.
+pub fn rename_at(
+    from_dir: BorrowedFd,
+    from_name: &OsStr,
+    to_dir: BorrowedFd,
+    to_name: &OsStr,
+    no_replace: bool,
+) -> io::Result<()> {
+    let from_name = c_path(Path::new(from_name))?;
+    let to_name = c_path(Path::new(to_name))?;
+    let flags = if no_replace {
+        libc::RENAME_NOREPLACE
+    } else {
+        0
+    };
+    // SAFETY: both names are valid C strings and both descriptors are open.
+    cvt(unsafe {
+        libc::renameat2(
+            from_dir.as_raw_fd(),
+            from_name.as_ptr(),
+            to_dir.as_raw_fd(),
+            to_name.as_ptr(),
+            flags,
+        )
+    })
+}
+
+/// Target of the symlink `name` in `dir`.
+pub fn read_link_at(dir: BorrowedFd, name: &OsStr) -> io::Result<PathBuf> {
+    std::fs::read_link(fd_path(dir).join(name))
+}
+
+/// Creates the symlink `name` in `dir`, pointing at `target` as given; it is never followed.
+pub fn symlink_at(target: &Path, dir: BorrowedFd, name: &OsStr) -> io::Result<()> {
+    let target = c_path(target)?;
+    let name = c_path(Path::new(name))?;
+    // SAFETY: both names are valid C strings and `dir` an open descriptor.
+    cvt(unsafe { libc::symlinkat(target.as_ptr(), dir.as_raw_fd(), name.as_ptr()) })
+}
+
+pub fn mkfifo_at(dir: BorrowedFd, name: &OsStr, mode: u32) -> io::Result<()> {
+    let name = c_path(Path::new(name))?;
+    // SAFETY: `name` is a valid C string and `dir` an open descriptor.
+    cvt(unsafe { libc::mkfifoat(dir.as_raw_fd(), name.as_ptr(), mode as libc::mode_t) })
+}
+
+pub fn unlink_at(dir: BorrowedFd, name: &OsStr, is_dir: bool) -> io::Result<()> {
+    let name = c_path(Path::new(name))?;
+    let flags = if is_dir { libc::AT_REMOVEDIR } else { 0 };
+    // SAFETY: `name` is a valid C string and `dir` an open descriptor.
+    cvt(unsafe { libc::unlinkat(dir.as_raw_fd(), name.as_ptr(), flags) })
+}
+
+fn check_access_at(dir: BorrowedFd, mode: libc::c_int) -> io::Result<()> {
+    // SAFETY: the path is a valid C string and `dir` an open descriptor.
+    cvt(unsafe { libc::faccessat(dir.as_raw_fd(), c".".as_ptr(), mode, libc::AT_EACCESS) })
+}
+
+/// Whether the thread's filesystem identity may add and remove entries in `dir`.
+pub fn check_writable_at(dir: BorrowedFd) -> io::Result<()> {
+    check_access_at(dir, libc::W_OK | libc::X_OK)
+}
+
+/// Whether the thread's filesystem identity may empty the directory `name` of `dir`: list
+/// and change it and every directory below it. Symlinks are not followed.
+pub fn check_removable_tree_at(dir: BorrowedFd, name: &OsStr) -> io::Result<()> {
+    let dir = open_dir_at(dir, Path::new(name))?;
+    check_access_at(dir.as_fd(), libc::R_OK | libc::W_OK | libc::X_OK)?;
+    for entry in read_dir_at(dir.as_fd())? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            check_removable_tree_at(dir.as_fd(), &entry.file_name())?;
+        }
+    }
+    Ok(())
+}
+
+/// Changes the owner of `name` in `dir`, without following it if it is a symlink.
+pub fn chown_at(
+    dir: BorrowedFd,
+    name: &OsStr,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+) -> io::Result<()> {
+    let name = c_path(Path::new(name))?;
+    // SAFETY: `name` is a valid C string and `dir` an open descriptor.
+    cvt(unsafe {
+        libc::fchownat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            uid,
+            gid,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    })
+}
+
+/// A directory entry held by its parent handle and its name.
+pub struct Entry {
+    pub parent: OwnedFd,
+    pub name: OsString,
+}
+
+impl Entry {
+    pub fn dir(&self) -> BorrowedFd<'_> {
+        self.parent.as_fd()
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        metadata_at(self.dir(), &self.name)
+    }
+
+    pub fn exists(&self) -> bool {
+        exists_at(self.dir(), &self.name)
+    }
+}
+
+/// `root/a/b` as sent by the website, to `a/b` relative to the project root; `root/` alone is
+/// the project root itself. The path was validated with the action, so it only holds plain
+/// names.
+pub fn project_rel_path(path: &ProjectRelPath) -> PathBuf {
+    path.components().collect()
+}
+
+/// Inverse of `project_rel_path`.
+pub fn display_path(rel: &Path) -> String {
+    format!("root/{}", rel.to_string_lossy())
+}
+
+/// Handle on a project root, the base every project path is resolved from.
+pub struct ProjectDir {
+    root: OwnedFd,
+}
+
+impl ProjectDir {
+    pub fn open(project_slug: &ProjectSlugStr) -> ServerResult<Self> {
+        Ok(Self {
+            root: open_dir_path(Path::new(&get_project_dev_path(project_slug)))?,
+        })
+    }
+
+    pub fn root(&self) -> BorrowedFd<'_> {
+        self.root.as_fd()
+    }
+
+    pub fn open_dir(&self, rel: &Path) -> ServerResult<OwnedFd> {
+        open_dir_at(self.root(), rel).map_err(resolve_error)
+    }
+
+    pub fn open_file(&self, rel: &Path) -> ServerResult<File> {
+        open_file_at(self.root(), rel).map_err(resolve_error)
+    }
+
+    /// An existing entry; the project root itself has no parent and is refused.
+    pub fn entry(&self, rel: &Path) -> ServerResult<Entry> {
+        let (parent, name) = split(rel)?;
+        let entry = Entry {
+            parent: self.open_dir(parent)?,
+            name: name.to_os_string(),
+        };
+        entry.metadata()?;
+        Ok(entry)
+    }
+
+    pub fn dir_entry(&self, rel: &Path) -> ServerResult<Entry> {
+        let entry = self.entry(rel)?;
+        if !entry.metadata()?.is_dir() {
+            return Err(ServerError::PathIsNotDir);
+        }
+        Ok(entry)
+    }
+
+    pub fn file_entry(&self, rel: &Path) -> ServerResult<Entry> {
+        let entry = self.entry(rel)?;
+        if !entry.metadata()?.is_file() {
+            return Err(ServerError::PathIsNotFile);
+        }
+        Ok(entry)
+    }
+
+    /// Where a new entry goes: an existing parent directory and a sanitized name.
+    pub fn new_entry(&self, rel: &Path) -> ServerResult<Entry> {
+        let (parent, name) = split(rel)?;
+        let name = sanitize_filename::sanitize(name.to_string_lossy());
+        if name.is_empty() {
+            return Err(ServerError::SanityCheckFailed);
+        }
+        Ok(Entry {
+            parent: self.open_dir(parent)?,
+            name: name.into(),
+        })
+    }
+
+    /// Creates every missing directory of `rel`, one handle at a time.
+    pub fn create_dir_all(&self, rel: &Path) -> ServerResult<OwnedFd> {
+        let mut dir = open_dir_at(self.root(), Path::new(""))?;
+        for component in rel.components() {
+            let name = component.as_os_str();
+            dir = match open_dir_at(dir.as_fd(), Path::new(name)) {
+                Ok(child) => child,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    match mkdir_at(dir.as_fd(), name) {
+                        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e.into()),
+                        _ => {}
+                    }
+                    open_dir_at(dir.as_fd(), Path::new(name)).map_err(resolve_error)?
+                }
+                Err(e) => return Err(resolve_error(e)),
+            };
+        }
+        Ok(dir)
+    }
+}
+
+fn split(rel: &Path) -> ServerResult<(&Path, &OsStr)> {
+    Ok((
+        rel.parent().ok_or(ServerError::PathHasNoParent)?,
+        rel.file_name().ok_or(ServerError::PathHasNoParent)?,
+    ))
+}
+
+/// `ELOOP` is a symlink on the way and `EXDEV` an attempt to leave the project.
+fn resolve_error(e: io::Error) -> ServerError {
+    match e.raw_os_error() {
+        Some(libc::ENOTDIR) => ServerError::PathIsNotDir,
+        Some(libc::ELOOP | libc::EXDEV) => ServerError::PathNotAValidProjectPath,
+        _ => e.into(),
+    }
+}
@@ -0,0 +1,130 @@
+//! Changes to a project run one at a time, in the order they arrive. A git pull, a snapshot
+//! and a restore of the same project would otherwise interleave and leave a torn snapshot or
+//! a broken dev tree. Reads don't queue and keep running next to them.
+
+use common::server_action::project_action::{ProjectOperation, ProjectStatus};
+use common::{ProjectSlugStr, UserSlugStr};
+use dashmap::DashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+use tokio::sync::OwnedMutexGuard;
+use tracing::debug;
+
+#[derive(Debug, Default)]
+pub struct ProjectLocks {
+    projects: Arc<DashMap<ProjectSlugStr, Arc<ProjectQueue>>>,
+}
+
+#[derive(Debug, Default)]
+struct ProjectQueue {
+    // tokio's mutex hands the lock over in the order it was asked for.
+    turn: Arc<tokio::sync::Mutex<()>>,
+    state: Mutex<QueueState>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    running: Option<Running>,
+    waiting: u32,
+}
+
+#[derive(Debug)]
+struct Running {
+    name: String,
+    user_slug: Option<UserSlugStr>,
+    since: Instant,
+}
+
+/// Holds a project for one change; the next one starts when it is dropped.
+pub struct ProjectGuard {
+    projects: Arc<DashMap<ProjectSlugStr, Arc<ProjectQueue>>>,
+    project_slug: ProjectSlugStr,
+    queue: Arc<ProjectQueue>,
+    _turn: OwnedMutexGuard<()>,
+}
+
+/// Counts a change as waiting until it gets its turn or is cancelled.
+struct Waiting<'a>(&'a ProjectQueue);
+
+impl ProjectLocks {
+    /// Waits for the changes queued before on `project_slug`, then holds it for the change
+    /// called `name`.
+    pub async fn lock(
+        &self,
+        project_slug: &ProjectSlugStr,
+        name: String,
+        user_slug: Option<UserSlugStr>,
+    ) -> ProjectGuard {
+        let queue = self
+            .projects
+            .entry(project_slug.clone())
+            .or_default()
+            .clone();
+        let turn = match queue.turn.clone().try_lock_owned() {
+            Ok(turn) => turn,
+            Err(_) => {
+                debug!("{name} waits for the changes queued on {}", project_slug.0);
+                let _waiting = Waiting::new(&queue);
+                queue.turn.clone().lock_owned().await
+            }
+        };
+        queue.state().running = Some(Running {
+            name,
+            user_slug,
+            since: Instant::now(),
+        });
+        ProjectGuard {
+            projects: self.projects.clone(),
+            project_slug: project_slug.clone(),
+            queue,
+            _turn: turn,
+        }
+    }
+
+    pub fn status(&self, project_slug: &ProjectSlugStr) -> ProjectStatus {
+        let Some(queue) = self.projects.get(project_slug).map(|queue| queue.clone()) else {
+            return ProjectStatus::default();
+        };
+        let state = queue.state();
+        ProjectStatus {
+            running: state.running.as_ref().map(|running| ProjectOperation {
+                name: running.name.clone(),
+                user_slug: running.user_slug.clone(),
+                running_for: running.since.elapsed(),
+            }),
+            waiting: state.waiting,
+        }
+    }
+}
+
+impl ProjectQueue {
+    fn state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for ProjectGuard {
+    // Runs before the turn is released, so it can't clear the next change's entry.
+    fn drop(&mut self) {
+        self.queue.state().running = None;
+        // Every change queued or starting holds the queue, so only the map and this guard
+        // holding it means none is. The map's shard lock keeps a new one from starting
+        // between the check and the removal; it gets a fresh queue after.
+        self.projects.remove_if(&self.project_slug, |_, queue| {
+            Arc::ptr_eq(queue, &self.queue) && Arc::strong_count(queue) == 2
+        });
+    }
+}
+
+impl<'a> Waiting<'a> {
+    fn new(queue: &'a ProjectQueue) -> Self {
+        queue.state().waiting += 1;
+        Waiting(queue)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.state().waiting -= 1;
+    }
+}
@@ -1,5 +1,5 @@
-use crate::{ServerResult, TarpcHelperClient};
-use common::command::run_external_command;
+use crate::{ServerResult, TarpcHelperClient, GIT_COMMAND_TIMEOUT};
+use common::command::{run_external_command_with, CommandLimits};
 use common::helper_command::{HelperCommand, HelperResponse};
 use common::server_action::permission::Permission;
 use common::server_action::user_action::{ServerUserAction, ServerUserResponse};
@@ -23,7 +23,17 @@ pub async fn handle_user_action(
             user_slug,
             project_slug,
             github_info,
-        } => create_project(server_helper, user_slug, project_slug, github_info).await?,
+            quota_bytes,
+        } => {
+            create_project(
+                server_helper,
+                user_slug,
+                project_slug,
+                github_info,
+                quota_bytes,
+            )
+            .await?
+        }
         ServerUserAction::RemoveProject {
             user_slugs,
             project_slug,
@@ -37,6 +47,17 @@ pub async fn handle_user_action(
             helper_commands.push(HelperCommand::DeleteProject { project_slug });
             server_helper.execute(helper_commands).await?
         }
+        ServerUserAction::SetProjectQuota {
+            project_slug,
+            quota_bytes,
+        } => {
+            server_helper
+                .execute(vec![HelperCommand::SetProjectQuota {
+                    project_slug,
+                    limit_bytes: quota_bytes,
+                }])
+                .await?
+        }
     };
     Ok(ServerUserResponse::Helper(r))
 }
@@ -55,31 +76,42 @@ pub async fn create_project(
     user_slug: UserSlugStr,
     project_slug: ProjectSlugStr,
     github_info: Option<(Option<GitTokenStr>, GitRepoFullNameStr, GitBranchNameStr)>,
+    quota_bytes: Option<u64>,
 ) -> ServerResult<HelperResponse> {
     let dev_path = get_project_dev_path(&project_slug);
-    server_helper
+    let helper_response = server_helper
         .execute(vec![
             HelperCommand::CreateProject {
                 project_slug: project_slug.clone(),
                 user_slug: user_slug.clone(),
                 with_index_html: github_info.is_none(),
             },
+            // Before the clone, so the repository counts against the limit too.
+            HelperCommand::SetProjectQuota {
+                project_slug: project_slug.clone(),
+                limit_bytes: quota_bytes,
+            },
             HelperCommand::BindMountUserProject {
                 project_slug,
                 user_slug,
             },
         ])
         .await?;
+    if helper_response != HelperResponse::Ok {
+        // The helper rolled the batch back, there is nothing to clone into.
+        return Ok(helper_response);
+    }
     if let Some((token, full_name, branch)) = github_info {
         let token = token
             .map(|token| format!("oauth2:{}@", token.0))
             .unwrap_or_default();
         let url = format!("https://{token}github.com/{}.git", full_name.0);
-        let r1 = run_external_command(
+        let r1 = run_external_command_with(
             "git",
             &[
                 "clone", &url, "--branch", &branch.0, "--depth", "1", &dev_path,
             ],
+            CommandLimits::with_timeout(GIT_COMMAND_TIMEOUT),
         )
         .await?;
         info!("Server successfully cloned github repo: {r1}");
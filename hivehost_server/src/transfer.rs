@@ -0,0 +1,334 @@
+use crate::project_fs::{
+    create_file_at, exists_at, metadata_at, mkdir_at, mkfifo_at, open_dir_at, open_file_at,
+    read_dir_at, read_link_at, rename_at, symlink_at, unlink_at, Entry,
+};
+use crate::{ServerError, ServerResult};
+use common::server_action::project_action::io_action::{
+    ConflictStrategy, IoReportEntry, IoReportOutcome, ServerProjectIoReport,
+};
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::fs::FileType;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+/// `_IOW(0x94, 9, int)`: share the source extents with the destination (Btrfs reflink).
+const FICLONE: u64 = 0x4004_9409;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    Copy,
+    Move,
+}
+
+/// Clones `source` into `target` with FICLONE, falling back to a full copy when the
+/// filesystem does not support reflinks (or the two files are on different filesystems).
+pub fn reflink_or_copy(mut source: &File, mut target: &File) -> io::Result<()> {
+    // SAFETY: both descriptors stay open for the duration of the call.
+    let result = unsafe { libc::ioctl(target.as_raw_fd(), FICLONE as _, source.as_raw_fd()) };
+    if result != 0 {
+        target.set_len(0)?;
+        io::copy(&mut source, &mut target)?;
+    }
+    target.set_permissions(source.metadata()?.permissions())
+}
+
+/// Copies the file `source_name` of `source_dir` to `target_name` in `target_dir`.
+pub fn copy_file_at(
+    source_dir: BorrowedFd,
+    source_name: &OsStr,
+    target_dir: BorrowedFd,
+    target_name: &OsStr,
+    overwrite: bool,
+) -> io::Result<()> {
+    let source = open_file_at(source_dir, Path::new(source_name))?;
+    let target = create_file_at(target_dir, target_name, overwrite)?;
+    reflink_or_copy(&source, &target)
+}
+
+/// Renames a file, copying then removing it across filesystems or subvolumes.
+pub fn move_file_at(
+    source_dir: BorrowedFd,
+    source_name: &OsStr,
+    target_dir: BorrowedFd,
+    target_name: &OsStr,
+) -> io::Result<()> {
+    match rename_at(source_dir, source_name, target_dir, target_name, false) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            copy_file_at(source_dir, source_name, target_dir, target_name, true)?;
+            unlink_at(source_dir, source_name, false)
+        }
+        result => result,
+    }
+}
+
+/// Recreates the symlink or FIFO `source_name` of `source_dir` as `target_name` in
+/// `target_dir`. A symlink keeps its target as written and is never followed. Sockets and
+/// devices only mean something on the machine that made them and are refused.
+pub fn copy_special_at(
+    source_dir: BorrowedFd,
+    source_name: &OsStr,
+    target_dir: BorrowedFd,
+    target_name: &OsStr,
+    overwrite: bool,
+) -> io::Result<()> {
+    let metadata = metadata_at(source_dir, source_name)?;
+    let file_type = metadata.file_type();
+    if overwrite && exists_at(target_dir, target_name) {
+        unlink_at(target_dir, target_name, false)?;
+    }
+    if file_type.is_symlink() {
+        symlink_at(
+            &read_link_at(source_dir, source_name)?,
+            target_dir,
+            target_name,
+        )
+    } else if file_type.is_fifo() {
+        mkfifo_at(
+            target_dir,
+            target_name,
+            metadata.permissions().mode() & 0o777,
+        )
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Sockets and device files are not transferred",
+        ))
+    }
+}
+
+/// Renames a symlink or FIFO, recreating it then removing it across filesystems or
+/// subvolumes.
+pub fn move_special_at(
+    source_dir: BorrowedFd,
+    source_name: &OsStr,
+    target_dir: BorrowedFd,
+    target_name: &OsStr,
+) -> io::Result<()> {
+    match rename_at(source_dir, source_name, target_dir, target_name, false) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            copy_special_at(source_dir, source_name, target_dir, target_name, true)?;
+            unlink_at(source_dir, source_name, false)
+        }
+        result => result,
+    }
+}
+
+/// First free `name (n).ext` in `dir`.
+pub fn free_name(dir: BorrowedFd, name: &OsStr) -> OsString {
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = name
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|i| OsString::from(format!("{stem} ({i}){extension}")))
+        .find(|candidate| !exists_at(dir, candidate))
+        .expect("an unused name exists")
+}
+
+/// Moves or copies the directory `source` to `target`; `source_display` and `target_display`
+/// name them in the report.
+///
+/// An existing target directory is merged into: conflicting files are skipped, overwritten
+/// or written under a free name depending on `on_conflict`, and with `Rename` an existing
+/// target directory gets a free name itself. Per-entry failures do not stop the transfer,
+/// they end up in the returned report. Symlinks and FIFOs are transferred as themselves. Every
+/// entry is reached through its parent handle, so a directory swapped for a symlink midway is
+/// reported as failed, never followed.
+pub fn transfer_dir(
+    source: &Entry,
+    target: &Entry,
+    source_display: &str,
+    target_display: &str,
+    mode: TransferMode,
+    on_conflict: ConflictStrategy,
+) -> ServerResult<ServerProjectIoReport> {
+    let mut transfer = Transfer {
+        mode,
+        on_conflict,
+        report: ServerProjectIoReport::default(),
+    };
+    let target_name = match target.metadata() {
+        Ok(_) if on_conflict == ConflictStrategy::Rename => {
+            let renamed = free_name(target.dir(), &target.name);
+            let new_path = sibling_display(target_display, &renamed);
+            transfer.push(source_display, IoReportOutcome::Renamed { new_path });
+            renamed
+        }
+        Ok(meta) if !meta.is_dir() => return Err(ServerError::PathIsNotDir),
+        Ok(_) => target.name.clone(),
+        Err(_) => {
+            if mode == TransferMode::Move {
+                match rename_at(source.dir(), &source.name, target.dir(), &target.name, true) {
+                    Ok(()) => {
+                        transfer.report.done += 1;
+                        return Ok(transfer.report);
+                    }
+                    Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            target.name.clone()
+        }
+    };
+    let target_display = sibling_display(target_display, &target_name);
+    transfer.dir(
+        source.dir(),
+        &source.name,
+        target.dir(),
+        &target_name,
+        source_display,
+        &target_display,
+    );
+    Ok(transfer.report)
+}
+
+fn sibling_display(display: &str, name: &OsStr) -> String {
+    match display.rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/{}", name.to_string_lossy()),
+        None => name.to_string_lossy().to_string(),
+    }
+}
+
+struct Transfer {
+    mode: TransferMode,
+    on_conflict: ConflictStrategy,
+    report: ServerProjectIoReport,
+}
+
+impl Transfer {
+    fn push(&mut self, path: &str, outcome: IoReportOutcome) {
+        self.report.issues.push(IoReportEntry {
+            path: path.to_string(),
+            outcome,
+        });
+    }
+
+    fn failed(&mut self, path: &str, message: String) {
+        self.push(path, IoReportOutcome::Failed { message });
+    }
+
+    fn dir(
+        &mut self,
+        source_parent: BorrowedFd,
+        source_name: &OsStr,
+        target_parent: BorrowedFd,
+        target_name: &OsStr,
+        display: &str,
+        target_display: &str,
+    ) {
+        let source = match open_dir_at(source_parent, Path::new(source_name)) {
+            Ok(source) => source,
+            Err(e) => {
+                self.failed(display, e.to_string());
+                return;
+            }
+        };
+        if !exists_at(target_parent, target_name) {
+            if let Err(e) = mkdir_at(target_parent, target_name) {
+                self.failed(display, e.to_string());
+                return;
+            }
+        }
+        let target = match open_dir_at(target_parent, Path::new(target_name)) {
+            Ok(target) => target,
+            Err(e) => {
+                self.failed(display, e.to_string());
+                return;
+            }
+        };
+        let entries = match read_dir_at(source.as_fd()) {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.failed(display, e.to_string());
+                return;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    self.failed(display, e.to_string());
+                    continue;
+                }
+            };
+            let name = entry.file_name();
+            let child_display = format!("{display}/{}", name.to_string_lossy());
+            let child_target_display = format!("{target_display}/{}", name.to_string_lossy());
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => self.dir(
+                    source.as_fd(),
+                    &name,
+                    target.as_fd(),
+                    &name,
+                    &child_display,
+                    &child_target_display,
+                ),
+                Ok(file_type) => self.file(
+                    source.as_fd(),
+                    target.as_fd(),
+                    &name,
+                    file_type,
+                    &child_display,
+                    &child_target_display,
+                ),
+                Err(e) => self.failed(&child_display, e.to_string()),
+            }
+        }
+        if self.mode == TransferMode::Move {
+            // Fails, and keeps the source, when some entries were skipped or failed.
+            let _ = unlink_at(source_parent, source_name, true);
+        }
+    }
+
+    /// Transfers anything but a directory: regular files by content, symlinks and FIFOs as
+    /// themselves.
+    fn file(
+        &mut self,
+        source: BorrowedFd,
+        target: BorrowedFd,
+        name: &OsStr,
+        file_type: FileType,
+        display: &str,
+        target_display: &str,
+    ) {
+        let (target_name, overwrite) = if exists_at(target, name) {
+            match self.on_conflict {
+                ConflictStrategy::Skip => {
+                    self.push(display, IoReportOutcome::Skipped);
+                    return;
+                }
+                ConflictStrategy::Overwrite => (name.to_os_string(), true),
+                ConflictStrategy::Rename => {
+                    let renamed = free_name(target, name);
+                    let new_path = sibling_display(target_display, &renamed);
+                    self.push(display, IoReportOutcome::Renamed { new_path });
+                    (renamed, false)
+                }
+            }
+        } else {
+            (name.to_os_string(), false)
+        };
+        let result = match (self.mode, file_type.is_file()) {
+            (TransferMode::Copy, true) => {
+                copy_file_at(source, name, target, &target_name, overwrite)
+            }
+            (TransferMode::Copy, false) => {
+                copy_special_at(source, name, target, &target_name, overwrite)
+            }
+            (TransferMode::Move, true) => move_file_at(source, name, target, &target_name),
+            (TransferMode::Move, false) => move_special_at(source, name, target, &target_name),
+        };
+        match result {
+            Ok(()) => self.report.done += 1,
+            Err(e) => self.failed(display, e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use crate::fs_user::{as_service, FsUser};
+use crate::project_fs::{
+    check_writable_at, chown_at, display_path, exists_at, metadata_at, open_dir_at, open_dir_path,
+    project_rel_path, read_dir_at, Entry, ProjectDir,
+};
+use crate::project_lock::ProjectLocks;
+use crate::transfer::{free_name, move_file_at, transfer_dir, TransferMode};
+use crate::{ServerError, ServerResult};
+use common::paths::paths;
+use common::server_action::project_action::io_action::trash_action::{
+    TrashEntry, TRASH_MAX_SIZE, TRASH_RETENTION_DAYS,
+};
+use common::server_action::project_action::io_action::{
+    ConflictStrategy, IoReportEntry, IoReportOutcome, ServerProjectIoReport,
+};
+use common::{get_project_trash_path, ProjectRelPath, ProjectSlugStr};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+const TRASH_RETENTION: Duration = Duration::from_secs(TRASH_RETENTION_DAYS * 24 * 3600);
+/// Entries without metadata are a delete that is still running, or one that crashed midway.
+const TRASH_INCOMPLETE_GRACE: Duration = Duration::from_secs(3600);
+
+const TRASH_ITEM_NAME: &str = "item";
+const TRASH_META_NAME: &str = "meta.json";
+
+fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn entry_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+fn read_entry(entry_dir: &Path) -> ServerResult<TrashEntry> {
+    let meta = fs::read(entry_dir.join(TRASH_META_NAME))?;
+    Ok(serde_json::from_slice(&meta)?)
+}
+
+fn entry_dir(project_slug: &ProjectSlugStr, id: &str) -> ServerResult<PathBuf> {
+    let entry_dir = Path::new(&get_project_trash_path(project_slug)).join(id);
+    if !entry_dir.is_dir() {
+        return Err(ServerError::TrashEntryNotFound);
+    }
+    Ok(entry_dir)
+}
+
+/// The item slot of a trash entry, as a transfer endpoint.
+fn trash_item(entry_dir: &Path) -> ServerResult<Entry> {
+    Ok(Entry {
+        parent: open_dir_path(entry_dir)?,
+        name: OsString::from(TRASH_ITEM_NAME),
+    })
+}
+
+/// Moves `source`, the project entry at `rel`, into the project trash, then purges the trash.
+/// Runs as the service, which owns the trash; callers acting for a user check first that
+/// the user may remove `source`.
+pub fn move_to_trash(
+    project_slug: &ProjectSlugStr,
+    source: &Entry,
+    rel: &Path,
+) -> ServerResult<()> {
+    let is_dir = source.metadata()?.is_dir();
+    let trash_root = PathBuf::from(get_project_trash_path(project_slug));
+    let id = Uuid::new_v4().simple().to_string();
+    let entry_dir = trash_root.join(&id);
+    fs::create_dir_all(&entry_dir)?;
+
+    let original_path = display_path(rel);
+    let item = entry_dir.join(TRASH_ITEM_NAME);
+    let target = trash_item(&entry_dir)?;
+    let complete = if is_dir {
+        let report = transfer_dir(
+            source,
+            &target,
+            &original_path,
+            &original_path,
+            TransferMode::Move,
+            ConflictStrategy::Skip,
+        )?;
+        report.issues.is_empty()
+    } else {
+        move_file_at(source.dir(), &source.name, target.dir(), &target.name)?;
+        true
+    };
+    let entry = TrashEntry {
+        id,
+        original_path,
+        is_dir,
+        // Measured once in the trash, which project users cannot reach.
+        size: entry_size(&item),
+        deleted_at: now_secs(),
+    };
+    // Written last: whatever did reach the trash stays restorable.
+    fs::write(entry_dir.join(TRASH_META_NAME), serde_json::to_vec(&entry)?)?;
+    if let Err(e) = purge_trash(&trash_root) {
+        warn!("Trash purge failed for {}: {e}", project_slug.0);
+    }
+    if complete {
+        Ok(())
+    } else {
+        Err(ServerError::TrashIncomplete)
+    }
+}
+
+/// Newest first.
+pub fn list_trash(project_slug: &ProjectSlugStr) -> ServerResult<Vec<TrashEntry>> {
+    let trash_root = PathBuf::from(get_project_trash_path(project_slug));
+    if !trash_root.is_dir() {
+        return Ok(Vec::new());
+    }
+    purge_trash(&trash_root)?;
+    let mut entries = fs::read_dir(&trash_root)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| read_entry(&entry.path()).ok())
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at));
+    Ok(entries)
+}
+
+/// Puts the entry back at its original path, recreating missing parent folders. When that
+/// path is taken again, the entry is restored under a free name and the report says so.
+///
+/// Called on a thread switched to `fs_user`: the destination is created and checked as the
+/// user, the trash is read as the service, and the restored entry is handed to the user.
+pub fn restore_from_trash(
+    project_slug: &ProjectSlugStr,
+    project: &ProjectDir,
+    id: &str,
+    fs_user: FsUser,
+) -> ServerResult<ServerProjectIoReport> {
+    let (entry_dir, entry) = as_service(|| {
+        let entry_dir = entry_dir(project_slug, id)?;
+        let entry = read_entry(&entry_dir)?;
+        Ok((entry_dir, entry))
+    })?;
+    let relative = project_rel_path(&ProjectRelPath::from_str(&entry.original_path)?);
+    let name = relative
+        .file_name()
+        .ok_or(ServerError::PathNotAValidProjectPath)?;
+    let parent = project.create_dir_all(relative.parent().unwrap_or(Path::new("")))?;
+    check_writable_at(parent.as_fd())?;
+
+    let mut report = ServerProjectIoReport::default();
+    let target = if exists_at(parent.as_fd(), name) {
+        let renamed = free_name(parent.as_fd(), name);
+        report.issues.push(IoReportEntry {
+            path: entry.original_path.clone(),
+            outcome: IoReportOutcome::Renamed {
+                new_path: display_path(&relative.with_file_name(&renamed)),
+            },
+        });
+        Entry {
+            parent,
+            name: renamed,
+        }
+    } else {
+        Entry {
+            parent,
+            name: name.to_os_string(),
+        }
+    };
+
+    as_service(|| {
+        let item = trash_item(&entry_dir)?;
+        let complete = if entry.is_dir {
+            let transfer = transfer_dir(
+                &item,
+                &target,
+                &entry.original_path,
+                &display_path(&relative.with_file_name(&target.name)),
+                TransferMode::Move,
+                ConflictStrategy::Skip,
+            )?;
+            let complete = transfer.issues.is_empty();
+            report.issues.extend(transfer.issues);
+            complete
+        } else {
+            move_file_at(item.dir(), &item.name, target.dir(), &target.name)?;
+            true
+        };
+        // Copied out of the trash by the service; the user restoring it becomes the owner.
+        let owned = chown_tree(target.dir(), &target.name, fs_user);
+        if complete {
+            owned?;
+            fs::remove_dir_all(&entry_dir)?;
+            report.done = 1;
+        }
+        Ok(report)
+    })
+}
+
+fn chown_tree(dir: BorrowedFd, name: &OsStr, fs_user: FsUser) -> io::Result<()> {
+    chown_at(dir, name, fs_user.uid, fs_user.gid)?;
+    if metadata_at(dir, name)?.is_dir() {
+        let child = open_dir_at(dir, Path::new(name))?;
+        for entry in read_dir_at(child.as_fd())? {
+            chown_tree(child.as_fd(), &entry?.file_name(), fs_user)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn delete_from_trash(project_slug: &ProjectSlugStr, id: &str) -> ServerResult<()> {
+    fs::remove_dir_all(entry_dir(project_slug, id)?)?;
+    Ok(())
+}
+
+pub fn empty_trash(project_slug: &ProjectSlugStr) -> ServerResult<()> {
+    let trash_root = PathBuf::from(get_project_trash_path(project_slug));
+    if trash_root.is_dir() {
+        fs::remove_dir_all(trash_root)?;
+    }
+    Ok(())
+}
+
+/// Drops expired entries, then the oldest ones until the trash fits in `TRASH_MAX_SIZE`.
+pub fn purge_trash(trash_root: &Path) -> ServerResult<()> {
+    let expired_before = now_secs() - TRASH_RETENTION.as_secs() as i64;
+    let mut kept = Vec::new();
+    for dir_entry in fs::read_dir(trash_root)?.filter_map(|entry| entry.ok()) {
+        let path = dir_entry.path();
+        match read_entry(&path) {
+            Ok(entry) if entry.deleted_at < expired_before => fs::remove_dir_all(&path)?,
+            Ok(entry) => kept.push((path, entry)),
+            Err(_) => {
+                let is_stale = dir_entry
+                    .metadata()
+                    .and_then(|meta| meta.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|age| age > TRASH_INCOMPLETE_GRACE);
+                if is_stale {
+                    fs::remove_dir_all(&path)?;
+                }
+            }
+        }
+    }
+
+    kept.sort_by(|(_, a), (_, b)| a.deleted_at.cmp(&b.deleted_at));
+    let mut total = kept.iter().map(|(_, entry)| entry.size).sum::<u64>();
+    // The newest entry is always kept, so the last delete can be undone whatever its size.
+    let purgeable = kept.len().saturating_sub(1);
+    for (path, entry) in kept.into_iter().take(purgeable) {
+        if total <= TRASH_MAX_SIZE {
+            break;
+        }
+        fs::remove_dir_all(&path)?;
+        total -= entry.size;
+    }
+    Ok(())
+}
+
+/// Purges every project trash; run periodically so idle projects are cleaned too. Each
+/// project is held while its trash is purged, so a restore doesn't lose its entry midway.
+pub async fn purge_all_trash(project_locks: &ProjectLocks) {
+    let Ok(trash_roots) = fs::read_dir(&paths().trash_root) else {
+        return;
+    };
+    for trash_root in trash_roots.filter_map(|entry| entry.ok()) {
+        let Ok(name) = trash_root.file_name().into_string() else {
+            continue;
+        };
+        let project_slug = ProjectSlugStr(name);
+        let _turn = project_locks
+            .lock(&project_slug, "trash.purge".to_string(), None)
+            .await;
+        let path = trash_root.path();
+        match tokio::task::spawn_blocking(move || purge_trash(&path)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Trash purge failed for {}: {e}", project_slug.0),
+            Err(e) => warn!("Trash purge task failed for {}: {e}", project_slug.0),
+        }
+    }
+}
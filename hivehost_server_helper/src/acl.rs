@@ -0,0 +1,351 @@
+//! POSIX ACLs read and written as the `system.posix_acl_access` and `system.posix_acl_default`
+//! extended attributes, in the format the kernel stores them in. Changes follow `setfacl`:
+//! the mask is recalculated after each one, and a default ACL created from nothing starts
+//! from the owner, group and other entries of the access ACL.
+//!
+//! Project files belong to users, so every file is opened relative to its parent directory
+//! without following symlinks, and symlinks are skipped; a link planted in a project can't
+//! point a recursive change elsewhere.
+
+use std::ffi::{CStr, CString};
+use std::fs::ReadDir;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStringExt;
+
+const XATTR_ACCESS: &CStr = c"system.posix_acl_access";
+const XATTR_DEFAULT: &CStr = c"system.posix_acl_default";
+const ACL_XATTR_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+const ACL_READ: u16 = 0x04;
+const ACL_WRITE: u16 = 0x02;
+const ACL_EXECUTE: u16 = 0x01;
+
+/// Gives `uid` read access to `path`, write access too unless `read_only`, and execute
+/// access to directories and to files someone can already execute, like `setfacl -m
+/// u:NAME:rwX`. `recursive` applies it to everything under `path` as well. A directory also
+/// gets the entry in its default ACL, so files created in it later inherit it.
+pub fn grant(path: &str, uid: u32, read_only: bool, recursive: bool) -> io::Result<()> {
+    let root = Node::open(path)?;
+    let perm = if read_only {
+        ACL_READ
+    } else {
+        ACL_READ | ACL_WRITE
+    };
+    let grant_access = |node: &Node| {
+        let mut acl = node.access_acl()?;
+        acl.set_user(uid, perm | node.execute_perm());
+        node.write_acl(XATTR_ACCESS, &acl)
+    };
+    if recursive {
+        walk(root.clone_fd()?, grant_access)?;
+    } else {
+        grant_access(&root)?;
+    }
+    if root.is_dir() {
+        let mut acl = root.default_acl()?;
+        acl.set_user(uid, perm | ACL_EXECUTE);
+        root.write_acl(XATTR_DEFAULT, &acl)?;
+    }
+    Ok(())
+}
+
+/// Takes the entries of `uid` out of the access and default ACLs of `path`.
+pub fn revoke(path: &str, uid: u32) -> io::Result<()> {
+    let root = Node::open(path)?;
+    for name in [XATTR_ACCESS, XATTR_DEFAULT] {
+        if let Some(mut acl) = root.read_acl(name)?
+            && acl.remove_user(uid)
+        {
+            root.write_acl(name, &acl)?;
+        }
+    }
+    Ok(())
+}
+
+/// Users named in the access ACL of `path`, and whether their entry is read-only.
+pub fn users(path: &str) -> io::Result<Vec<(u32, bool)>> {
+    let acl = Node::open(path)?.read_acl(XATTR_ACCESS)?;
+    Ok(acl
+        .iter()
+        .flat_map(|acl| &acl.0)
+        .filter(|entry| entry.tag == ACL_USER)
+        .map(|entry| (entry.id, entry.perm & ACL_WRITE == 0))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    tag: u16,
+    perm: u16,
+    id: u32,
+}
+
+impl Entry {
+    fn new(tag: u16, perm: u16) -> Self {
+        Entry {
+            tag,
+            perm,
+            id: ACL_UNDEFINED_ID,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Acl(Vec<Entry>);
+
+impl Acl {
+    /// The ACL a file without extended entries has, read from its permission bits.
+    fn from_mode(mode: u32) -> Self {
+        Acl(vec![
+            Entry::new(ACL_USER_OBJ, (mode >> 6) as u16 & 0o7),
+            Entry::new(ACL_GROUP_OBJ, (mode >> 3) as u16 & 0o7),
+            Entry::new(ACL_OTHER, mode as u16 & 0o7),
+        ])
+    }
+
+    fn parse(bytes: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed ACL attribute");
+        let (version, entries) = bytes.split_first_chunk::<4>().ok_or_else(invalid)?;
+        if u32::from_le_bytes(*version) != ACL_XATTR_VERSION || entries.len() % 8 != 0 {
+            return Err(invalid());
+        }
+        Ok(Acl(entries
+            .chunks_exact(8)
+            .map(|entry| Entry {
+                tag: u16::from_le_bytes([entry[0], entry[1]]),
+                perm: u16::from_le_bytes([entry[2], entry[3]]),
+                id: u32::from_le_bytes([entry[4], entry[5], entry[6], entry[7]]),
+            })
+            .collect()))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + 8 * self.0.len());
+        bytes.extend(ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.0 {
+            bytes.extend(entry.tag.to_le_bytes());
+            bytes.extend(entry.perm.to_le_bytes());
+            bytes.extend(entry.id.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn set_user(&mut self, uid: u32, perm: u16) {
+        match self
+            .0
+            .iter_mut()
+            .find(|entry| entry.tag == ACL_USER && entry.id == uid)
+        {
+            Some(entry) => entry.perm = perm,
+            None => self.0.push(Entry {
+                tag: ACL_USER,
+                perm,
+                id: uid,
+            }),
+        }
+        self.update_mask();
+    }
+
+    fn remove_user(&mut self, uid: u32) -> bool {
+        let len = self.0.len();
+        self.0
+            .retain(|entry| !(entry.tag == ACL_USER && entry.id == uid));
+        if self.0.len() == len {
+            return false;
+        }
+        self.update_mask();
+        true
+    }
+
+    /// Sets the mask to the union of the group class entries, adding it when named entries
+    /// need one, and puts the entries in the order the kernel requires.
+    fn update_mask(&mut self) {
+        let needs_mask = self
+            .0
+            .iter()
+            .any(|entry| matches!(entry.tag, ACL_USER | ACL_GROUP | ACL_MASK));
+        if needs_mask {
+            let perm = self
+                .0
+                .iter()
+                .filter(|entry| matches!(entry.tag, ACL_USER | ACL_GROUP_OBJ | ACL_GROUP))
+                .fold(0, |perm, entry| perm | entry.perm);
+            self.0.retain(|entry| entry.tag != ACL_MASK);
+            self.0.push(Entry::new(ACL_MASK, perm));
+        }
+        self.0.sort_by_key(|entry| (entry.tag, entry.id));
+    }
+}
+
+/// A file opened with `O_PATH`, which reads nothing and follows no symlink.
+struct Node {
+    fd: OwnedFd,
+    mode: u32,
+}
+
+impl Node {
+    fn open(path: &str) -> io::Result<Self> {
+        let path = CString::new(path).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Self::open_at(None, &path)
+    }
+
+    fn open_at(dir: Option<BorrowedFd>, name: &CStr) -> io::Result<Self> {
+        let dir = dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+        let flags = libc::O_PATH | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        // SAFETY: `name` is NUL terminated and outlives the call.
+        let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: openat just returned this descriptor and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: `stat` is plain data, filled in by fstat.
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        // SAFETY: the descriptor is open and `stat` is valid for writes.
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Node {
+            fd,
+            mode: stat.st_mode,
+        })
+    }
+
+    fn clone_fd(&self) -> io::Result<Self> {
+        Ok(Node {
+            fd: self.fd.try_clone()?,
+            mode: self.mode,
+        })
+    }
+
+    fn is_dir(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFDIR
+    }
+
+    fn is_symlink(&self) -> bool {
+        self.mode & libc::S_IFMT == libc::S_IFLNK
+    }
+
+    /// `X`: directories are searched, files only run if someone could already run them.
+    fn execute_perm(&self) -> u16 {
+        if self.is_dir() || self.mode & 0o111 != 0 {
+            ACL_EXECUTE
+        } else {
+            0
+        }
+    }
+
+    /// Path that reaches the opened file itself, however it was renamed since. Extended
+    /// attributes can't be read or written through an `O_PATH` descriptor directly.
+    fn proc_path(&self) -> CString {
+        CString::new(format!("/proc/self/fd/{}", self.fd.as_raw_fd()))
+            .expect("a number has no NUL byte")
+    }
+
+    fn access_acl(&self) -> io::Result<Acl> {
+        Ok(self
+            .read_acl(XATTR_ACCESS)?
+            .unwrap_or_else(|| Acl::from_mode(self.mode)))
+    }
+
+    fn default_acl(&self) -> io::Result<Acl> {
+        if let Some(acl) = self.read_acl(XATTR_DEFAULT)? {
+            return Ok(acl);
+        }
+        let mut acl = self.access_acl()?;
+        acl.0
+            .retain(|entry| matches!(entry.tag, ACL_USER_OBJ | ACL_GROUP_OBJ | ACL_OTHER));
+        Ok(acl)
+    }
+
+    fn read_acl(&self, name: &CStr) -> io::Result<Option<Acl>> {
+        let path = self.proc_path();
+        let mut buf = vec![0u8; 4 + 8 * 32];
+        loop {
+            // SAFETY: both strings are NUL terminated and `buf` is valid for `buf.len()` bytes.
+            let len = unsafe {
+                libc::getxattr(
+                    path.as_ptr(),
+                    name.as_ptr(),
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                )
+            };
+            if len >= 0 {
+                return Acl::parse(&buf[..len as usize]).map(Some);
+            }
+            let e = io::Error::last_os_error();
+            match e.raw_os_error() {
+                Some(libc::ENODATA) => return Ok(None),
+                Some(libc::ERANGE) if buf.len() < 1 << 16 => buf.resize(buf.len() * 4, 0),
+                _ => return Err(e),
+            }
+        }
+    }
+
+    fn write_acl(&self, name: &CStr, acl: &Acl) -> io::Result<()> {
+        let path = self.proc_path();
+        let bytes = acl.to_bytes();
+        // SAFETY: both strings are NUL terminated and `bytes` is valid for its length.
+        let rc = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                bytes.as_ptr().cast(),
+                bytes.len(),
+                0,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Calls `f` on `root` and everything under it, depth first, skipping symlinks. Files removed
+/// while the walk runs are skipped too.
+fn walk(root: Node, mut f: impl FnMut(&Node) -> io::Result<()>) -> io::Result<()> {
+    f(&root)?;
+    if !root.is_dir() {
+        return Ok(());
+    }
+    let entries = read_dir(&root)?;
+    let mut stack: Vec<(Node, ReadDir)> = vec![(root, entries)];
+    while let Some((dir, entries)) = stack.last_mut() {
+        let Some(entry) = entries.next() else {
+            stack.pop();
+            continue;
+        };
+        let name = CString::new(entry?.file_name().into_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidFilename))?;
+        let node = match Node::open_at(Some(dir.fd.as_fd()), &name) {
+            Ok(node) => node,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if node.is_symlink() {
+            continue;
+        }
+        f(&node)?;
+        if node.is_dir() {
+            let entries = read_dir(&node)?;
+            stack.push((node, entries));
+        }
+    }
+    Ok(())
+}
+
+fn read_dir(node: &Node) -> io::Result<ReadDir> {
+    let path = node.proc_path();
+    std::fs::read_dir(path.to_str().expect("the path is ASCII"))
+}
@@ -0,0 +1,229 @@
+//! Everything the helper changes on the machine goes through `HelperBackend`, so commands,
+//! batches and their rollback can run against something other than a real system.
+//! `SystemBackend` makes the changes for real and needs root and the Btrfs device;
+//! `MemoryBackend` models them for unprivileged tests and development machines.
+
+use crate::users::NOLOGIN_SHELL;
+use crate::{acl, btrfs, mount, users};
+use common::helper_command::QgroupUsage;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+
+pub mod memory;
+
+pub use memory::MemoryBackend;
+
+pub type Backend = Arc<dyn HelperBackend>;
+
+/// Calls block; the slow ones, recursive ACL changes and account edits, are moved off the
+/// async workers by their callers.
+pub trait HelperBackend: Send + Sync + 'static {
+    /// Adds a system account in `group` with a locked password, no login shell and `home`.
+    fn create_system_user(&self, name: &str, group: &str, home: &str) -> io::Result<()>;
+    /// Removes an account; its home is left to the caller.
+    fn delete_user(&self, name: &str) -> io::Result<()>;
+    fn user_id(&self, name: &str) -> io::Result<Option<u32>>;
+    /// Account names by uid.
+    fn user_names(&self) -> io::Result<HashMap<u32, String>>;
+
+    fn exists(&self, path: &str) -> io::Result<bool>;
+    fn create_dir_all(&self, path: &str) -> io::Result<()>;
+    fn remove_dir(&self, path: &str) -> io::Result<()>;
+    /// Removes `path` and everything under it; a missing path is not an error.
+    fn remove_all(&self, path: &str) -> io::Result<()>;
+    /// Creates the directory `path` if needed and hands it to root with `mode`.
+    fn root_owned_dir(&self, path: &str, mode: u32) -> io::Result<()>;
+    /// Creates or replaces the file `path`.
+    fn write_file(&self, path: &str, contents: &[u8]) -> io::Result<()>;
+    /// Names of the entries of the directory `path`.
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>>;
+    /// Swaps two paths in a single step.
+    fn exchange_paths(&self, a: &str, b: &str) -> io::Result<()>;
+
+    /// Gives `uid` access to `path`, and to everything under it when `recursive`, as
+    /// `acl::grant` describes.
+    fn grant_acl(&self, path: &str, uid: u32, read_only: bool, recursive: bool) -> io::Result<()>;
+    fn revoke_acl(&self, path: &str, uid: u32) -> io::Result<()>;
+    /// Uids named in the access ACL of `path`, and whether their entry is read-only.
+    fn acl_users(&self, path: &str) -> io::Result<Vec<(u32, bool)>>;
+
+    fn bind_mount(&self, source: &str, target: &str) -> io::Result<()>;
+    /// Mounts the subvolume named `subvolume` at the top of the Btrfs filesystem on `target`.
+    fn mount_subvolume(&self, subvolume: &str, target: &str, read_only: bool) -> io::Result<()>;
+    fn unmount(&self, target: &str) -> io::Result<()>;
+    /// Path, inside its filesystem, of what is mounted at `path`, or `None` when nothing is.
+    fn mount_root(&self, path: &str) -> io::Result<Option<String>>;
+
+    fn is_mount_point(&self, path: &str) -> io::Result<bool> {
+        Ok(self.mount_root(path)?.is_some())
+    }
+
+    fn create_subvolume(&self, path: &str, qgroup: Option<u64>) -> io::Result<()>;
+    fn snapshot(
+        &self,
+        source: &str,
+        path: &str,
+        read_only: bool,
+        qgroup: Option<u64>,
+    ) -> io::Result<()>;
+    fn delete_subvolume(&self, path: &str) -> io::Result<()>;
+    fn enable_quota(&self, path: &str) -> io::Result<()>;
+    fn create_qgroup(&self, path: &str, qgroup: u64) -> io::Result<()>;
+    fn limit_qgroup(&self, path: &str, qgroup: u64, limit_bytes: Option<u64>) -> io::Result<()>;
+    /// Id of the subvolume holding `path`.
+    fn subvolume_id(&self, path: &str) -> io::Result<u64>;
+    /// Usage and referenced limit of `qgroup`; one that isn't tracked reads as empty.
+    fn qgroup_usage(&self, path: &str, qgroup: u64) -> io::Result<(QgroupUsage, Option<u64>)>;
+}
+
+/// The machine itself, through system calls.
+#[derive(Debug, Clone, Default)]
+pub struct SystemBackend {
+    /// Device snapshots are mounted from.
+    pub btrfs_device: String,
+}
+
+impl HelperBackend for SystemBackend {
+    fn create_system_user(&self, name: &str, group: &str, home: &str) -> io::Result<()> {
+        users::create_system_user(name, group, home, NOLOGIN_SHELL)
+    }
+
+    fn delete_user(&self, name: &str) -> io::Result<()> {
+        users::delete_user(name)
+    }
+
+    fn user_id(&self, name: &str) -> io::Result<Option<u32>> {
+        users::uid(name)
+    }
+
+    fn user_names(&self) -> io::Result<HashMap<u32, String>> {
+        users::names()
+    }
+
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        std::fs::exists(path)
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        std::fs::remove_dir(path)
+    }
+
+    fn remove_all(&self, path: &str) -> io::Result<()> {
+        match std::fs::remove_dir_all(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn root_owned_dir(&self, path: &str, mode: u32) -> io::Result<()> {
+        std::fs::create_dir_all(path)?;
+        std::os::unix::fs::chown(path, Some(0), Some(0))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        std::fs::read_dir(path)?
+            .filter_map(|entry| match entry {
+                Ok(entry) => entry.file_name().into_string().ok().map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect()
+    }
+
+    /// `renameat2(RENAME_EXCHANGE)`.
+    fn exchange_paths(&self, a: &str, b: &str) -> io::Result<()> {
+        let a = CString::new(a).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let b = CString::new(b).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        // SAFETY: both paths are NUL terminated and outlive the call.
+        let rc = unsafe {
+            libc::renameat2(
+                libc::AT_FDCWD,
+                a.as_ptr(),
+                libc::AT_FDCWD,
+                b.as_ptr(),
+                libc::RENAME_EXCHANGE,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn grant_acl(&self, path: &str, uid: u32, read_only: bool, recursive: bool) -> io::Result<()> {
+        acl::grant(path, uid, read_only, recursive)
+    }
+
+    fn revoke_acl(&self, path: &str, uid: u32) -> io::Result<()> {
+        acl::revoke(path, uid)
+    }
+
+    fn acl_users(&self, path: &str) -> io::Result<Vec<(u32, bool)>> {
+        acl::users(path)
+    }
+
+    fn bind_mount(&self, source: &str, target: &str) -> io::Result<()> {
+        mount::bind_mount(source, target)
+    }
+
+    fn mount_subvolume(&self, subvolume: &str, target: &str, read_only: bool) -> io::Result<()> {
+        mount::mount_subvolume(&self.btrfs_device, subvolume, target, read_only)
+    }
+
+    fn unmount(&self, target: &str) -> io::Result<()> {
+        mount::unmount(target)
+    }
+
+    fn mount_root(&self, path: &str) -> io::Result<Option<String>> {
+        mount::mount_root(path)
+    }
+
+    fn create_subvolume(&self, path: &str, qgroup: Option<u64>) -> io::Result<()> {
+        btrfs::create_subvolume(path, qgroup)
+    }
+
+    fn snapshot(
+        &self,
+        source: &str,
+        path: &str,
+        read_only: bool,
+        qgroup: Option<u64>,
+    ) -> io::Result<()> {
+        btrfs::snapshot(source, path, read_only, qgroup)
+    }
+
+    fn delete_subvolume(&self, path: &str) -> io::Result<()> {
+        btrfs::delete_subvolume(path)
+    }
+
+    fn enable_quota(&self, path: &str) -> io::Result<()> {
+        btrfs::enable_quota(path)
+    }
+
+    fn create_qgroup(&self, path: &str, qgroup: u64) -> io::Result<()> {
+        btrfs::create_qgroup(path, qgroup)
+    }
+
+    fn limit_qgroup(&self, path: &str, qgroup: u64, limit_bytes: Option<u64>) -> io::Result<()> {
+        btrfs::limit_qgroup(path, qgroup, limit_bytes)
+    }
+
+    fn subvolume_id(&self, path: &str) -> io::Result<u64> {
+        btrfs::root_id(path)
+    }
+
+    fn qgroup_usage(&self, path: &str, qgroup: u64) -> io::Result<(QgroupUsage, Option<u64>)> {
+        btrfs::qgroup_usage(path, qgroup)
+    }
+}
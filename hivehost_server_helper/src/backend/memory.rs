@@ -0,0 +1,691 @@
+//! A machine kept in memory: accounts, directories and files, subvolumes with their qgroups,
+//! ACLs and mounts, changed the way the helper changes the real ones. It fails where the
+//! kernel would and the helper's flows depend on it: missing parents, existing paths, busy
+//! mounts, read-only snapshots and exceeded limits.
+//!
+//! Groups, permission checks and extent sharing aren't modeled: a snapshot counts its files
+//! in full. Mounts are recorded but not followed, so a mount point doesn't show the mounted
+//! tree.
+
+use super::HelperBackend;
+use common::SERVICE_USER;
+use common::helper_command::QgroupUsage;
+use common::paths::paths;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Id of the top level subvolume; the ones created count up from 256, as in Btrfs.
+const TOP_LEVEL_ID: u64 = 5;
+const FIRST_SUBVOLUME_ID: u64 = 256;
+const FIRST_UID: u32 = 1000;
+
+#[derive(Debug)]
+pub struct MemoryBackend {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: BTreeMap<String, u32>,
+    next_uid: u32,
+    /// Every directory and file, by absolute path.
+    nodes: BTreeMap<String, Node>,
+    next_subvolume_id: u64,
+    /// In the order they were made; the last one on a target is the visible one.
+    mounts: Vec<Mount>,
+    quota_enabled: bool,
+    /// Referenced limit of each qgroup created.
+    qgroups: BTreeMap<u64, Option<u64>>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    /// `None` for a directory.
+    contents: Option<Vec<u8>>,
+    owner: u32,
+    mode: u32,
+    /// Named user entries, and whether each is read-only.
+    acl: BTreeMap<u32, bool>,
+    default_acl: BTreeMap<u32, bool>,
+    /// Set on the directory a subvolume starts at.
+    subvolume: Option<Subvolume>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Subvolume {
+    id: u64,
+    read_only: bool,
+    qgroup: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+struct Mount {
+    target: String,
+    /// Path of the mounted tree.
+    source: String,
+}
+
+impl MemoryBackend {
+    /// A machine with the hivehost directories and the service account in place.
+    pub fn new() -> Self {
+        let mut state = State {
+            next_uid: FIRST_UID,
+            next_subvolume_id: FIRST_SUBVOLUME_ID,
+            ..State::default()
+        };
+        state.nodes.insert("/".to_string(), Node::dir(0, 0o755));
+        let paths = paths();
+        for path in [
+            &paths.dev_root,
+            &paths.prod_root,
+            &paths.users_root,
+            &paths.trash_root,
+        ] {
+            state
+                .create_dir_all(path)
+                .expect("the root directory exists");
+        }
+        state.add_user(SERVICE_USER);
+        MemoryBackend {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Owner and permission bits of `path`, for checks on what the helper left behind.
+    pub fn ownership(&self, path: &str) -> Option<(u32, u32)> {
+        let state = self.state();
+        let node = state.nodes.get(path)?;
+        Some((node.owner, node.mode))
+    }
+
+    /// Contents of the file `path`.
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        self.state().nodes.get(path)?.contents.clone()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HelperBackend for MemoryBackend {
+    fn create_system_user(&self, name: &str, _group: &str, home: &str) -> io::Result<()> {
+        let mut state = self.state();
+        if state.users.contains_key(name) {
+            return Err(error(io::ErrorKind::AlreadyExists, name));
+        }
+        if !state.nodes.contains_key(home) {
+            state.insert(home, Node::dir(0, 0o700))?;
+        }
+        let uid = state.add_user(name);
+        state.node_mut(home)?.owner = uid;
+        Ok(())
+    }
+
+    fn delete_user(&self, name: &str) -> io::Result<()> {
+        match self.state().users.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(error(io::ErrorKind::NotFound, name)),
+        }
+    }
+
+    fn user_id(&self, name: &str) -> io::Result<Option<u32>> {
+        Ok(self.state().users.get(name).copied())
+    }
+
+    fn user_names(&self) -> io::Result<HashMap<u32, String>> {
+        Ok(self
+            .state()
+            .users
+            .iter()
+            .map(|(name, uid)| (*uid, name.clone()))
+            .collect())
+    }
+
+    fn exists(&self, path: &str) -> io::Result<bool> {
+        Ok(self.state().nodes.contains_key(path))
+    }
+
+    fn create_dir_all(&self, path: &str) -> io::Result<()> {
+        self.state().create_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state();
+        if !state.node(path)?.is_dir() {
+            return Err(error(io::ErrorKind::NotADirectory, path));
+        }
+        if state.paths_within(path).len() > 1 {
+            return Err(error(io::ErrorKind::DirectoryNotEmpty, path));
+        }
+        state.check_removable(path)?;
+        state.nodes.remove(path);
+        Ok(())
+    }
+
+    fn remove_all(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state();
+        if !state.nodes.contains_key(path) {
+            return Ok(());
+        }
+        state.check_removable(path)?;
+        for path in state.paths_within(path) {
+            state.nodes.remove(&path);
+        }
+        Ok(())
+    }
+
+    fn root_owned_dir(&self, path: &str, mode: u32) -> io::Result<()> {
+        let mut state = self.state();
+        state.create_dir_all(path)?;
+        state.check_writable(path)?;
+        let node = state.node_mut(path)?;
+        node.owner = 0;
+        node.mode = mode;
+        Ok(())
+    }
+
+    fn write_file(&self, path: &str, contents: &[u8]) -> io::Result<()> {
+        let mut state = self.state();
+        let replaced = match state.nodes.get(path) {
+            Some(node) if node.is_dir() => return Err(error(io::ErrorKind::IsADirectory, path)),
+            Some(node) => node.contents.as_ref().map_or(0, Vec::len),
+            None => 0,
+        };
+        state.check_writable(path)?;
+        state.check_quota(path, contents.len().saturating_sub(replaced) as u64)?;
+        if !state.nodes.contains_key(path) {
+            state.insert(path, Node::file(0, 0o644))?;
+        }
+        state.node_mut(path)?.contents = Some(contents.to_vec());
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &str) -> io::Result<Vec<String>> {
+        let state = self.state();
+        if !state.node(path)?.is_dir() {
+            return Err(error(io::ErrorKind::NotADirectory, path));
+        }
+        Ok(state
+            .nodes
+            .keys()
+            .filter(|child| *child != path && parent(child) == path)
+            .map(|child| file_name(child).to_string())
+            .collect())
+    }
+
+    fn exchange_paths(&self, a: &str, b: &str) -> io::Result<()> {
+        let mut state = self.state();
+        state.node(a)?;
+        state.node(b)?;
+        if is_within(a, b) || is_within(b, a) {
+            return Err(error(io::ErrorKind::InvalidInput, a));
+        }
+        for path in [a, b] {
+            state.check_writable(parent(path))?;
+            if state.is_busy(path) {
+                return Err(error(io::ErrorKind::ResourceBusy, path));
+            }
+        }
+        let a_nodes = state.take_within(a);
+        let b_nodes = state.take_within(b);
+        for (path, node) in a_nodes {
+            state.nodes.insert(moved(&path, a, b), node);
+        }
+        for (path, node) in b_nodes {
+            state.nodes.insert(moved(&path, b, a), node);
+        }
+        Ok(())
+    }
+
+    fn grant_acl(&self, path: &str, uid: u32, read_only: bool, recursive: bool) -> io::Result<()> {
+        let mut state = self.state();
+        state.node(path)?;
+        state.check_writable(path)?;
+        let paths = if recursive {
+            state.paths_within(path)
+        } else {
+            vec![path.to_string()]
+        };
+        for path in paths {
+            state.node_mut(&path)?.acl.insert(uid, read_only);
+        }
+        let node = state.node_mut(path)?;
+        if node.is_dir() {
+            node.default_acl.insert(uid, read_only);
+        }
+        Ok(())
+    }
+
+    fn revoke_acl(&self, path: &str, uid: u32) -> io::Result<()> {
+        let mut state = self.state();
+        state.check_writable(path)?;
+        let node = state.node_mut(path)?;
+        node.acl.remove(&uid);
+        node.default_acl.remove(&uid);
+        Ok(())
+    }
+
+    fn acl_users(&self, path: &str) -> io::Result<Vec<(u32, bool)>> {
+        Ok(self
+            .state()
+            .node(path)?
+            .acl
+            .iter()
+            .map(|(uid, read_only)| (*uid, *read_only))
+            .collect())
+    }
+
+    fn bind_mount(&self, source: &str, target: &str) -> io::Result<()> {
+        let mut state = self.state();
+        state.node(source)?;
+        state.mount(source, target)
+    }
+
+    fn mount_subvolume(&self, subvolume: &str, target: &str, _read_only: bool) -> io::Result<()> {
+        let mut state = self.state();
+        let source = format!("{}/{subvolume}", paths().dev_root);
+        if state.node(&source)?.subvolume.is_none() {
+            return Err(error(io::ErrorKind::InvalidInput, &source));
+        }
+        state.mount(&source, target)
+    }
+
+    fn unmount(&self, target: &str) -> io::Result<()> {
+        let mut state = self.state();
+        match state
+            .mounts
+            .iter()
+            .rposition(|mount| mount.target == target)
+        {
+            Some(index) => {
+                state.mounts.remove(index);
+                Ok(())
+            }
+            None => Err(error(io::ErrorKind::InvalidInput, target)),
+        }
+    }
+
+    fn mount_root(&self, path: &str) -> io::Result<Option<String>> {
+        Ok(self
+            .state()
+            .mounts
+            .iter()
+            .rfind(|mount| mount.target == path)
+            .map(|mount| {
+                // The Btrfs filesystem is mounted at the dev root.
+                match mount.source.strip_prefix(paths().dev_root.as_str()) {
+                    Some(root) if root.starts_with('/') => root.to_string(),
+                    _ => mount.source.clone(),
+                }
+            }))
+    }
+
+    fn create_subvolume(&self, path: &str, qgroup: Option<u64>) -> io::Result<()> {
+        let mut state = self.state();
+        check_btrfs(path)?;
+        state.check_qgroup(qgroup)?;
+        let mut node = Node::dir(0, 0o755);
+        node.subvolume = Some(state.new_subvolume(false, qgroup));
+        state.insert(path, node)
+    }
+
+    fn snapshot(
+        &self,
+        source: &str,
+        path: &str,
+        read_only: bool,
+        qgroup: Option<u64>,
+    ) -> io::Result<()> {
+        let mut state = self.state();
+        check_btrfs(path)?;
+        if state.node(source)?.subvolume.is_none() {
+            return Err(error(io::ErrorKind::InvalidInput, source));
+        }
+        if state.nodes.contains_key(path) {
+            return Err(error(io::ErrorKind::AlreadyExists, path));
+        }
+        if !state.node(parent(path))?.is_dir() {
+            return Err(error(io::ErrorKind::NotADirectory, path));
+        }
+        state.check_writable(parent(path))?;
+        state.check_qgroup(qgroup)?;
+        let subvolume = state.new_subvolume(read_only, qgroup);
+        let copies: Vec<(String, Node)> = state
+            .paths_within(source)
+            .into_iter()
+            .map(|from| (moved(&from, source, path), state.nodes[&from].clone()))
+            .collect();
+        state.nodes.extend(copies);
+        state.node_mut(path)?.subvolume = Some(subvolume);
+        Ok(())
+    }
+
+    fn delete_subvolume(&self, path: &str) -> io::Result<()> {
+        let mut state = self.state();
+        if state.node(path)?.subvolume.is_none() {
+            return Err(error(io::ErrorKind::InvalidInput, path));
+        }
+        state.check_writable(parent(path))?;
+        if state.is_busy(path) {
+            return Err(error(io::ErrorKind::ResourceBusy, path));
+        }
+        state.take_within(path);
+        Ok(())
+    }
+
+    fn enable_quota(&self, path: &str) -> io::Result<()> {
+        check_btrfs(path)?;
+        self.state().quota_enabled = true;
+        Ok(())
+    }
+
+    fn create_qgroup(&self, path: &str, qgroup: u64) -> io::Result<()> {
+        check_btrfs(path)?;
+        let mut state = self.state();
+        state.check_quota_enabled()?;
+        if state.qgroups.contains_key(&qgroup) {
+            return Err(error(io::ErrorKind::AlreadyExists, &qgroup_name(qgroup)));
+        }
+        state.qgroups.insert(qgroup, None);
+        Ok(())
+    }
+
+    fn limit_qgroup(&self, path: &str, qgroup: u64, limit_bytes: Option<u64>) -> io::Result<()> {
+        check_btrfs(path)?;
+        let mut state = self.state();
+        state.check_quota_enabled()?;
+        let limit = state
+            .qgroups
+            .get_mut(&qgroup)
+            .ok_or_else(|| error(io::ErrorKind::NotFound, &qgroup_name(qgroup)))?;
+        *limit = limit_bytes;
+        Ok(())
+    }
+
+    fn subvolume_id(&self, path: &str) -> io::Result<u64> {
+        check_btrfs(path)?;
+        let state = self.state();
+        state.node(path)?;
+        Ok(state
+            .subvolume_of(path)
+            .map_or(TOP_LEVEL_ID, |(_, subvolume)| subvolume.id))
+    }
+
+    fn qgroup_usage(&self, path: &str, qgroup: u64) -> io::Result<(QgroupUsage, Option<u64>)> {
+        check_btrfs(path)?;
+        let state = self.state();
+        let tracked = state.quota_enabled
+            && (state.qgroups.contains_key(&qgroup)
+                || state.nodes.values().any(|node| {
+                    node.subvolume
+                        .is_some_and(|subvolume| subvolume.id == qgroup)
+                }));
+        if !tracked {
+            return Ok(Default::default());
+        }
+        let limit = state.qgroups.get(&qgroup).copied().flatten();
+        Ok((state.usage(qgroup), limit))
+    }
+}
+
+impl Node {
+    fn dir(owner: u32, mode: u32) -> Self {
+        Node {
+            contents: None,
+            owner,
+            mode,
+            acl: BTreeMap::new(),
+            default_acl: BTreeMap::new(),
+            subvolume: None,
+        }
+    }
+
+    fn file(owner: u32, mode: u32) -> Self {
+        Node {
+            contents: Some(Vec::new()),
+            ..Node::dir(owner, mode)
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        self.contents.is_none()
+    }
+}
+
+impl State {
+    fn node(&self, path: &str) -> io::Result<&Node> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| error(io::ErrorKind::NotFound, path))
+    }
+
+    fn node_mut(&mut self, path: &str) -> io::Result<&mut Node> {
+        self.nodes
+            .get_mut(path)
+            .ok_or_else(|| error(io::ErrorKind::NotFound, path))
+    }
+
+    fn add_user(&mut self, name: &str) -> u32 {
+        let uid = self.next_uid;
+        self.next_uid += 1;
+        self.users.insert(name.to_string(), uid);
+        uid
+    }
+
+    /// `path` and everything under it.
+    fn paths_within(&self, path: &str) -> Vec<String> {
+        self.nodes
+            .keys()
+            .filter(|node| is_within(node, path))
+            .cloned()
+            .collect()
+    }
+
+    fn take_within(&mut self, path: &str) -> Vec<(String, Node)> {
+        self.paths_within(path)
+            .into_iter()
+            .filter_map(|path| {
+                let node = self.nodes.remove(&path)?;
+                Some((path, node))
+            })
+            .collect()
+    }
+
+    /// Adds `node` at `path`, which inherits the default ACL of its directory.
+    fn insert(&mut self, path: &str, mut node: Node) -> io::Result<()> {
+        if self.nodes.contains_key(path) {
+            return Err(error(io::ErrorKind::AlreadyExists, path));
+        }
+        let parent = self.node(parent(path))?;
+        if !parent.is_dir() {
+            return Err(error(io::ErrorKind::NotADirectory, path));
+        }
+        let inherited = parent.default_acl.clone();
+        self.check_writable(path)?;
+        if node.is_dir() {
+            node.default_acl = inherited.clone();
+        }
+        node.acl = inherited;
+        self.nodes.insert(path.to_string(), node);
+        Ok(())
+    }
+
+    fn create_dir_all(&mut self, path: &str) -> io::Result<()> {
+        match self.nodes.get(path) {
+            Some(node) if node.is_dir() => return Ok(()),
+            Some(_) => return Err(error(io::ErrorKind::NotADirectory, path)),
+            None => {}
+        }
+        self.create_dir_all(parent(path))?;
+        self.insert(path, Node::dir(0, 0o755))
+    }
+
+    /// The subvolume holding `path`, with the path it starts at.
+    fn subvolume_of(&self, path: &str) -> Option<(&str, Subvolume)> {
+        self.nodes
+            .iter()
+            .filter(|(root, _)| is_within(path, root))
+            .filter_map(|(root, node)| Some((root.as_str(), node.subvolume?)))
+            .max_by_key(|(root, _)| root.len())
+    }
+
+    fn check_writable(&self, path: &str) -> io::Result<()> {
+        match self.subvolume_of(path) {
+            Some((_, subvolume)) if subvolume.read_only => {
+                Err(error(io::ErrorKind::ReadOnlyFilesystem, path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether something is mounted on or from `path` or anything under it.
+    fn is_busy(&self, path: &str) -> bool {
+        self.mounts
+            .iter()
+            .any(|mount| is_within(&mount.target, path) || is_within(&mount.source, path))
+    }
+
+    fn check_removable(&self, path: &str) -> io::Result<()> {
+        self.check_writable(path)?;
+        if self.is_busy(path) {
+            return Err(error(io::ErrorKind::ResourceBusy, path));
+        }
+        // Subvolumes go with `delete_subvolume`, never with a plain removal.
+        if self
+            .paths_within(path)
+            .iter()
+            .any(|path| self.nodes[path].subvolume.is_some())
+        {
+            return Err(error(io::ErrorKind::PermissionDenied, path));
+        }
+        Ok(())
+    }
+
+    fn mount(&mut self, source: &str, target: &str) -> io::Result<()> {
+        if !self.node(target)?.is_dir() {
+            return Err(error(io::ErrorKind::NotADirectory, target));
+        }
+        self.mounts.push(Mount {
+            target: target.to_string(),
+            source: source.to_string(),
+        });
+        Ok(())
+    }
+
+    fn new_subvolume(&mut self, read_only: bool, qgroup: Option<u64>) -> Subvolume {
+        let id = self.next_subvolume_id;
+        self.next_subvolume_id += 1;
+        Subvolume {
+            id,
+            read_only,
+            qgroup,
+        }
+    }
+
+    fn check_quota_enabled(&self) -> io::Result<()> {
+        if !self.quota_enabled {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "quotas are not enabled",
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_qgroup(&self, qgroup: Option<u64>) -> io::Result<()> {
+        match qgroup {
+            Some(qgroup) if !self.qgroups.contains_key(&qgroup) => {
+                Err(error(io::ErrorKind::NotFound, &qgroup_name(qgroup)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails when `added` more bytes at `path` would take a qgroup past its limit.
+    fn check_quota(&self, path: &str, added: u64) -> io::Result<()> {
+        let Some((_, subvolume)) = self.subvolume_of(path).filter(|_| self.quota_enabled) else {
+            return Ok(());
+        };
+        for qgroup in [Some(subvolume.id), subvolume.qgroup].into_iter().flatten() {
+            if let Some(Some(limit)) = self.qgroups.get(&qgroup)
+                && self.usage(qgroup).referenced + added > *limit
+            {
+                return Err(error(io::ErrorKind::QuotaExceeded, path));
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes of the files in the subvolumes `qgroup` counts: its own subvolume for a level 0
+    /// qgroup, the member subvolumes for a higher one.
+    fn usage(&self, qgroup: u64) -> QgroupUsage {
+        let bytes = self
+            .nodes
+            .iter()
+            .filter_map(|(path, node)| {
+                let contents = node.contents.as_ref()?;
+                let (_, subvolume) = self.subvolume_of(path)?;
+                let counted = if qgroup >> 48 == 0 {
+                    subvolume.id == qgroup
+                } else {
+                    subvolume.qgroup == Some(qgroup)
+                };
+                counted.then_some(contents.len() as u64)
+            })
+            .sum();
+        QgroupUsage {
+            referenced: bytes,
+            exclusive: bytes,
+        }
+    }
+}
+
+fn error(kind: io::ErrorKind, what: &str) -> io::Error {
+    io::Error::new(kind, what.to_string())
+}
+
+/// Whether `path` is `dir` or under it.
+fn is_within(path: &str, dir: &str) -> bool {
+    dir == "/"
+        || path
+            .strip_prefix(dir)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(index) => &path[..index],
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// `path`, under `from`, moved to the same place under `to`.
+fn moved(path: &str, from: &str, to: &str) -> String {
+    format!("{to}{}", &path[from.len()..])
+}
+
+fn check_btrfs(path: &str) -> io::Result<()> {
+    if !is_within(path, &paths().dev_root) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path} is not on the Btrfs filesystem"),
+        ));
+    }
+    Ok(())
+}
+
+fn qgroup_name(qgroup: u64) -> String {
+    format!("qgroup {}/{}", qgroup >> 48, qgroup & ((1 << 48) - 1))
+}
@@ -0,0 +1,289 @@
+//! Batches apply entirely or not at all: before each command runs, the commands that would
+//! undo it are worked out from the current state, and when a later command fails the
+//! completed ones are undone in reverse order. A request that expired or was cancelled stops
+//! before its next command; the undo runs outside the request's command scope, so it still
+//! happens then.
+
+use crate::ServerHelperResult;
+use crate::backend::Backend;
+use crate::command::{acl_members, execute_command, project_qgroup};
+use common::command::{check_scope, unscoped};
+use common::helper_command::{HelperCommand, HelperResponse};
+use common::paths::paths;
+use common::{
+    SnapShotNameStr, Validate, get_project_dev_path, get_project_prod_path, get_user_project_path,
+};
+use std::str::FromStr;
+use tracing::error;
+
+/// Runs `actions` in order and answers with the last command's response, or with the failed
+/// step and whether the ones before it were undone.
+pub async fn execute_batch(backend: &Backend, actions: Vec<HelperCommand>) -> HelperResponse {
+    let mut response = HelperResponse::Ok;
+    let mut undo_stack = Vec::with_capacity(actions.len());
+    for (step, action) in actions.into_iter().enumerate() {
+        let result = match check_scope() {
+            Ok(()) => undo_commands(backend, &action),
+            Err(e) => Err(e.into()),
+        };
+        let result = match result {
+            Ok(undo) => execute_command(backend, action).await.map(|r| (r, undo)),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((r, undo)) => {
+                response = r;
+                undo_stack.push(undo);
+            }
+            Err(e) => {
+                error!("Error executing command {step}: {e}");
+                let rolled_back = unscoped(roll_back(backend, undo_stack)).await;
+                return HelperResponse::Error {
+                    step,
+                    error: (&e).into(),
+                    rolled_back,
+                };
+            }
+        }
+    }
+    response
+}
+
+/// Undoes completed steps, last first. Keeps going past failures so as much as possible is
+/// put back, and reports whether everything was.
+async fn roll_back(backend: &Backend, undo_stack: Vec<Option<Vec<HelperCommand>>>) -> bool {
+    let mut rolled_back = true;
+    for undo in undo_stack.into_iter().rev() {
+        let Some(undo) = undo else {
+            error!("Rollback skipped an irreversible command");
+            rolled_back = false;
+            continue;
+        };
+        for command in undo {
+            if let Err(e) = execute_command(backend, command.clone()).await {
+                error!("Rollback command {command:?} failed: {e}");
+                rolled_back = false;
+            }
+        }
+    }
+    rolled_back
+}
+
+/// Commands that undo `action`, read from the state before it runs. `None` when it can't be
+/// undone, like a deletion; batches put those last.
+fn undo_commands(
+    backend: &Backend,
+    action: &HelperCommand,
+) -> ServerHelperResult<Option<Vec<HelperCommand>>> {
+    action.validate()?;
+    Ok(match action {
+        HelperCommand::CreateUser { user_slug } => Some(vec![HelperCommand::DeleteUser {
+            user_slug: user_slug.clone(),
+        }]),
+        HelperCommand::CreateProject { project_slug, .. } => {
+            Some(vec![HelperCommand::DeleteProject {
+                project_slug: project_slug.clone(),
+            }])
+        }
+        HelperCommand::SetAcl {
+            project_slug,
+            user_slug,
+            ..
+        }
+        | HelperCommand::RemoveAcl {
+            project_slug,
+            user_slug,
+        } => {
+            let members = acl_members(backend, &get_project_dev_path(project_slug))?;
+            let undo = match members.into_iter().find(|(member, _)| member == user_slug) {
+                Some((_, is_read_only)) => HelperCommand::SetAcl {
+                    project_slug: project_slug.clone(),
+                    user_slug: user_slug.clone(),
+                    is_read_only,
+                },
+                None => HelperCommand::RemoveAcl {
+                    project_slug: project_slug.clone(),
+                    user_slug: user_slug.clone(),
+                },
+            };
+            Some(vec![undo])
+        }
+        HelperCommand::BindMountUserProject {
+            project_slug,
+            user_slug,
+        } => Some(vec![HelperCommand::UnmountUserProject {
+            project_slug: project_slug.clone(),
+            user_slug: user_slug.clone(),
+        }]),
+        HelperCommand::UnmountUserProject {
+            project_slug,
+            user_slug,
+        } => {
+            let path = get_user_project_path(user_slug, project_slug);
+            if backend.is_mount_point(&path)? {
+                Some(vec![HelperCommand::BindMountUserProject {
+                    project_slug: project_slug.clone(),
+                    user_slug: user_slug.clone(),
+                }])
+            } else {
+                Some(vec![])
+            }
+        }
+        HelperCommand::CreateSnapshot { snapshot_name, .. } => {
+            Some(vec![HelperCommand::DeleteSnapshot {
+                snapshot_name: snapshot_name.clone(),
+            }])
+        }
+        HelperCommand::MountSnapshot { project_slug, .. } => {
+            Some(vec![HelperCommand::UnmountProd {
+                project_slug: project_slug.clone(),
+            }])
+        }
+        HelperCommand::UnmountProd { project_slug } => {
+            let path = get_project_prod_path(project_slug);
+            // Prod mounts a snapshot subvolume, which the mount table shows as the root.
+            if let Some(fs_root) = backend.mount_root(&path)? {
+                SnapShotNameStr::from_str(fs_root.trim_start_matches('/'))
+                    .ok()
+                    .map(|snapshot_name| {
+                        vec![HelperCommand::MountSnapshot {
+                            project_slug: project_slug.clone(),
+                            snapshot_name,
+                        }]
+                    })
+            } else {
+                Some(vec![])
+            }
+        }
+        HelperCommand::SetProjectQuota { project_slug, .. } => {
+            let (_, limit_bytes) =
+                backend.qgroup_usage(&paths().dev_root, project_qgroup(project_slug)?)?;
+            Some(vec![HelperCommand::SetProjectQuota {
+                project_slug: project_slug.clone(),
+                limit_bytes,
+            }])
+        }
+        HelperCommand::EnableQuota | HelperCommand::ProjectUsage { .. } => Some(vec![]),
+        // Restores roll themselves back and keep a backup; deletions are final.
+        HelperCommand::RestoreSnapshot { .. }
+        | HelperCommand::DeleteUser { .. }
+        | HelperCommand::DeleteProject { .. }
+        | HelperCommand::DeleteSnapshot { .. } => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use common::{ProjectSlugStr, UserSlugStr, get_project_snapshot_path, get_user_path};
+    use std::sync::Arc;
+
+    fn user(slug: &str) -> UserSlugStr {
+        UserSlugStr(slug.to_string())
+    }
+
+    fn project(slug: &str) -> ProjectSlugStr {
+        ProjectSlugStr(slug.to_string())
+    }
+
+    /// `alice-1` owning `site-1`.
+    fn set_up_project() -> Vec<HelperCommand> {
+        vec![
+            HelperCommand::EnableQuota,
+            HelperCommand::CreateUser {
+                user_slug: user("alice-1"),
+            },
+            HelperCommand::CreateProject {
+                project_slug: project("site-1"),
+                user_slug: user("alice-1"),
+                with_index_html: true,
+            },
+        ]
+    }
+
+    #[tokio::test]
+    async fn a_failed_step_undoes_the_ones_before_it() {
+        let backend: Backend = Arc::new(MemoryBackend::new());
+        assert_eq!(
+            execute_batch(&backend, set_up_project()).await,
+            HelperResponse::Ok
+        );
+        let dev_path = get_project_dev_path(&project("site-1"));
+        let members = acl_members(&backend, &dev_path).unwrap();
+
+        let response = execute_batch(
+            &backend,
+            vec![
+                HelperCommand::CreateUser {
+                    user_slug: user("bob-2"),
+                },
+                HelperCommand::SetAcl {
+                    project_slug: project("site-1"),
+                    user_slug: user("bob-2"),
+                    is_read_only: false,
+                },
+                HelperCommand::SetAcl {
+                    project_slug: project("site-1"),
+                    user_slug: user("alice-1"),
+                    is_read_only: true,
+                },
+                // No such project to snapshot.
+                HelperCommand::CreateSnapshot {
+                    project_slug: project("ghost-9"),
+                    snapshot_name: SnapShotNameStr("ghost-9_snapshot_a".to_string()),
+                },
+            ],
+        )
+        .await;
+        assert!(matches!(
+            response,
+            HelperResponse::Error {
+                step: 3,
+                rolled_back: true,
+                ..
+            }
+        ));
+        assert_eq!(backend.user_id("bob-2").unwrap(), None);
+        assert!(!backend.exists(&get_user_path(&user("bob-2"))).unwrap());
+        assert_eq!(acl_members(&backend, &dev_path).unwrap(), members);
+    }
+
+    #[tokio::test]
+    async fn an_irreversible_step_is_reported_as_not_rolled_back() {
+        let backend: Backend = Arc::new(MemoryBackend::new());
+        let mut setup = set_up_project();
+        setup.push(HelperCommand::CreateSnapshot {
+            project_slug: project("site-1"),
+            snapshot_name: SnapShotNameStr("site-1_snapshot_a".to_string()),
+        });
+        assert_eq!(execute_batch(&backend, setup).await, HelperResponse::Ok);
+
+        let response = execute_batch(
+            &backend,
+            vec![
+                HelperCommand::DeleteSnapshot {
+                    snapshot_name: SnapShotNameStr("site-1_snapshot_a".to_string()),
+                },
+                HelperCommand::MountSnapshot {
+                    project_slug: project("site-1"),
+                    snapshot_name: SnapShotNameStr("site-1_snapshot_a".to_string()),
+                },
+            ],
+        )
+        .await;
+        assert!(matches!(
+            response,
+            HelperResponse::Error {
+                step: 1,
+                rolled_back: false,
+                ..
+            }
+        ));
+        assert!(
+            !backend
+                .exists(&get_project_snapshot_path("site-1_snapshot_a"))
+                .unwrap()
+        );
+    }
+}
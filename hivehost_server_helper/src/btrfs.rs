@@ -0,0 +1,326 @@
+//! Btrfs subvolumes, snapshots and qgroups through the kernel's ioctls, the ones the `btrfs`
+//! tool itself issues. Qgroup usage is read from sysfs, which the kernel keeps up to date
+//! with the same numbers `btrfs qgroup show` prints.
+
+use common::helper_command::QgroupUsage;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+const BTRFS_IOCTL_MAGIC: u64 = 0x94;
+const BTRFS_PATH_NAME_MAX: usize = 4087;
+const BTRFS_SUBVOL_NAME_MAX: usize = 4039;
+const BTRFS_INO_LOOKUP_PATH_MAX: usize = 4080;
+const BTRFS_FIRST_FREE_OBJECTID: u64 = 256;
+const BTRFS_SUBVOL_RDONLY: u64 = 1 << 1;
+const BTRFS_SUBVOL_QGROUP_INHERIT: u64 = 1 << 2;
+const BTRFS_QGROUP_LIMIT_MAX_RFER: u64 = 1 << 0;
+const BTRFS_QUOTA_CTL_ENABLE: u64 = 1;
+
+const BTRFS_IOC_SNAP_DESTROY: u64 = iow::<VolArgs>(15);
+const BTRFS_IOC_INO_LOOKUP: u64 = iowr::<InoLookupArgs>(18);
+const BTRFS_IOC_SNAP_CREATE_V2: u64 = iow::<VolArgsV2>(23);
+const BTRFS_IOC_SUBVOL_CREATE_V2: u64 = iow::<VolArgsV2>(24);
+const BTRFS_IOC_FS_INFO: u64 = ior::<FsInfoArgs>(31);
+const BTRFS_IOC_QUOTA_CTL: u64 = iowr::<QuotaCtlArgs>(40);
+const BTRFS_IOC_QGROUP_CREATE: u64 = iow::<QgroupCreateArgs>(42);
+// Declared read-only in the kernel headers although the kernel only reads it.
+const BTRFS_IOC_QGROUP_LIMIT: u64 = ior::<QgroupLimitArgs>(43);
+
+const fn ioc<T>(direction: u64, number: u64) -> u64 {
+    (direction << 30) | ((size_of::<T>() as u64) << 16) | (BTRFS_IOCTL_MAGIC << 8) | number
+}
+
+const fn iow<T>(number: u64) -> u64 {
+    ioc::<T>(1, number)
+}
+
+const fn ior<T>(number: u64) -> u64 {
+    ioc::<T>(2, number)
+}
+
+const fn iowr<T>(number: u64) -> u64 {
+    ioc::<T>(3, number)
+}
+
+// The argument structs below mirror the kernel's; their fields are read by the kernel, which
+// the dead code lint can't see.
+#[repr(C)]
+#[allow(dead_code)]
+struct VolArgs {
+    fd: i64,
+    name: [u8; BTRFS_PATH_NAME_MAX + 1],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct VolArgsV2 {
+    fd: i64,
+    transid: u64,
+    flags: u64,
+    size: u64,
+    qgroup_inherit: u64,
+    unused: [u64; 2],
+    name: [u8; BTRFS_SUBVOL_NAME_MAX + 1],
+}
+
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct QgroupLimit {
+    flags: u64,
+    max_referenced: u64,
+    max_exclusive: u64,
+    reserved_referenced: u64,
+    reserved_exclusive: u64,
+}
+
+/// `btrfs_qgroup_inherit` with room for the one qgroup a new subvolume joins.
+#[repr(C)]
+#[derive(Default)]
+#[allow(dead_code)]
+struct QgroupInherit {
+    flags: u64,
+    num_qgroups: u64,
+    num_ref_copies: u64,
+    num_excl_copies: u64,
+    limit: QgroupLimit,
+    qgroups: [u64; 1],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct InoLookupArgs {
+    tree_id: u64,
+    object_id: u64,
+    name: [u8; BTRFS_INO_LOOKUP_PATH_MAX],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct FsInfoArgs {
+    max_id: u64,
+    num_devices: u64,
+    fsid: [u8; 16],
+    node_size: u32,
+    sector_size: u32,
+    clone_alignment: u32,
+    csum_type: u16,
+    csum_size: u16,
+    flags: u64,
+    generation: u64,
+    metadata_uuid: [u8; 16],
+    reserved: [u8; 944],
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct QuotaCtlArgs {
+    cmd: u64,
+    status: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct QgroupCreateArgs {
+    create: u64,
+    qgroup_id: u64,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct QgroupLimitArgs {
+    qgroup_id: u64,
+    limit: QgroupLimit,
+}
+
+/// Id of qgroup `level/id` as the kernel packs it.
+pub fn qgroup_id(level: u16, id: u64) -> u64 {
+    ((level as u64) << 48) | id
+}
+
+/// Creates the subvolume `path`, optionally as a member of `qgroup`.
+pub fn create_subvolume(path: &str, qgroup: Option<u64>) -> io::Result<()> {
+    let (parent, name) = split_parent(path)?;
+    let mut args = vol_args_v2(name, qgroup.is_some())?;
+    let mut inherit = qgroup.map(inherit_from);
+    if let Some(inherit) = &mut inherit {
+        args.size = size_of::<QgroupInherit>() as u64;
+        args.qgroup_inherit = inherit as *mut QgroupInherit as u64;
+    }
+    ioctl(&parent, BTRFS_IOC_SUBVOL_CREATE_V2, &mut args)
+}
+
+/// Snapshots the subvolume `source` to `path`, optionally read-only and as a member of `qgroup`.
+pub fn snapshot(source: &str, path: &str, read_only: bool, qgroup: Option<u64>) -> io::Result<()> {
+    let source = File::open(source)?;
+    let (parent, name) = split_parent(path)?;
+    let mut args = vol_args_v2(name, qgroup.is_some())?;
+    args.fd = source.as_raw_fd() as i64;
+    if read_only {
+        args.flags |= BTRFS_SUBVOL_RDONLY;
+    }
+    let mut inherit = qgroup.map(inherit_from);
+    if let Some(inherit) = &mut inherit {
+        args.size = size_of::<QgroupInherit>() as u64;
+        args.qgroup_inherit = inherit as *mut QgroupInherit as u64;
+    }
+    ioctl(&parent, BTRFS_IOC_SNAP_CREATE_V2, &mut args)
+}
+
+/// Deletes the subvolume or snapshot `path` with everything in it. The space is given back
+/// by the kernel in the background.
+pub fn delete_subvolume(path: &str) -> io::Result<()> {
+    let (parent, name) = split_parent(path)?;
+    let mut args = VolArgs {
+        fd: 0,
+        name: [0; BTRFS_PATH_NAME_MAX + 1],
+    };
+    copy_name(&mut args.name, name)?;
+    ioctl(&parent, BTRFS_IOC_SNAP_DESTROY, &mut args)
+}
+
+/// Turns quotas on for the filesystem holding `path`; already on is not an error.
+pub fn enable_quota(path: &str) -> io::Result<()> {
+    let mut args = QuotaCtlArgs {
+        cmd: BTRFS_QUOTA_CTL_ENABLE,
+        status: 0,
+    };
+    ioctl(&File::open(path)?, BTRFS_IOC_QUOTA_CTL, &mut args)
+}
+
+pub fn create_qgroup(path: &str, qgroup: u64) -> io::Result<()> {
+    let mut args = QgroupCreateArgs {
+        create: 1,
+        qgroup_id: qgroup,
+    };
+    ioctl(&File::open(path)?, BTRFS_IOC_QGROUP_CREATE, &mut args)
+}
+
+/// Limits the bytes `qgroup` may reference; `None` lifts the limit.
+pub fn limit_qgroup(path: &str, qgroup: u64, limit_bytes: Option<u64>) -> io::Result<()> {
+    let mut args = QgroupLimitArgs {
+        qgroup_id: qgroup,
+        limit: QgroupLimit {
+            flags: BTRFS_QGROUP_LIMIT_MAX_RFER,
+            // The kernel reads the largest value as no limit.
+            max_referenced: limit_bytes.unwrap_or(u64::MAX),
+            ..QgroupLimit::default()
+        },
+    };
+    ioctl(&File::open(path)?, BTRFS_IOC_QGROUP_LIMIT, &mut args)
+}
+
+/// Id of the subvolume holding `path`, the `id` of its level 0 qgroup.
+pub fn root_id(path: &str) -> io::Result<u64> {
+    let mut args = InoLookupArgs {
+        tree_id: 0,
+        object_id: BTRFS_FIRST_FREE_OBJECTID,
+        name: [0; BTRFS_INO_LOOKUP_PATH_MAX],
+    };
+    ioctl(&File::open(path)?, BTRFS_IOC_INO_LOOKUP, &mut args)?;
+    Ok(args.tree_id)
+}
+
+/// Usage and referenced limit of `qgroup` on the filesystem holding `path`. A qgroup the
+/// kernel doesn't track yet reads as empty and unlimited.
+pub fn qgroup_usage(path: &str, qgroup: u64) -> io::Result<(QgroupUsage, Option<u64>)> {
+    let dir = Path::new("/sys/fs/btrfs")
+        .join(fsid(path)?)
+        .join("qgroups")
+        .join(format!("{}_{}", qgroup >> 48, qgroup & ((1 << 48) - 1)));
+    let read = |name: &str| -> io::Result<u64> {
+        std::fs::read_to_string(dir.join(name))?
+            .trim()
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{name} of qgroup")))
+    };
+    let usage = match read("referenced") {
+        Ok(referenced) => QgroupUsage {
+            referenced,
+            exclusive: read("exclusive")?,
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Default::default()),
+        Err(e) => return Err(e),
+    };
+    let limit = if read("limit_flags")? & BTRFS_QGROUP_LIMIT_MAX_RFER != 0 {
+        Some(read("max_referenced")?)
+    } else {
+        None
+    };
+    Ok((usage, limit))
+}
+
+/// The filesystem's UUID, which names its directory in sysfs.
+fn fsid(path: &str) -> io::Result<String> {
+    // SAFETY: the arguments are plain data, all zeroes is a valid request.
+    let mut args: FsInfoArgs = unsafe { std::mem::zeroed() };
+    ioctl(&File::open(path)?, BTRFS_IOC_FS_INFO, &mut args)?;
+    let hex: String = args.fsid.iter().map(|b| format!("{b:02x}")).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+fn inherit_from(qgroup: u64) -> QgroupInherit {
+    QgroupInherit {
+        num_qgroups: 1,
+        qgroups: [qgroup],
+        ..QgroupInherit::default()
+    }
+}
+
+fn vol_args_v2(name: &[u8], inherit: bool) -> io::Result<VolArgsV2> {
+    let mut args = VolArgsV2 {
+        fd: 0,
+        transid: 0,
+        flags: 0,
+        size: 0,
+        qgroup_inherit: 0,
+        unused: [0; 2],
+        name: [0; BTRFS_SUBVOL_NAME_MAX + 1],
+    };
+    if inherit {
+        args.flags |= BTRFS_SUBVOL_QGROUP_INHERIT;
+    }
+    copy_name(&mut args.name, name)?;
+    Ok(args)
+}
+
+/// Opens the directory `path` is created in or deleted from, the one the ioctls act on.
+fn split_parent(path: &str) -> io::Result<(File, &[u8])> {
+    let path = Path::new(path);
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no parent directory", path.display()),
+        ));
+    };
+    Ok((File::open(parent)?, name.as_bytes()))
+}
+
+/// Copies `name` into a NUL terminated buffer.
+fn copy_name(buf: &mut [u8], name: &[u8]) -> io::Result<()> {
+    if name.len() >= buf.len() || name.contains(&0) {
+        return Err(io::ErrorKind::InvalidFilename.into());
+    }
+    buf[..name.len()].copy_from_slice(name);
+    Ok(())
+}
+
+fn ioctl<T>(file: &File, request: u64, args: &mut T) -> io::Result<()> {
+    // SAFETY: `request` encodes the size of `T`, which is laid out as the kernel expects, and
+    // any pointer inside `args` outlives the call.
+    let rc = unsafe { libc::ioctl(file.as_raw_fd(), request as _, args as *mut T) };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -1,125 +1,96 @@
-use crate::{AppState, BTRFS_DEVICE, ServerHelperResult};
-use common::command::run_external_command;
+use crate::backend::{Backend, HelperBackend};
+use crate::batch::execute_batch;
+use crate::{AppState, ServerHelperError, ServerHelperResult, btrfs};
+use common::command::{CommandScope, unscoped};
+use common::error::RemoteErrorKind;
+use common::protocol::ProtocolInfo;
+use common::rpc_auth::{ChannelAuth, constant_time_eq};
+use common::telemetry;
 use secrecy::ExposeSecret;
 
-use common::helper_command::tarpc::ServerHelper;
-use common::helper_command::{HelperCommand, HelperResponse};
+use common::helper_command::tarpc::{ServerHelper, protocol_info};
+use common::helper_command::{HelperCommand, HelperResponse, ProjectUsage, QgroupUsage};
+use common::paths::paths;
 use common::{
-    AuthResponse, AuthToken, SERVICE_USER, USER_GROUP, Validate, get_project_dev_path,
-    get_project_prod_path, get_project_snapshot_path, get_user_path, get_user_project_path,
+    AuthResponse, AuthToken, ProjectSlugStr, SERVICE_USER, Slug, SnapShotNameStr, USER_GROUP,
+    UserSlugStr, Validate, get_project_dev_path, get_project_prod_path, get_project_snapshot_path,
+    get_project_snapshot_prefix, get_project_trash_path, get_user_path, get_user_project_path,
     get_user_projects_path,
 };
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
 use tarpc::context::Context;
-use tokio::fs::OpenOptions;
-use tokio::io::AsyncWriteExt;
-use tracing::info;
+use tracing::{Instrument, error, info, instrument};
 
+/// One per channel; nothing runs until that channel has authenticated.
 #[derive(Clone)]
-pub struct ServerHelperServer(pub AppState);
+pub struct ServerHelperServer(pub AppState, pub Arc<ChannelAuth>);
 
 impl ServerHelper for ServerHelperServer {
-    async fn execute(self, _: Context, actions: Vec<HelperCommand>) -> HelperResponse {
-        info!("Helper actions: {:?}", actions);
-        for action in actions {
-            if let Err(e) = execute_command(action).await {
-                tracing::error!("Error executing command: {}", e);
-                return HelperResponse::Error(e.to_string());
-            }
+    #[instrument(skip_all, fields(request_id = telemetry::request_id()))]
+    async fn execute(self, context: Context, actions: Vec<HelperCommand>) -> HelperResponse {
+        if !self.1.is_authenticated() {
+            return HelperResponse::Error {
+                step: 0,
+                error: RemoteErrorKind::Unavailable.into(),
+                rolled_back: true,
+            };
         }
-        HelperResponse::Ok
+        info!("Helper actions: {:?}", actions);
+        // The batch runs on a task of its own so a request that expires or is cancelled still
+        // gets rolled back: dropping `_cancel` only stops the batch before its next command.
+        let (scope, _cancel) = CommandScope::new(context.deadline);
+        let backend = self.0.backend.clone();
+        let batch = scope.run(async move { execute_batch(&backend, actions).await });
+        tokio::spawn(batch.in_current_span())
+            .await
+            .unwrap_or_else(|e| {
+                error!("Helper batch panicked: {e}");
+                HelperResponse::Error {
+                    step: 0,
+                    error: RemoteErrorKind::Internal.into(),
+                    rolled_back: false,
+                }
+            })
     }
 
-    async fn auth(self, _: Context, token: AuthToken) -> AuthResponse {
-        let mut connected = self.0.connected.write().await;
-        if self.0.server_auth.expose_secret().eq(&token.0) {
-            info!("Token auth success");
-            *connected = true;
-            AuthResponse::Ok
-        } else {
-            *connected = false;
-            info!("Token auth failed");
-            AuthResponse::Error
-        }
+    async fn ping(self, _: Context) {}
+
+    async fn auth(self, _: Context, token: AuthToken, client: ProtocolInfo) -> AuthResponse {
+        let expected = self.0.server_auth.expose_secret();
+        self.0.auth_limiter.auth_response(
+            &self.1,
+            &client,
+            protocol_info(env!("CARGO_PKG_VERSION")),
+            || constant_time_eq(expected.as_bytes(), token.0.as_bytes()),
+        )
     }
 }
 
-pub async fn execute_command(action: HelperCommand) -> ServerHelperResult<()> {
+/// Runs one command; only queries answer with something other than `HelperResponse::Ok`.
+#[instrument(skip(backend))]
+pub async fn execute_command(
+    backend: &Backend,
+    action: HelperCommand,
+) -> ServerHelperResult<HelperResponse> {
     action.validate()?;
     match action {
         HelperCommand::CreateUser { user_slug } => {
-            let user_path = get_user_path(&user_slug);
-            let user_projects_path = get_user_projects_path(&user_slug);
-            run_external_command(
-                "useradd",
-                &[
-                    "--system",
-                    "--gid",
-                    USER_GROUP,
-                    "--home-dir",
-                    &user_path,
-                    "--create-home",
-                    "--shell",
-                    "/usr/sbin/nologin",
-                    &user_slug.0,
-                ],
-            )
-            .await?;
-
-            run_external_command("chown", &["root:root", &user_path]).await?;
-            run_external_command("chmod", &["755", &user_path]).await?;
-            run_external_command("mkdir", &["-p", &user_projects_path]).await?;
-            run_external_command("chown", &["root:root", &user_projects_path]).await?;
-            run_external_command("chmod", &["755", &user_projects_path]).await?;
+            create_user(backend, &user_slug).await?;
         }
         HelperCommand::DeleteUser { user_slug } => {
-            let user_path = get_user_path(&user_slug);
-            run_external_command("userdel", &["--remove", &user_slug.0]).await?;
-            run_external_command("rm", &["-rf", &user_path]).await?;
+            delete_user(backend, &user_slug).await?;
         }
         HelperCommand::CreateProject {
             project_slug,
             user_slug,
             with_index_html,
         } => {
-            let dev_path = get_project_dev_path(&project_slug);
-            let prod_path = get_project_prod_path(&project_slug);
-
-            run_external_command("btrfs", &["subvolume", "create", &dev_path]).await?;
-            run_external_command("chown", &["root:root", &dev_path]).await?;
-            run_external_command("chmod", &["700", &dev_path]).await?;
-
-            let user_acl_rwx_entry = format!("u:{}:rwX", user_slug.0);
-            let service_acl_rwx_entry = format!("u:{SERVICE_USER}:rwX");
-
-            run_external_command("setfacl", &["-m", &user_acl_rwx_entry, &dev_path]).await?;
-            run_external_command("setfacl", &["-d", "-m", &user_acl_rwx_entry, &dev_path]).await?;
-            run_external_command("setfacl", &["-m", &service_acl_rwx_entry, &dev_path]).await?;
-            run_external_command("setfacl", &["-d", "-m", &service_acl_rwx_entry, &dev_path])
-                .await?;
-
-            run_external_command("mkdir", &["-p", &prod_path]).await?;
-            run_external_command("chown", &["root:root", &prod_path]).await?;
-            run_external_command("chmod", &["755", &prod_path]).await?;
-
-            if with_index_html {
-                let index_file_path = format!("{dev_path}/index.html");
-                let mut index_file = OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(&index_file_path)
-                    .await?;
-                index_file
-                    .write_all(b"<html><body><h1>Hello World</h1></body></html>")
-                    .await?;
-                index_file.flush().await?
-            }
+            create_project(backend, &project_slug, &user_slug, with_index_html).await?;
         }
         HelperCommand::DeleteProject { project_slug } => {
-            let project_path = get_project_dev_path(&project_slug);
-            let prod_path = get_project_prod_path(&project_slug);
-            run_external_command("rm", &["-rf", &prod_path]).await?;
-            run_external_command("btrfs", &["subvolume", "delete", &project_path]).await?;
+            delete_project(backend, &project_slug).await?;
         }
         HelperCommand::SetAcl {
             project_slug,
@@ -127,19 +98,14 @@ pub async fn execute_command(action: HelperCommand) -> ServerHelperResult<()> {
             is_read_only,
         } => {
             let path = get_project_dev_path(&project_slug);
-            let perms = if is_read_only { "r-X" } else { "rwX" };
-            let acl_entry = format!("u:{}:{perms}", user_slug.0);
-            run_external_command("setfacl", &["-R", "-m", &acl_entry, &path]).await?;
-            run_external_command("setfacl", &["-d", "-m", &acl_entry, &path]).await?;
+            set_acl(backend, &path, &user_slug, is_read_only).await?;
         }
         HelperCommand::RemoveAcl {
             project_slug,
             user_slug,
         } => {
             let path = get_project_dev_path(&project_slug);
-            let acl_spec = format!("u:{}", user_slug.0);
-            run_external_command("setfacl", &["-x", &acl_spec, &path]).await?;
-            run_external_command("setfacl", &["-d", "-x", &acl_spec, &path]).await?;
+            remove_acl(backend, &path, &user_slug)?;
         }
         HelperCommand::BindMountUserProject {
             project_slug,
@@ -147,24 +113,18 @@ pub async fn execute_command(action: HelperCommand) -> ServerHelperResult<()> {
         } => {
             let project_path = get_project_dev_path(&project_slug);
             let user_project_path = get_user_project_path(&user_slug, &project_slug);
-            run_external_command("mkdir", &["-p", &user_project_path]).await?;
-            run_external_command("mount", &["--bind", &project_path, &user_project_path]).await?;
+            backend.create_dir_all(&user_project_path)?;
+            backend.bind_mount(&project_path, &user_project_path)?;
         }
         HelperCommand::UnmountUserProject {
             project_slug,
             user_slug,
         } => {
             let user_project_path = get_user_project_path(&user_slug, &project_slug);
-            let r = run_external_command(
-                "findmnt",
-                &["-n", "-o", "TARGET", "--target", &user_project_path],
-            )
-            .await?;
-            if !r.is_empty() && !r.eq("/")  {
-                run_external_command("umount", &[&user_project_path]).await?;
+            if backend.is_mount_point(&user_project_path)? {
+                backend.unmount(&user_project_path)?;
             }
-
-            tokio::fs::remove_dir(&user_project_path).await?;
+            backend.remove_dir(&user_project_path)?;
         }
         HelperCommand::CreateSnapshot {
             project_slug,
@@ -172,51 +132,602 @@ pub async fn execute_command(action: HelperCommand) -> ServerHelperResult<()> {
         } => {
             let path = get_project_dev_path(&project_slug);
             let snapshot_path = get_project_snapshot_path(&snapshot_name.0);
-            run_external_command(
-                "btrfs",
-                &["subvolume", "snapshot", "-r", &path, &snapshot_path],
-            )
-            .await?;
+            let qgroup = project_qgroup(&project_slug)?;
+            backend.snapshot(&path, &snapshot_path, true, Some(qgroup))?;
         }
         HelperCommand::DeleteSnapshot { snapshot_name } => {
             let snapshot_path = get_project_snapshot_path(&snapshot_name.0);
-            run_external_command("btrfs", &["subvolume", "delete", &snapshot_path]).await?;
+            backend.delete_subvolume(&snapshot_path)?;
         }
         HelperCommand::MountSnapshot {
             project_slug,
             snapshot_name,
         } => {
             let path = get_project_prod_path(&project_slug);
-            run_external_command(
-                "mount",
-                &[
-                    "-o",
-                    &format!("subvol={},ro", snapshot_name.0),
-                    BTRFS_DEVICE.as_str(),
-                    &path,
-                ],
-            )
-            .await?;
+            backend.mount_subvolume(&snapshot_name.0, &path, true)?;
         }
         HelperCommand::UnmountProd { project_slug } => {
             let path = get_project_prod_path(&project_slug);
-            let r = run_external_command(
-                "findmnt",
-                &["-n", "-o", "TARGET", "--target", &path],
-            ).await?;
-            if !r.is_empty() && !r.eq("/") {
-                run_external_command("umount", &[&path]).await?;
+            if backend.is_mount_point(&path)? {
+                backend.unmount(&path)?;
             }
         }
         HelperCommand::RestoreSnapshot {
             project_slug,
             snapshot_name,
+            backup_snapshot_name,
         } => {
-            let path = get_project_dev_path(&project_slug);
-            let snapshot_path = get_project_snapshot_path(&snapshot_name.0);
-            run_external_command("btrfs", &["subvolume", "snapshot", &snapshot_path, &path])
-                .await?;
+            restore_snapshot(
+                backend,
+                &project_slug,
+                &snapshot_name,
+                &backup_snapshot_name,
+            )
+            .await?;
+        }
+        HelperCommand::EnableQuota => {
+            backend.enable_quota(&paths().dev_root)?;
+        }
+        HelperCommand::SetProjectQuota {
+            project_slug,
+            limit_bytes,
+        } => {
+            let qgroup = project_qgroup(&project_slug)?;
+            backend.limit_qgroup(&paths().dev_root, qgroup, limit_bytes)?;
+        }
+        HelperCommand::ProjectUsage { project_slug } => {
+            return Ok(HelperResponse::ProjectUsage(project_usage(
+                backend,
+                &project_slug,
+            )?));
+        }
+    }
+    Ok(HelperResponse::Ok)
+}
+
+async fn create_user(backend: &Backend, user_slug: &UserSlugStr) -> ServerHelperResult<()> {
+    let user_path = get_user_path(user_slug);
+    let user_projects_path = get_user_projects_path(user_slug);
+    let (name, home) = (user_slug.0.clone(), user_path.clone());
+    blocking(backend, move |backend| {
+        backend.create_system_user(&name, USER_GROUP, &home)
+    })
+    .await?;
+
+    let setup = backend
+        .root_owned_dir(&user_path, 0o755)
+        .and_then(|()| backend.root_owned_dir(&user_projects_path, 0o755));
+    if let Err(e) = setup {
+        // Don't leave a half set up account behind.
+        log_cleanup(delete_user(backend, user_slug)).await;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+async fn delete_user(backend: &Backend, user_slug: &UserSlugStr) -> ServerHelperResult<()> {
+    let user_path = get_user_path(user_slug);
+    let name = user_slug.0.clone();
+    blocking(backend, move |backend| backend.delete_user(&name)).await?;
+    backend.remove_all(&user_path)?;
+    Ok(())
+}
+
+async fn create_project(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+    user_slug: &UserSlugStr,
+    with_index_html: bool,
+) -> ServerHelperResult<()> {
+    let dev_path = get_project_dev_path(project_slug);
+    let prod_path = get_project_prod_path(project_slug);
+    let qgroup = project_qgroup(project_slug)?;
+
+    backend.create_qgroup(&paths().dev_root, qgroup)?;
+    backend.create_subvolume(&dev_path, Some(qgroup))?;
+
+    let setup = || -> ServerHelperResult<()> {
+        backend.root_owned_dir(&dev_path, 0o700)?;
+        backend.grant_acl(&dev_path, user_uid(backend, &user_slug.0)?, false, false)?;
+        backend.grant_acl(&dev_path, user_uid(backend, SERVICE_USER)?, false, false)?;
+        backend.root_owned_dir(&prod_path, 0o755)?;
+
+        if with_index_html {
+            backend.write_file(
+                &format!("{dev_path}/index.html"),
+                b"<html><body><h1>Hello World</h1></body></html>",
+            )?;
+        }
+        Ok(())
+    };
+    if let Err(e) = setup() {
+        // Don't leave a subvolume without its ACLs or prod dir behind.
+        log_cleanup(delete_project(backend, project_slug)).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+async fn delete_project(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+) -> ServerHelperResult<()> {
+    let project_path = get_project_dev_path(project_slug);
+    let prod_path = get_project_prod_path(project_slug);
+    backend.remove_all(&prod_path)?;
+    backend.remove_all(&get_project_trash_path(project_slug))?;
+    backend.delete_subvolume(&project_path)?;
+    Ok(())
+}
+
+/// Runs slow backend work, such as recursive ACL changes and account edits, on a thread of its
+/// own, off the async workers.
+async fn blocking<T: Send + 'static>(
+    backend: &Backend,
+    f: impl FnOnce(&dyn HelperBackend) -> io::Result<T> + Send + 'static,
+) -> ServerHelperResult<T> {
+    let backend = backend.clone();
+    Ok(tokio::task::spawn_blocking(move || f(backend.as_ref()))
+        .await
+        .map_err(|e| ServerHelperError::Other(e.to_string()))??)
+}
+
+fn user_uid(backend: &Backend, name: &str) -> ServerHelperResult<u32> {
+    backend
+        .user_id(name)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no user {name}")).into())
+}
+
+/// Cleanup after a failure is best effort; the error that caused it is the one reported. It
+/// runs unscoped, so a cancelled request still cleans up after itself.
+async fn log_cleanup(cleanup: impl Future<Output = ServerHelperResult<()>>) {
+    if let Err(e) = unscoped(cleanup).await {
+        error!("Cleanup after a failed command failed: {e}");
+    }
+}
+
+async fn set_acl(
+    backend: &Backend,
+    path: &str,
+    user_slug: &UserSlugStr,
+    is_read_only: bool,
+) -> ServerHelperResult<()> {
+    let uid = user_uid(backend, &user_slug.0)?;
+    let path = path.to_string();
+    blocking(backend, move |backend| {
+        backend.grant_acl(&path, uid, is_read_only, true)
+    })
+    .await
+}
+
+fn remove_acl(backend: &Backend, path: &str, user_slug: &UserSlugStr) -> ServerHelperResult<()> {
+    backend.revoke_acl(path, user_uid(backend, &user_slug.0)?)?;
+    Ok(())
+}
+
+/// Users with an entry in the access ACL of `path`, other than the service, and whether the
+/// entry is read-only. Entries of deleted accounts have no name and are skipped.
+pub(crate) fn acl_members(
+    backend: &Backend,
+    path: &str,
+) -> ServerHelperResult<Vec<(UserSlugStr, bool)>> {
+    let names = backend.user_names()?;
+    Ok(backend
+        .acl_users(path)?
+        .into_iter()
+        .filter_map(|(uid, is_read_only)| {
+            let name = names.get(&uid).filter(|name| *name != SERVICE_USER)?;
+            let user_slug = Slug::from_str(name).ok()?.to_user_slug_str();
+            Some((user_slug, is_read_only))
+        })
+        .collect())
+}
+
+/// Replaces the dev subvolume with a writable copy of the snapshot. The copy is built next to
+/// dev with the members' current ACLs, then the two are exchanged in a single rename, so dev
+/// always exists. Members are read from dev's ACL and their bind mounts moved to the new
+/// subvolume. Any failure puts the previous dev and mounts back and drops the backup.
+async fn restore_snapshot(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+    snapshot_name: &SnapShotNameStr,
+    backup_snapshot_name: &SnapShotNameStr,
+) -> ServerHelperResult<()> {
+    let dev_path = get_project_dev_path(project_slug);
+    let restore_path = format!("{dev_path}.restore");
+    let snapshot_path = get_project_snapshot_path(&snapshot_name.0);
+    let backup_path = get_project_snapshot_path(&backup_snapshot_name.0);
+    let qgroup = project_qgroup(project_slug)?;
+    let members = acl_members(backend, &dev_path)?;
+
+    backend.snapshot(&dev_path, &backup_path, true, Some(qgroup))?;
+
+    let prepared: ServerHelperResult<()> = async {
+        backend.snapshot(&snapshot_path, &restore_path, false, Some(qgroup))?;
+        sync_member_acls(backend, &restore_path, &members).await
+    }
+    .await;
+    if let Err(e) = prepared {
+        discard_restore(backend, &restore_path, &backup_path);
+        return Err(e);
+    }
+
+    if let Err(e) = unmount_members(backend, project_slug, &members) {
+        log_cleanup(async { mount_members(backend, project_slug, &members) }).await;
+        discard_restore(backend, &restore_path, &backup_path);
+        return Err(e);
+    }
+    if let Err(e) = backend.exchange_paths(&restore_path, &dev_path) {
+        log_cleanup(async { mount_members(backend, project_slug, &members) }).await;
+        discard_restore(backend, &restore_path, &backup_path);
+        return Err(e.into());
+    }
+    if let Err(e) = mount_members(backend, project_slug, &members) {
+        log_cleanup(async { unmount_members(backend, project_slug, &members) }).await;
+        if let Err(rollback) = backend.exchange_paths(&restore_path, &dev_path) {
+            // Both subvolumes are kept, nothing is lost but dev needs fixing by hand.
+            error!(
+                "Could not put {dev_path} back, previous state is at {restore_path}: {rollback}"
+            );
+            return Err(e);
+        }
+        log_cleanup(async { mount_members(backend, project_slug, &members) }).await;
+        discard_restore(backend, &restore_path, &backup_path);
+        return Err(e);
+    }
+
+    // The previous dev, now at `restore_path`, lives on as the backup.
+    discard_subvolume(backend, &restore_path);
+    Ok(())
+}
+
+/// Gives `path`, a restored copy, the members' current ACLs: users who left since the
+/// snapshot lose their entry, the others get theirs set again.
+async fn sync_member_acls(
+    backend: &Backend,
+    path: &str,
+    members: &[(UserSlugStr, bool)],
+) -> ServerHelperResult<()> {
+    for (user_slug, _) in acl_members(backend, path)? {
+        if !members.iter().any(|(member, _)| *member == user_slug) {
+            remove_acl(backend, path, &user_slug)?;
+        }
+    }
+    for (user_slug, is_read_only) in members {
+        set_acl(backend, path, user_slug, *is_read_only).await?;
+    }
+    Ok(())
+}
+
+fn unmount_members(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+    members: &[(UserSlugStr, bool)],
+) -> ServerHelperResult<()> {
+    for (user_slug, _) in members {
+        let user_project_path = get_user_project_path(user_slug, project_slug);
+        if backend.is_mount_point(&user_project_path)? {
+            backend.unmount(&user_project_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn mount_members(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+    members: &[(UserSlugStr, bool)],
+) -> ServerHelperResult<()> {
+    let project_path = get_project_dev_path(project_slug);
+    for (user_slug, _) in members {
+        let user_project_path = get_user_project_path(user_slug, project_slug);
+        backend.create_dir_all(&user_project_path)?;
+        if !backend.is_mount_point(&user_project_path)? {
+            backend.bind_mount(&project_path, &user_project_path)?;
         }
     }
     Ok(())
 }
+
+fn discard_restore(backend: &Backend, restore_path: &str, backup_path: &str) {
+    discard_subvolume(backend, restore_path);
+    discard_subvolume(backend, backup_path);
+}
+
+fn discard_subvolume(backend: &Backend, path: &str) {
+    if !backend.exists(path).unwrap_or(false) {
+        return;
+    }
+    if let Err(e) = backend.delete_subvolume(path) {
+        error!("Could not delete subvolume {path}: {e}");
+    }
+}
+
+/// Parent qgroup of a project's dev subvolume and snapshots, so a single limit covers both.
+pub(crate) fn project_qgroup(project_slug: &ProjectSlugStr) -> ServerHelperResult<u64> {
+    Ok(btrfs::qgroup_id(
+        1,
+        Slug::from_str(&project_slug.0)?.id as u64,
+    ))
+}
+
+fn project_usage(
+    backend: &Backend,
+    project_slug: &ProjectSlugStr,
+) -> ServerHelperResult<ProjectUsage> {
+    let (total, limit_bytes) =
+        backend.qgroup_usage(&paths().dev_root, project_qgroup(project_slug)?)?;
+    let dev = subvolume_usage(backend, &get_project_dev_path(project_slug))?;
+
+    let prefix = get_project_snapshot_prefix(project_slug);
+    let mut snapshots = Vec::new();
+    for name in backend.list_dir(&paths().dev_root)? {
+        if !name.starts_with(&prefix) {
+            continue;
+        }
+        let Ok(snapshot_name) = SnapShotNameStr::from_str(&name) else {
+            continue;
+        };
+        let usage = subvolume_usage(backend, &get_project_snapshot_path(&name))?;
+        snapshots.push((snapshot_name, usage));
+    }
+    snapshots.sort_by(|a, b| a.0.0.cmp(&b.0.0));
+
+    Ok(ProjectUsage {
+        total,
+        limit_bytes,
+        dev,
+        snapshots,
+    })
+}
+
+/// Usage of the subvolume at `path`, from its own level 0 qgroup.
+fn subvolume_usage(backend: &Backend, path: &str) -> ServerHelperResult<QgroupUsage> {
+    let qgroup = btrfs::qgroup_id(0, backend.subvolume_id(path)?);
+    Ok(backend.qgroup_usage(&paths().dev_root, qgroup)?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    fn memory_backend() -> (Arc<MemoryBackend>, Backend) {
+        let memory = Arc::new(MemoryBackend::new());
+        (memory.clone(), memory)
+    }
+
+    fn user(slug: &str) -> UserSlugStr {
+        UserSlugStr(slug.to_string())
+    }
+
+    fn project(slug: &str) -> ProjectSlugStr {
+        ProjectSlugStr(slug.to_string())
+    }
+
+    fn snapshot(name: &str) -> SnapShotNameStr {
+        SnapShotNameStr(name.to_string())
+    }
+
+    async fn run(backend: &Backend, action: HelperCommand) -> HelperResponse {
+        execute_command(backend, action)
+            .await
+            .expect("the command succeeds")
+    }
+
+    /// `alice-1` owning `site-1`, created with its index.html.
+    async fn set_up_project(backend: &Backend) {
+        run(backend, HelperCommand::EnableQuota).await;
+        run(
+            backend,
+            HelperCommand::CreateUser {
+                user_slug: user("alice-1"),
+            },
+        )
+        .await;
+        run(
+            backend,
+            HelperCommand::CreateProject {
+                project_slug: project("site-1"),
+                user_slug: user("alice-1"),
+                with_index_html: true,
+            },
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn creates_and_deletes_users_and_projects() {
+        let (memory, backend) = memory_backend();
+        set_up_project(&backend).await;
+
+        let user_path = get_user_path(&user("alice-1"));
+        let dev_path = get_project_dev_path(&project("site-1"));
+        let alice = backend.user_id("alice-1").unwrap().expect("alice exists");
+        assert_eq!(memory.ownership(&user_path), Some((0, 0o755)));
+        assert_eq!(
+            memory.ownership(&get_user_projects_path(&user("alice-1"))),
+            Some((0, 0o755))
+        );
+        assert_eq!(memory.ownership(&dev_path), Some((0, 0o700)));
+        assert!(
+            backend
+                .acl_users(&dev_path)
+                .unwrap()
+                .contains(&(alice, false))
+        );
+        let index = memory
+            .read_file(&format!("{dev_path}/index.html"))
+            .expect("index.html was written");
+        assert!(String::from_utf8(index).unwrap().contains("Hello World"));
+        assert!(
+            backend
+                .exists(&get_project_prod_path(&project("site-1")))
+                .unwrap()
+        );
+
+        run(
+            &backend,
+            HelperCommand::DeleteProject {
+                project_slug: project("site-1"),
+            },
+        )
+        .await;
+        assert!(!backend.exists(&dev_path).unwrap());
+        assert!(
+            !backend
+                .exists(&get_project_prod_path(&project("site-1")))
+                .unwrap()
+        );
+
+        run(
+            &backend,
+            HelperCommand::DeleteUser {
+                user_slug: user("alice-1"),
+            },
+        )
+        .await;
+        assert_eq!(backend.user_id("alice-1").unwrap(), None);
+        assert!(!backend.exists(&user_path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn snapshots_are_read_only_copies() {
+        let (memory, backend) = memory_backend();
+        set_up_project(&backend).await;
+        let dev_path = get_project_dev_path(&project("site-1"));
+        let snapshot_path = get_project_snapshot_path("site-1_snapshot_a");
+
+        run(
+            &backend,
+            HelperCommand::CreateSnapshot {
+                project_slug: project("site-1"),
+                snapshot_name: snapshot("site-1_snapshot_a"),
+            },
+        )
+        .await;
+        assert_eq!(
+            memory.read_file(&format!("{snapshot_path}/index.html")),
+            memory.read_file(&format!("{dev_path}/index.html"))
+        );
+        let error = backend
+            .write_file(&format!("{snapshot_path}/index.html"), b"changed")
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::ReadOnlyFilesystem);
+
+        let HelperResponse::ProjectUsage(usage) = run(
+            &backend,
+            HelperCommand::ProjectUsage {
+                project_slug: project("site-1"),
+            },
+        )
+        .await
+        else {
+            panic!("ProjectUsage answers with the usage");
+        };
+        assert_eq!(usage.snapshots.len(), 1);
+        assert_eq!(usage.snapshots[0].0, snapshot("site-1_snapshot_a"));
+    }
+
+    #[tokio::test]
+    async fn restore_swaps_dev_with_the_snapshot() {
+        let (memory, backend) = memory_backend();
+        set_up_project(&backend).await;
+        let dev_path = get_project_dev_path(&project("site-1"));
+        let index_path = format!("{dev_path}/index.html");
+        let original = memory.read_file(&index_path);
+        let user_project_path = get_user_project_path(&user("alice-1"), &project("site-1"));
+
+        run(
+            &backend,
+            HelperCommand::CreateSnapshot {
+                project_slug: project("site-1"),
+                snapshot_name: snapshot("site-1_snapshot_a"),
+            },
+        )
+        .await;
+        run(
+            &backend,
+            HelperCommand::BindMountUserProject {
+                project_slug: project("site-1"),
+                user_slug: user("alice-1"),
+            },
+        )
+        .await;
+        backend.write_file(&index_path, b"changed").unwrap();
+
+        run(
+            &backend,
+            HelperCommand::RestoreSnapshot {
+                project_slug: project("site-1"),
+                snapshot_name: snapshot("site-1_snapshot_a"),
+                backup_snapshot_name: snapshot("site-1_snapshot_b"),
+            },
+        )
+        .await;
+        assert_eq!(memory.read_file(&index_path), original);
+        let backup_path = get_project_snapshot_path("site-1_snapshot_b");
+        assert_eq!(
+            memory.read_file(&format!("{backup_path}/index.html")),
+            Some(b"changed".to_vec())
+        );
+        assert!(!backend.exists(&format!("{dev_path}.restore")).unwrap());
+        assert!(backend.is_mount_point(&user_project_path).unwrap());
+        assert_eq!(
+            acl_members(&backend, &dev_path).unwrap(),
+            vec![(user("alice-1"), false)]
+        );
+        // The restored dev is writable again.
+        backend.write_file(&index_path, b"edited").unwrap();
+    }
+
+    #[tokio::test]
+    async fn grants_and_revokes_acls() {
+        let (_, backend) = memory_backend();
+        set_up_project(&backend).await;
+        let dev_path = get_project_dev_path(&project("site-1"));
+        run(
+            &backend,
+            HelperCommand::CreateUser {
+                user_slug: user("bob-2"),
+            },
+        )
+        .await;
+        let bob = backend.user_id("bob-2").unwrap().expect("bob exists");
+
+        run(
+            &backend,
+            HelperCommand::SetAcl {
+                project_slug: project("site-1"),
+                user_slug: user("bob-2"),
+                is_read_only: true,
+            },
+        )
+        .await;
+        assert!(
+            acl_members(&backend, &dev_path)
+                .unwrap()
+                .contains(&(user("bob-2"), true))
+        );
+        // Granted on what the project already holds, not only its root.
+        assert!(
+            backend
+                .acl_users(&format!("{dev_path}/index.html"))
+                .unwrap()
+                .contains(&(bob, true))
+        );
+
+        run(
+            &backend,
+            HelperCommand::RemoveAcl {
+                project_slug: project("site-1"),
+                user_slug: user("bob-2"),
+            },
+        )
+        .await;
+        assert_eq!(
+            acl_members(&backend, &dev_path).unwrap(),
+            vec![(user("alice-1"), false)]
+        );
+    }
+}
@@ -0,0 +1,49 @@
+//! Settings of the helper: the `[paths]` it manages, the `[rpc]` socket it listens on and its
+//! own `[helper]`.
+
+use common::config::{PathsConfig, RpcConfig, Settings};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub rpc: RpcConfig,
+    pub helper: HelperConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HelperConfig {
+    /// Block device of the Btrfs filesystem holding the paths, mounted to expose snapshots.
+    /// It has no default: mounting the wrong device would expose another filesystem.
+    pub btrfs_device: String,
+    pub backend: BackendKind,
+}
+
+/// What the helper changes: the machine, or an in-memory fake of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    System,
+    Memory,
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<(), String> {
+        self.paths.validate()?;
+        self.rpc.validate()?;
+        if self.helper.backend == BackendKind::System {
+            let device = &self.helper.btrfs_device;
+            if device.is_empty() {
+                return Err("helper.btrfs_device must be set".to_string());
+            }
+            if !Path::new(device).exists() {
+                return Err(format!("helper.btrfs_device doesn't exist: {device}"));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,18 @@
+use crate::backend::Backend;
+use common::config::ConfigError;
+use common::error::{RemoteError, RemoteErrorKind};
+use common::rpc_auth::AuthLimiter;
 use secrecy::SecretString;
-use std::sync::{Arc, LazyLock};
-use tokio::sync::RwLock;
+use std::sync::Arc;
 
+pub mod acl;
+pub mod backend;
+pub mod batch;
+pub mod btrfs;
 pub mod command;
-
-pub static BTRFS_DEVICE: LazyLock<String> =
-    LazyLock::new(|| dotenvy::var("BTRFS_DEVICE").unwrap_or_else(|_| "/dev/sda".to_string()));
+pub mod config;
+pub mod mount;
+pub mod users;
 
 pub type ServerHelperResult<T> = Result<T, ServerHelperError>;
 
@@ -13,14 +20,29 @@ pub type ServerHelperResult<T> = Result<T, ServerHelperError>;
 pub enum ServerHelperError {
     #[error("IO Error {0}")]
     IoError(#[from] tokio::io::Error),
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
     #[error("Failed to execute command: {0}")]
     Other(String),
     #[error("Sanitize Error {0}")]
     SanitizeError(#[from] common::SanitizeError),
 }
 
+impl From<&ServerHelperError> for RemoteError {
+    fn from(error: &ServerHelperError) -> Self {
+        match error {
+            ServerHelperError::IoError(e) => e.into(),
+            ServerHelperError::Other(_) | ServerHelperError::Config(_) => {
+                RemoteErrorKind::Internal.into()
+            }
+            ServerHelperError::SanitizeError(e) => e.clone().into(),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct AppState {
     pub server_auth: Arc<SecretString>,
-    pub connected: Arc<RwLock<bool>>,
+    pub auth_limiter: Arc<AuthLimiter>,
+    pub backend: Backend,
 }
@@ -1,46 +1,62 @@
-use common::helper_command::tarpc::{HELPER_SOCKET_PATH, ServerHelper};
+use common::config;
+use common::helper_command::tarpc::ServerHelper;
+use common::paths::set_paths;
+use common::rpc_auth::{AuthLimiter, ChannelAuth};
+use common::telemetry::{self, OTLP_ENDPOINT_VAR};
+use common::unix_listener::{self, AllowedPeer};
 use futures::StreamExt;
+use hivehost_server_helper::backend::{Backend, MemoryBackend, SystemBackend};
 use hivehost_server_helper::command::ServerHelperServer;
-use hivehost_server_helper::{AppState, BTRFS_DEVICE, ServerHelperResult};
+use hivehost_server_helper::config::{BackendKind, Config};
+use hivehost_server_helper::{AppState, ServerHelperResult};
 use secrecy::SecretString;
-use std::future;
-use std::sync::{Arc, LazyLock};
+use std::sync::Arc;
 use tarpc::server;
 use tarpc::server::Channel;
-use tarpc::tokio_serde::formats::Bincode;
-use tokio::sync::RwLock;
-use tracing::info;
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> ServerHelperResult<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
-        )
-        .with(tracing_subscriber::fmt::layer().with_ansi(true))
-        .init();
+    let config: Config = config::load()?;
+    set_paths(config.paths.clone());
+    let _telemetry = telemetry::init(
+        env!("CARGO_CRATE_NAME"),
+        dotenvy::var(OTLP_ENDPOINT_VAR).ok(),
+    );
 
-    LazyLock::force(&BTRFS_DEVICE);
-    let _ = tokio::fs::remove_file(HELPER_SOCKET_PATH).await;
-    let server_auth = dotenvy::var("SERVER_AUTH").unwrap_or_else(|_| "hivehost".to_string());
-    let connected = Arc::new(RwLock::new(false));
+    let server_auth = dotenvy::var("SERVER_AUTH").expect("SERVER_AUTH must be set");
+    // Only the server's account may connect; the token alone never lets anyone in.
+    let (backend, peer): (Backend, _) = match config.helper.backend {
+        BackendKind::Memory => {
+            warn!("Using the in-memory backend, nothing is changed on this machine");
+            (
+                Arc::new(MemoryBackend::new()),
+                AllowedPeer::current_process(),
+            )
+        }
+        BackendKind::System => (
+            Arc::new(SystemBackend {
+                btrfs_device: config.helper.btrfs_device.clone(),
+            }),
+            AllowedPeer::service_user()?,
+        ),
+    };
     let app_state = AppState {
         server_auth: Arc::new(SecretString::from(server_auth)),
-        connected: connected.clone(),
+        auth_limiter: Arc::new(AuthLimiter::default()),
+        backend,
     };
 
-    info!("Server helper socket path: {}", HELPER_SOCKET_PATH);
-    let mut listener =
-        tarpc::serde_transport::unix::listen(HELPER_SOCKET_PATH, Bincode::default).await?;
-    listener.config_mut().max_frame_length(10 * 10 * 1024);
-    listener
-        .filter_map(|r| future::ready(r.ok()))
-        .map(server::BaseChannel::with_defaults)
-        .map(|channel| {
-            let server = ServerHelperServer(app_state.clone());
+    info!(
+        "Server helper socket path: {}, allowed peer: {:?}",
+        config.rpc.helper_socket, peer
+    );
+    unix_listener::listen(&config.rpc.helper_socket, peer, config.rpc.max_frame_length)?
+        .map(|(cred, transport)| {
+            let channel = server::BaseChannel::with_defaults(transport);
+            // Failed auth attempts are counted by account.
+            let channel_auth = ChannelAuth::new(format!("uid {}", cred.uid()));
+            let server = ServerHelperServer(app_state.clone(), channel_auth);
             channel
                 .execute(server.serve())
                 .for_each(|response| async move {
@@ -0,0 +1,105 @@
+//! Mounts through mount(2) and umount2(2), and the mount table as the kernel reports it in
+//! `/proc/self/mountinfo`.
+
+use std::ffi::CString;
+use std::io;
+use std::ptr;
+
+pub fn bind_mount(source: &str, target: &str) -> io::Result<()> {
+    let source = c_string(source)?;
+    let target = c_string(target)?;
+    // SAFETY: every string is NUL terminated and outlives the call; a bind mount reads
+    // neither the filesystem type nor the data.
+    let rc = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND,
+            ptr::null(),
+        )
+    };
+    check(rc)
+}
+
+/// Mounts subvolume `subvolume` of the Btrfs filesystem on `device` at `target`.
+pub fn mount_subvolume(
+    device: &str,
+    subvolume: &str,
+    target: &str,
+    read_only: bool,
+) -> io::Result<()> {
+    let device = c_string(device)?;
+    let target = c_string(target)?;
+    let data = c_string(&format!("subvol={subvolume}"))?;
+    let flags = if read_only { libc::MS_RDONLY } else { 0 };
+    // SAFETY: every string is NUL terminated and outlives the call.
+    let rc = unsafe {
+        libc::mount(
+            device.as_ptr(),
+            target.as_ptr(),
+            c"btrfs".as_ptr(),
+            flags,
+            data.as_ptr().cast(),
+        )
+    };
+    check(rc)
+}
+
+pub fn unmount(target: &str) -> io::Result<()> {
+    let target = c_string(target)?;
+    // SAFETY: `target` is NUL terminated and outlives the call.
+    check(unsafe { libc::umount2(target.as_ptr(), 0) })
+}
+
+pub fn is_mount_point(path: &str) -> io::Result<bool> {
+    Ok(mount_root(path)?.is_some())
+}
+
+/// Path, inside its filesystem, of what is mounted at `path`, or `None` when nothing is. For
+/// a Btrfs subvolume mount that is the subvolume's path.
+pub fn mount_root(path: &str) -> io::Result<Option<String>> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    // Stacked mounts are listed in the order they were made, so the last one is visible.
+    Ok(mountinfo.lines().rev().find_map(|line| {
+        // Mount id, parent id, device, root, mount point and more, space separated.
+        let mut fields = line.split(' ').skip(3);
+        let root = fields.next()?;
+        let mount_point = fields.next()?;
+        (unescape(mount_point) == path).then(|| unescape(root))
+    }))
+}
+
+/// Undoes the octal escapes mountinfo uses for spaces, tabs, newlines and backslashes.
+fn unescape(field: &str) -> String {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .filter(|_| byte == b'\\')
+            .and_then(|digits| u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn c_string(s: &str) -> io::Result<CString> {
+    CString::new(s).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
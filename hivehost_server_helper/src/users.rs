@@ -0,0 +1,270 @@
+//! System accounts written straight to `/etc/passwd`, `/etc/shadow` and `/etc/group`. Edits
+//! hold the lock glibc's `lckpwdf` takes on `/etc/.pwd.lock`, and each file is replaced by
+//! renaming a complete copy over it, so readers never see half an edit.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::time::{Duration, Instant, SystemTime};
+
+const PASSWD: &str = "/etc/passwd";
+const SHADOW: &str = "/etc/shadow";
+const GROUP: &str = "/etc/group";
+const GSHADOW: &str = "/etc/gshadow";
+const LOGIN_DEFS: &str = "/etc/login.defs";
+const LOCK: &CStr = c"/etc/.pwd.lock";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(15);
+/// Used when `/etc/login.defs` doesn't set `SYS_UID_MIN` and `SYS_UID_MAX`, as by `useradd`.
+const SYS_UID_RANGE: (u32, u32) = (101, 999);
+
+pub const NOLOGIN_SHELL: &str = "/usr/sbin/nologin";
+
+/// Adds the system account `name` to the existing `group` with a locked password, and creates
+/// its `home`, like `useradd --system --gid GROUP --home-dir HOME --create-home --shell SHELL`.
+/// The uid is the highest free one in the system range. Nothing is copied from `/etc/skel`.
+pub fn create_system_user(name: &str, group: &str, home: &str, shell: &str) -> io::Result<()> {
+    if [name, home, shell]
+        .iter()
+        .any(|field| field.is_empty() || field.contains([':', '\n']))
+    {
+        return Err(io::ErrorKind::InvalidInput.into());
+    }
+    let _lock = DbLock::acquire()?;
+    let passwd = std::fs::read_to_string(PASSWD)?;
+    if find_entry(&passwd, name).is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("user {name} already exists"),
+        ));
+    }
+    let gid = find_entry(&std::fs::read_to_string(GROUP)?, group)
+        .and_then(|fields| fields.get(2)?.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no group {group}")))?;
+    let uid = free_system_uid(&passwd)?;
+
+    let created_home = match DirBuilder::new().mode(0o700).create(home) {
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => false,
+        Err(e) => return Err(e),
+    };
+    let written = std::os::unix::fs::chown(home, Some(uid), Some(gid)).and_then(|()| {
+        let days = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            / 86400;
+        // A line left over by an interrupted deletion would shadow the new one.
+        let shadow = std::fs::read_to_string(SHADOW)?;
+        let new_shadow = without_entry(&shadow, name).unwrap_or_else(|| shadow.clone());
+        replace_file(
+            SHADOW,
+            &with_line(&new_shadow, &format!("{name}:!:{days}::::::")),
+        )?;
+        let line = format!("{name}:x:{uid}:{gid}::{home}:{shell}");
+        replace_file(PASSWD, &with_line(&passwd, &line)).inspect_err(|_| {
+            let _ = replace_file(SHADOW, &shadow);
+        })
+    });
+    if written.is_err() && created_home {
+        let _ = std::fs::remove_dir(home);
+    }
+    written
+}
+
+/// Removes the account `name` and takes it out of the member lists of its groups, like
+/// `userdel`. The home directory is left for the caller.
+pub fn delete_user(name: &str) -> io::Result<()> {
+    let _lock = DbLock::acquire()?;
+    let passwd = std::fs::read_to_string(PASSWD)?;
+    let Some(new_passwd) = without_entry(&passwd, name) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no user {name}"),
+        ));
+    };
+    replace_file(PASSWD, &new_passwd)?;
+    if let Some(shadow) = without_entry(&std::fs::read_to_string(SHADOW)?, name) {
+        replace_file(SHADOW, &shadow)?;
+    }
+    // Members are the fourth field of both; gshadow also lists group administrators third.
+    if let Some(group) = without_member(&std::fs::read_to_string(GROUP)?, name, &[3]) {
+        replace_file(GROUP, &group)?;
+    }
+    match std::fs::read_to_string(GSHADOW) {
+        Ok(gshadow) => {
+            if let Some(gshadow) = without_member(&gshadow, name, &[2, 3]) {
+                replace_file(GSHADOW, &gshadow)?;
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+pub fn uid(name: &str) -> io::Result<Option<u32>> {
+    Ok(find_entry(&std::fs::read_to_string(PASSWD)?, name)
+        .and_then(|fields| fields.get(2)?.parse().ok()))
+}
+
+/// Account names by uid.
+pub fn names() -> io::Result<HashMap<u32, String>> {
+    Ok(entries(&std::fs::read_to_string(PASSWD)?)
+        .filter_map(|fields| Some((fields.get(2)?.parse().ok()?, fields[0].to_string())))
+        .collect())
+}
+
+/// Held while the account files are read and rewritten; the lock goes with the descriptor.
+struct DbLock {
+    _fd: OwnedFd,
+}
+
+impl DbLock {
+    fn acquire() -> io::Result<Self> {
+        // SAFETY: the path is NUL terminated.
+        let fd = unsafe {
+            libc::open(
+                LOCK.as_ptr(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC,
+                0o600,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: open just returned this descriptor and nothing else owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        // SAFETY: `flock` is plain data; all zeroes with a type set locks the whole file.
+        let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+        lock.l_type = libc::F_WRLCK as _;
+        lock.l_whence = libc::SEEK_SET as _;
+        let started = Instant::now();
+        loop {
+            // SAFETY: the descriptor is open and `lock` outlives the call.
+            if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETLK, &lock) } == 0 {
+                return Ok(DbLock { _fd: fd });
+            }
+            let e = io::Error::last_os_error();
+            if !matches!(e.raw_os_error(), Some(libc::EACCES | libc::EAGAIN)) {
+                return Err(e);
+            }
+            if started.elapsed() >= LOCK_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the account files stayed locked",
+                ));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+/// Replaces `path` with `contents`, keeping its owner and mode.
+fn replace_file(path: &str, contents: &str) -> io::Result<()> {
+    let metadata = std::fs::metadata(path)?;
+    let temp_path = format!("{path}+");
+    let written = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&temp_path)
+        .and_then(|mut file| {
+            std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+            file.set_permissions(metadata.permissions())?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Colon separated fields of each account line, leaving out comments and NIS markers.
+fn entries(content: &str) -> impl Iterator<Item = Vec<&str>> {
+    content
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with(['#', '+', '-']))
+        .map(|line| line.split(':').collect())
+}
+
+fn find_entry<'a>(content: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    entries(content).find(|fields| fields[0] == name)
+}
+
+/// `content` without the line of `name`, or `None` when it has none.
+fn without_entry(content: &str, name: &str) -> Option<String> {
+    let mut found = false;
+    let mut kept = String::with_capacity(content.len());
+    for line in content.lines() {
+        if line.split(':').next() == Some(name) {
+            found = true;
+        } else {
+            kept.push_str(line);
+            kept.push('\n');
+        }
+    }
+    found.then_some(kept)
+}
+
+/// `content` with `name` taken out of the comma separated lists in `fields` of every line, or
+/// `None` when it is in none of them.
+fn without_member(content: &str, name: &str, fields: &[usize]) -> Option<String> {
+    let mut found = false;
+    let mut kept = String::with_capacity(content.len());
+    for line in content.lines() {
+        let mut columns: Vec<String> = line.split(':').map(str::to_string).collect();
+        for &field in fields {
+            let Some(column) = columns.get_mut(field) else {
+                continue;
+            };
+            let members: Vec<&str> = column.split(',').filter(|member| *member != name).collect();
+            if members.len() != column.split(',').count() {
+                found = true;
+                *column = members.join(",");
+            }
+        }
+        kept.push_str(&columns.join(":"));
+        kept.push('\n');
+    }
+    found.then_some(kept)
+}
+
+fn with_line(content: &str, line: &str) -> String {
+    let mut content = content.to_string();
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(line);
+    content.push('\n');
+    content
+}
+
+/// Highest uid in the system range that no account has, as `useradd --system` picks them.
+fn free_system_uid(passwd: &str) -> io::Result<u32> {
+    let login_defs = match std::fs::read_to_string(LOGIN_DEFS) {
+        Ok(login_defs) => login_defs,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let setting = |key: &str| {
+        login_defs.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            (words.next()? == key).then(|| words.next()?.parse().ok())?
+        })
+    };
+    let min = setting("SYS_UID_MIN").unwrap_or(SYS_UID_RANGE.0);
+    let max = setting("SYS_UID_MAX").unwrap_or(SYS_UID_RANGE.1);
+    let used: Vec<u32> = entries(passwd)
+        .filter_map(|fields| fields.get(2)?.parse().ok())
+        .collect();
+    (min..=max)
+        .rev()
+        .find(|uid| !used.contains(uid))
+        .ok_or_else(|| io::Error::other("no free system uid left"))
+}
@@ -0,0 +1,57 @@
+//! Settings of the hosting: the shared `[paths]` and `[rpc]` sections and its own `[hosting]`.
+
+use common::config::{PathsConfig, RpcConfig, Settings};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::OnceLock;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    pub paths: PathsConfig,
+    pub rpc: RpcConfig,
+    pub hosting: HostingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostingConfig {
+    /// Address of the HTTP listener serving the projects.
+    pub bind_address: SocketAddr,
+    /// What follows the project slug in the `Host` of a request, port included.
+    pub host_suffix: String,
+}
+
+impl Default for HostingConfig {
+    fn default() -> Self {
+        HostingConfig {
+            bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3002),
+            host_suffix: ".localhost:3002".to_string(),
+        }
+    }
+}
+
+impl Settings for Config {
+    fn validate(&self) -> Result<(), String> {
+        self.paths.validate()?;
+        self.rpc.validate()?;
+        if !self.hosting.host_suffix.starts_with('.') || self.hosting.host_suffix.len() < 2 {
+            return Err(format!(
+                "hosting.host_suffix must be a domain starting with '.': {:?}",
+                self.hosting.host_suffix
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Installs the settings of this process, once, before anything reads them.
+pub fn set_config(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -1,26 +1,35 @@
+use std::sync::Arc;
 use secrecy::ExposeSecret;
 use tarpc::context::Context;
 use crate::{cache_project_path, AppState, CACHE};
 use common::hosting_command::{HostingCommand, HostingResponse};
-use tracing::{info};
-use common::hosting_command::tarpc::ServerHosting;
+use tracing::{info, instrument, Instrument};
+use common::hosting_command::tarpc::{protocol_info, ServerHosting};
 use common::{AuthResponse, AuthToken, ProjectSlugStr, Validate};
+use common::error::RemoteErrorKind;
+use common::protocol::ProtocolInfo;
+use common::rpc_auth::{constant_time_eq, ChannelAuth};
+use common::telemetry;
 
 #[derive(Clone)]
-pub struct ServerToHostingServer(pub AppState);
+pub struct ServerToHostingServer(pub AppState, pub Arc<ChannelAuth>);
 
 impl ServerHosting for ServerToHostingServer {
+    #[instrument(skip_all, fields(request_id = telemetry::request_id(), project = %project_slug_str.0, ?action))]
     async fn hosting(self, _: Context, project_slug_str: ProjectSlugStr,action: HostingCommand) -> HostingResponse {
+        if !self.1.is_authenticated() {
+            return HostingResponse::Error(RemoteErrorKind::Unavailable.into());
+        }
         match project_slug_str.validate() {
             Ok(_) => {}
             Err(e) => {
-                return HostingResponse::Error(format!("Invalid action: {e}"));
+                return HostingResponse::Error(e.into());
             }
         };
         match action {
             HostingCommand::ServeReloadProject => {
                 info!("Reloading project {:?}", project_slug_str);
-                tokio::spawn(cache_project_path(project_slug_str));
+                tokio::spawn(cache_project_path(project_slug_str).in_current_span());
             }
             HostingCommand::StopServingProject => {
                 CACHE.remove(&project_slug_str);
@@ -29,17 +38,12 @@ impl ServerHosting for ServerToHostingServer {
         HostingResponse::Ok
     }
 
-    async fn auth(self, _: Context, token: AuthToken) -> AuthResponse {
-        let mut connected= self.0.connected.write().await;
-        if self.0.server_auth.expose_secret().eq(&token.0){
-            info!("Token auth success");
-            *connected = true;
-            AuthResponse::Ok
-        }else{
-            *connected = false;
-            info!("Token auth failed");
-            AuthResponse::Error
-        }
+    async fn ping(self, _: Context) {}
 
+    async fn auth(self, _: Context, token: AuthToken, client: ProtocolInfo) -> AuthResponse {
+        let expected = self.0.server_auth.expose_secret();
+        self.0.auth_limiter.auth_response(&self.1, &client, protocol_info(env!("CARGO_PKG_VERSION")), || {
+            constant_time_eq(expected.as_bytes(), token.0.as_bytes())
+        })
     }
 }
@@ -1,6 +1,9 @@
+use crate::config::config;
 use async_compression::tokio::bufread::BrotliEncoder;
+use common::config::ConfigError;
 use common::{Slug};
 use common::{get_project_prod_path, ProjectSlugStr};
+use common::rpc_auth::AuthLimiter;
 use dashmap::DashMap;
 use deadpool_postgres::tokio_postgres::NoTls;
 use deadpool_postgres::{tokio_postgres, Pool};
@@ -26,17 +29,12 @@ use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::{runtime, task};
-use tokio::sync::RwLock;
 use tracing::{debug, error, info};
 use walkdir::WalkDir;
 
+pub mod config;
 pub mod handler;
 
-pub static HOSTING_PREFIX: LazyLock<String> = LazyLock::new(|| {
-     ".localhost:3002".to_string()
-    
-});
-
 pub type HostingResult<T> = Result<T, HostingError>;
 
 #[derive(Debug, Error)]
@@ -51,6 +49,8 @@ pub enum HostingError {
     AddrParseError(#[from] AddrParseError),
     #[error("DotEnv error: {0}")]
     DotEnv(#[from] dotenvy::Error),
+    #[error("Config error: {0}")]
+    Config(#[from] ConfigError),
     #[error("Tokio postgres error: {0}")]
     TokioPostgres(#[from] tokio_postgres::Error),
     #[error("Custom {0}")]
@@ -107,7 +107,7 @@ pub struct ProjectCache {
 #[derive(Clone)]
 pub struct AppState{
     pub server_auth: Arc<SecretString>,
-    pub connected:Arc<RwLock<bool>>,
+    pub auth_limiter: Arc<AuthLimiter>,
 }
 
 
@@ -232,7 +232,7 @@ pub async fn handle_request(
         .headers()
         .get(header::HOST)
         .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.strip_suffix(HOSTING_PREFIX.as_str()))
+        .and_then(|s| s.strip_suffix(config().hosting.host_suffix.as_str()))
         .unwrap_or_default()).map_err(|e|HostingError::Custom(e.to_string()))?;
 
     let base_path = request.uri().path();
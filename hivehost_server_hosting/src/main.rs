@@ -1,39 +1,37 @@
 use common::Slug;
 use futures::StreamExt;
+use hivehost_server_hosting::config::{self, Config};
 use hivehost_server_hosting::handler::ServerToHostingServer;
 use hivehost_server_hosting::{accept_hosting_loop, cache_project_path, create_socket, AppState, HostingResult, CACHE, DB, TOKEN};
-use std::future;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::{Arc, LazyLock};
 use std::thread::available_parallelism;
 use secrecy::SecretString;
 use tarpc::server;
 use tarpc::server::Channel;
-use tarpc::tokio_serde::formats::Bincode;
 use tokio::net::TcpListener;
 use tokio::runtime;
-use tokio::sync::RwLock;
 use tracing::{error, info};
-use tracing_subscriber::layer::SubscriberExt;
-use tracing_subscriber::util::SubscriberInitExt;
-use common::hosting_command::tarpc::{ServerHosting, HOSTING_SOCKET_PATH};
+use common::hosting_command::tarpc::ServerHosting;
+use common::paths::set_paths;
+use common::rpc_auth::{AuthLimiter, ChannelAuth};
+use common::telemetry::{self, OTLP_ENDPOINT_VAR};
+use common::unix_listener::{self, AllowedPeer};
 
 pub fn main() -> HostingResult<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| format!("{}=debug", env!("CARGO_CRATE_NAME")).into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-    LazyLock::force(&CACHE);
-    LazyLock::force(&TOKEN);
-    LazyLock::force(&DB);
+    let config: Config = common::config::load()?;
+    set_paths(config.paths.clone());
+    config::set_config(config);
     let cpus = available_parallelism()?.get();
     let runtime = runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(cpus)
         .build()?;
+    // The span exporter runs on the runtime.
+    let _runtime_guard = runtime.enter();
+    let _telemetry = telemetry::init(env!("CARGO_CRATE_NAME"), dotenvy::var(OTLP_ENDPOINT_VAR).ok());
+    LazyLock::force(&CACHE);
+    LazyLock::force(&TOKEN);
+    LazyLock::force(&DB);
     runtime.block_on(serve(runtime.handle()))
 }
 
@@ -53,35 +51,31 @@ async fn serve(handle: &runtime::Handle) -> HostingResult<()> {
     }
     drop(db);
     
-    let _ = tokio::fs::remove_file(HOSTING_SOCKET_PATH).await;
-
-    let server_auth = dotenvy::var("SERVER_AUTH").unwrap_or_else(|_| "hivehost".to_string());
-    let connected = Arc::new(RwLock::new(false));
+    let server_auth = dotenvy::var("SERVER_AUTH").expect("SERVER_AUTH must be set");
     let app_state = AppState {
         server_auth: Arc::new(SecretString::from(server_auth)),
-        connected: connected.clone(),
+        auth_limiter: Arc::new(AuthLimiter::default()),
     };
     
     
 
     
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127,0,0,1)), 3002);
-    let socket = create_socket(addr).expect("Failed to create socket");
+    let config = config::config();
+    let socket = create_socket(config.hosting.bind_address).expect("Failed to create socket");
     let listener = TcpListener::from_std(socket.into())?;
     let accept_hosting_loop = accept_hosting_loop(handle.clone(), listener);
-    let mut listener =
-        tarpc::serde_transport::unix::listen(HOSTING_SOCKET_PATH, Bincode::default)
-            .await?;
-    
-    listener.config_mut().max_frame_length(10*10*1024);
+    // Only the server's account may connect; the token alone never lets anyone in.
+    let peer = AllowedPeer::service_user()?;
+    info!("Hosting socket path: {}, allowed peer: {:?}", config.rpc.hosting_socket, peer);
+    let listener = unix_listener::listen(&config.rpc.hosting_socket, peer, config.rpc.max_frame_length)?;
     let (http_res, command_res) = tokio::join!(
         handle.spawn(accept_hosting_loop),
         handle.spawn(
             listener
-                .filter_map(|r| future::ready(r.ok()))
-                .map(server::BaseChannel::with_defaults)
-                .map(move |channel| {
-                    let server = ServerToHostingServer(app_state.clone());
+                .map(move |(cred, transport)| {
+                    let channel = server::BaseChannel::with_defaults(transport);
+                    let channel_auth = ChannelAuth::new(format!("uid {}", cred.uid()));
+                    let server = ServerToHostingServer(app_state.clone(), channel_auth);
                     channel
                         .execute(server.serve())
                         .for_each(|response| async move {
@@ -0,0 +1,14 @@
+create table if not exists plans
+(
+    id                 bigserial primary key,
+    name               text   not null unique,
+    -- Referenced bytes a project and its snapshots may use together.
+    project_disk_bytes bigint not null
+);
+
+INSERT INTO public.plans (id, name, project_disk_bytes)
+VALUES (1, 'free', 1073741824),
+       (2, 'pro', 10737418240);
+
+alter table users
+    add column if not exists plan_id BIGINT references plans (id) default 1 not null;
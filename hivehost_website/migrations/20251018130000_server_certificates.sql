@@ -0,0 +1,15 @@
+create table if not exists server_certificates
+(
+    id          bigserial primary key,
+    server_id   BIGINT references servers (id) on delete cascade not null,
+    -- Hex, as in the certificate.
+    serial      text                                             not null unique,
+    cert_pem    text                                             not null,
+    not_after   timestamptz                                      not null,
+    created_at  timestamptz default now()                        not null,
+    -- Set when a newer certificate is issued for the server.
+    replaced_at timestamptz
+);
+
+create unique index if not exists server_certificates_current
+    on server_certificates (server_id) where replaced_at is null;
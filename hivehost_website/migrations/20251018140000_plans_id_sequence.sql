@@ -0,0 +1,2 @@
+-- The plans were inserted with their ids, which leaves the sequence behind them.
+SELECT setval(pg_get_serial_sequence('public.plans', 'id'), (SELECT max(id) FROM public.plans));
@@ -68,11 +68,19 @@ pub fn fetch_api(
 pub mod ssr {
     use crate::ssr::WsClients;
     use crate::{AppError, AppResult};
+    use common::helper_command::ProjectUsage;
+    use common::server_action::project_action::quota::ProjectQuotaAction;
     use common::server_action::project_action::{ProjectAction, ProjectResponse};
-    use common::server_action::user_action::{ServerUserAction, ServerUserResponse};
+    use common::server_action::user_action::{
+        ServerUserAction, ServerUserResponse, SET_PROJECT_QUOTA_CAPABILITY,
+    };
     use common::tarpc_client::TarpcClientError;
-    use common::{ProjectSlugStr, ServerId};
+    use common::{ProjectId, ProjectSlugStr, ServerId, Slug};
+    use sqlx::PgPool;
+    use tracing::{info, warn};
 
+    /// For actions the website runs on its own behalf; the server refuses file actions
+    /// without an acting user.
     pub async fn request_server_project_action(
         server_id: i64,
         project_slug: ProjectSlugStr,
@@ -82,10 +90,10 @@ pub mod ssr {
         let handle_client = |client: WsClients| async move {
             match client.get(&server_id) {
                 None => Err(AppError::TrpcClientError(TarpcClientError::NotConnected)),
-                Some(client) => client
-                    .project_action(project_slug, action)
-                    .await
-                    .map_err(Into::into),
+                Some(client) => Ok(client
+                    .project_action(project_slug, None, action)
+                    .await?
+                    .into_result()?),
             }
         };
 
@@ -98,13 +106,112 @@ pub mod ssr {
         }
     }
 
+    pub async fn request_project_usage(
+        server_id: ServerId,
+        project_slug: ProjectSlugStr,
+        client: Option<WsClients>,
+    ) -> AppResult<ProjectUsage> {
+        match request_server_project_action(
+            server_id,
+            project_slug,
+            ProjectQuotaAction::Usage.into(),
+            client,
+        )
+        .await?
+        {
+            ProjectResponse::Usage(usage) => Ok(usage),
+            response => Err(AppError::Custom(format!(
+                "Unexpected usage response: {response:?}"
+            ))),
+        }
+    }
+
+    /// Disk limit of a project, from its owner's plan.
+    pub async fn project_disk_limit(pool: &PgPool, project_id: ProjectId) -> AppResult<u64> {
+        let plan = sqlx::query!(
+            r#"SELECT plans.project_disk_bytes
+               FROM permissions
+               JOIN users ON users.id = permissions.user_id
+               JOIN plans ON plans.id = users.plan_id
+               WHERE permissions.project_id = $1 AND permissions.permission = 'owner'
+               ORDER BY plans.project_disk_bytes DESC
+               LIMIT 1"#,
+            project_id
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(plan.project_disk_bytes.max(0) as u64)
+    }
+
+    /// Sends every project's limit, from its owners' plans as they are now, to the project's
+    /// server. Projects from before limits existed get one this way, and plan changes reach
+    /// the qgroups that enforce them. Projects on servers that are down or too old are left
+    /// for the next run.
+    pub async fn sync_project_quotas(pool: &PgPool, ws_clients: &WsClients) -> AppResult<()> {
+        let projects = sqlx::query!(
+            r#"SELECT projects.id, projects.name, projects.server_id,
+                      MAX(plans.project_disk_bytes) AS "project_disk_bytes!"
+               FROM projects
+               JOIN permissions ON permissions.project_id = projects.id
+                                AND permissions.permission = 'owner'
+               JOIN users ON users.id = permissions.user_id
+               JOIN plans ON plans.id = users.plan_id
+               GROUP BY projects.id"#
+        )
+        .fetch_all(pool)
+        .await?;
+        let mut synced = 0;
+        for project in projects {
+            let project_slug = Slug::new(project.id, project.name).to_project_slug_str();
+            let Some(client) = ws_clients.get(&project.server_id) else {
+                warn!(
+                    "No client for server {}, skipping {}",
+                    project.server_id, project_slug.0
+                );
+                continue;
+            };
+            let action = ServerUserAction::SetProjectQuota {
+                project_slug: project_slug.clone(),
+                quota_bytes: Some(project.project_disk_bytes.max(0) as u64),
+            };
+            let result: AppResult<ServerUserResponse> = async {
+                client.require_capability(SET_PROJECT_QUOTA_CAPABILITY)?;
+                Ok(client.user_action(action).await?.into_result()?)
+            }
+            .await;
+            match result {
+                Ok(_) => synced += 1,
+                Err(e) => warn!("Could not set the limit of {}: {e}", project_slug.0),
+            }
+        }
+        info!("Project limits synced: {synced}");
+        Ok(())
+    }
+
+    /// Refuses actions that grow a project once it uses its whole plan. The qgroup limit
+    /// stops the writes anyway, this fails them before they start.
+    pub async fn ensure_project_disk_quota(
+        pool: &PgPool,
+        server_id: ServerId,
+        project_slug: &Slug,
+        client: Option<WsClients>,
+    ) -> AppResult<()> {
+        let limit = project_disk_limit(pool, project_slug.id).await?;
+        let usage =
+            request_project_usage(server_id, project_slug.to_project_slug_str(), client).await?;
+        if usage.total.referenced >= limit {
+            return Err(AppError::DiskQuotaExceeded);
+        }
+        Ok(())
+    }
+
     pub async fn request_user_action(
         server_id: ServerId,
         action: ServerUserAction,
     ) -> AppResult<ServerUserResponse> {
         match crate::ssr::ws_clients()?.get(&server_id) {
             None => Err(AppError::TrpcClientError(TarpcClientError::NotConnected)),
-            Some(client) => client.user_action(action).await.map_err(Into::into),
+            Some(client) => Ok(client.user_action(action).await?.into_result()?),
         }
     }
 }
@@ -132,7 +239,9 @@ pub async fn get_action_token_action(
 ) -> AppResult<UsedTokenActionResponse> {
     log!("get_action_token_action: {project_slug} {action:?} {csrf:?}");
     let token_url = request_token_action_front(server_id, project_slug, action, csrf).await?;
-    fetch_api(token_url, form)
-        .await
-        .ok_or(AppError::Custom("Error fetching token action".to_string()))
+    match fetch_api(token_url, form).await {
+        Some(UsedTokenActionResponse::Error(e)) => Err(e.into()),
+        Some(response) => Ok(response),
+        None => Err(AppError::Custom("Error fetching token action".to_string())),
+    }
 }
@@ -172,7 +172,14 @@ pub fn NewProjectPage(
                                                 {servers
                                                     .into_iter()
                                                     .map(|server| {
-                                                        view! { <option value=server.id>{server.name}</option> }
+                                                        view! {
+                                                            <option
+                                                                value=server.id
+                                                                disabled=server.is_incompatible()
+                                                            >
+                                                                {server.label()}
+                                                            </option>
+                                                        }
                                                     })
                                                     .collect::<Vec<_>>()}
                                             </select>
@@ -754,9 +761,20 @@ pub mod server_fns {
     pub async fn get_servers() -> AppResult<Vec<Server>> {
         let _ = crate::ssr::auth(false)?;
         let pool = crate::ssr::pool()?;
-        Ok(sqlx::query_as!(Server, "SELECT id, name FROM servers")
+        let ws_clients = crate::ssr::ws_clients()?;
+        let servers = sqlx::query!("SELECT id, name FROM servers")
             .fetch_all(&pool)
-            .await?)
+            .await?;
+        Ok(servers
+            .into_iter()
+            .map(|server| Server {
+                protocol: ws_clients
+                    .get(&server.id)
+                    .and_then(|client| client.peer_protocol()),
+                id: server.id,
+                name: server.name,
+            })
+            .collect())
     }
 
     #[server(input=Bincode, output=Bincode)]
@@ -874,7 +892,7 @@ pub mod server_fns {
             name: String,
             github_info: Option<GithubInfoFront>,
         ) -> AppResult<Project> {
-            use crate::api::ssr::request_user_action;
+            use crate::api::ssr::{project_disk_limit, request_user_action};
             let pool = crate::ssr::pool()?;
             let project_form = CreateProjectForm { name: name.clone() };
             project_form.validate()?;
@@ -953,15 +971,25 @@ pub mod server_fns {
                 _ => None,
             };
 
-            request_user_action(
+            let quota_bytes = project_disk_limit(&pool, project_id).await?;
+
+            if let Err(e) = request_user_action(
                 server_id,
                 ServerUserAction::AddProject {
                     user_slug: user_slug.to_user_slug_str(),
                     project_slug: Slug::new(project_id, project_form.name).to_project_slug_str(),
                     github_info: github_info_server,
+                    quota_bytes: Some(quota_bytes),
                 },
             )
-            .await?;
+            .await
+            {
+                // The helper undid its part, drop the records so nothing points at it.
+                sqlx::query!("DELETE FROM projects WHERE id = $1", project_id)
+                    .execute(&pool)
+                    .await?;
+                return Err(e);
+            }
             Ok(Project {
                 id: project_id,
                 name,
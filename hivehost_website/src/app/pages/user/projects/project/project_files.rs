@@ -1,14 +1,22 @@
 use leptos::prelude::CustomAttribute;
 use leptos::prelude::{
-    AddAnyAttr, For, NodeRef, NodeRefAttribute, OnAttribute, RwSignal, Set, Show,
+    AddAnyAttr, For, NodeRef, NodeRefAttribute, OnAttribute, RwSignal, Set, Show, Update,
 };
 use leptos::prelude::{Effect, IntoAnyAttribute};
 pub mod file_content_view;
+pub mod file_diff;
+pub mod file_preview;
+pub mod project_files_search;
 pub mod project_files_sidebar;
+pub mod project_files_trash;
 
 use crate::api::{get_action_server_project_action, get_action_token_action};
 use crate::app::pages::user::projects::project::project_files::file_content_view::FileContentView;
-use crate::app::pages::user::projects::project::project_files::project_files_sidebar::ProjectFilesSidebar;
+use crate::app::pages::user::projects::project::project_files::project_files_search::ProjectFilesSearch;
+use crate::app::pages::user::projects::project::project_files::project_files_sidebar::{
+    describe_io_response, ProjectFilesSidebar,
+};
+use crate::app::pages::user::projects::project::project_files::project_files_trash::ProjectFilesTrash;
 use crate::app::pages::user::projects::project::ProjectSlugSignal;
 use crate::app::IntoView;
 use leptos_router::params::Params;
@@ -24,11 +32,11 @@ use crate::models::ProjectSlugStrFront;
 use crate::security::permission::request_server_project_action_front;
 use common::server_action::project_action::io_action::file_action::ProjectIoFileAction;
 use common::server_action::token_action::{TokenAction, UsedTokenActionResponse};
-use common::ServerId;
+use common::{ProjectRelPath, ServerId};
 use leptos::html::Input;
 use leptos::logging::log;
 use leptos::prelude::{expect_context, signal, ClassAttribute, CollectView, Get, IntoMaybeErased};
-use leptos::prelude::{Callback, Signal};
+use leptos::prelude::{Callback, GetUntracked, Signal};
 use leptos::reactive::spawn_local;
 use leptos::server::Resource;
 use leptos::{component, view, Params};
@@ -36,6 +44,7 @@ use leptos_router::components::A;
 use leptos_router::hooks::{use_navigate, use_params};
 use leptos_router::params::ParamsError;
 use reactive_stores::{OptionStoreExt, Store};
+use std::str::FromStr;
 use wasm_bindgen::JsCast;
 use web_sys::{FormData, HtmlFormElement, SubmitEvent};
 
@@ -70,6 +79,9 @@ pub fn ProjectFiles() -> impl IntoView {
             })
             .unwrap_or_else(|| "root/".to_string())
     });
+    let current_rel_path = Signal::derive(move || {
+        ProjectRelPath::from_str(&current_path.get()).unwrap_or_else(|_| ProjectRelPath::root())
+    });
 
     let global_state: Store<GlobalState> = expect_context();
 
@@ -106,6 +118,7 @@ pub fn ProjectFiles() -> impl IntoView {
             )
         },
         |(_, path, server_id, slug, _)| {
+            let path = ProjectRelPath::from_str(&path).unwrap_or_else(|_| ProjectRelPath::root());
             request_server_project_action_front(
                 server_id,
                 slug,
@@ -131,14 +144,38 @@ pub fn ProjectFiles() -> impl IntoView {
         }
         segments
     };
+    // A file picked from search results in another folder is selected once we land there.
+    let (pending_selected_file, set_pending_selected_file) = signal::<Option<String>>(None);
     Effect::new(move |_| {
         let _ = current_path.get();
-        set_selected_file(None);
+        set_selected_file(pending_selected_file.get_untracked());
+        set_pending_selected_file(None);
     });
 
     let handle_select_file = Callback::new(move |file_path: String| {
         set_selected_file(Some(file_path));
     });
+    let handle_search_select_file = Callback::new(move |file_path: String| {
+        let parent = file_path
+            .rsplit_once('/')
+            .map(|(parent, _)| format!("{parent}/"))
+            .unwrap_or_else(|| "root/".to_string());
+        if parent == current_path.get_untracked() {
+            set_selected_file(Some(file_path));
+        } else {
+            set_pending_selected_file(Some(file_path));
+        }
+    });
+    // Per-entry problems reported by batch, move and copy actions.
+    let (io_issues, set_io_issues) = signal(Vec::<String>::new());
+    Effect::new(move |_| {
+        set_io_issues(match server_project_action.value().get() {
+            Some(Ok(response)) => describe_io_response(&response),
+            Some(Err(e)) => vec![e.to_string()],
+            None => vec![],
+        });
+    });
+    let show_trash = RwSignal::new(false);
     let folder_name_ref: NodeRef<Input> = NodeRef::new();
     let file_name_ref: NodeRef<Input> = NodeRef::new();
 
@@ -148,13 +185,14 @@ pub fn ProjectFiles() -> impl IntoView {
         if folder_name.trim().is_empty() {
             return;
         }
+        let Ok(path) = current_rel_path.get().join(&folder_name) else {
+            set_io_issues(vec![format!("Invalid folder name: {folder_name}")]);
+            return;
+        };
         server_project_action.dispatch((
             server_id(),
             slug(),
-            ProjectIoDirAction::Create {
-                path: format!("{}{}", current_path.get(), folder_name),
-            }
-            .into(),
+            ProjectIoDirAction::Create { path }.into(),
             Some(
                 csrf_signal
                     .read()
@@ -171,13 +209,14 @@ pub fn ProjectFiles() -> impl IntoView {
         if file_name.trim().is_empty() {
             return;
         }
+        let Ok(path) = current_rel_path.get().join(&file_name) else {
+            set_io_issues(vec![format!("Invalid file name: {file_name}")]);
+            return;
+        };
         server_project_action.dispatch((
             server_id(),
             slug(),
-            ProjectIoFileAction::Create {
-                path: format!("{}{}", current_path.get(), file_name),
-            }
-            .into(),
+            ProjectIoFileAction::Create { path }.into(),
             Some(
                 csrf_signal
                     .read()
@@ -231,6 +270,37 @@ pub fn ProjectFiles() -> impl IntoView {
                     }}
                 </nav>
             </div>
+            <div class="flex-shrink-0 p-4 border-b border-gray-700">
+                <ProjectFilesSearch
+                    server_id
+                    slug
+                    current_path
+                    on_select_file=handle_search_select_file
+                />
+            </div>
+            <Show when=move || !io_issues.get().is_empty()>
+                <div class="flex-shrink-0 p-4 border-b border-gray-700">
+                    <div class="flex items-start justify-between gap-x-2 p-3 bg-gray-800 rounded-md text-xs shadow">
+                        <ul class="max-h-32 overflow-y-auto space-y-1 text-red-400">
+                            {move || {
+                                io_issues
+                                    .get()
+                                    .into_iter()
+                                    .map(|issue| view! { <li>{issue}</li> })
+                                    .collect_view()
+                            }}
+                        </ul>
+                        <button
+                            type="button"
+                            class="text-gray-400 hover:text-white"
+                            title="Dismiss"
+                            on:click=move |_| set_io_issues(Vec::new())
+                        >
+                            "x"
+                        </button>
+                    </div>
+                </div>
+            </Show>
             <div
                 class="flex-shrink-0 p-4 border-b border-gray-700"
                 class=("hidden", move || !permission_signal().can_edit())
@@ -299,7 +369,27 @@ pub fn ProjectFiles() -> impl IntoView {
                         </div>
                     </form>
 
-                    <FileUploadArea slug current_path csrf_signal refresh_signal server_id />
+                    <FileUploadArea
+                        slug
+                        current_path=current_rel_path
+                        csrf_signal
+                        refresh_signal
+                        server_id
+                    />
+                </div>
+                <div class="mt-4">
+                    <button
+                        type="button"
+                        class="btn btn-secondary px-2 py-1 text-xs"
+                        on:click=move |_| show_trash.update(|show| *show = !*show)
+                    >
+                        {move || if show_trash.get() { "Hide trash" } else { "Show trash" }}
+                    </button>
+                    <Show when=move || show_trash.get()>
+                        <div class="mt-2">
+                            <ProjectFilesTrash server_id slug csrf_signal server_project_action />
+                        </div>
+                    </Show>
                 </div>
             </div>
 
@@ -368,7 +458,7 @@ pub fn FileUploadArea(
     server_id: Signal<ServerId>,
     slug: Signal<ProjectSlugStrFront>,
 
-    current_path: Signal<String>,
+    current_path: Signal<ProjectRelPath>,
     csrf_signal: Signal<Option<String>>,
     refresh_signal: RwSignal<u32>,
 ) -> impl IntoView {
@@ -424,14 +514,8 @@ pub fn FileUploadArea(
                                 .collect();
                             set_upload_messages(messages);
                         }
-                        Ok(UsedTokenActionResponse::Error(e)) => {
-                            set_upload_messages(vec![format!("Upload failed: {}", e)]);
-                        }
                         Err(e) => {
-                            set_upload_messages(vec![format!(
-                                "Server error during upload: {:?}",
-                                e
-                            )]);
+                            set_upload_messages(vec![format!("Upload failed: {}", e)]);
                         }
                         _ => {
                             set_upload_messages(vec![
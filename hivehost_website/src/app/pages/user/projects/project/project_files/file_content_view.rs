@@ -1,18 +1,28 @@
 use crate::api::{get_action_server_project_action, get_action_token_action};
+use crate::app::pages::user::projects::project::project_files::file_diff::{
+    diff_lines, merge_with_markers, DiffLine,
+};
+use crate::app::pages::user::projects::project::project_files::file_preview::{
+    is_editable, MediaPreview, TextRangeViewer,
+};
 use crate::models::ProjectSlugStrFront;
 use common::server_action::permission::Permission;
-use common::server_action::token_action::{TokenAction, UsedTokenActionResponse};
-use common::ServerId;
-use leptos::either::EitherOf4;
+use common::server_action::token_action::{
+    FileInfo, FileKind, TokenAction, UsedTokenActionResponse,
+};
+use common::{ProjectRelPath, ServerId};
+use leptos::either::{EitherOf3, EitherOf4};
 use leptos::html::Textarea;
 use leptos::leptos_dom::log;
 use leptos::prelude::{signal, ElementChild, GlobalAttributes, NodeRef, NodeRefAttribute, Show};
 use leptos::prelude::{ClassAttribute, Get, Signal, Transition};
+use leptos::prelude::{CollectView, Effect};
 use leptos::prelude::{GetUntracked, OnAttribute};
 use leptos::prelude::{IntoMaybeErased, ServerFnError, Suspend};
 use leptos::reactive::spawn_local;
 use leptos::server::LocalResource;
 use leptos::{component, view, IntoView};
+use std::str::FromStr;
 use std::sync::Arc;
 use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{js_sys, Blob, FormData, SubmitEvent};
@@ -28,10 +38,12 @@ pub fn FileContentView(
     let file_content_resource = LocalResource::new(move || async move {
         match selected_file.get() {
             Some(file_path) => {
+                let path = ProjectRelPath::from_str(&file_path)
+                    .map_err(|_| ServerFnError::new("Invalid file path"))?;
                 match get_action_token_action(
                     server_id(),
                     slug.get(),
-                    TokenAction::ViewFile { path: file_path },
+                    TokenAction::ViewFile { path },
                     None,
                     None,
                 )
@@ -40,7 +52,7 @@ pub fn FileContentView(
                     Ok(UsedTokenActionResponse::File(file_info)) => Ok(file_info),
                     Err(e) => {
                         leptos::logging::error!("Error fetching file: {:?}", e);
-                        Err(ServerFnError::new("Failed to fetch file"))
+                        Err(ServerFnError::new(format!("Failed to fetch file: {e}")))
                     }
                     _ => Err(ServerFnError::new("Invalid response type")),
                 }
@@ -50,18 +62,27 @@ pub fn FileContentView(
     });
 
     let (current_file_path_for_form, set_current_file_path_for_form) = signal(String::new());
+    let (file_version, set_file_version) = signal(String::new());
+    // Content that was refused on save, with the file as it is on the server now.
+    let (conflict, set_conflict) = signal::<Option<(String, FileInfo)>>(None);
+    Effect::new(move |_| {
+        let _ = selected_file.get();
+        set_conflict(None);
+    });
 
     let server_save_action = get_action_server_project_action();
     let node_ref: NodeRef<Textarea> = NodeRef::new();
 
     let handle_download_file = move |file_path: Arc<String>| {
+        let Ok(path) = ProjectRelPath::from_str(&file_path) else {
+            leptos::logging::error!("Invalid file path: {file_path}");
+            return;
+        };
         spawn_local(async move {
             match get_action_token_action(
                 server_id(),
                 slug(),
-                TokenAction::DownloadFile {
-                    path: file_path.to_string(),
-                },
+                TokenAction::DownloadFile { path },
                 None,
                 None,
             )
@@ -97,35 +118,41 @@ pub fn FileContentView(
         });
     };
 
-    let handle_on_submit = move |ev: SubmitEvent| {
-        ev.prevent_default();
-
+    let save_file = move |content: String, version: String| {
         let form_data = FormData::new().unwrap();
-        form_data
-            .append_with_str("file_content", &node_ref.get().unwrap().value())
-            .unwrap();
+        form_data.append_with_str("file_content", &content).unwrap();
 
-        let path_to_save = current_file_path_for_form.get_untracked();
+        let Ok(path_to_save) =
+            ProjectRelPath::from_str(&current_file_path_for_form.get_untracked())
+        else {
+            leptos::logging::error!("Invalid file path, not saving.");
+            return;
+        };
         let csrf_token_val = csrf_signal.get_untracked();
 
         spawn_local(async move {
             match get_action_token_action(
                 server_id(),
                 slug(),
-                TokenAction::UpdateFile { path: path_to_save },
+                TokenAction::UpdateFile {
+                    path: path_to_save,
+                    version,
+                },
                 csrf_token_val,
                 Some(form_data),
             )
             .await
             {
-                Ok(UsedTokenActionResponse::Ok) => {
+                Ok(UsedTokenActionResponse::Saved(version)) => {
                     log!("File saved successfully.");
+                    set_file_version(version);
+                    set_conflict(None);
                 }
-                Ok(UsedTokenActionResponse::Error(e)) => {
-                    leptos::logging::error!("Error saving file: {:?}", e);
+                Ok(UsedTokenActionResponse::Conflict(current)) => {
+                    set_conflict(Some((content, current)));
                 }
                 Err(e) => {
-                    leptos::logging::error!("Server error saving file: {:?}", e);
+                    leptos::logging::error!("Error saving file: {:?}", e);
                 }
                 _ => {
                     leptos::logging::warn!("Unexpected response type after saving file.");
@@ -134,6 +161,126 @@ pub fn FileContentView(
         });
     };
 
+    let handle_on_submit = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        save_file(
+            node_ref.get().unwrap().value(),
+            file_version.get_untracked(),
+        );
+    };
+
+    // Replaces the editor content and continues from the server version, so the next save
+    // only conflicts again if the file changes once more.
+    let continue_from_server = move |content: String, version: String| {
+        if let Some(textarea) = node_ref.get() {
+            textarea.set_value(&content);
+        }
+        set_file_version(version);
+        set_conflict(None);
+    };
+
+    let conflict_view = move || {
+        conflict.get().map(|(mine, theirs)| {
+            let server_content = theirs
+                .content
+                .clone()
+                .filter(|_| theirs.content_end == theirs.size);
+            let can_merge = server_content.is_some();
+            let diff = server_content
+                .as_deref()
+                .map(|server_content| diff_lines(server_content, &mine))
+                .unwrap_or_default();
+            let (mine_for_overwrite, version_for_overwrite) = (mine.clone(), theirs.version.clone());
+            let (server_for_discard, version_for_discard) = (
+                server_content.clone().unwrap_or_default(),
+                theirs.version.clone(),
+            );
+            let (server_for_merge, version_for_merge) =
+                (server_content.unwrap_or_default(), theirs.version.clone());
+            view! {
+                <div class="mb-4 p-3 rounded-md border border-yellow-600 bg-gray-800 text-sm space-y-3 flex-shrink-0">
+                    <p class="text-yellow-400 font-semibold">
+                        {format!(
+                            "This file was changed by someone else ({}) since you opened it.",
+                            theirs.last_modified,
+                        )}
+                    </p>
+                    <Show
+                        when=move || can_merge
+                        fallback=|| {
+                            view! {
+                                <p class="text-gray-400">
+                                    "The new server version is too large to compare here."
+                                </p>
+                            }
+                        }
+                    >
+                        <p class="text-xs text-gray-400">
+                            <span class="text-red-400">"- server"</span>
+                            " / "
+                            <span class="text-green-400">"+ yours"</span>
+                        </p>
+                    </Show>
+                    <pre class="max-h-64 overflow-auto bg-gray-900 rounded p-2 font-mono text-xs">
+                        {diff
+                            .into_iter()
+                            .map(|line| {
+                                let (class, line) = match line {
+                                    DiffLine::Same(line) => ("text-gray-500", format!("  {line}")),
+                                    DiffLine::Removed(line) => ("text-red-400", format!("- {line}")),
+                                    DiffLine::Added(line) => ("text-green-400", format!("+ {line}")),
+                                };
+                                view! { <div class=class>{line}</div> }
+                            })
+                            .collect_view()}
+                    </pre>
+                    <div class="flex flex-wrap gap-2">
+                        <button
+                            type="button"
+                            class="btn btn-secondary px-3 py-1 text-sm"
+                            class=("hidden", !can_merge)
+                            title="Put both versions in the editor with conflict markers"
+                            on:click=move |_| continue_from_server(
+                                merge_with_markers(&server_for_merge, &mine),
+                                version_for_merge.clone(),
+                            )
+                        >
+                            "Merge by hand"
+                        </button>
+                        <button
+                            type="button"
+                            class="btn btn-secondary px-3 py-1 text-sm"
+                            class=("hidden", !can_merge)
+                            on:click=move |_| continue_from_server(
+                                server_for_discard.clone(),
+                                version_for_discard.clone(),
+                            )
+                        >
+                            "Discard mine"
+                        </button>
+                        <button
+                            type="button"
+                            class="btn btn-danger px-3 py-1 text-sm"
+                            on:click=move |_| save_file(
+                                mine_for_overwrite.clone(),
+                                version_for_overwrite.clone(),
+                            )
+                        >
+                            "Overwrite with mine"
+                        </button>
+                        <button
+                            type="button"
+                            class="btn btn-secondary px-3 py-1 text-sm"
+                            on:click=move |_| set_conflict(None)
+                        >
+                            "Keep editing"
+                        </button>
+                    </div>
+                </div>
+            }
+        })
+    };
+
     view! {
         <Transition fallback=move || {
             view! { <p class="text-gray-400">"Loading..."</p> }
@@ -164,8 +311,10 @@ pub fn FileContentView(
                     (Some(_), Some(Ok(file_info))) => {
                         let can_edit = permission_signal.get().can_edit();
                         set_current_file_path_for_form(file_info.path.clone());
+                        set_file_version(file_info.version.clone());
                         let content = file_info.content.clone();
-                        let has_content = content.is_some();
+                        let has_content = is_editable(&file_info);
+                        let preview_info = file_info.clone();
                         let file_path_clone = Arc::new(file_info.path.clone());
                         let content = Signal::derive(move || content.clone().unwrap_or_default());
                         EitherOf4::D(
@@ -205,27 +354,72 @@ pub fn FileContentView(
                                             </form>
                                         </Show>
                                     </div>
+                                    {conflict_view}
                                     <Show
                                         when=move || has_content
                                         fallback=move || {
                                             let file_path_clone = file_path_clone.clone();
-                                            view! {
-                                                <div class="flex-grow p-4 text-gray-400">
-                                                    <p class="font-semibold mb-2">
-                                                        "Content not displayable in editor."
-                                                    </p>
-                                                    <p>
-                                                        "This might be a binary file, too large, or not valid text."
-                                                    </p>
-                                                    <button
-                                                        class="btn btn-primary mt-4"
-                                                        on:click=move |_| handle_download_file(
-                                                            file_path_clone.clone(),
-                                                        )
-                                                    >
-                                                        Download File
-                                                    </button>
-                                                </div>
+                                            let download_button = view! {
+                                                <button
+                                                    class="btn btn-primary mt-4"
+                                                    on:click=move |_| handle_download_file(
+                                                        file_path_clone.clone(),
+                                                    )
+                                                >
+                                                    Download File
+                                                </button>
+                                            };
+                                            let file_info = preview_info.clone();
+                                            match file_info.kind {
+                                                FileKind::Text if file_info.content.is_some() => {
+                                                    EitherOf3::A(
+                                                        view! {
+                                                            <div class="flex-grow min-h-0 flex flex-col">
+                                                                <TextRangeViewer
+                                                                    server_id=server_id
+                                                                    slug=slug
+                                                                    file_info=file_info
+                                                                />
+                                                                {download_button}
+                                                            </div>
+                                                        },
+                                                    )
+                                                }
+                                                FileKind::Image
+                                                | FileKind::Audio
+                                                | FileKind::Video
+                                                | FileKind::Pdf => {
+                                                    EitherOf3::B(
+                                                        view! {
+                                                            <div class="flex-grow min-h-0 flex flex-col">
+                                                                <MediaPreview
+                                                                    server_id=server_id
+                                                                    slug=slug
+                                                                    file_info=file_info
+                                                                />
+                                                                {download_button}
+                                                            </div>
+                                                        },
+                                                    )
+                                                }
+                                                _ => {
+                                                    EitherOf3::C(
+                                                        view! {
+                                                            <div class="flex-grow p-4 text-gray-400">
+                                                                <p class="font-semibold mb-2">
+                                                                    "Content not displayable in editor."
+                                                                </p>
+                                                                <p>
+                                                                    {format!(
+                                                                        "This looks like a binary file ({}).",
+                                                                        file_info.mime,
+                                                                    )}
+                                                                </p>
+                                                                {download_button}
+                                                            </div>
+                                                        },
+                                                    )
+                                                }
                                             }
                                         }
                                     >
@@ -258,7 +452,7 @@ pub mod server_fns {
     }}
 }
 
-fn format_bytes(bytes: u64) -> String {
+pub fn format_bytes(bytes: u64) -> String {
     const KB: f64 = 1024.0;
     const MB: f64 = KB * 1024.0;
     const GB: f64 = MB * 1024.0;
@@ -0,0 +1,101 @@
+/// Above this many line pairs the diff is not computed and the files are shown as fully replaced.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    /// Only in the first text.
+    Removed(String),
+    /// Only in the second text.
+    Added(String),
+}
+
+/// Line diff from `old` to `new`, based on their longest common subsequence.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    if old.len().saturating_mul(new.len()) > MAX_DIFF_CELLS {
+        return old
+            .iter()
+            .map(|line| DiffLine::Removed(line.to_string()))
+            .chain(new.iter().map(|line| DiffLine::Added(line.to_string())))
+            .collect();
+    }
+
+    // lcs[i][j]: length of the common subsequence of old[i..] and new[j..].
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut diff = Vec::with_capacity(old.len().max(new.len()));
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push(DiffLine::Same(old[i].to_string()));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            diff.push(DiffLine::Removed(old[i].to_string()));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new[j].to_string()));
+            j += 1;
+        }
+    }
+    diff.extend(
+        old[i..]
+            .iter()
+            .map(|line| DiffLine::Removed(line.to_string())),
+    );
+    diff.extend(
+        new[j..]
+            .iter()
+            .map(|line| DiffLine::Added(line.to_string())),
+    );
+    diff
+}
+
+/// Keeps the lines both texts share and wraps every differing hunk in git style conflict
+/// markers, the `theirs` side first, for the user to resolve by hand.
+pub fn merge_with_markers(theirs: &str, mine: &str) -> String {
+    let mut merged = String::new();
+    let mut their_hunk = Vec::new();
+    let mut my_hunk = Vec::new();
+    let flush = |merged: &mut String, their_hunk: &mut Vec<String>, my_hunk: &mut Vec<String>| {
+        if their_hunk.is_empty() && my_hunk.is_empty() {
+            return;
+        }
+        merged.push_str("<<<<<<< server\n");
+        for line in their_hunk.drain(..) {
+            merged.push_str(&line);
+            merged.push('\n');
+        }
+        merged.push_str("=======\n");
+        for line in my_hunk.drain(..) {
+            merged.push_str(&line);
+            merged.push('\n');
+        }
+        merged.push_str(">>>>>>> yours\n");
+    };
+    for line in diff_lines(theirs, mine) {
+        match line {
+            DiffLine::Same(line) => {
+                flush(&mut merged, &mut their_hunk, &mut my_hunk);
+                merged.push_str(&line);
+                merged.push('\n');
+            }
+            DiffLine::Removed(line) => their_hunk.push(line),
+            DiffLine::Added(line) => my_hunk.push(line),
+        }
+    }
+    flush(&mut merged, &mut their_hunk, &mut my_hunk);
+    merged
+}
@@ -0,0 +1,223 @@
+use crate::api::get_action_token_action;
+use crate::app::pages::user::projects::project::project_files::file_content_view::format_bytes;
+use crate::models::ProjectSlugStrFront;
+use crate::security::permission::request_token_action_front;
+use crate::AppResult;
+use common::server_action::token_action::{
+    FileInfo, FileKind, TokenAction, UsedTokenActionResponse,
+};
+use common::{ProjectRelPath, ServerId};
+use leptos::either::EitherOf5;
+use leptos::prelude::*;
+use leptos::reactive::spawn_local;
+use std::str::FromStr;
+
+/// Whether the editor can open the file: fully loaded UTF-8 text, so saving it back does not
+/// truncate or re-encode anything.
+pub fn is_editable(file_info: &FileInfo) -> bool {
+    file_info.kind == FileKind::Text
+        && file_info.content.is_some()
+        && file_info.content_end == file_info.size
+        && file_info.encoding.as_deref() == Some("utf-8")
+}
+
+/// Read-only view of a text file too large or not UTF-8, loaded a page at a time.
+#[component]
+pub fn TextRangeViewer(
+    server_id: Signal<ServerId>,
+    slug: Signal<ProjectSlugStrFront>,
+    file_info: FileInfo,
+) -> impl IntoView {
+    let (text, set_text) = signal(file_info.content.clone().unwrap_or_default());
+    let (next_offset, set_next_offset) = signal(file_info.content_end);
+    let (loading, set_loading) = signal(false);
+    let (error, set_error) = signal::<Option<String>>(None);
+    let size = file_info.size;
+    let path = ProjectRelPath::from_str(&file_info.path);
+
+    let load_more = move |_| {
+        let Ok(path) = path.clone() else {
+            set_error(Some("Invalid file path".to_string()));
+            return;
+        };
+        set_loading(true);
+        spawn_local(async move {
+            match get_action_token_action(
+                server_id.get_untracked(),
+                slug.get_untracked(),
+                TokenAction::ReadFileRange {
+                    path,
+                    offset: next_offset.get_untracked(),
+                },
+                None,
+                None,
+            )
+            .await
+            {
+                Ok(UsedTokenActionResponse::TextRange(range)) => {
+                    set_text.update(|text| text.push_str(&range.content));
+                    set_next_offset(range.end);
+                    set_error(None);
+                }
+                Err(e) => set_error(Some(e.to_string())),
+                _ => set_error(Some("Unexpected response".to_string())),
+            }
+            set_loading(false);
+        });
+    };
+
+    view! {
+        <div class="flex flex-col h-full min-h-0 space-y-2">
+            <p class="text-xs text-gray-400 flex-shrink-0">
+                {format!(
+                    "Read-only preview ({}).",
+                    file_info.encoding.clone().unwrap_or_default(),
+                )}
+                {move || {
+                    format!(
+                        " Showing {} of {}.",
+                        format_bytes(next_offset.get()),
+                        format_bytes(size),
+                    )
+                }}
+            </p>
+            <pre class="flex-grow min-h-0 overflow-auto bg-gray-800 text-gray-200 border border-gray-700 rounded-md p-3 font-mono text-sm">
+                {move || text.get()}
+            </pre>
+            <Show when=move || error.get().is_some()>
+                <p class="text-red-400 text-sm">{move || error.get().unwrap_or_default()}</p>
+            </Show>
+            <Show when=move || { next_offset.get() < size }>
+                <button
+                    type="button"
+                    class="btn btn-secondary px-3 py-1 text-sm self-start"
+                    disabled=move || loading.get()
+                    on:click=load_more.clone()
+                >
+                    {move || if loading.get() { "Loading..." } else { "Load more" }}
+                </button>
+            </Show>
+        </div>
+    }
+}
+
+/// Inline preview of images, audio, video and PDF, loaded from stream token URLs.
+#[component]
+pub fn MediaPreview(
+    server_id: Signal<ServerId>,
+    slug: Signal<ProjectSlugStrFront>,
+    file_info: FileInfo,
+) -> impl IntoView {
+    let path = ProjectRelPath::from_str(&file_info.path);
+    let kind = file_info.kind;
+    // SVG is rendered by the browser, not decoded into a thumbnail on the server.
+    let use_thumbnail = kind == FileKind::Image && file_info.mime != "image/svg+xml";
+    let urls = LocalResource::new(move || {
+        let path = path.clone();
+        async move {
+            let path = path?;
+            let stream_url = request_token_action_front(
+                server_id(),
+                slug(),
+                TokenAction::StreamFile { path: path.clone() },
+                None,
+            )
+            .await?;
+            let thumbnail_url = if use_thumbnail {
+                Some(
+                    request_token_action_front(
+                        server_id(),
+                        slug(),
+                        TokenAction::Thumbnail { path },
+                        None,
+                    )
+                    .await?,
+                )
+            } else {
+                None
+            };
+            AppResult::Ok((stream_url, thumbnail_url))
+        }
+    });
+    let (full_size, set_full_size) = signal(false);
+    let name = file_info.name;
+
+    view! {
+        <Transition fallback=move || {
+            view! { <p class="text-gray-400">"Loading preview..."</p> }
+        }>
+            {move || {
+                let name = name.clone();
+                Suspend::new(async move {
+                    let (stream_url, thumbnail_url) = match urls.await {
+                        Ok(urls) => urls,
+                        Err(e) => {
+                            return EitherOf5::A(
+                                view! {
+                                    <p class="text-red-400">
+                                        {format!("Could not load the preview: {e}")}
+                                    </p>
+                                },
+                            );
+                        }
+                    };
+                    match kind {
+                        FileKind::Audio => {
+                            EitherOf5::B(
+                                view! {
+                                    <audio controls preload="metadata" class="w-full" src=stream_url />
+                                },
+                            )
+                        }
+                        FileKind::Video => {
+                            EitherOf5::C(
+                                view! {
+                                    <video
+                                        controls
+                                        preload="metadata"
+                                        class="max-w-full max-h-full rounded-md"
+                                        src=stream_url
+                                    />
+                                },
+                            )
+                        }
+                        FileKind::Pdf => {
+                            EitherOf5::D(
+                                view! {
+                                    <iframe
+                                        class="w-full h-full min-h-[32rem] rounded-md bg-white"
+                                        src=stream_url
+                                    />
+                                },
+                            )
+                        }
+                        _ => {
+                            let thumbnail_url = thumbnail_url.unwrap_or_else(|| stream_url.clone());
+                            EitherOf5::E(
+                                view! {
+                                    <div class="flex flex-col items-start space-y-2">
+                                        <img
+                                            class="max-w-full rounded-md bg-gray-800 cursor-pointer"
+                                            alt=name
+                                            src=move || {
+                                                if full_size.get() {
+                                                    stream_url.clone()
+                                                } else {
+                                                    thumbnail_url.clone()
+                                                }
+                                            }
+                                            on:click=move |_| set_full_size.update(|full| *full = !*full)
+                                        />
+                                        <p class="text-xs text-gray-500">
+                                            "Click the image to toggle full size."
+                                        </p>
+                                    </div>
+                                },
+                            )
+                        }
+                    }
+                })
+            }}
+        </Transition>
+    }
+}
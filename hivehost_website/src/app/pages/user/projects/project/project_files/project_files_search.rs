@@ -0,0 +1,258 @@
+use crate::api::get_action_server_project_action;
+use crate::models::ProjectSlugStrFront;
+use common::server_action::project_action::io_action::search_action::{
+    ProjectIoSearchAction, SearchCursor, SearchMatch,
+};
+use common::server_action::project_action::ProjectResponse;
+use common::{ProjectRelPath, ServerId};
+use leptos::either::Either;
+use leptos::html::Input;
+use leptos::prelude::*;
+use leptos_router::components::A;
+use std::str::FromStr;
+use web_sys::SubmitEvent;
+
+#[derive(Clone, Debug, PartialEq)]
+struct SearchQuery {
+    path: ProjectRelPath,
+    query: String,
+    search_content: bool,
+    is_regex: bool,
+}
+
+impl SearchQuery {
+    fn to_action(&self, cursor: Option<SearchCursor>) -> ProjectIoSearchAction {
+        if self.search_content {
+            ProjectIoSearchAction::Content {
+                path: self.path.clone(),
+                query: self.query.clone(),
+                is_regex: self.is_regex,
+                case_sensitive: false,
+                cursor,
+            }
+        } else {
+            ProjectIoSearchAction::Name {
+                path: self.path.clone(),
+                pattern: self.query.clone(),
+                cursor,
+            }
+        }
+    }
+}
+
+fn parent_path(path: &str) -> String {
+    path.rsplit_once('/')
+        .map(|(parent, _)| format!("{parent}/"))
+        .unwrap_or_else(|| "root/".to_string())
+}
+
+#[component]
+pub fn ProjectFilesSearch(
+    server_id: Signal<ServerId>,
+    slug: Signal<ProjectSlugStrFront>,
+    current_path: Signal<String>,
+    on_select_file: Callback<String>,
+) -> impl IntoView {
+    let search_action = get_action_server_project_action();
+    let query_ref: NodeRef<Input> = NodeRef::new();
+    let (search_content, set_search_content) = signal(false);
+    let (is_regex, set_is_regex) = signal(false);
+    let (last_query, set_last_query) = signal::<Option<SearchQuery>>(None);
+    let (results, set_results) = signal(Vec::<SearchMatch>::new());
+    let (next_cursor, set_next_cursor) = signal::<Option<SearchCursor>>(None);
+    let (feedback, set_feedback) = signal(String::new());
+
+    Effect::new(move |_| {
+        if let Some(result) = search_action.value().get() {
+            match result {
+                Ok(ProjectResponse::Search(response)) => {
+                    set_next_cursor(response.next);
+                    set_results.update(|results| results.extend(response.inner));
+                    if response.truncated {
+                        set_feedback(
+                            "Search paused, this folder is large. Load more to keep searching."
+                                .to_string(),
+                        );
+                    } else if results.read_untracked().is_empty() {
+                        set_feedback("No matches.".to_string());
+                    } else {
+                        set_feedback(String::new());
+                    }
+                }
+                Ok(_) => set_feedback("Unexpected response from server.".to_string()),
+                Err(e) => set_feedback(format!("Search failed: {e}")),
+            }
+        }
+    });
+
+    let on_search_submit = move |ev: SubmitEvent| {
+        ev.prevent_default();
+        let query = query_ref.get().unwrap().value();
+        if query.trim().is_empty() {
+            set_last_query(None);
+            set_results(Vec::new());
+            set_next_cursor(None);
+            return;
+        }
+        let Ok(path) = ProjectRelPath::from_str(&current_path.get_untracked()) else {
+            set_feedback("Invalid folder.".to_string());
+            return;
+        };
+        let search_query = SearchQuery {
+            path,
+            query,
+            search_content: search_content.get_untracked(),
+            is_regex: is_regex.get_untracked(),
+        };
+        set_results(Vec::new());
+        set_next_cursor(None);
+        set_feedback("Searching...".to_string());
+        search_action.dispatch((
+            server_id(),
+            slug(),
+            search_query.to_action(None).into(),
+            None,
+        ));
+        set_last_query(Some(search_query));
+    };
+
+    let on_load_more = move |_| {
+        if let (Some(search_query), Some(cursor)) =
+            (last_query.get_untracked(), next_cursor.get_untracked())
+        {
+            search_action.dispatch((
+                server_id(),
+                slug(),
+                search_query.to_action(Some(cursor)).into(),
+                None,
+            ));
+        }
+    };
+
+    view! {
+        <div class="flex flex-col space-y-2">
+            <form on:submit=on_search_submit class="flex flex-col space-y-2">
+                <label for="search_query" class="form-label text-xs">
+                    "Search in this folder"
+                </label>
+                <div class="flex items-center gap-x-2">
+                    <input
+                        type="text"
+                        name="search_query"
+                        node_ref=query_ref
+                        class="form-input flex-grow"
+                        placeholder=move || {
+                            if search_content.get() { "Text or regex..." } else { "*.html, index.*..." }
+                        }
+                    />
+                    <button
+                        type="submit"
+                        class="btn btn-primary flex-shrink-0"
+                        disabled=move || search_action.pending().get()
+                    >
+                        "Search"
+                    </button>
+                </div>
+                <div class="flex items-center gap-x-4 text-xs text-gray-400">
+                    <label class="flex items-center gap-x-1">
+                        <input
+                            type="checkbox"
+                            prop:checked=search_content
+                            on:change=move |ev| set_search_content(event_target_checked(&ev))
+                        />
+                        "File contents"
+                    </label>
+                    <label
+                        class="flex items-center gap-x-1"
+                        class=("hidden", move || !search_content.get())
+                    >
+                        <input
+                            type="checkbox"
+                            prop:checked=is_regex
+                            on:change=move |ev| set_is_regex(event_target_checked(&ev))
+                        />
+                        "Regex"
+                    </label>
+                </div>
+            </form>
+
+            <Show when=move || !feedback.get().is_empty()>
+                <p class="text-xs text-gray-400">{move || feedback.get()}</p>
+            </Show>
+
+            <Show when=move || !results.get().is_empty()>
+                <ul class="p-2 bg-gray-800 rounded-md max-h-64 overflow-y-auto text-xs space-y-1 shadow">
+                    {move || {
+                        results
+                            .get()
+                            .into_iter()
+                            .map(|search_match| {
+                                let label = search_match
+                                    .path
+                                    .trim_start_matches("root/")
+                                    .to_string();
+                                let line = search_match
+                                    .line
+                                    .map(|line| format!("{}: {}", line.line_number, line.content));
+                                if search_match.is_dir {
+                                    let dir_path = format!("{}/", search_match.path);
+                                    Either::Left(
+                                        view! {
+                                            <li>
+                                                <A
+                                                    attr:class="block truncate text-sky-400 hover:text-sky-300 hover:underline"
+                                                    href=move || {
+                                                        format!("/user/projects/{}/files/{}", slug.get(), dir_path)
+                                                    }
+                                                >
+                                                    {format!("{label}/")}
+                                                </A>
+                                            </li>
+                                        },
+                                    )
+                                } else {
+                                    let file_path = search_match.path.clone();
+                                    let parent = parent_path(&search_match.path);
+                                    Either::Right(
+                                        view! {
+                                            <li>
+                                                <A
+                                                    attr:class="block text-gray-300 hover:text-white"
+                                                    href=move || {
+                                                        format!("/user/projects/{}/files/{}", slug.get(), parent)
+                                                    }
+                                                    on:click=move |_| on_select_file.run(file_path.clone())
+                                                >
+                                                    <span class="block truncate hover:underline">{label}</span>
+                                                    {line
+                                                        .map(|line| {
+                                                            view! {
+                                                                <span class="block truncate font-mono text-gray-500">
+                                                                    {line}
+                                                                </span>
+                                                            }
+                                                        })}
+                                                </A>
+                                            </li>
+                                        },
+                                    )
+                                }
+                            })
+                            .collect_view()
+                    }}
+                </ul>
+            </Show>
+
+            <Show when=move || next_cursor.with(Option::is_some)>
+                <button
+                    type="button"
+                    class="btn btn-secondary w-full text-xs"
+                    disabled=move || search_action.pending().get()
+                    on:click=on_load_more
+                >
+                    "Load more"
+                </button>
+            </Show>
+        </div>
+    }
+}
@@ -1,24 +1,122 @@
 use crate::api::ServerProjectActionFront;
 use common::server_action::permission::Permission;
+use common::server_action::project_action::io_action::batch_action::{
+    ProjectIoBatchAction, ProjectIoBatchItem,
+};
 use common::server_action::project_action::io_action::dir_action::{LsElement, ProjectIoDirAction};
 use common::server_action::project_action::io_action::file_action::ProjectIoFileAction;
-use common::ServerId;
+use common::server_action::project_action::io_action::{ConflictStrategy, IoReportOutcome};
+use common::server_action::project_action::ProjectResponse;
+use common::{FileNameStr, ProjectRelPath, ServerId};
 use leptos::callback::Callback;
 use leptos::either::Either;
 use leptos::html::Input;
 use leptos::prelude::AddAnyAttr;
 use leptos::prelude::CustomAttribute;
 use leptos::prelude::IntoAnyAttribute;
+use leptos::prelude::{event_target_value, Effect, GetUntracked, RwSignal, Set, Update};
 use leptos::prelude::{signal, NodeRef, NodeRefAttribute, ReadSignal};
 use leptos::prelude::{Callable, Get, IntoMaybeErased};
 use leptos::prelude::{ClassAttribute, CollectView, GlobalAttributes, OnAttribute, Signal};
-use leptos::prelude::{ElementChild, Read, Show};
+use leptos::prelude::{ElementChild, PropAttribute, Read, Show};
 use leptos::{component, view, IntoView};
 use leptos_router::components::A;
+use std::str::FromStr;
 use web_sys::SubmitEvent;
 
 pub type FileListSignal = ReadSignal<Option<Vec<LsElement>>>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchTransfer {
+    Move,
+    Copy,
+}
+
+/// Accepts `root/dir`, `/dir/` or `dir` and returns the `root/dir/` form of `current_path`.
+fn normalize_target_dir(target: &str) -> String {
+    let target = target.trim().trim_matches('/');
+    let relative = if target == "root" {
+        ""
+    } else {
+        target.strip_prefix("root/").unwrap_or(target)
+    };
+    if relative.is_empty() {
+        "root/".to_string()
+    } else {
+        format!("root/{relative}/")
+    }
+}
+
+/// Entries whose name is not a valid project path component cannot be addressed and are left
+/// out of batches.
+fn batch_delete_items(items: &[LsElement], base_path: &str) -> Vec<ProjectIoBatchItem> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let path = ProjectRelPath::from_str(&format!("{base_path}{}", item.name)).ok()?;
+            Some(if item.is_dir {
+                ProjectIoDirAction::Delete { path }.into()
+            } else {
+                ProjectIoFileAction::Delete { path }.into()
+            })
+        })
+        .collect()
+}
+
+fn batch_transfer_items(
+    items: &[LsElement],
+    base_path: &str,
+    target_dir: &str,
+    transfer: BatchTransfer,
+    on_conflict: ConflictStrategy,
+) -> Vec<ProjectIoBatchItem> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let path = ProjectRelPath::from_str(&format!("{base_path}{}", item.name)).ok()?;
+            let new_path = ProjectRelPath::from_str(&format!("{target_dir}{}", item.name)).ok()?;
+            Some(match (item.is_dir, transfer) {
+                (true, BatchTransfer::Move) => ProjectIoDirAction::Move {
+                    path,
+                    new_path,
+                    on_conflict,
+                }
+                .into(),
+                (true, BatchTransfer::Copy) => ProjectIoDirAction::Copy {
+                    path,
+                    new_path,
+                    on_conflict,
+                }
+                .into(),
+                (false, BatchTransfer::Move) => ProjectIoFileAction::Move { path, new_path }.into(),
+                (false, BatchTransfer::Copy) => ProjectIoFileAction::Copy { path, new_path }.into(),
+            })
+        })
+        .collect()
+}
+
+/// Flattens batch and transfer reports into one line per failed, skipped or renamed entry.
+pub fn describe_io_response(response: &ProjectResponse) -> Vec<String> {
+    match response {
+        ProjectResponse::Error(e) => vec![e.to_string()],
+        ProjectResponse::Batch(responses) => {
+            responses.iter().flat_map(describe_io_response).collect()
+        }
+        ProjectResponse::IoReport(report) => report
+            .issues
+            .iter()
+            .map(|entry| match &entry.outcome {
+                IoReportOutcome::Skipped => format!("{}: skipped, target exists", entry.path),
+                IoReportOutcome::Renamed { new_path } => {
+                    format!("{}: written as {new_path}", entry.path)
+                }
+                IoReportOutcome::Failed { message } => format!("{}: {message}", entry.path),
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
 #[component]
 pub fn ProjectFilesSidebar(
     file_list: FileListSignal,
@@ -30,8 +128,185 @@ pub fn ProjectFilesSidebar(
     csrf_signal: Signal<Option<String>>,
     permission_signal: Signal<Permission>,
 ) -> impl IntoView {
+    // Names of the selected entries of the current folder.
+    let selection = RwSignal::new(Vec::<String>::new());
+    let last_selected = RwSignal::new(None::<usize>);
+    // Names being dragged, the selection or the single item the drag started on.
+    let dragged = RwSignal::new(Vec::<String>::new());
+    let target_dir_ref: NodeRef<Input> = NodeRef::new();
+    let (on_conflict, set_on_conflict) = signal(ConflictStrategy::Skip);
+
+    Effect::new(move |_| {
+        let _ = current_path.get();
+        selection.set(Vec::new());
+        last_selected.set(None);
+    });
+
+    let items_named = move |names: &[String]| {
+        file_list
+            .get_untracked()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|item| names.contains(&item.name))
+            .collect::<Vec<_>>()
+    };
+
+    let dispatch_batch = move |items: Vec<ProjectIoBatchItem>| {
+        if items.is_empty() {
+            return;
+        }
+        server_project_action.dispatch((
+            server_id(),
+            slug(),
+            ProjectIoBatchAction { items }.into(),
+            Some(
+                csrf_signal
+                    .read()
+                    .as_ref()
+                    .map(|csrf| csrf.clone())
+                    .unwrap_or_default(),
+            ),
+        ));
+        selection.set(Vec::new());
+        last_selected.set(None);
+    };
+
+    let on_toggle_select = Callback::new(move |(index, extend): (usize, bool)| {
+        let Some(list) = file_list.get_untracked() else {
+            return;
+        };
+        let Some(item) = list.get(index) else {
+            return;
+        };
+        let select = !selection.get_untracked().contains(&item.name);
+        let range = match (extend, last_selected.get_untracked()) {
+            (true, Some(last)) if last < list.len() => last.min(index)..=last.max(index),
+            _ => index..=index,
+        };
+        selection.update(|selection| {
+            for item in &list[range] {
+                let is_selected = selection.contains(&item.name);
+                if select && !is_selected {
+                    selection.push(item.name.clone());
+                } else if !select && is_selected {
+                    selection.retain(|name| *name != item.name);
+                }
+            }
+        });
+        last_selected.set(Some(index));
+    });
+
+    let on_drop_into = Callback::new(move |target_dir: String| {
+        let names = dragged.get_untracked();
+        dragged.set(Vec::new());
+        let base_path = current_path.get_untracked();
+        let target_dir = normalize_target_dir(&target_dir);
+        if names.is_empty() || target_dir == base_path {
+            return;
+        }
+        dispatch_batch(batch_transfer_items(
+            &items_named(&names),
+            &base_path,
+            &target_dir,
+            BatchTransfer::Move,
+            on_conflict.get_untracked(),
+        ));
+    });
+
+    let on_batch_delete = move |_| {
+        let items = items_named(&selection.get_untracked());
+        dispatch_batch(batch_delete_items(&items, &current_path.get_untracked()));
+    };
+
+    let on_batch_transfer = move |transfer: BatchTransfer| {
+        let target_dir = normalize_target_dir(&target_dir_ref.get().unwrap().value());
+        let base_path = current_path.get_untracked();
+        if target_dir == base_path {
+            return;
+        }
+        let items = items_named(&selection.get_untracked());
+        dispatch_batch(batch_transfer_items(
+            &items,
+            &base_path,
+            &target_dir,
+            transfer,
+            on_conflict.get_untracked(),
+        ));
+    };
+
     view! {
         <div class="p-4 h-full flex flex-col">
+            <Show when=move || permission_signal().can_edit() && !selection.read().is_empty()>
+                <div class="flex-shrink-0 mb-3 p-2 bg-gray-800 rounded-md space-y-2 text-xs">
+                    <div class="flex items-center justify-between gap-x-2">
+                        <span class="text-gray-300">
+                            {move || format!("{} selected", selection.read().len())}
+                        </span>
+                        <div class="flex items-center gap-x-1">
+                            <button
+                                type="button"
+                                class="btn btn-danger px-2 py-1 text-xs"
+                                disabled=move || server_project_action.pending().get()
+                                on:click=on_batch_delete
+                            >
+                                "Delete"
+                            </button>
+                            <button
+                                type="button"
+                                class="btn btn-secondary px-2 py-1 text-xs"
+                                on:click=move |_| {
+                                    selection.set(Vec::new());
+                                    last_selected.set(None);
+                                }
+                            >
+                                "Clear"
+                            </button>
+                        </div>
+                    </div>
+                    <input
+                        type="text"
+                        name="batch_target_dir"
+                        node_ref=target_dir_ref
+                        class="form-input w-full px-2 py-1 text-xs"
+                        placeholder="Target folder, e.g. root/assets/"
+                    />
+                    <div class="flex items-center gap-x-1">
+                        <select
+                            class="form-select flex-grow px-2 py-1 text-xs"
+                            title="When the target already exists"
+                            on:change=move |ev| {
+                                set_on_conflict(
+                                    match event_target_value(&ev).as_str() {
+                                        "overwrite" => ConflictStrategy::Overwrite,
+                                        "rename" => ConflictStrategy::Rename,
+                                        _ => ConflictStrategy::Skip,
+                                    },
+                                )
+                            }
+                        >
+                            <option value="skip">"Skip existing"</option>
+                            <option value="overwrite">"Overwrite"</option>
+                            <option value="rename">"Keep both"</option>
+                        </select>
+                        <button
+                            type="button"
+                            class="btn btn-primary px-2 py-1 text-xs"
+                            disabled=move || server_project_action.pending().get()
+                            on:click=move |_| on_batch_transfer(BatchTransfer::Move)
+                        >
+                            "Move"
+                        </button>
+                        <button
+                            type="button"
+                            class="btn btn-secondary px-2 py-1 text-xs"
+                            disabled=move || server_project_action.pending().get()
+                            on:click=move |_| on_batch_transfer(BatchTransfer::Copy)
+                        >
+                            "Copy"
+                        </button>
+                    </div>
+                </div>
+            </Show>
 
             <div class="flex-grow overflow-y-auto -mr-4 pr-4">
                 {move || match file_list.get() {
@@ -52,7 +327,17 @@ pub fn ProjectFilesSidebar(
                                                 .to_string()
                                         };
                                         view! {
-                                            <div>
+                                            <div
+                                                on:dragover=move |ev| {
+                                                    if !dragged.read().is_empty() {
+                                                        ev.prevent_default();
+                                                    }
+                                                }
+                                                on:drop=move |ev| {
+                                                    ev.prevent_default();
+                                                    on_drop_into.run(prev_path(current_path.get_untracked()));
+                                                }
+                                            >
                                                 <A
                                                     attr:class="flex items-center w-full gap-x-2 px-2 py-1.5 text-sm rounded-md text-indigo-400 hover:bg-gray-700 hover:text-indigo-300"
                                                     href=move || {
@@ -89,13 +374,19 @@ pub fn ProjectFilesSidebar(
                                     <ul class="space-y-1">
                                         {file_list
                                             .iter()
-                                            .map(|item| {
+                                            .enumerate()
+                                            .map(|(index, item)| {
                                                 view! {
                                                     <ProjectFilesSidebarItem
                                                         csrf_signal=csrf_signal
                                                         slug=slug
                                                         current_path=current_path
                                                         item=item.clone()
+                                                        index
+                                                        selection
+                                                        dragged
+                                                        on_toggle_select
+                                                        on_drop_into
                                                         server_project_action=server_project_action
                                                         on_select_file=on_select_file
                                                         permission_signal=permission_signal
@@ -123,6 +414,11 @@ pub fn ProjectFilesSidebarItem(
     server_id: Signal<ServerId>,
     current_path: Signal<String>,
     item: LsElement,
+    index: usize,
+    selection: RwSignal<Vec<String>>,
+    dragged: RwSignal<Vec<String>>,
+    on_toggle_select: Callback<(usize, bool)>,
+    on_drop_into: Callback<String>,
     server_project_action: ServerProjectActionFront,
     on_select_file: Callback<String>,
     permission_signal: Signal<Permission>,
@@ -131,12 +427,34 @@ pub fn ProjectFilesSidebarItem(
     let new_name_ref: NodeRef<Input> = NodeRef::new();
     let (item_name, _) = signal(item.name.clone());
     let item_path = move || format!("{}{}", current_path.get(), item_name());
+    let item_rel_path = move || ProjectRelPath::from_str(&item_path());
+    let is_selected = move || selection.read().contains(&item_name.read());
+    let on_drag_start = move |ev: web_sys::DragEvent| {
+        let name = item_name.get_untracked();
+        let names = if selection.get_untracked().contains(&name) {
+            selection.get_untracked()
+        } else {
+            vec![name]
+        };
+        if let Some(data_transfer) = ev.data_transfer() {
+            // Firefox only starts a drag when some data is set.
+            let _ = data_transfer.set_data("text/plain", &names.join("\n"));
+            data_transfer.set_effect_allowed("move");
+        }
+        dragged.set(names);
+    };
+    let accepts_drop = move || {
+        item.is_dir && !dragged.read().is_empty() && !dragged.read().contains(&item_name.read())
+    };
     let on_delete_item_submit = move |ev: SubmitEvent| {
         ev.prevent_default();
+        let Ok(path) = item_rel_path() else {
+            return;
+        };
         let action = if item.is_dir {
-            ProjectIoDirAction::Delete { path: item_path() }.into()
+            ProjectIoDirAction::Delete { path }.into()
         } else {
-            ProjectIoFileAction::Delete { path: item_path() }.into()
+            ProjectIoFileAction::Delete { path }.into()
         };
         server_project_action.dispatch((
             server_id(),
@@ -159,18 +477,13 @@ pub fn ProjectFilesSidebarItem(
         if new_name.trim().is_empty() || new_name == old_name {
             return;
         }
+        let (Ok(path), Ok(new_name)) = (item_rel_path(), FileNameStr::from_str(&new_name)) else {
+            return;
+        };
         let action = if item.is_dir {
-            ProjectIoDirAction::Rename {
-                path: format!("{}{}", current_path.get(), old_name),
-                new_name,
-            }
-            .into()
+            ProjectIoDirAction::Rename { path, new_name }.into()
         } else {
-            ProjectIoFileAction::Rename {
-                path: format!("{}{}", current_path.get(), old_name),
-                new_name,
-            }
-            .into()
+            ProjectIoFileAction::Rename { path, new_name }.into()
         };
         server_project_action.dispatch((
             server_id(),
@@ -188,7 +501,35 @@ pub fn ProjectFilesSidebarItem(
     };
 
     view! {
-        <li class="group flex items-center justify-between gap-x-1 px-2 py-1.5 text-sm rounded-md text-gray-300 hover:bg-gray-700 hover:text-white">
+        <li
+            class="group flex items-center justify-between gap-x-1 px-2 py-1.5 text-sm rounded-md text-gray-300 hover:bg-gray-700 hover:text-white"
+            class=("bg-gray-700", is_selected)
+            draggable=move || if permission_signal().can_edit() { "true" } else { "false" }
+            on:dragstart=on_drag_start
+            on:dragend=move |_| dragged.set(Vec::new())
+            on:dragover=move |ev| {
+                if accepts_drop() {
+                    ev.prevent_default();
+                }
+            }
+            on:drop=move |ev| {
+                if accepts_drop() {
+                    ev.prevent_default();
+                    on_drop_into.run(item_path());
+                }
+            }
+        >
+            <input
+                type="checkbox"
+                class="flex-shrink-0"
+                class=("hidden", move || is_renaming_item.get() || !permission_signal().can_edit())
+                title="Select, shift-click to select a range"
+                prop:checked=is_selected
+                on:click=move |ev| {
+                    ev.stop_propagation();
+                    on_toggle_select.run((index, ev.shift_key()));
+                }
+            />
             <form
                 on:submit=on_rename_item_submit
                 class="flex items-center gap-x-2 flex-grow "
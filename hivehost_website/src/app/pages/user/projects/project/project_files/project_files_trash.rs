@@ -0,0 +1,158 @@
+use crate::api::ServerProjectActionFront;
+use crate::app::pages::user::projects::project::project_files::file_content_view::format_bytes;
+use crate::models::ProjectSlugStrFront;
+use crate::security::permission::request_server_project_action_front;
+use common::server_action::project_action::io_action::trash_action::{
+    ProjectIoTrashAction, TrashEntry, TRASH_MAX_SIZE, TRASH_RETENTION_DAYS,
+};
+use common::server_action::project_action::ProjectResponse;
+use common::ServerId;
+use leptos::either::EitherOf3;
+use leptos::prelude::*;
+
+fn format_deleted_at(deleted_at: i64) -> String {
+    time::format_description::parse("[year]-[month]-[day] [hour]:[minute]")
+        .ok()
+        .zip(time::OffsetDateTime::from_unix_timestamp(deleted_at).ok())
+        .and_then(|(format, date)| date.format(&format).ok())
+        .unwrap_or_default()
+}
+
+#[component]
+pub fn ProjectFilesTrash(
+    server_id: Signal<ServerId>,
+    slug: Signal<ProjectSlugStrFront>,
+    csrf_signal: Signal<Option<String>>,
+    server_project_action: ServerProjectActionFront,
+) -> impl IntoView {
+    let trash_resource = Resource::new_bincode(
+        move || (server_id(), slug(), server_project_action.version().get()),
+        |(server_id, slug, _)| {
+            request_server_project_action_front(
+                server_id,
+                slug,
+                ProjectIoTrashAction::List.into(),
+                None,
+            )
+        },
+    );
+
+    let dispatch_trash_action = move |action: ProjectIoTrashAction| {
+        server_project_action.dispatch((
+            server_id(),
+            slug(),
+            action.into(),
+            Some(
+                csrf_signal
+                    .read()
+                    .as_ref()
+                    .map(|csrf| csrf.clone())
+                    .unwrap_or_default(),
+            ),
+        ));
+    };
+
+    let trash_row = move |entry: TrashEntry| {
+        let restore_id = entry.id.clone();
+        let delete_id = entry.id;
+        let label = if entry.is_dir {
+            format!("{}/", entry.original_path.trim_start_matches("root/"))
+        } else {
+            entry.original_path.trim_start_matches("root/").to_string()
+        };
+        view! {
+            <li class="flex items-center justify-between gap-x-2 py-1">
+                <div class="min-w-0">
+                    <span
+                        class="block truncate"
+                        class=("text-sky-400", entry.is_dir)
+                        class=("text-gray-300", !entry.is_dir)
+                        title=entry.original_path
+                    >
+                        {label}
+                    </span>
+                    <span class="block text-gray-500">
+                        {format!(
+                            "{} - {}",
+                            format_deleted_at(entry.deleted_at),
+                            format_bytes(entry.size),
+                        )}
+                    </span>
+                </div>
+                <div class="flex items-center gap-x-1 flex-shrink-0">
+                    <button
+                        type="button"
+                        class="btn btn-secondary px-2 py-1 text-xs"
+                        disabled=move || server_project_action.pending().get()
+                        on:click=move |_| {
+                            dispatch_trash_action(ProjectIoTrashAction::Restore {
+                                id: restore_id.clone(),
+                            })
+                        }
+                    >
+                        "Restore"
+                    </button>
+                    <button
+                        type="button"
+                        class="btn btn-danger px-2 py-1 text-xs"
+                        title="Delete permanently"
+                        disabled=move || server_project_action.pending().get()
+                        on:click=move |_| {
+                            dispatch_trash_action(ProjectIoTrashAction::Delete {
+                                id: delete_id.clone(),
+                            })
+                        }
+                    >
+                        "Delete"
+                    </button>
+                </div>
+            </li>
+        }
+    };
+
+    view! {
+        <div class="flex flex-col space-y-2 text-xs">
+            <div class="flex items-center justify-between gap-x-2">
+                <p class="text-gray-400">
+                    {format!(
+                        "Deleted items are kept for {TRASH_RETENTION_DAYS} days; past {} the oldest are purged first.",
+                        format_bytes(TRASH_MAX_SIZE),
+                    )}
+                </p>
+                <button
+                    type="button"
+                    class="btn btn-danger px-2 py-1 text-xs flex-shrink-0"
+                    disabled=move || server_project_action.pending().get()
+                    on:click=move |_| dispatch_trash_action(ProjectIoTrashAction::Empty)
+                >
+                    "Empty trash"
+                </button>
+            </div>
+            <Transition fallback=move || {
+                view! { <p class="text-gray-400">"Loading trash..."</p> }
+            }>
+                {move || Suspend::new(async move {
+                    match trash_resource.await {
+                        Ok(ProjectResponse::Trash(response)) if !response.inner.is_empty() => {
+                            EitherOf3::A(
+                                view! {
+                                    <ul class="p-2 bg-gray-800 rounded-md max-h-64 overflow-y-auto divide-y divide-gray-700 shadow">
+                                        {response.inner.into_iter().map(trash_row).collect_view()}
+                                    </ul>
+                                },
+                            )
+                        }
+                        Ok(ProjectResponse::Trash(_)) => {
+                            EitherOf3::B(view! { <p class="text-gray-500">"Trash is empty."</p> })
+                        }
+                        _ => {
+                            EitherOf3::C(
+                                view! { <p class="text-red-400">"Could not load the trash."</p> },
+                            )
+                        }
+                    }
+                })}
+            </Transition>
+        </div>
+    }
+}
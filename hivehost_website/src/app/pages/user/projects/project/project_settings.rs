@@ -832,7 +832,7 @@ pub mod server_fns {
         use std::str::FromStr;
         use common::{GitBranchNameStr, GitCommitStr, GitRepoFullNameStr, GitTokenStr, ServerId, Slug};
         use common::server_action::project_action::git_action::ProjectGitAction;
-        use crate::api::ssr::request_server_project_action;
+        use crate::api::ssr::{ensure_project_disk_quota, request_server_project_action};
         use crate::app::pages::user::projects::project::project_snapshots::server_fns::ssr::inner_set_snapshot_prod;
         use crate::app::pages::user::projects::project::project_snapshots::server_fns::ssr::inner_create_snapshot;
         use crate::AppResult;
@@ -852,6 +852,8 @@ pub mod server_fns {
             branch_name: GitBranchNameStr,
             last_commit: GitCommitStr,
         ) -> AppResult<()> {
+            ensure_project_disk_quota(pool, server_id, &project_slug, Some(ws_clients.clone()))
+                .await?;
             sqlx::query!(
                 "UPDATE projects_github SET dev_commit = $1 WHERE id = $2",
                 last_commit.0,
@@ -1,3 +1,4 @@
+use crate::app::pages::user::projects::project::project_files::file_content_view::format_bytes;
 use crate::app::pages::user::projects::project::project_snapshots::server_fns::{
     CreateProjectSnapshot, DeleteProjectSnapshot, RestoreProjectSnapshot, SetActiveProjectSnapshot,
     UnsetActiveProjectSnapshot,
@@ -63,11 +64,35 @@ pub fn ProjectSnapshots() -> impl IntoView {
                 delete_snapshot_action.version().get(),
                 set_active_snapshot_action.version().get(),
                 unset_active_snapshot_action.version().get(),
+                restore_snapshot_action.version().get(),
             )
         },
-        move |(slug, _, _, _, _)| async move { server_fns::get_project_snapshots(slug).await },
+        move |(slug, _, _, _, _, _)| async move { server_fns::get_project_snapshots(slug).await },
     );
 
+    let disk_usage_resource = Resource::new_bincode(
+        move || {
+            (
+                server_id(),
+                slug_signal.get(),
+                create_snapshot_action.version().get(),
+                delete_snapshot_action.version().get(),
+                restore_snapshot_action.version().get(),
+            )
+        },
+        move |(server_id, slug, _, _, _)| async move {
+            server_fns::get_project_disk_usage(server_id, slug).await
+        },
+    );
+    let snapshot_size = move |snapshot_name: &str| {
+        disk_usage_resource
+            .get()
+            .and_then(Result::ok)
+            .and_then(|disk_usage| disk_usage.snapshot_exclusive_bytes(snapshot_name))
+            .map(format_bytes)
+            .unwrap_or_default()
+    };
+
     let snapshot_name_ref = NodeRef::<Input>::new();
     let snapshot_description_ref = NodeRef::<Textarea>::new();
 
@@ -146,7 +171,9 @@ pub fn ProjectSnapshots() -> impl IntoView {
         ev.prevent_default();
         let confirmed = if let Some(window) = web_sys::window() {
             window
-                .confirm_with_message("Are you sure you want to Restore this snapshot?")
+                .confirm_with_message(
+                    "Are you sure you want to Restore this snapshot? The current state is kept as a pre-restore backup.",
+                )
                 .unwrap_or(false)
         } else {
             false
@@ -264,6 +291,53 @@ pub fn ProjectSnapshots() -> impl IntoView {
 
     view! {
         <div class="space-y-10">
+            <div class="section-border">
+                <h2 class="section-title">"Disk Usage"</h2>
+                <p class="section-desc">
+                    "Space used by the project and its snapshots, against your plan's limit."
+                </p>
+                <Transition fallback=move || {
+                    view! { <p class="text-gray-400 mt-4">"Loading usage..."</p> }
+                }>
+                    {move || {
+                        Suspend::new(async move {
+                            match disk_usage_resource.get() {
+                                Some(Ok(disk_usage)) => {
+                                    EitherOf3::A(
+                                        view! {
+                                            <p class="mt-4 text-sm text-gray-300">
+                                                {format!(
+                                                    "{} of {} used, {} in the working copy.",
+                                                    format_bytes(disk_usage.usage.total.referenced),
+                                                    format_bytes(disk_usage.plan_limit_bytes),
+                                                    format_bytes(disk_usage.usage.dev.referenced),
+                                                )}
+                                            </p>
+                                            <div class="mt-2 h-2 w-full rounded-full bg-white/10">
+                                                <div
+                                                    class="h-2 rounded-full bg-indigo-500"
+                                                    style=format!("width: {}%", disk_usage.used_percent())
+                                                ></div>
+                                            </div>
+                                        },
+                                    )
+                                }
+                                Some(Err(e)) => {
+                                    EitherOf3::B(
+                                        view! {
+                                            <p class="mt-4 text-sm text-yellow-400">
+                                                {format!("Error loading usage: {e}")}
+                                            </p>
+                                        },
+                                    )
+                                }
+                                None => EitherOf3::C(()),
+                            }
+                        })
+                    }}
+                </Transition>
+            </div>
+
             <div class="section-border" class=("hidden", move || !permission_signal().is_owner())>
                 <h2 class="section-title">"Create New Snapshot"</h2>
                 <p class="section-desc">"Create a snapshot of the current project state."</p>
@@ -370,6 +444,13 @@ pub fn ProjectSnapshots() -> impl IntoView {
                                                                         <th scope="col" class="table-th">
                                                                             "Created At"
                                                                         </th>
+                                                                        <th
+                                                                            scope="col"
+                                                                            class="table-th"
+                                                                            title="Space freed by deleting the snapshot"
+                                                                        >
+                                                                            "Size"
+                                                                        </th>
                                                                         <th scope="col" class="relative py-3.5 pl-3 pr-4 sm:pr-0">
                                                                             <span class="sr-only">Actions</span>
                                                                         </th>
@@ -417,6 +498,9 @@ pub fn ProjectSnapshots() -> impl IntoView {
                                                                                     <td class="table-td text-gray-400 whitespace-nowrap">
                                                                                         {snapshot.created_at}
                                                                                     </td>
+                                                                                    <td class="table-td text-gray-400 whitespace-nowrap">
+                                                                                        {move || snapshot_size(&name_signal())}
+                                                                                    </td>
                                                                                     <td class="relative whitespace-nowrap py-4 pl-3 pr-4 text-right text-sm font-medium sm:pr-0">
                                                                                         <div
                                                                                             class="flex justify-end items-center space-x-2"
@@ -515,15 +599,17 @@ pub mod server_fns {
     use leptos::server;
     use leptos::server_fn::codec::Bincode;
 
-    use crate::models::{ProjectSlugStrFront, ProjectSnapshot};
+    use crate::models::{ProjectDiskUsage, ProjectSlugStrFront, ProjectSnapshot};
     use crate::AppResult;
     use common::ServerId;
 
     cfg_if::cfg_if! { if #[cfg(feature = "ssr")] {
         use crate::security::permission::ssr::handle_project_permission_request;
-        use crate::api::ssr::{request_server_project_action};
+        use crate::api::ssr::{project_disk_limit, request_project_usage, request_server_project_action};
         use common::server_action::permission::Permission;
-        use common::{GitBranchNameStr, GitCommitStr, SnapShotNameStr};
+        use common::SnapShotNameStr;
+        use common::helper_command::HelperResponse;
+        use common::server_action::project_action::ProjectResponse;
         use common::server_action::project_action::snapshot::ProjectSnapshotAction;
         use crate::AppError;
         use std::str::FromStr;
@@ -566,6 +652,29 @@ pub mod server_fns {
             .await
     }
 
+    #[server(input=Bincode, output=Bincode)]
+    pub async fn get_project_disk_usage(
+        server_id: ServerId,
+        project_slug: ProjectSlugStrFront,
+    ) -> AppResult<ProjectDiskUsage> {
+        handle_project_permission_request(
+            project_slug,
+            Permission::Read,
+            None,
+            |_, pool, project_slug| async move {
+                let plan_limit_bytes = project_disk_limit(&pool, project_slug.id).await?;
+                let usage =
+                    request_project_usage(server_id, project_slug.to_project_slug_str(), None)
+                        .await?;
+                Ok(ProjectDiskUsage {
+                    usage,
+                    plan_limit_bytes,
+                })
+            },
+        )
+        .await
+    }
+
     #[server(input=Bincode, output=Bincode)]
     pub async fn create_project_snapshot(
         csrf: String,
@@ -588,23 +697,7 @@ pub mod server_fns {
             Permission::Write,
             Some(csrf),
             |_, pool, project_slug| async move {
-                let project_github = sqlx::query!(
-                    r#"SELECT pg.dev_commit as dev_commit, pg.branch_name as branch_name 
-                        FROM projects
-                        left join projects_github as pg on pg.id = projects.project_github_id
-                        WHERE projects.id = $1 and projects.project_github_id is not null"#,
-                    project_slug.id
-                )
-                .fetch_optional(&pool)
-                .await?;
-                let (branch_name, dev_commit) = project_github
-                    .map(|pg| {
-                        (
-                            Some(GitBranchNameStr(pg.branch_name)),
-                            Some(GitCommitStr(pg.dev_commit)),
-                        )
-                    })
-                    .unwrap_or((None, None));
+                let (branch_name, dev_commit) = ssr::dev_git_info(&pool, project_slug.id).await?;
                 let _ = ssr::inner_create_snapshot(
                     &pool,
                     crate::ssr::ws_clients()?,
@@ -676,7 +769,7 @@ pub mod server_fns {
             Some(csrf),
             |_, pool, project_slug| async move {
                 let snapshot = sqlx::query!(
-                     "SELECT id,snapshot_name FROM projects_snapshots WHERE id = $1 AND project_id = $2",
+                     "SELECT id,snapshot_name,name,version FROM projects_snapshots WHERE id = $1 AND project_id = $2",
                      snapshot_id,
                      project_slug.id
                  )
@@ -687,14 +780,46 @@ pub mod server_fns {
                 }
                 let snapshot = snapshot.unwrap();
                 let snapshot_name = SnapShotNameStr::from_str(&snapshot.snapshot_name)?;
-                request_server_project_action(
+
+                // The current state is kept as a snapshot, so a restore can be undone.
+                let backup_snapshot_name = ssr::new_snapshot_name(&project_slug);
+                let (branch_name, dev_commit) = ssr::dev_git_info(&pool, project_slug.id).await?;
+                let backup_id = ssr::insert_snapshot(
+                    &pool,
+                    &project_slug,
+                    &backup_snapshot_name,
+                    Some("Pre-restore backup".to_string()),
+                    Some(format!(
+                        "Automatic backup before restoring {}",
+                        snapshot.name.unwrap_or(format!("Version {}", snapshot.version))
+                    )),
+                    branch_name,
+                    dev_commit,
+                )
+                .await?;
+                let restored = match request_server_project_action(
                     server_id,
                     project_slug.to_project_slug_str(),
-                    ProjectSnapshotAction::Restore{
+                    ProjectSnapshotAction::Restore {
                         snapshot_name,
-                    }.into(),
+                        backup_snapshot_name: SnapShotNameStr::from_str(&backup_snapshot_name)?,
+                    }
+                    .into(),
                     None,
-                ).await?;
+                )
+                .await
+                {
+                    Ok(ProjectResponse::HelperResponses(HelperResponse::Ok)) => Ok(()),
+                    Ok(response) => Err(AppError::Custom(format!("Restore failed: {response:?}"))),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = restored {
+                    // The helper rolled back and dropped the backup subvolume.
+                    sqlx::query!("DELETE FROM projects_snapshots WHERE id = $1", backup_id)
+                        .execute(&pool)
+                        .await?;
+                    return Err(e);
+                }
                 Ok(())
             },
         )
@@ -766,12 +891,15 @@ pub mod server_fns {
 
     #[cfg(feature = "ssr")]
     pub mod ssr {
-        use crate::api::ssr::request_server_project_action;
+        use crate::api::ssr::{ensure_project_disk_quota, request_server_project_action};
         use crate::security::utils::ssr::SANITIZED_REGEX;
         use crate::ssr::WsClients;
         use crate::{AppError, AppResult};
         use common::server_action::project_action::snapshot::ProjectSnapshotAction;
-        use common::{GitBranchNameStr, GitCommitStr, ServerId, Slug, SnapShotNameStr};
+        use common::{
+            get_project_snapshot_prefix, GitBranchNameStr, GitCommitStr, ProjectId, ServerId, Slug,
+            SnapShotNameStr,
+        };
         use std::str::FromStr;
         use validator::Validate;
 
@@ -802,6 +930,76 @@ pub mod server_fns {
                 description: description.clone().unwrap_or_default(),
             };
             form.validate()?;
+            ensure_project_disk_quota(pool, server_id, &project_slug, Some(ws_clients.clone()))
+                .await?;
+            let snapshot_name = new_snapshot_name(&project_slug);
+            let project_snapshot_id = insert_snapshot(
+                pool,
+                &project_slug,
+                &snapshot_name,
+                name,
+                description,
+                branch_name,
+                commit,
+            )
+            .await?;
+
+            request_server_project_action(
+                server_id,
+                project_slug.to_project_slug_str(),
+                ProjectSnapshotAction::Create {
+                    snapshot_name: SnapShotNameStr::from_str(&snapshot_name)?,
+                }
+                .into(),
+                Some(ws_clients),
+            )
+            .await?;
+            Ok(project_snapshot_id)
+        }
+
+        /// Subvolume name for a new snapshot of the project.
+        pub fn new_snapshot_name(project_slug: &Slug) -> String {
+            format!(
+                "{}{}",
+                get_project_snapshot_prefix(&project_slug.to_project_slug_str()),
+                chrono::Utc::now().format("%Y_%m_%d_%H_%M_%S")
+            )
+        }
+
+        /// Branch and commit the dev subvolume is at, for git linked projects.
+        pub async fn dev_git_info(
+            pool: &sqlx::PgPool,
+            project_id: ProjectId,
+        ) -> AppResult<(Option<GitBranchNameStr>, Option<GitCommitStr>)> {
+            let project_github = sqlx::query!(
+                r#"SELECT pg.dev_commit as dev_commit, pg.branch_name as branch_name 
+                    FROM projects
+                    left join projects_github as pg on pg.id = projects.project_github_id
+                    WHERE projects.id = $1 and projects.project_github_id is not null"#,
+                project_id
+            )
+            .fetch_optional(pool)
+            .await?;
+            Ok(project_github
+                .map(|pg| {
+                    (
+                        Some(GitBranchNameStr(pg.branch_name)),
+                        Some(GitCommitStr(pg.dev_commit)),
+                    )
+                })
+                .unwrap_or((None, None)))
+        }
+
+        /// Records a snapshot with the next version number and returns its id.
+        pub async fn insert_snapshot(
+            pool: &sqlx::PgPool,
+            project_slug: &Slug,
+            snapshot_name: &str,
+            name: Option<String>,
+            description: Option<String>,
+            branch_name: Option<GitBranchNameStr>,
+            commit: Option<GitCommitStr>,
+        ) -> AppResult<i64> {
             let prev_snap = sqlx::query!(
                 "SELECT version FROM projects_snapshots WHERE project_id = $1 ORDER BY version DESC LIMIT 1",
                 project_slug.id
@@ -810,12 +1008,7 @@ pub mod server_fns {
                 .await?;
             let prev_version = prev_snap.map(|row| row.version).unwrap_or(0);
             let new_version = prev_version + 1;
-            let snapshot_name = format!(
-                "{}_snapshot_{}",
-                project_slug,
-                chrono::Utc::now().format("%Y_%m_%d_%H_%M_%S")
-            );
-            let project_snapshot_id = sqlx::query!(
+            Ok(sqlx::query!(
                      r#"
                      INSERT INTO projects_snapshots (project_id, name, version,snapshot_name, description, git_commit,git_branch , created_at)
                      VALUES ($1, $2, $3,$4,$5,$6,$7, NOW() at time zone 'utc')
@@ -830,19 +1023,7 @@ pub mod server_fns {
                         branch_name.map(|i|i.0),
                  )
                 .fetch_one(pool)
-                .await?.id;
-
-            request_server_project_action(
-                server_id,
-                project_slug.to_project_slug_str(),
-                ProjectSnapshotAction::Create {
-                    snapshot_name: SnapShotNameStr::from_str(&snapshot_name)?,
-                }
-                .into(),
-                Some(ws_clients),
-            )
-            .await?;
-            Ok(project_snapshot_id)
+                .await?.id)
         }
 
         pub async fn inner_set_snapshot_prod(
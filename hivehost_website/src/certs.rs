@@ -0,0 +1,224 @@
+#[cfg(feature = "ssr")]
+pub mod ssr {
+    //! Certificates for the website-to-server RPC. The website keeps a small CA that issues its
+    //! own client certificate and one certificate per server, valid for the server's ip.
+    //!
+    //! `hivehost_website certs <command>`:
+    //! - `init` creates the CA at `WEBSITE_CA_CERT` / `WEBSITE_CA_KEY`
+    //! - `issue-website` issues the website's certificate at `WEBSITE_TLS_CERT` / `WEBSITE_TLS_KEY`
+    //! - `issue-server <server id> <dir>` issues a server certificate into `<dir>`, replacing the
+    //!   server's current one
+    //! - `list` shows the current certificate of each server and when it expires
+
+    use crate::{AppError, AppResult};
+    use common::ServerId;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+        ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose, SerialNumber,
+    };
+    use sqlx::PgPool;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+    use time::{Duration, OffsetDateTime};
+    use uuid::Uuid;
+
+    const CA_VALIDITY: Duration = Duration::days(3650);
+    /// Short enough that a leaked key doesn't stay useful for long; `list` shows what to rotate.
+    const CERT_VALIDITY: Duration = Duration::days(90);
+
+    pub async fn run(pool: &PgPool, args: &[String]) -> AppResult<()> {
+        match args {
+            [command] if command == "init" => init(),
+            [command] if command == "issue-website" => issue_website(),
+            [command, server_id, dir] if command == "issue-server" => {
+                let server_id = server_id.parse().map_err(|_| {
+                    AppError::InvalidInput(format!("Invalid server id {server_id}"))
+                })?;
+                issue_server(pool, server_id, Path::new(dir)).await
+            }
+            [command] if command == "list" => list(pool).await,
+            _ => Err(AppError::InvalidInput(
+                "usage: certs init | issue-website | issue-server <server id> <dir> | list"
+                    .to_string(),
+            )),
+        }
+    }
+
+    fn init() -> AppResult<()> {
+        let cert_path = dotenvy::var("WEBSITE_CA_CERT")?;
+        let key_path = dotenvy::var("WEBSITE_CA_KEY")?;
+        if Path::new(&cert_path).exists() {
+            return Err(AppError::Conflict(format!("{cert_path} already exists")));
+        }
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::default();
+        params.distinguished_name = name("hivehost CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        params.serial_number = Some(SerialNumber::from_slice(&serial()));
+        params.not_before = OffsetDateTime::now_utc();
+        params.not_after = params.not_before + CA_VALIDITY;
+        let cert = params.self_signed(&key)?;
+        write_file(Path::new(&key_path), &key.serialize_pem(), true)?;
+        write_file(Path::new(&cert_path), &cert.pem(), false)?;
+        println!("CA written to {cert_path}");
+        Ok(())
+    }
+
+    fn issue_website() -> AppResult<()> {
+        let cert_path = dotenvy::var("WEBSITE_TLS_CERT")?;
+        let key_path = dotenvy::var("WEBSITE_TLS_KEY")?;
+        let issued = issue(
+            "hivehost website",
+            vec![],
+            ExtendedKeyUsagePurpose::ClientAuth,
+        )?;
+        write_file(Path::new(&key_path), &issued.key_pem, true)?;
+        write_file(Path::new(&cert_path), &issued.cert_pem, false)?;
+        println!(
+            "Website certificate written to {cert_path}, valid until {}",
+            issued.not_after
+        );
+        Ok(())
+    }
+
+    async fn issue_server(pool: &PgPool, server_id: ServerId, dir: &Path) -> AppResult<()> {
+        let server = sqlx::query!(r#"SELECT name, ip FROM servers WHERE id = $1"#, server_id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| AppError::NotFound(format!("No server {server_id}")))?;
+        let issued = issue(
+            &server.name,
+            vec![server.ip.clone()],
+            ExtendedKeyUsagePurpose::ServerAuth,
+        )?;
+
+        let mut tx = pool.begin().await?;
+        sqlx::query!(
+            r#"UPDATE server_certificates SET replaced_at = now()
+               WHERE server_id = $1 AND replaced_at IS NULL"#,
+            server_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            r#"INSERT INTO server_certificates (server_id, serial, cert_pem, not_after)
+               VALUES ($1, $2, $3, $4)"#,
+            server_id,
+            issued.serial,
+            issued.cert_pem,
+            issued.not_after
+        )
+        .execute(&mut *tx)
+        .await?;
+        // The key is only ever on disk here and on the server it is copied to.
+        write_file(&dir.join("server.key"), &issued.key_pem, true)?;
+        write_file(&dir.join("server.crt"), &issued.cert_pem, false)?;
+        tx.commit().await?;
+
+        println!(
+            "Certificate for {} ({}) written to {}, valid until {}. Install it as \
+             SERVER_TLS_CERT / SERVER_TLS_KEY and restart hivehost_server.",
+            server.name,
+            server.ip,
+            dir.display(),
+            issued.not_after
+        );
+        Ok(())
+    }
+
+    async fn list(pool: &PgPool) -> AppResult<()> {
+        let certificates = sqlx::query!(
+            r#"SELECT s.id, s.name, s.ip, c.serial as "serial?", c.not_after as "not_after?"
+               FROM servers s
+               LEFT JOIN server_certificates c ON c.server_id = s.id AND c.replaced_at IS NULL
+               ORDER BY s.id"#
+        )
+        .fetch_all(pool)
+        .await?;
+        let now = OffsetDateTime::now_utc();
+        for certificate in certificates {
+            let status = match certificate.not_after {
+                None => "no certificate".to_string(),
+                Some(not_after) if not_after <= now => format!("expired {not_after}"),
+                Some(not_after) => format!(
+                    "expires {not_after} (in {} days)",
+                    (not_after - now).whole_days()
+                ),
+            };
+            println!(
+                "{}\t{}\t{}\t{}\t{status}",
+                certificate.id,
+                certificate.name,
+                certificate.ip,
+                certificate.serial.unwrap_or_default()
+            );
+        }
+        Ok(())
+    }
+
+    struct Issued {
+        serial: String,
+        cert_pem: String,
+        key_pem: String,
+        not_after: OffsetDateTime,
+    }
+
+    fn issue(
+        common_name: &str,
+        subject_alt_names: Vec<String>,
+        usage: ExtendedKeyUsagePurpose,
+    ) -> AppResult<Issued> {
+        let (ca_cert, ca_key) = load_ca()?;
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(subject_alt_names)?;
+        params.distinguished_name = name(common_name);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![usage];
+        let serial = serial();
+        params.serial_number = Some(SerialNumber::from_slice(&serial));
+        params.not_before = OffsetDateTime::now_utc();
+        params.not_after = params.not_before + CERT_VALIDITY;
+        let cert = params.signed_by(&key, &ca_cert, &ca_key)?;
+        Ok(Issued {
+            serial: hex::encode(serial),
+            cert_pem: cert.pem(),
+            key_pem: key.serialize_pem(),
+            not_after: params.not_after,
+        })
+    }
+
+    fn load_ca() -> AppResult<(Certificate, KeyPair)> {
+        let cert_pem = std::fs::read_to_string(dotenvy::var("WEBSITE_CA_CERT")?)?;
+        let key = KeyPair::from_pem(&std::fs::read_to_string(dotenvy::var("WEBSITE_CA_KEY")?)?)?;
+        // Signing only needs the CA's name and key, which re-signing its params reproduces.
+        let cert = CertificateParams::from_ca_cert_pem(&cert_pem)?.self_signed(&key)?;
+        Ok((cert, key))
+    }
+
+    fn name(common_name: &str) -> DistinguishedName {
+        let mut name = DistinguishedName::new();
+        name.push(DnType::CommonName, common_name);
+        name
+    }
+
+    fn serial() -> [u8; 16] {
+        let mut serial = *Uuid::new_v4().as_bytes();
+        // Serials are positive integers.
+        serial[0] &= 0x7f;
+        serial
+    }
+
+    fn write_file(path: &Path, contents: &str, private: bool) -> AppResult<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(if private { 0o600 } else { 0o644 })
+            .open(path)?;
+        file.write_all(contents.as_bytes())?;
+        Ok(())
+    }
+}
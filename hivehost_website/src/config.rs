@@ -0,0 +1,61 @@
+#[cfg(feature = "ssr")]
+pub mod ssr {
+    //! Settings of the website: the shared `[rpc]` section and its own `[website]`.
+
+    use common::config::{require_positive_secs, RpcConfig, Settings};
+    use serde::{Deserialize, Serialize};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::Duration;
+
+    #[derive(Debug, Clone, Default, Deserialize, Serialize)]
+    #[serde(default)]
+    pub struct Config {
+        pub rpc: RpcConfig,
+        pub website: WebsiteConfig,
+    }
+
+    #[derive(Debug, Clone, Deserialize, Serialize)]
+    #[serde(default, deny_unknown_fields)]
+    pub struct WebsiteConfig {
+        pub bind_address: SocketAddr,
+        /// How long a user's permission on a project is cached; a revoked one still works
+        /// until then.
+        pub permissions_ttl_secs: u64,
+        /// How long an installation reported by the GitHub webhook waits for its install callback.
+        pub github_install_ttl_secs: u64,
+    }
+
+    impl Default for WebsiteConfig {
+        fn default() -> Self {
+            WebsiteConfig {
+                bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 3000),
+                permissions_ttl_secs: 900,
+                github_install_ttl_secs: 20,
+            }
+        }
+    }
+
+    impl WebsiteConfig {
+        pub fn permissions_ttl(&self) -> Duration {
+            Duration::from_secs(self.permissions_ttl_secs)
+        }
+
+        pub fn github_install_ttl(&self) -> Duration {
+            Duration::from_secs(self.github_install_ttl_secs)
+        }
+    }
+
+    impl Settings for Config {
+        fn validate(&self) -> Result<(), String> {
+            self.rpc.validate()?;
+            require_positive_secs(
+                "website.permissions_ttl_secs",
+                self.website.permissions_ttl_secs,
+            )?;
+            require_positive_secs(
+                "website.github_install_ttl_secs",
+                self.website.github_install_ttl_secs,
+            )
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use common::error::{RemoteError, RemoteErrorKind};
 use common::SanitizeError;
 use std::str::FromStr;
 use thiserror::Error;
@@ -6,8 +7,12 @@ use thiserror::Error;
 use crate::github::ssr::SignatureError;
 #[cfg(feature = "ssr")]
 use axum_session::SessionError;
+#[cfg(feature = "ssr")]
+use bytes::Bytes;
 use leptos::prelude::{FromServerFnError, ServerFnErrorErr};
 use leptos::server_fn::codec::BincodeEncoding;
+#[cfg(feature = "ssr")]
+use leptos::server_fn::codec::Encodes;
 use serde::{Deserialize, Serialize};
 #[cfg(feature = "ssr")]
 use sqlx::migrate::MigrateError;
@@ -16,6 +21,8 @@ use validator::{ValidationError, ValidationErrors};
 
 pub mod api;
 pub mod app;
+pub mod certs;
+pub mod config;
 pub mod github;
 pub mod models;
 pub mod rate_limiter;
@@ -56,6 +63,9 @@ pub enum AppError {
     #[cfg(feature = "ssr")]
     #[error("AddrParse error: {0}")]
     AddrParse(String),
+    #[cfg(feature = "ssr")]
+    #[error("Config error: {0}")]
+    Config(String),
     #[error("Pool not found")]
     PoolNotFound,
     #[error("Server vars not found")]
@@ -100,16 +110,41 @@ pub enum AppError {
     CantDeleteActiveSnapshot,
     #[error("No Active snapshot")]
     NoActiveSnapshot,
+    #[error("Project disk quota exceeded")]
+    DiskQuotaExceeded,
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Server disk full")]
+    ServerDiskFull,
+    #[error("Server unavailable, try again later")]
+    ServerUnavailable,
+    #[error("Server error")]
+    ServerError,
     #[cfg(feature = "ssr")]
     #[error("Io error: {0}")]
     Io(String),
     #[cfg(feature = "ssr")]
+    #[error("Certificate error: {0}")]
+    Certificate(String),
+    #[cfg(feature = "ssr")]
     #[error("TarpcClientError: {0}")]
     TrpcClientError(#[from] common::tarpc_client::TarpcClientError),
 
     #[cfg(feature = "ssr")]
     #[error("Signature error: {0}")]
     SignatureError(#[from] SignatureError),
+
+    /// How a server function error reaches the browser, with the id to look the request up
+    /// in the logs of every service it went through.
+    #[error("{error} (request {request_id})")]
+    WithRequestId {
+        error: Box<AppError>,
+        request_id: String,
+    },
 }
 
 #[cfg(feature = "ssr")]
@@ -135,17 +170,59 @@ ssr_macros::impl_from_to_string!(AppError, AppError::MigrateError, MigrateError)
 #[cfg(feature = "ssr")]
 ssr_macros::impl_from_to_string!(AppError, AppError::DotEnv, dotenvy::Error);
 #[cfg(feature = "ssr")]
+ssr_macros::impl_from_to_string!(AppError, AppError::Config, common::config::ConfigError);
+#[cfg(feature = "ssr")]
 ssr_macros::impl_from_to_string!(AppError, AppError::RequestError, reqwest::Error);
 #[cfg(feature = "ssr")]
 ssr_macros::impl_from_to_string!(AppError, AppError::SqlxError, sqlx::Error);
 #[cfg(feature = "ssr")]
 ssr_macros::impl_from_to_string!(AppError, AppError::Io, std::io::Error);
+#[cfg(feature = "ssr")]
+ssr_macros::impl_from_to_string!(AppError, AppError::Certificate, rcgen::Error);
+
+/// The server only sends messages meant for users; internal failures keep their detail in
+/// the server logs.
+impl From<RemoteError> for AppError {
+    fn from(error: RemoteError) -> Self {
+        match error.kind {
+            RemoteErrorKind::InvalidInput => AppError::InvalidInput(error.message),
+            RemoteErrorKind::NotFound => AppError::NotFound(error.message),
+            RemoteErrorKind::PermissionDenied => AppError::UnauthorizedProjectAction,
+            RemoteErrorKind::AlreadyExists | RemoteErrorKind::Conflict => {
+                AppError::Conflict(error.message)
+            }
+            RemoteErrorKind::QuotaExceeded => AppError::DiskQuotaExceeded,
+            RemoteErrorKind::DiskFull => AppError::ServerDiskFull,
+            RemoteErrorKind::TimedOut | RemoteErrorKind::Unavailable => AppError::ServerUnavailable,
+            RemoteErrorKind::Internal => AppError::ServerError,
+        }
+    }
+}
 
 impl FromServerFnError for AppError {
     type Encoder = BincodeEncoding;
     fn from_server_fn_error(value: ServerFnErrorErr) -> Self {
         value.into()
     }
+
+    #[cfg(feature = "ssr")]
+    fn ser(&self) -> Bytes {
+        let error = match common::telemetry::request_id() {
+            Some(request_id) if !matches!(self, AppError::WithRequestId { .. }) => {
+                AppError::WithRequestId {
+                    error: Box::new(self.clone()),
+                    request_id,
+                }
+            }
+            _ => self.clone(),
+        };
+        Self::Encoder::encode(&error).unwrap_or_else(|e| {
+            Self::Encoder::encode(&Self::from_server_fn_error(
+                ServerFnErrorErr::Serialization(e.to_string()),
+            ))
+            .expect("the serialization error itself serializes")
+        })
+    }
 }
 
 pub struct BoolInput(pub bool);
@@ -179,12 +256,16 @@ pub mod ssr {
         body::Body as AxumBody,
         extract::{FromRef, Path, State},
         http::Request,
+        middleware::Next,
         response::{IntoResponse, Response},
     };
+    use common::protocol::ProtocolInfo;
     use common::server_action::permission::Permission;
-    use common::server_action::tarpc::WebsiteToServerClient;
-    use common::tarpc_client::{TarpcClient, TarpcClientError};
-    use common::{AuthResponse, AuthToken, ProjectId, ServerId, UserId};
+    use common::server_action::tarpc::{auth_proof, protocol_info, WebsiteToServerClient};
+    use common::tarpc_client::{check_auth_response, TarpcClient, TarpcClientError};
+    use common::telemetry;
+    use common::tls::{self, ClientConfig};
+    use common::{ProjectId, ServerId, UserId};
     use dashmap::DashMap;
     use leptos::config::LeptosOptions;
     use leptos::context::{provide_context, use_context};
@@ -194,11 +275,10 @@ pub mod ssr {
     use secrecy::SecretString;
     use sqlx::types::Uuid;
     use sqlx::PgPool;
-    use std::str::FromStr;
     use std::sync::atomic::Ordering;
     use std::sync::Arc;
-    use tarpc::tokio_serde::formats::Bincode;
     use tarpc::{client, context};
+    use tracing::{field, info_span, Instrument};
 
     pub type Permissions = Arc<Cache<(UserId, ProjectId), Permission>>;
     pub type GithubInstallCache = Arc<Cache<i64, (String, String, String)>>;
@@ -224,15 +304,22 @@ pub mod ssr {
         pub git_pem: Arc<Vec<u8>>,
         pub github_client_id: Arc<String>,
         pub github_webhook_secret: Arc<String>,
+        /// Port of every server's token listener, where browsers upload and download.
+        pub server_token_port: u16,
     }
 
     impl ServerVars {
-        pub fn new(github_client_id: String, github_webhook_secret: String) -> Self {
+        pub fn new(
+            github_client_id: String,
+            github_webhook_secret: String,
+            server_token_port: u16,
+        ) -> Self {
             Self {
                 csrf_server: Arc::new(CsrfServer::default()),
                 git_pem: Arc::new(include_bytes!("../../hivehost-git.private-key.pem").to_vec()),
                 github_client_id: Arc::new(github_client_id),
                 github_webhook_secret: Arc::new(github_webhook_secret),
+                server_token_port,
             }
         }
     }
@@ -285,6 +372,21 @@ pub mod ssr {
         use_context::<Permissions>().ok_or(AppError::PermissionsNotFound)
     }
 
+    /// Runs each request in a span carrying its request id, which server function errors
+    /// report back and the RPCs it makes pass on to the servers.
+    pub async fn trace_request(request: Request<AxumBody>, next: Next) -> Response {
+        let span = info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.uri().path(),
+            request_id = field::Empty,
+        );
+        if let Some(request_id) = span.in_scope(telemetry::request_id) {
+            span.record("request_id", request_id);
+        }
+        next.run(request).instrument(span).await
+    }
+
     pub async fn server_fn_handler(
         State(app_state): State<AppState>,
         auth_session: AppAuthSession,
@@ -326,20 +428,25 @@ pub mod ssr {
         handler(State(options), req).await.into_response()
     }
 
+    /// Connects over TLS to the server at `addr`, whose certificate must be issued for
+    /// `server_name`, and proves it knows `token` without sending it.
     pub async fn connect_website_client(
+        tls_config: Arc<ClientConfig>,
+        server_name: String,
         addr: String,
         token: String,
-    ) -> Result<WebsiteToServerClient, TarpcClientError> {
-        let mut transport = tarpc::serde_transport::tcp::connect(addr, Bincode::default);
-        transport.config_mut().max_frame_length(10 * 10 * 1024);
-        let client =
-            WebsiteToServerClient::new(client::Config::default(), transport.await?).spawn();
-        match client
-            .auth(context::current(), AuthToken::from_str(&token).unwrap())
-            .await
-        {
-            Ok(AuthResponse::Ok) => Ok(client),
-            _ => Err(TarpcClientError::ConnectionError("Auth failed".to_string())),
-        }
+        max_frame_length: usize,
+    ) -> Result<(WebsiteToServerClient, ProtocolInfo), TarpcClientError> {
+        let transport = tls::connect(tls_config, &addr, &server_name, max_frame_length).await?;
+        let client = WebsiteToServerClient::new(client::Config::default(), transport).spawn();
+        let challenge = client.auth_challenge(context::current()).await?;
+        let response = client
+            .auth(
+                context::current(),
+                auth_proof(&token, &challenge),
+                protocol_info(env!("CARGO_PKG_VERSION")),
+            )
+            .await;
+        Ok((client, check_auth_response(response)?))
     }
 }
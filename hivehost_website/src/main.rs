@@ -6,31 +6,34 @@ async fn main() -> hivehost_website::AppResult<()> {
     use axum_session::{SessionConfig, SessionLayer, SessionStore};
     use axum_session_auth::{AuthConfig, AuthSessionLayer};
     use axum_session_sqlx::SessionPgPool;
+    use common::config;
     use common::server_action::tarpc::WebsiteToServerClient;
     use common::tarpc_client::TarpcClient;
+    use common::telemetry::{self, OTLP_ENDPOINT_VAR};
+    use common::tls;
     use common::UserId;
-    use common::SERVER_PORT;
     use dashmap::DashMap;
     use hivehost_website::app::*;
+    use hivehost_website::config::ssr::Config;
     use hivehost_website::github::ssr::github_post_install_callback;
     use hivehost_website::github::ssr::github_webhook;
     use hivehost_website::models::User;
     use hivehost_website::rate_limiter::ssr::RateLimiter;
     use hivehost_website::ssr::connect_website_client;
     use hivehost_website::ssr::ServerVars;
-    use hivehost_website::ssr::{leptos_routes_handler, server_fn_handler, AppState};
+    use hivehost_website::ssr::{
+        leptos_routes_handler, server_fn_handler, trace_request, AppState,
+    };
     use hivehost_website::tasks::refresh_server_csrf::RefreshServerCsrf;
     use hivehost_website::tasks::ssr::TaskDirector;
-    use leptos::logging::error;
+    use hivehost_website::tasks::sync_project_quotas::SyncProjectQuotas;
     use leptos::logging::log;
     use leptos::prelude::*;
     use leptos_axum::{generate_route_list, LeptosRoutes};
     use moka::future::Cache;
     use sqlx::PgPool;
     use std::net::SocketAddr;
-    use std::net::{IpAddr, Ipv4Addr};
     use std::sync::Arc;
-    use std::time::Duration;
 
     #[allow(unused_imports)]
     use memory_serve::{load_assets, CacheControl, MemoryServe};
@@ -42,12 +45,23 @@ async fn main() -> hivehost_website::AppResult<()> {
     use tower_http::CompressionLevel;
 
     dotenvy::dotenv().ok();
+    let config: Config = config::load()?;
+    let _telemetry = telemetry::init(
+        env!("CARGO_CRATE_NAME"),
+        dotenvy::var(OTLP_ENDPOINT_VAR).ok(),
+    );
 
     let database_url = dotenvy::var("DATABASE_URL")?;
     let pool = PgPool::connect(database_url.as_str())
         .await
         .expect("Could not connect to database");
     sqlx::migrate!().run(&pool).await?;
+
+    let args = config::other_args();
+    if args.first().is_some_and(|arg| arg == "certs") {
+        return hivehost_website::certs::ssr::run(&pool, &args[1..]).await;
+    }
+
     let session_config = SessionConfig::default().with_table_name("sessions");
     let session_store =
         SessionStore::<SessionPgPool>::new(Some(pool.clone().into()), session_config).await?;
@@ -55,32 +69,46 @@ async fn main() -> hivehost_website::AppResult<()> {
     let auth_config = AuthConfig::<UserId>::default().with_anonymous_user_id(Some(-1));
 
     let mut conf = get_configuration(None).unwrap();
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 3000);
+    let addr = config.website.bind_address;
     conf.leptos_options.site_addr = addr;
     let leptos_options = conf.leptos_options;
     let github_client_id = dotenvy::var("GITHUB_CLIENT_ID")?;
     let github_webhook_secret = dotenvy::var("GITHUB_WEBHOOK_SECRET")?;
     let routes = generate_route_list(App);
-    let server_vars = ServerVars::new(github_client_id, github_webhook_secret);
+    let server_vars = ServerVars::new(
+        github_client_id,
+        github_webhook_secret,
+        config.rpc.server_token_port,
+    );
     let csrf_server = server_vars.csrf_server.clone();
 
     let rate_limiter = Arc::new(RateLimiter::default());
     let ws_clients = DashMap::new();
+    let tls_config = tls::client_config(
+        &dotenvy::var("WEBSITE_CA_CERT")?,
+        &dotenvy::var("WEBSITE_TLS_CERT")?,
+        &dotenvy::var("WEBSITE_TLS_KEY")?,
+    )?;
     let servers = sqlx::query!(r#"SELECT id,ip,hosting_address , token FROM servers"#,)
         .fetch_all(&pool)
         .await?;
+    let max_frame_length = config.rpc.max_frame_length;
     for server in servers {
+        let tls_config = tls_config.clone();
+        let server_name = server.ip.clone();
         let client = TarpcClient::<WebsiteToServerClient>::new(
-            format!("{}:{SERVER_PORT}", server.ip),
+            format!("{}:{}", server.ip, config.rpc.server_port),
             server.token,
-            connect_website_client,
+            move |addr, token| {
+                connect_website_client(
+                    tls_config.clone(),
+                    server_name.clone(),
+                    addr,
+                    token,
+                    max_frame_length,
+                )
+            },
         );
-        let connect_website_client = client.clone();
-        tokio::spawn(async move {
-            if let Err(e) = connect_website_client.connect().await {
-                error!("Initial WebsiteServerClient connection failed: {:?}", e);
-            }
-        });
         ws_clients.insert(server.id, client);
     }
 
@@ -92,19 +120,20 @@ async fn main() -> hivehost_website::AppResult<()> {
         server_vars,
         permissions: Arc::new(
             Cache::builder()
-                .time_to_live(Duration::from_secs(900)) // 15 minutes
+                .time_to_live(config.website.permissions_ttl())
                 .build(),
         ),
         github_install_cache: Arc::new(
             Cache::builder()
-                .time_to_live(Duration::from_secs(20))
+                .time_to_live(config.website.github_install_ttl())
                 .build(),
         ),
-        ws_clients,
+        ws_clients: ws_clients.clone(),
     };
 
     let mut task_director = TaskDirector::default();
     task_director.add_task(RefreshServerCsrf::new(csrf_server, 0, false));
+    task_director.add_task(SyncProjectQuotas::new(pool.clone(), ws_clients));
     tokio::spawn(async move {
         task_director.run().await;
     });
@@ -155,6 +184,7 @@ async fn main() -> hivehost_website::AppResult<()> {
                 .with_config(auth_config),
         )
         .layer(SessionLayer::new(session_store))
+        .layer(axum::middleware::from_fn(trace_request))
         // .route_layer(axum::middleware::from_fn_with_state(
         //     rate_limiter.clone(),
         //     rate_limit_middleware,
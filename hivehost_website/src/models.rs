@@ -1,3 +1,5 @@
+use common::helper_command::ProjectUsage;
+use common::protocol::ProtocolInfo;
 use common::server_action::permission::Permission;
 use common::{ProjectId, ServerId, Slug, UserId};
 use reactive_stores::{Patch, Store};
@@ -81,6 +83,28 @@ pub struct GitProject {
 pub struct Server {
     pub id: ServerId,
     pub name: String,
+    /// What the server reported the last time the website connected to it.
+    #[cfg_attr(feature = "ssr", sqlx(skip))]
+    pub protocol: Option<ProtocolInfo>,
+}
+
+impl Server {
+    /// Connected before, with a protocol version this website doesn't speak.
+    pub fn is_incompatible(&self) -> bool {
+        self.protocol
+            .as_ref()
+            .is_some_and(|protocol| !protocol.is_supported())
+    }
+
+    pub fn label(&self) -> String {
+        match &self.protocol {
+            Some(protocol) if !protocol.is_supported() => {
+                format!("{} ({protocol}, incompatible)", self.name)
+            }
+            Some(protocol) => format!("{} ({protocol})", self.name),
+            None => format!("{} (not reached yet)", self.name),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -96,6 +120,31 @@ pub struct ProjectSnapshot {
     pub created_at: String,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectDiskUsage {
+    pub usage: ProjectUsage,
+    /// Limit of the project owner's plan.
+    pub plan_limit_bytes: u64,
+}
+
+impl ProjectDiskUsage {
+    pub fn used_percent(&self) -> u64 {
+        if self.plan_limit_bytes == 0 {
+            return 100;
+        }
+        (self.usage.total.referenced.saturating_mul(100) / self.plan_limit_bytes).min(100)
+    }
+
+    /// Bytes only this snapshot holds, freed when it is deleted.
+    pub fn snapshot_exclusive_bytes(&self, snapshot_name: &str) -> Option<u64> {
+        self.usage
+            .snapshots
+            .iter()
+            .find(|(name, _)| name.0 == snapshot_name)
+            .map(|(_, usage)| usage.exclusive)
+    }
+}
+
 impl Project {
     pub fn get_slug(&self) -> Slug {
         Slug::new(self.id, self.name.clone())
@@ -17,7 +17,9 @@ pub async fn request_token_action_front(
     action: TokenAction,
     csrf: Option<String>,
 ) -> AppResult<String> {
-    use crate::ssr::pool;
+    use crate::api::ssr::ensure_project_disk_quota;
+    use crate::security::utils::ssr::get_auth_session_user_slug;
+    use crate::ssr::{pool, server_vars};
     use common::server_action::project_action::IsProjectServerAction;
     use common::server_action::token_action::TokenActionResponse;
     use common::tarpc_client::TarpcClientError;
@@ -26,13 +28,20 @@ pub async fn request_token_action_front(
         project_slug,
         action.permission(),
         action.require_csrf().then_some(csrf.unwrap_or_default()),
-        |_, _, project_slug| async move {
+        |auth, pool, project_slug| async move {
+            if matches!(action, TokenAction::UploadFiles { .. }) {
+                ensure_project_disk_quota(&pool, server_id, &project_slug, None).await?;
+            }
+            // The server opens the files as this user.
+            let user_slug = get_auth_session_user_slug(&auth)
+                .ok_or(crate::AppError::UnauthorizedProjectAccess)?
+                .to_user_slug_str();
             match crate::ssr::ws_clients()?.get(&server_id) {
                 None => Err(crate::AppError::TrpcClientError(
                     TarpcClientError::NotConnected,
                 )),
                 Some(client) => client
-                    .token_action(project_slug.to_project_slug_str(), action)
+                    .token_action(project_slug.to_project_slug_str(), user_slug, action)
                     .await
                     .map_err(Into::into),
             }
@@ -46,12 +55,10 @@ pub async fn request_token_action_front(
                 .fetch_one(&pool)
                 .await
                 .map_err(|_| crate::AppError::UnauthorizedProjectAccess)?;
-            let server_url = format!("{}:{}", server.ip, common::SERVER_TOKEN_PORT);
+            let server_url = format!("{}:{}", server.ip, server_vars()?.server_token_port);
             Ok(token_url(&server_url, &token))
         }
-        TokenActionResponse::Error(e) => Err(crate::AppError::TrpcClientError(
-            TarpcClientError::RpcError(e),
-        )),
+        TokenActionResponse::Error(e) => Err(e.into()),
     }
 }
 
@@ -62,6 +69,7 @@ pub async fn request_server_project_action_front(
     action: ProjectAction,
     csrf: Option<String>,
 ) -> AppResult<ProjectResponse> {
+    use crate::security::utils::ssr::get_auth_session_user_slug;
     use common::server_action::project_action::IsProjectServerAction;
     use common::tarpc_client::TarpcClientError;
 
@@ -69,15 +77,18 @@ pub async fn request_server_project_action_front(
         project_slug,
         action.permission(),
         action.require_csrf().then_some(csrf.unwrap_or_default()),
-        |_, _, project_slug| async move {
+        |auth, _, project_slug| async move {
+            let user_slug = get_auth_session_user_slug(&auth)
+                .ok_or(crate::AppError::UnauthorizedProjectAccess)?
+                .to_user_slug_str();
             match crate::ssr::ws_clients()?.get(&server_id) {
                 None => Err(crate::AppError::TrpcClientError(
                     TarpcClientError::NotConnected,
                 )),
-                Some(client) => client
-                    .project_action(project_slug.to_project_slug_str(), action)
-                    .await
-                    .map_err(Into::into),
+                Some(client) => Ok(client
+                    .project_action(project_slug.to_project_slug_str(), Some(user_slug), action)
+                    .await?
+                    .into_result()?),
             }
         },
     )
@@ -1,6 +1,8 @@
 #[cfg(feature = "ssr")]
 pub mod refresh_server_csrf;
 #[cfg(feature = "ssr")]
+pub mod sync_project_quotas;
+#[cfg(feature = "ssr")]
 pub mod ssr {
     use async_trait::async_trait;
     use chrono::Timelike;
@@ -0,0 +1,73 @@
+use crate::api::ssr::sync_project_quotas;
+use crate::ssr::WsClients;
+use crate::tasks::ssr::Task;
+use async_trait::async_trait;
+use sqlx::PgPool;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::error;
+
+/// Gives the servers time to connect before the first run.
+const STARTUP_DELAY: Duration = Duration::from_secs(30);
+const INTERVAL: Duration = Duration::from_secs(3600);
+
+pub struct SyncProjectQuotas {
+    pub pool: PgPool,
+    pub ws_clients: WsClients,
+    pub next_run: Instant,
+    pub running: Arc<AtomicBool>,
+}
+
+impl SyncProjectQuotas {
+    pub fn new(pool: PgPool, ws_clients: WsClients) -> Self {
+        Self {
+            pool,
+            ws_clients,
+            next_run: Instant::now() + STARTUP_DELAY,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+#[async_trait]
+impl Task for SyncProjectQuotas {
+    async fn execute(&self) {
+        if let Err(e) = sync_project_quotas(&self.pool, &self.ws_clients).await {
+            error!("Could not sync project limits: {e}");
+        }
+    }
+
+    fn next_execution(&self) -> Instant {
+        self.next_run
+    }
+
+    fn update_schedule(&mut self) {
+        self.next_run = Instant::now() + INTERVAL;
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn set_running(&self, running: bool) {
+        self.running.store(running, Ordering::SeqCst);
+    }
+
+    fn clone_box(&self) -> Box<dyn Task> {
+        Box::new(Self {
+            pool: self.pool.clone(),
+            ws_clients: self.ws_clients.clone(),
+            next_run: self.next_run,
+            running: self.running.clone(),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "Sync Project Quotas"
+    }
+
+    fn allow_concurrent(&self) -> bool {
+        false
+    }
+}
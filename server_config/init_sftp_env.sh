@@ -87,6 +87,13 @@ else
   exit 1 # ACLs are critical for this design
 fi
 
+# Per-project trash for web deletions, on the dev volume but outside every project subvolume
+TRASH_BASE="$BTRFS_DEV_MOUNT_POINT/.trash"
+echo "Creating trash directory '$TRASH_BASE'..."
+mkdir -p "$TRASH_BASE"
+chown "$SERVICE_USER":"$SERVICE_GROUP" "$TRASH_BASE"
+chmod 700 "$TRASH_BASE"
+
 # Grant service user access to manage production mount points
 echo "Granting service user access to '$PROD_MOUNT_BASE'..."
 setfacl -m "u:$SERVICE_USER:rwx" "$PROD_MOUNT_BASE"